@@ -0,0 +1,93 @@
+'use strict';
+
+// Node.js wrapper of the addon built with
+// `cargo rustc --release --lib --features napi --crate-type cdylib`,
+// copied next to this file as megatec_ups.node
+
+const { EventEmitter } = require('events');
+const native = require('./megatec_ups.node');
+
+/**
+ * Connection to a Megatec UPS
+ *
+ * Every call returns a Promise and runs on the libuv thread pool, so the
+ * USB transfers and the 1 second acknowledgment delay of `status()` do not
+ * block the event loop. Calls on one connection run one after the other.
+ */
+class Ups {
+  constructor(handle) {
+    this._handle = handle;
+  }
+
+  /** Connect to the USB device with the given vendor and product ID */
+  static async connect(vendorId, productId) {
+    return new Ups(await native.connect(vendorId, productId));
+  }
+
+  /** Connect to the first well-known Megatec device that answers */
+  static async auto() {
+    return new Ups(await native.auto());
+  }
+
+  /** Resolve to the status: voltages, load, frequency, temperature and flags */
+  status() {
+    return native.status(this._handle);
+  }
+
+  /** Resolve to the name the UPS reports */
+  name() {
+    return native.name(this._handle);
+  }
+
+  /** Test for 10 seconds, resolving to 'in progress', 'passed', 'failed' or 'not started' */
+  test() {
+    return native.test(this._handle);
+  }
+
+  abortTest() {
+    return native.abortTest(this._handle);
+  }
+
+  switchBeep() {
+    return native.switchBeep(this._handle);
+  }
+
+  /** Shut down after `minutes`, 1 minute when omitted */
+  shutdown(minutes) {
+    return native.shutdown(this._handle, minutes);
+  }
+
+  cancelShutdown() {
+    return native.cancelShutdown(this._handle);
+  }
+
+  /**
+   * Poll every `intervalMs` on a background thread and emit the events;
+   * wait for the pending calls first
+   */
+  monitor(intervalMs = 5000) {
+    return new UpsMonitor(this, intervalMs);
+  }
+}
+
+/**
+ * Emits every monitor event under its name ('power-failure', 'power-restored',
+ * 'low-battery', 'communication-lost', ...) with the status snapshot, and all
+ * of them as 'event' with the name first. The connection is in use until
+ * `stop()`.
+ */
+class UpsMonitor extends EventEmitter {
+  constructor(ups, intervalMs) {
+    super();
+    this._handle = native.monitor(ups._handle, intervalMs, (name, status) => {
+      this.emit('event', name, status);
+      this.emit(name, status);
+    });
+  }
+
+  stop() {
+    native.stopMonitor(this._handle);
+  }
+}
+
+module.exports = { Ups, UpsMonitor };
@@ -2,8 +2,8 @@ use megatec_ups_control::{MegatecUps, Result, UpsStatus};
 
 fn main() -> Result<()> {
     // Create a new UPS connection
-    // Replace these with your actual vendor and product IDs
-    let ups = match MegatecUps::new(0x0001, 0x0000) {
+    // Use MegatecUps::new(vendor_id, product_id) if your device is not detected
+    let ups = match MegatecUps::auto() {
         Ok(ups) => {
             println!("Successfully connected to UPS device");
             ups
@@ -24,10 +24,11 @@ fn main() -> Result<()> {
     println!("  Input Voltage: {} V", status.input_voltage);
     println!("  Input Fault Voltage: {} V", status.input_fault_voltage);
     println!("  Output Voltage: {} V", status.output_voltage);
-    println!("  Output Current: {}%", status.output_current);
+    println!("  Load: {}%", status.load_raw);
     println!("  Input Frequency: {} Hz", status.input_frequency);
     println!("  Battery Voltage: {} V", status.battery_voltage);
     println!("  Temperature: {} °C", status.temperature);
+    println!("  On Battery: {}", status.flags.on_battery());
 
     // Perform a 10-second test
     println!("Performing 10-second test...");
@@ -0,0 +1,104 @@
+/*
+ * C interface of the megatec-ups-control library, built with the `ffi` feature:
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * or `--crate-type staticlib` for a static library.
+ *
+ * Functions returning int give MEGATEC_UPS_OK or a negative
+ * MEGATEC_UPS_ERROR_* code; megatec_ups_last_error() describes the last
+ * failure of the calling thread.
+ */
+
+#ifndef MEGATEC_UPS_H
+#define MEGATEC_UPS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define MEGATEC_UPS_OK 0
+#define MEGATEC_UPS_ERROR_USB -1
+#define MEGATEC_UPS_ERROR_IO -2
+#define MEGATEC_UPS_ERROR_NOT_FOUND -3
+#define MEGATEC_UPS_ERROR_PERMISSION_DENIED -4
+#define MEGATEC_UPS_ERROR_TIMEOUT -5
+#define MEGATEC_UPS_ERROR_DISCONNECTED -6
+#define MEGATEC_UPS_ERROR_INVALID_RESPONSE -7
+#define MEGATEC_UPS_ERROR_INVALID_TIME -8
+#define MEGATEC_UPS_ERROR_UNSUPPORTED -9
+#define MEGATEC_UPS_ERROR_NO_SHUTDOWN_PENDING -10
+#define MEGATEC_UPS_ERROR_CANCELLED -11
+#define MEGATEC_UPS_ERROR_NULL_ARGUMENT -12
+
+/* Opaque connection */
+typedef struct MegatecUps MegatecUps;
+
+/* Status reading; missing values are NaN */
+typedef struct MegatecUpsStatus {
+    double input_voltage;
+    double input_fault_voltage;
+    double output_voltage;
+    double load_raw;
+    double input_frequency;
+    double battery_voltage;
+    double temperature;
+    bool utility_fail;
+    bool battery_low;
+    bool bypass_active;
+    bool ups_failed;
+    bool standby;
+    bool test_in_progress;
+    bool shutdown_active;
+    bool beeper_on;
+} MegatecUpsStatus;
+
+/* Outcome of a battery test */
+typedef enum MegatecUpsTestResult {
+    MEGATEC_UPS_TEST_IN_PROGRESS = 0,
+    MEGATEC_UPS_TEST_PASSED = 1,
+    MEGATEC_UPS_TEST_FAILED = 2,
+    MEGATEC_UPS_TEST_NOT_STARTED = 3,
+} MegatecUpsTestResult;
+
+/* Connect to a USB device, NULL on failure */
+MegatecUps *megatec_ups_connect(uint16_t vendor_id, uint16_t product_id);
+
+/* Connect to the first well-known Megatec device that answers, NULL on failure */
+MegatecUps *megatec_ups_auto(void);
+
+/* Close a connection and release it; NULL is ignored */
+void megatec_ups_free(MegatecUps *ups);
+
+int megatec_ups_get_status(const MegatecUps *ups, MegatecUpsStatus *status);
+
+/* Copy the name as a NUL-terminated string, truncated to `length` bytes */
+int megatec_ups_get_name(const MegatecUps *ups, char *buffer, size_t length);
+
+/* Test for 10 seconds; `result` may be NULL */
+int megatec_ups_test(const MegatecUps *ups, MegatecUpsTestResult *result);
+
+int megatec_ups_abort_test(const MegatecUps *ups);
+
+int megatec_ups_switch_beep(const MegatecUps *ups);
+
+/* Shut down after 1 minute */
+int megatec_ups_shutdown(const MegatecUps *ups);
+
+/* Shut down after .2 to .9 minutes or 1 to 10 whole minutes */
+int megatec_ups_shutdown_after(const MegatecUps *ups, float minutes);
+
+int megatec_ups_cancel_shutdown(const MegatecUps *ups);
+
+/* Message of the last failure on this thread, NULL when none */
+const char *megatec_ups_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MEGATEC_UPS_H */
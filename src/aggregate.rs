@@ -0,0 +1,135 @@
+use crate::alarms::Quantity;
+use crate::status::UpsStatus;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// Windows summarized by [`Aggregator::default`]: 1 minute, 15 minutes and 1 hour
+pub const DEFAULT_WINDOWS: [Duration; 3] = [
+    Duration::from_secs(60),
+    Duration::from_secs(15 * 60),
+    Duration::from_secs(60 * 60),
+];
+
+/// Minimum, maximum and mean of a quantity over a window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Number of readings with a value
+    pub count: usize,
+}
+
+/// Compact form such as `min 228.0 max 231.5 mean 229.8 (12 readings)`
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "min {:.1} max {:.1} mean {:.1} ({} readings)",
+            self.min, self.max, self.mean, self.count
+        )
+    }
+}
+
+/// Summarizes every [`Quantity`] of the recent status readings over time windows
+///
+/// Readings older than the longest window are dropped. Windows end at the
+/// newest reading, and missing (NaN) values are skipped.
+#[derive(Debug, Clone)]
+pub struct Aggregator {
+    windows: Vec<Duration>,
+    samples: VecDeque<(SystemTime, [f64; Quantity::ALL.len()])>,
+}
+
+impl Aggregator {
+    /// Summarize over `windows`
+    pub fn new(windows: &[Duration]) -> Self {
+        Self {
+            windows: windows.to_vec(),
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Get the windows
+    pub fn windows(&self) -> &[Duration] {
+        &self.windows
+    }
+
+    /// Add a reading taken now
+    pub fn record(&mut self, status: &UpsStatus) {
+        self.record_at(SystemTime::now(), status)
+    }
+
+    /// Add a reading taken at `time`
+    pub fn record_at(&mut self, time: SystemTime, status: &UpsStatus) {
+        self.samples
+            .push_back((time, Quantity::ALL.map(|quantity| quantity.value(status))));
+
+        let longest = self.windows.iter().max().copied().unwrap_or_default();
+        while let Some((oldest, _)) = self.samples.front() {
+            match time.duration_since(*oldest) {
+                Ok(age) if age > longest => self.samples.pop_front(),
+                _ => break,
+            };
+        }
+    }
+
+    /// Summarize a quantity over the `window` ending at the newest reading
+    ///
+    /// Returns `None` when no reading in the window has a value.
+    pub fn summary(&self, window: Duration, quantity: Quantity) -> Option<Summary> {
+        let index = Quantity::ALL.iter().position(|q| *q == quantity)?;
+        let (newest, _) = self.samples.back()?;
+
+        let mut summary: Option<Summary> = None;
+        let mut sum = 0.0;
+        for (time, values) in self.samples.iter().rev() {
+            if newest.duration_since(*time).unwrap_or_default() > window {
+                break;
+            }
+            let value = values[index];
+            if value.is_nan() {
+                continue;
+            }
+            sum += value;
+            summary = Some(match summary {
+                Some(summary) => Summary {
+                    min: summary.min.min(value),
+                    max: summary.max.max(value),
+                    mean: 0.0,
+                    count: summary.count + 1,
+                },
+                None => Summary {
+                    min: value,
+                    max: value,
+                    mean: 0.0,
+                    count: 1,
+                },
+            });
+        }
+        summary.map(|summary| Summary {
+            mean: sum / summary.count as f64,
+            ..summary
+        })
+    }
+
+    /// Summarize every quantity with a value over `window`
+    pub fn summaries(&self, window: Duration) -> Vec<(Quantity, Summary)> {
+        Quantity::ALL
+            .iter()
+            .filter_map(|quantity| Some((*quantity, self.summary(window, *quantity)?)))
+            .collect()
+    }
+
+    /// Forget the readings seen so far
+    pub fn reset(&mut self) {
+        self.samples.clear();
+    }
+}
+
+impl Default for Aggregator {
+    fn default() -> Self {
+        Self::new(&DEFAULT_WINDOWS)
+    }
+}
@@ -0,0 +1,309 @@
+use crate::status::UpsStatus;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Hysteresis of [`Threshold::over_temperature`] in degrees Celsius
+pub const OVER_TEMPERATURE_HYSTERESIS: f64 = 2.0;
+/// Load of the UPS at its rating, in percent
+pub const RATED_LOAD_PERCENT: f64 = 100.0;
+/// Time the load must exceed the limit of [`Threshold::overload`]
+pub const DEFAULT_OVERLOAD_DURATION: Duration = Duration::from_secs(10);
+
+/// Status value an alarm threshold watches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Quantity {
+    InputVoltage,
+    OutputVoltage,
+    /// Load in percent, reported as [`UpsStatus::load_raw`]
+    Load,
+    InputFrequency,
+    BatteryVoltage,
+    Temperature,
+}
+
+impl Quantity {
+    /// Every quantity, in status order
+    pub const ALL: [Quantity; 6] = [
+        Quantity::InputVoltage,
+        Quantity::OutputVoltage,
+        Quantity::Load,
+        Quantity::InputFrequency,
+        Quantity::BatteryVoltage,
+        Quantity::Temperature,
+    ];
+
+    /// Read the value from a status snapshot
+    pub fn value(&self, status: &UpsStatus) -> f64 {
+        match self {
+            Quantity::InputVoltage => status.input_voltage,
+            Quantity::OutputVoltage => status.output_voltage,
+            Quantity::Load => status.load_raw,
+            Quantity::InputFrequency => status.input_frequency,
+            Quantity::BatteryVoltage => status.battery_voltage,
+            Quantity::Temperature => status.temperature,
+        }
+    }
+
+    /// Get the name used on the command line and in messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quantity::InputVoltage => "input-voltage",
+            Quantity::OutputVoltage => "output-voltage",
+            Quantity::Load => "load",
+            Quantity::InputFrequency => "input-frequency",
+            Quantity::BatteryVoltage => "battery-voltage",
+            Quantity::Temperature => "temperature",
+        }
+    }
+
+    /// Get the unit of the value
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Quantity::InputVoltage | Quantity::OutputVoltage | Quantity::BatteryVoltage => "V",
+            Quantity::Load => "%",
+            Quantity::InputFrequency => "Hz",
+            Quantity::Temperature => "°C",
+        }
+    }
+
+    /// Find a quantity by its [`name`](Self::name)
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|quantity| quantity.name() == name)
+    }
+}
+
+/// Side of the limit that raises the alarm
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Above(f64),
+    Below(f64),
+}
+
+/// Alarm condition on one status value
+///
+/// The alarm is raised once the value has been beyond the limit for
+/// `min_duration`, and cleared once it is back past the limit by more than
+/// `hysteresis`, so a value hovering around the limit does not flap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Threshold {
+    pub quantity: Quantity,
+    pub limit: Limit,
+    /// Distance the value must move back past the limit before the alarm clears
+    pub hysteresis: f64,
+    /// Time the limit must be exceeded before the alarm is raised
+    pub min_duration: Duration,
+}
+
+impl Threshold {
+    /// Alarm when the value rises above `limit`
+    pub fn above(quantity: Quantity, limit: f64) -> Self {
+        Self::new(quantity, Limit::Above(limit))
+    }
+
+    /// Alarm when the value falls below `limit`
+    pub fn below(quantity: Quantity, limit: f64) -> Self {
+        Self::new(quantity, Limit::Below(limit))
+    }
+
+    /// Alarm when the internal temperature rises above `limit` degrees Celsius
+    ///
+    /// Clears [`OVER_TEMPERATURE_HYSTERESIS`] below the limit, so a sensor
+    /// hovering around it does not flap.
+    pub fn over_temperature(limit: f64) -> Self {
+        Self::above(Quantity::Temperature, limit).with_hysteresis(OVER_TEMPERATURE_HYSTERESIS)
+    }
+
+    /// Alarm when the load stays above `limit` percent for [`DEFAULT_OVERLOAD_DURATION`]
+    ///
+    /// Use [`RATED_LOAD_PERCENT`] for the rated load, or a lower limit to shed
+    /// loads before the UPS itself complains.
+    pub fn overload(limit: f64) -> Self {
+        Self::above(Quantity::Load, limit).with_min_duration(DEFAULT_OVERLOAD_DURATION)
+    }
+
+    fn new(quantity: Quantity, limit: Limit) -> Self {
+        Self {
+            quantity,
+            limit,
+            hysteresis: 0.0,
+            min_duration: Duration::ZERO,
+        }
+    }
+
+    /// Set the hysteresis
+    pub fn with_hysteresis(self, hysteresis: f64) -> Self {
+        Self { hysteresis, ..self }
+    }
+
+    /// Set the minimum duration
+    pub fn with_min_duration(self, min_duration: Duration) -> Self {
+        Self {
+            min_duration,
+            ..self
+        }
+    }
+
+    /// Check if a value is beyond the limit
+    fn exceeded(&self, value: f64) -> bool {
+        match self.limit {
+            Limit::Above(limit) => value > limit,
+            Limit::Below(limit) => value < limit,
+        }
+    }
+
+    /// Check if a value is back past the limit by more than the hysteresis
+    fn recovered(&self, value: f64) -> bool {
+        match self.limit {
+            Limit::Above(limit) => value <= limit - self.hysteresis,
+            Limit::Below(limit) => value >= limit + self.hysteresis,
+        }
+    }
+}
+
+/// Condition and limit, such as `load > 80 %`
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (operator, limit) = match self.limit {
+            Limit::Above(limit) => (">", limit),
+            Limit::Below(limit) => ("<", limit),
+        };
+        write!(
+            f,
+            "{} {} {} {}",
+            self.quantity.name(),
+            operator,
+            limit,
+            self.quantity.unit()
+        )
+    }
+}
+
+/// Alarm raised or cleared by [`Alarms::update`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlarmEvent {
+    /// The value stayed beyond the limit for the minimum duration
+    Raised { threshold: Threshold, value: f64 },
+    /// The value is back within the limit and the hysteresis
+    Cleared { threshold: Threshold, value: f64 },
+}
+
+impl AlarmEvent {
+    /// Get the threshold of the alarm
+    pub fn threshold(&self) -> &Threshold {
+        match self {
+            AlarmEvent::Raised { threshold, .. } | AlarmEvent::Cleared { threshold, .. } => {
+                threshold
+            }
+        }
+    }
+
+    /// Get the value that raised or cleared the alarm
+    pub fn value(&self) -> f64 {
+        match self {
+            AlarmEvent::Raised { value, .. } | AlarmEvent::Cleared { value, .. } => *value,
+        }
+    }
+}
+
+impl fmt::Display for AlarmEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            AlarmEvent::Raised { .. } => "raised",
+            AlarmEvent::Cleared { .. } => "cleared",
+        };
+        let threshold = self.threshold();
+        write!(
+            f,
+            "alarm {}: {} (now {} {})",
+            state,
+            threshold,
+            self.value(),
+            threshold.quantity.unit()
+        )
+    }
+}
+
+#[derive(Debug)]
+struct AlarmState {
+    threshold: Threshold,
+    exceeded_since: Option<Instant>,
+    active: bool,
+}
+
+/// Evaluates a set of alarm thresholds against status readings
+///
+/// Feed every successful status reading to [`update`](Self::update).
+/// Values missing from truncated replies leave the alarms unchanged.
+#[derive(Debug, Default)]
+pub struct Alarms {
+    alarms: Vec<AlarmState>,
+}
+
+impl Alarms {
+    /// Create an empty set of alarms
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a threshold
+    pub fn add(&mut self, threshold: Threshold) {
+        self.alarms.push(AlarmState {
+            threshold,
+            exceeded_since: None,
+            active: false,
+        });
+    }
+
+    /// Check if no threshold was added
+    pub fn is_empty(&self) -> bool {
+        self.alarms.is_empty()
+    }
+
+    /// Get the thresholds
+    pub fn thresholds(&self) -> impl Iterator<Item = &Threshold> {
+        self.alarms.iter().map(|alarm| &alarm.threshold)
+    }
+
+    /// Get the thresholds whose alarm is currently raised
+    pub fn active(&self) -> impl Iterator<Item = &Threshold> {
+        self.alarms
+            .iter()
+            .filter(|alarm| alarm.active)
+            .map(|alarm| &alarm.threshold)
+    }
+
+    /// Evaluate a status reading taken now
+    pub fn update(&mut self, status: &UpsStatus) -> Vec<AlarmEvent> {
+        self.update_at(Instant::now(), status)
+    }
+
+    /// Evaluate a status reading taken at `now`
+    pub fn update_at(&mut self, now: Instant, status: &UpsStatus) -> Vec<AlarmEvent> {
+        let mut events = Vec::new();
+        for alarm in &mut self.alarms {
+            let threshold = alarm.threshold;
+            let value = threshold.quantity.value(status);
+            if !value.is_finite() {
+                continue;
+            }
+
+            if threshold.exceeded(value) {
+                let since = *alarm.exceeded_since.get_or_insert(now);
+                if !alarm.active && now.duration_since(since) >= threshold.min_duration {
+                    alarm.active = true;
+                    events.push(AlarmEvent::Raised { threshold, value });
+                }
+                continue;
+            }
+
+            alarm.exceeded_since = None;
+            if alarm.active && threshold.recovered(value) {
+                alarm.active = false;
+                events.push(AlarmEvent::Cleared { threshold, value });
+            }
+        }
+        events
+    }
+}
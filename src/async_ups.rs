@@ -0,0 +1,901 @@
+use crate::error::{Result, UpsError};
+use crate::event::UpsEvent;
+use crate::extended::ExtendedStatus;
+use crate::info::UpsInfo;
+use crate::mode::UpsMode;
+use crate::rating::RatingInfo;
+use crate::status::{StatusFlags, TestResult, UpsStatus};
+use crate::transport::{Command, Transport};
+use crate::usb::UsbTransport;
+use crate::{MegatecUps, TEST_POLL_INTERVAL};
+use std::collections::VecDeque;
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Blocking worker threads [`ThreadRuntime`] runs at most
+const MAX_BLOCKING_THREADS: usize = 8;
+
+/// Idle blocking worker threads exit after this long
+const BLOCKING_KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+/// Future returned by [`AsyncRuntime::sleep`]
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Future run in the background by [`AsyncRuntime::spawn`]
+pub type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Blocking work, such as a USB transfer, run by [`AsyncRuntime::spawn_blocking`]
+pub type BlockingWork = Box<dyn FnOnce() + Send>;
+
+/// Future returned by [`AsyncRuntime::spawn_blocking`], completing with the work
+pub type Blocking = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Timer, task spawner and blocking pool of the async runtime the connection uses
+///
+/// Any `Fn(Duration) -> impl Future<Output = ()>` is a runtime, so
+/// `tokio::time::sleep` or `|d| async move { smol::Timer::after(d).await; }`
+/// plug in directly and keep the default `spawn` and `spawn_blocking`, which
+/// run on the threads of [`ThreadRuntime`]. A runtime of its own implements
+/// all three, for tokio with `tokio::spawn` and `tokio::task::spawn_blocking`,
+/// for smol with `smol::spawn(task).detach()` and `smol::unblock`.
+pub trait AsyncRuntime: Send + Sync {
+    /// Get a future completing after `duration`
+    fn sleep(&self, duration: Duration) -> Sleep;
+
+    /// Run a future in the background, on a thread of its own by default
+    fn spawn(&self, task: Task) {
+        ThreadRuntime.spawn(task);
+    }
+
+    /// Run blocking work off the executor threads, on the shared pool of
+    /// [`ThreadRuntime`] by default
+    fn spawn_blocking(&self, work: BlockingWork) -> Blocking {
+        ThreadRuntime.spawn_blocking(work)
+    }
+}
+
+impl<F, S> AsyncRuntime for F
+where
+    F: Fn(Duration) -> S + Send + Sync,
+    S: Future<Output = ()> + Send + 'static,
+{
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(self(duration))
+    }
+}
+
+/// Runtime of plain threads, working under every executor
+///
+/// Sleeps are [`Delay`]s, all served by one timer thread. Blocking work runs
+/// on a pool of up to 8 threads started on demand, and spawned tasks are
+/// driven each on a thread of their own.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadRuntime;
+
+impl AsyncRuntime for ThreadRuntime {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(Delay::new(duration))
+    }
+
+    fn spawn(&self, task: Task) {
+        thread::spawn(move || block_on(task));
+    }
+
+    fn spawn_blocking(&self, work: BlockingWork) -> Blocking {
+        let signal = Signal::new();
+        let done = Arc::clone(&signal);
+        BlockingPool::get().run(Box::new(move || {
+            // The waiting future completes even if the work panics
+            let _guard = SignalGuard(done);
+            work();
+        }));
+        Box::pin(Wait(signal))
+    }
+}
+
+/// Asynchronous interface to a Megatec UPS device, usable from any executor
+///
+/// Every command runs on the blocking pool of the runtime
+/// ([`AsyncRuntime::spawn_blocking`]), so USB transfers and retry backoffs
+/// never block an executor thread. The acknowledgment delay of
+/// [`get_status`](Self::get_status) is a sleep of the runtime and holds no
+/// thread at all.
+///
+/// Commands sent through this handle take turns, so none gets between the
+/// acknowledgment and the status read. Calls on [`blocking`](Self::blocking)
+/// bypass that order.
+pub struct AsyncMegatecUps<T: Transport = UsbTransport> {
+    inner: Arc<MegatecUps<T>>,
+    runtime: Arc<dyn AsyncRuntime>,
+    commands: AsyncLock,
+}
+
+impl AsyncMegatecUps<UsbTransport> {
+    /// Create a new UPS connection using vendor_id and product_id
+    pub fn new(vendor_id: u16, product_id: u16) -> Result<Self> {
+        Ok(Self::from_blocking(MegatecUps::new(vendor_id, product_id)?))
+    }
+}
+
+impl<T: Transport + Send + 'static> AsyncMegatecUps<T> {
+    /// Create a new UPS connection on top of an existing transport
+    pub fn with_transport(transport: T) -> Self {
+        Self::from_blocking(MegatecUps::with_transport(transport))
+    }
+
+    /// Wrap an existing blocking connection
+    pub fn from_blocking(inner: MegatecUps<T>) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            runtime: Arc::new(ThreadRuntime),
+            commands: AsyncLock::new(),
+        }
+    }
+
+    /// Sleep and run blocking work on `runtime` instead of [`ThreadRuntime`]
+    pub fn set_runtime<R: AsyncRuntime + 'static>(&mut self, runtime: R) {
+        self.runtime = Arc::new(runtime);
+    }
+
+    /// Get a reference to the blocking connection
+    pub fn blocking(&self) -> &MegatecUps<T> {
+        &self.inner
+    }
+
+    /// Run a call of the blocking connection on the blocking pool of the runtime,
+    /// after the commands sent before it
+    async fn unblock<R, F>(&self, call: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&MegatecUps<T>) -> Result<R> + Send + 'static,
+    {
+        let _turn = self.commands.lock().await;
+        self.run_blocking(call).await
+    }
+
+    /// Run a call of the blocking connection on the blocking pool of the runtime
+    async fn run_blocking<R, F>(&self, call: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&MegatecUps<T>) -> Result<R> + Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        let slot = Arc::new(Mutex::new(None));
+        let result = Arc::clone(&slot);
+        self.runtime
+            .spawn_blocking(Box::new(move || {
+                let value = call(&inner);
+                *result
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(value);
+            }))
+            .await;
+        let value = slot
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take();
+        // Only empty when the call panicked or the runtime dropped it
+        value.unwrap_or(Err(UpsError::Cancelled))
+    }
+
+    /// Get the UPS name
+    pub async fn get_name(&self) -> Result<String> {
+        self.unblock(|ups| ups.get_name()).await
+    }
+
+    /// Get the UPS status with acknowledgment, waiting without blocking the executor
+    ///
+    /// The acknowledgment and the status read each run on the blocking pool,
+    /// the delay between them is a sleep of the runtime. No other command of
+    /// this handle gets between both requests.
+    pub async fn get_status(&self) -> Result<UpsStatus> {
+        if let Some(status) = self.inner.cached_status() {
+            return Ok(status);
+        }
+        let _turn = self.commands.lock().await;
+        // Another reader may have refreshed the cache while this one waited for its turn
+        if let Some(status) = self.inner.cached_status() {
+            return Ok(status);
+        }
+
+        let command = self.inner.dialect().command();
+        self.run_blocking(move |ups| ups.send(command).map(drop))
+            .await?;
+        self.runtime.sleep(self.inner.effective_ack_delay()).await;
+        self.run_blocking(move |ups| {
+            for _ in 0..ups.quirks().warmup_reads {
+                ups.send(command)?;
+            }
+            let status = ups.get_status_no_ack()?;
+            ups.cache_status(Some(&status));
+            Ok(status)
+        })
+        .await
+    }
+
+    /// Read the status every `interval`, the first time right away
+    ///
+    /// The stream never ends; errors are yielded like readings, so a failed
+    /// read does not stop the watch.
+    pub fn watch(&self, interval: Duration) -> StatusStream<'_, T> {
+        StatusStream {
+            ups: self,
+            interval,
+            delay: None,
+            read: None,
+        }
+    }
+
+    /// Read the status every `interval` and yield the events derived from it
+    ///
+    /// Power failures, low battery, tests and communication losses are
+    /// reported like [`Monitor`](crate::Monitor) does, without its alarms and
+    /// scheduled tests.
+    pub fn watch_events(&self, interval: Duration) -> EventStream<'_, T> {
+        EventStream {
+            statuses: self.watch(interval),
+            last_flags: None,
+            communication_lost: false,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Get the UPS status without acknowledgment
+    pub async fn get_status_no_ack(&self) -> Result<UpsStatus> {
+        self.unblock(|ups| ups.get_status_no_ack()).await
+    }
+
+    /// Test UPS for 10 seconds
+    pub async fn test(&self) -> Result<TestResult> {
+        self.start_test(Command::Test).await
+    }
+
+    /// Test UPS until battery is low
+    pub async fn test_until_battery_low(&self) -> Result<TestResult> {
+        self.start_test(Command::TestUntilBatteryLow).await
+    }
+
+    /// Test UPS for specified minutes
+    pub async fn test_with_time(&self, minutes: u8) -> Result<TestResult> {
+        self.start_test(Command::TestWithTime(minutes)).await
+    }
+
+    /// Wait up to `timeout` for a running test to end and get its result
+    pub async fn wait_for_test(&self, timeout: Duration) -> Result<TestResult> {
+        let started = Instant::now();
+        loop {
+            let status = self.get_status().await?;
+            if let Some(result) = TestResult::after_wait(&status.flags) {
+                return Ok(result);
+            }
+            if started.elapsed() >= timeout {
+                return Ok(TestResult::InProgress);
+            }
+            self.runtime
+                .sleep(TEST_POLL_INTERVAL.min(timeout.saturating_sub(started.elapsed())))
+                .await;
+        }
+    }
+
+    async fn start_test(&self, command: Command) -> Result<TestResult> {
+        self.unblock(move |ups| ups.send(command).map(drop)).await?;
+        let status = self.get_status().await?;
+        Ok(TestResult::after_start(&status.flags))
+    }
+
+    /// Toggle UPS beep
+    pub async fn switch_beep(&self) -> Result<()> {
+        self.unblock(|ups| ups.switch_beep()).await
+    }
+
+    /// Check if the UPS beeper is enabled
+    pub async fn beeper_enabled(&self) -> Result<bool> {
+        Ok(self.get_status().await?.flags.beeper_on)
+    }
+
+    /// Check if a battery test is running
+    pub async fn test_in_progress(&self) -> Result<bool> {
+        Ok(self.get_status().await?.flags.test_in_progress)
+    }
+
+    /// Check if a shutdown countdown is running
+    pub async fn shutdown_pending(&self) -> Result<bool> {
+        Ok(self.get_status().await?.flags.shutdown_active)
+    }
+
+    /// Enable or disable the UPS beeper, toggling it only when needed
+    pub async fn set_beeper(&self, enabled: bool) -> Result<()> {
+        if self.get_status().await?.flags.beeper_on != enabled {
+            self.unblock(|ups| ups.switch_beep()).await?;
+        }
+        Ok(())
+    }
+
+    /// Abort current UPS test
+    pub async fn abort_test(&self) -> Result<()> {
+        self.unblock(|ups| ups.abort_test()).await
+    }
+
+    /// Get UPS rating information
+    pub async fn get_rating(&self) -> Result<RatingInfo> {
+        self.unblock(|ups| ups.get_rating()).await
+    }
+
+    /// Get UPS manufacturer information
+    pub async fn get_info(&self) -> Result<UpsInfo> {
+        self.unblock(|ups| ups.get_info()).await
+    }
+
+    /// Get the Voltronic/Axpert extended status (QPIGS)
+    pub async fn get_extended_status(&self) -> Result<ExtendedStatus> {
+        self.unblock(|ups| ups.get_extended_status()).await
+    }
+
+    /// Get the load in percent of the rated load
+    pub async fn load_percent(&self) -> Result<f64> {
+        let status = self.get_status().await?;
+        let quirks = self.inner.quirks();
+        if !quirks.load_in_amps {
+            return Ok(status.load_raw);
+        }
+        let rating = self.get_rating().await?;
+        Ok(status.load_percent(&rating, &quirks))
+    }
+
+    /// Get the operating mode
+    ///
+    /// Asks Voltronic devices with QMOD; pure Megatec devices, which do not
+    /// know the query, get the mode derived from the status flags.
+    pub async fn get_mode(&self) -> Result<UpsMode> {
+        self.unblock(|ups| ups.get_mode()).await
+    }
+
+    /// Shutdown UPS after 1 minute
+    pub async fn shutdown(&self) -> Result<()> {
+        self.unblock(|ups| ups.shutdown()).await
+    }
+
+    /// Shutdown UPS after .2 to .9 minutes (in steps of .1) or 1 to 10 whole minutes
+    pub async fn shutdown_after(&self, minutes: f32) -> Result<()> {
+        self.unblock(move |ups| ups.shutdown_after(minutes)).await
+    }
+
+    /// Cancel a pending shutdown, failing if the UPS reports none
+    pub async fn cancel_shutdown(&self) -> Result<()> {
+        self.unblock(|ups| ups.cancel_shutdown()).await
+    }
+
+    /// Shutdown UPS after `shutdown_minutes` and power the load back on `restore_minutes` later
+    pub async fn shutdown_and_restore(
+        &self,
+        shutdown_minutes: f32,
+        restore_minutes: u16,
+    ) -> Result<()> {
+        self.unblock(move |ups| ups.shutdown_and_restore(shutdown_minutes, restore_minutes))
+            .await
+    }
+}
+
+type StatusRead<'a> = Pin<Box<dyn Future<Output = Result<UpsStatus>> + Send + 'a>>;
+
+/// Stream of status readings returned by [`AsyncMegatecUps::watch`]
+///
+/// `poll_next` has the signature of the `Stream` trait of the `futures` crate,
+/// so a one-line adapter brings its combinators; [`next`](Self::next) awaits
+/// the next reading without one.
+pub struct StatusStream<'a, T: Transport> {
+    ups: &'a AsyncMegatecUps<T>,
+    interval: Duration,
+    delay: Option<Sleep>,
+    read: Option<(Instant, StatusRead<'a>)>,
+}
+
+impl<'a, T: Transport + Send + 'static> StatusStream<'a, T> {
+    /// Get the polling interval
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Wait for the next reading; never `None`
+    pub async fn next(&mut self) -> Option<Result<UpsStatus>> {
+        future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// Poll for the next reading; never `Ready(None)`
+    pub fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<UpsStatus>>> {
+        let this = &mut *self;
+        if let Some(delay) = &mut this.delay {
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.delay = None;
+        }
+
+        let ups = this.ups;
+        let (started, read) = this
+            .read
+            .get_or_insert_with(|| (Instant::now(), Box::pin(ups.get_status())));
+        match read.as_mut().poll(cx) {
+            Poll::Ready(result) => {
+                this.delay = Some(
+                    ups.runtime
+                        .sleep(this.interval.saturating_sub(started.elapsed())),
+                );
+                this.read = None;
+                Poll::Ready(Some(result))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Stream of events returned by [`AsyncMegatecUps::watch_events`]
+///
+/// Like [`StatusStream`], it never ends.
+pub struct EventStream<'a, T: Transport> {
+    statuses: StatusStream<'a, T>,
+    last_flags: Option<StatusFlags>,
+    communication_lost: bool,
+    pending: VecDeque<UpsEvent>,
+}
+
+impl<'a, T: Transport + Send + 'static> EventStream<'a, T> {
+    /// Wait for the next event; never `None`
+    pub async fn next(&mut self) -> Option<UpsEvent> {
+        future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// Poll for the next event; never `Ready(None)`
+    pub fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<UpsEvent>> {
+        let this = &mut *self;
+        loop {
+            if let Some(event) = this.pending.pop_front() {
+                return Poll::Ready(Some(event));
+            }
+
+            let result = match Pin::new(&mut this.statuses).poll_next(cx) {
+                Poll::Ready(Some(result)) => result,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            match result {
+                Ok(status) => {
+                    if this.communication_lost {
+                        this.communication_lost = false;
+                        this.pending
+                            .push_back(UpsEvent::CommunicationRestored(status.clone()));
+                    }
+                    let previous = this.last_flags.unwrap_or_default();
+                    this.pending
+                        .extend(UpsEvent::flag_changes(&previous, &status));
+                    this.last_flags = Some(status.flags);
+                }
+                Err(_) if !this.communication_lost => {
+                    this.communication_lost = true;
+                    this.pending.push_back(UpsEvent::CommunicationLost);
+                }
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+/// The futures and streams must stay usable on multi-threaded executors
+const _: fn() = || {
+    fn send<S: Send>(_: &S) {}
+    let ups = AsyncMegatecUps::with_transport(crate::MockTransport::online());
+    send(&ups.get_status());
+    send(&ups.test());
+    send(&ups.watch(Duration::ZERO));
+    send(&ups.watch_events(Duration::ZERO));
+    send(&ups.watch(Duration::ZERO).next());
+};
+
+/// Lock held across awaits, handed out in no particular order
+struct AsyncLock {
+    state: Mutex<(bool, Vec<Waker>)>,
+}
+
+impl AsyncLock {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new((false, Vec::new())),
+        }
+    }
+
+    /// Wait until the lock is free and take it
+    async fn lock(&self) -> AsyncLockGuard<'_> {
+        future::poll_fn(|cx| {
+            let mut state = self
+                .state
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if state.0 {
+                state.1.push(cx.waker().clone());
+                return Poll::Pending;
+            }
+            state.0 = true;
+            Poll::Ready(AsyncLockGuard(self))
+        })
+        .await
+    }
+}
+
+/// Frees the lock when dropped, waking every waiter to compete for it
+///
+/// Waking all of them keeps a waiter that was woken and then dropped from
+/// leaving the others asleep.
+struct AsyncLockGuard<'a>(&'a AsyncLock);
+
+impl Drop for AsyncLockGuard<'_> {
+    fn drop(&mut self) {
+        let waiters = {
+            let mut state = self
+                .0
+                .state
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            state.0 = false;
+            std::mem::take(&mut state.1)
+        };
+        for waker in waiters {
+            waker.wake();
+        }
+    }
+}
+
+/// Future that completes once the given duration has elapsed
+pub struct Delay {
+    deadline: Instant,
+    signal: Option<Arc<Signal>>,
+}
+
+impl Delay {
+    /// Create a delay that completes after the given duration
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            deadline: Instant::now() + duration,
+            signal: None,
+        }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        let deadline = self.deadline;
+        let signal = self.signal.get_or_insert_with(|| {
+            let signal = Signal::new();
+            Timer::get().wake_at(deadline, Arc::clone(&signal));
+            signal
+        });
+        signal.poll(cx)
+    }
+}
+
+/// Completion flag with the waker of the task waiting for it
+struct Signal {
+    state: Mutex<(bool, Option<Waker>)>,
+}
+
+impl Signal {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new((false, None)),
+        })
+    }
+
+    fn set(&self) {
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.0 = true;
+        if let Some(waker) = state.1.take() {
+            waker.wake();
+        }
+    }
+
+    fn poll(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if state.0 {
+            return Poll::Ready(());
+        }
+        state.1 = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Sets the signal when dropped, also while unwinding
+struct SignalGuard(Arc<Signal>);
+
+impl Drop for SignalGuard {
+    fn drop(&mut self) {
+        self.0.set();
+    }
+}
+
+/// Future completing once its signal is set
+struct Wait(Arc<Signal>);
+
+impl Future for Wait {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.0.poll(cx)
+    }
+}
+
+/// Timer thread setting the signals of every pending [`Delay`]
+struct Timer {
+    pending: Mutex<Vec<(Instant, Arc<Signal>)>>,
+    changed: Condvar,
+}
+
+impl Timer {
+    /// Get the timer, starting its thread on first use
+    fn get() -> &'static Timer {
+        static TIMER: OnceLock<Timer> = OnceLock::new();
+        static STARTED: std::sync::Once = std::sync::Once::new();
+        let timer = TIMER.get_or_init(|| Timer {
+            pending: Mutex::new(Vec::new()),
+            changed: Condvar::new(),
+        });
+        STARTED.call_once(|| {
+            thread::spawn(move || timer.run());
+        });
+        timer
+    }
+
+    fn wake_at(&self, deadline: Instant, signal: Arc<Signal>) {
+        self.pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push((deadline, signal));
+        self.changed.notify_one();
+    }
+
+    fn run(&self) {
+        let mut pending = self
+            .pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        loop {
+            let now = Instant::now();
+            pending.retain(|(deadline, signal)| {
+                if *deadline <= now {
+                    signal.set();
+                }
+                *deadline > now
+            });
+            pending = match pending.iter().map(|(deadline, _)| *deadline).min() {
+                Some(next) => {
+                    self.changed
+                        .wait_timeout(pending, next - now)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0
+                }
+                None => self
+                    .changed
+                    .wait(pending)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            };
+        }
+    }
+}
+
+/// Threads running the blocking work of [`ThreadRuntime`]
+struct BlockingPool {
+    state: Mutex<PoolState>,
+    queued: Condvar,
+}
+
+struct PoolState {
+    work: VecDeque<BlockingWork>,
+    threads: usize,
+    idle: usize,
+}
+
+impl BlockingPool {
+    fn get() -> &'static BlockingPool {
+        static POOL: OnceLock<BlockingPool> = OnceLock::new();
+        POOL.get_or_init(|| BlockingPool {
+            state: Mutex::new(PoolState {
+                work: VecDeque::new(),
+                threads: 0,
+                idle: 0,
+            }),
+            queued: Condvar::new(),
+        })
+    }
+
+    /// Queue work, starting a thread when none is idle and the pool is not full
+    fn run(&'static self, work: BlockingWork) {
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.work.push_back(work);
+        if state.idle == 0 && state.threads < MAX_BLOCKING_THREADS {
+            state.threads += 1;
+            thread::spawn(move || self.work());
+        } else {
+            self.queued.notify_one();
+        }
+    }
+
+    fn work(&self) {
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        loop {
+            if let Some(work) = state.work.pop_front() {
+                drop(state);
+                // A panic is reported by the waiting future, it does not stop the thread
+                let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(work));
+                state = self
+                    .state
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                continue;
+            }
+
+            state.idle += 1;
+            let (next, timeout) = self
+                .queued
+                .wait_timeout(state, BLOCKING_KEEP_ALIVE)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            state = next;
+            state.idle -= 1;
+            if timeout.timed_out() && state.work.is_empty() {
+                state.threads -= 1;
+                return;
+            }
+        }
+    }
+}
+
+/// Waker unparking the thread driving a spawned task
+struct ThreadWaker(thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Drive a future to completion on the current thread
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockTransport;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn delays_share_the_timer_thread() {
+        let started = Instant::now();
+        let delays: Vec<Delay> = (1..=50)
+            .map(|i| Delay::new(Duration::from_millis(i)))
+            .collect();
+        block_on(async move {
+            for delay in delays {
+                delay.await;
+            }
+        });
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn spawn_blocking_completes_with_the_work() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let works: Vec<Blocking> = (0..20)
+            .map(|_| {
+                let count = Arc::clone(&count);
+                ThreadRuntime.spawn_blocking(Box::new(move || {
+                    count.fetch_add(1, Ordering::SeqCst);
+                }))
+            })
+            .collect();
+        block_on(async move {
+            for work in works {
+                work.await;
+            }
+        });
+        assert_eq!(count.load(Ordering::SeqCst), 20);
+    }
+
+    #[test]
+    fn status_is_read_on_the_blocking_pool() {
+        let mut inner = MegatecUps::with_transport(MockTransport::online());
+        inner.set_ack_delay(Duration::from_millis(10));
+        let ups = AsyncMegatecUps::from_blocking(inner);
+        let status = block_on(ups.get_status());
+        assert_eq!(status.unwrap().input_voltage, 230.0);
+    }
+
+    /// Runtime recording the longest blocking work it ran
+    struct TimedRuntime(Arc<Mutex<Duration>>);
+
+    impl AsyncRuntime for TimedRuntime {
+        fn sleep(&self, duration: Duration) -> Sleep {
+            ThreadRuntime.sleep(duration)
+        }
+
+        fn spawn_blocking(&self, work: BlockingWork) -> Blocking {
+            let longest = Arc::clone(&self.0);
+            ThreadRuntime.spawn_blocking(Box::new(move || {
+                let started = Instant::now();
+                work();
+                let mut longest = longest.lock().unwrap();
+                *longest = (*longest).max(started.elapsed());
+            }))
+        }
+    }
+
+    #[test]
+    fn the_ack_delay_holds_no_blocking_thread() {
+        let mut inner = MegatecUps::with_transport(MockTransport::online());
+        inner.set_ack_delay(Duration::from_millis(300));
+        let mut ups = AsyncMegatecUps::from_blocking(inner);
+        let longest = Arc::new(Mutex::new(Duration::ZERO));
+        ups.set_runtime(TimedRuntime(Arc::clone(&longest)));
+
+        let started = Instant::now();
+        assert!(block_on(ups.get_status()).is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert!(*longest.lock().unwrap() < Duration::from_millis(150));
+    }
+
+    #[test]
+    fn commands_wait_for_the_acknowledged_read() {
+        let transport = MockTransport::online();
+        let mut inner = MegatecUps::with_transport(transport.clone());
+        inner.set_ack_delay(Duration::from_millis(200));
+        let ups = Arc::new(AsyncMegatecUps::from_blocking(inner));
+
+        let reader = Arc::clone(&ups);
+        let read = thread::spawn(move || block_on(reader.get_status()));
+        while transport.sent_commands().is_empty() {
+            thread::yield_now();
+        }
+        assert!(block_on(ups.get_rating()).is_ok());
+        assert!(read.join().unwrap().is_ok());
+        assert_eq!(
+            transport.sent_commands(),
+            [Command::Status, Command::Status, Command::Rating]
+        );
+    }
+
+    #[test]
+    fn spawn_blocking_completes_when_the_work_panics() {
+        block_on(ThreadRuntime.spawn_blocking(Box::new(|| panic!("transfer failed"))));
+    }
+}
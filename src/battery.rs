@@ -0,0 +1,234 @@
+use crate::rating::RatingInfo;
+use crate::status::UpsStatus;
+use std::time::Duration;
+
+/// Runtime of a fully charged battery at 100% load assumed by default
+pub const DEFAULT_FULL_LOAD_RUNTIME: Duration = Duration::from_secs(5 * 60);
+
+/// Lowest load the runtime is extrapolated to, to keep idle estimates finite
+const MIN_LOAD_PERCENT: f64 = 5.0;
+
+/// Battery chemistry, defining the cell voltages the charge is derived from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BatteryChemistry {
+    /// Sealed lead-acid (VRLA/AGM) batteries fitted to nearly every Megatec UPS
+    #[default]
+    LeadAcid,
+    /// Lithium iron phosphate (LiFePO4) replacement packs
+    LithiumIronPhosphate,
+}
+
+impl BatteryChemistry {
+    /// Nominal voltage of one cell
+    pub fn nominal_cell_voltage(&self) -> f64 {
+        match self {
+            BatteryChemistry::LeadAcid => 2.0,
+            BatteryChemistry::LithiumIronPhosphate => 3.2,
+        }
+    }
+
+    /// Cell voltage of a full battery at rest
+    pub fn full_cell_voltage(&self) -> f64 {
+        match self {
+            BatteryChemistry::LeadAcid => 2.15,
+            BatteryChemistry::LithiumIronPhosphate => 3.35,
+        }
+    }
+
+    /// Cell voltage at which the UPS cuts off
+    pub fn empty_cell_voltage(&self) -> f64 {
+        match self {
+            BatteryChemistry::LeadAcid => 1.75,
+            BatteryChemistry::LithiumIronPhosphate => 3.0,
+        }
+    }
+
+    /// Cell voltage drop under 100% load, added back before reading the charge
+    pub fn full_load_sag(&self) -> f64 {
+        match self {
+            BatteryChemistry::LeadAcid => 0.1,
+            BatteryChemistry::LithiumIronPhosphate => 0.05,
+        }
+    }
+
+    /// Peukert exponent, how much faster the capacity drains at higher loads
+    pub fn peukert_exponent(&self) -> f64 {
+        match self {
+            BatteryChemistry::LeadAcid => 1.2,
+            BatteryChemistry::LithiumIronPhosphate => 1.05,
+        }
+    }
+}
+
+/// Battery voltages mapped to a full and an empty charge gauge
+///
+/// Voltages in between are interpolated linearly and the percentage is
+/// clamped to 0-100. UPSes reporting the voltage of one cell instead of the
+/// whole string are scaled up by `cells`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatteryThresholds {
+    /// Battery voltage shown as 100%
+    pub full_voltage: f64,
+    /// Battery voltage shown as 0%
+    pub empty_voltage: f64,
+    /// Number of cells in the battery string
+    pub cells: u32,
+}
+
+impl BatteryThresholds {
+    /// Lead-acid thresholds for a battery of the nominal voltage, such as 12 or 24 V
+    pub fn for_nominal(nominal_voltage: f64) -> Self {
+        BatteryModel::new(BatteryChemistry::LeadAcid, nominal_voltage).thresholds()
+    }
+
+    /// Get the charge from 0 to 100%, `None` without a usable voltage
+    pub fn percent(&self, battery_voltage: f64) -> Option<f64> {
+        if !battery_voltage.is_finite() || battery_voltage <= 0.0 {
+            return None;
+        }
+        let range = self.full_voltage - self.empty_voltage;
+        if !range.is_finite() || range <= 0.0 {
+            return None;
+        }
+
+        let cells = f64::from(self.cells.max(1));
+        let voltage = if battery_voltage < self.empty_voltage / cells * 1.5 {
+            battery_voltage * cells
+        } else {
+            battery_voltage
+        };
+        Some(((voltage - self.empty_voltage) / range * 100.0).clamp(0.0, 100.0))
+    }
+}
+
+/// Model of the UPS battery used to turn voltage and load into a runtime
+///
+/// The charge is interpolated linearly between the empty and full cell
+/// voltages of the chemistry, after compensating the sag caused by the load.
+/// The runtime scales the full-load runtime by the charge and, following
+/// Peukert's law, by `(100 / load) ^ exponent`. Units reporting the voltage of
+/// one cell instead of the whole string are detected automatically.
+///
+/// The estimate is rough: measure `full_load_runtime` (or read it from the
+/// datasheet) for anything better than a ballpark figure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatteryModel {
+    pub chemistry: BatteryChemistry,
+    /// Nominal voltage of the battery string, such as 12 or 24 V
+    pub nominal_voltage: f64,
+    /// Runtime of a fully charged battery at 100% load
+    pub full_load_runtime: Duration,
+}
+
+impl BatteryModel {
+    /// Create a model with the default full-load runtime
+    pub fn new(chemistry: BatteryChemistry, nominal_voltage: f64) -> Self {
+        Self {
+            chemistry,
+            nominal_voltage,
+            full_load_runtime: DEFAULT_FULL_LOAD_RUNTIME,
+        }
+    }
+
+    /// Lead-acid model for the nominal battery voltage of the rating
+    pub fn from_rating(rating: &RatingInfo) -> Self {
+        Self::new(BatteryChemistry::default(), rating.battery_voltage)
+    }
+
+    /// Number of cells in the battery string
+    pub fn cells(&self) -> f64 {
+        (self.nominal_voltage / self.chemistry.nominal_cell_voltage())
+            .round()
+            .max(1.0)
+    }
+
+    /// Charge gauge thresholds of the whole battery string
+    pub fn thresholds(&self) -> BatteryThresholds {
+        let cells = self.cells();
+        BatteryThresholds {
+            full_voltage: self.chemistry.full_cell_voltage() * cells,
+            empty_voltage: self.chemistry.empty_cell_voltage() * cells,
+            cells: cells as u32,
+        }
+    }
+
+    /// Battery voltage of one cell, whether the UPS reports the string or a cell
+    pub fn cell_voltage(&self, status: &UpsStatus) -> f64 {
+        self.per_cell(status.battery_voltage)
+    }
+
+    /// Convert a reported battery voltage, of the string or of a cell, to one cell
+    pub fn per_cell(&self, voltage: f64) -> f64 {
+        if voltage < self.chemistry.nominal_cell_voltage() * 1.5 {
+            voltage
+        } else {
+            voltage / self.cells()
+        }
+    }
+
+    /// Estimate the state of charge from 0.0 to 1.0, `None` without a usable voltage
+    pub fn state_of_charge(&self, status: &UpsStatus) -> Option<f64> {
+        let cell_voltage = self.cell_voltage(status);
+        if !cell_voltage.is_finite() || cell_voltage <= 0.0 || self.nominal_voltage <= 0.0 {
+            return None;
+        }
+
+        let chemistry = &self.chemistry;
+        let load = load_fraction(status).unwrap_or(0.0);
+        let resting = cell_voltage + chemistry.full_load_sag() * load;
+        let charge = (resting - chemistry.empty_cell_voltage())
+            / (chemistry.full_cell_voltage() - chemistry.empty_cell_voltage());
+        Some(charge.clamp(0.0, 1.0))
+    }
+
+    /// Estimate how long the battery lasts at the current load
+    ///
+    /// Returns `None` when the battery voltage or the load is missing, or when
+    /// the load is in amps; convert it with
+    /// [`UpsStatus::load_percent`] first.
+    pub fn estimate_runtime(&self, status: &UpsStatus) -> Option<Duration> {
+        let charge = self.state_of_charge(status)?;
+        let load = load_fraction(status)?.max(MIN_LOAD_PERCENT / 100.0);
+        let factor = charge * (1.0 / load).powf(self.chemistry.peukert_exponent());
+        Some(self.full_load_runtime.mul_f64(factor))
+    }
+}
+
+/// Load from 0.0 to 1.0 (or above when overloaded), `None` for a load in amps
+fn load_fraction(status: &UpsStatus) -> Option<f64> {
+    let load = status.load_raw;
+    (!status.load_in_amps && load.is_finite() && load >= 0.0).then_some(load / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn runtime_needs_the_load_in_percent() {
+        let quirks = Quirks {
+            load_in_amps: true,
+            ..Quirks::NONE
+        };
+        let status =
+            UpsStatus::parse("230.0 230.0 230.0 002.0 50.0 13.2 30.0 00001000", &quirks).unwrap();
+        let rating = RatingInfo {
+            rated_voltage: 230.0,
+            rated_current: 4.0,
+            battery_voltage: 12.0,
+            rated_frequency: 50.0,
+        };
+        let model = BatteryModel::from_rating(&rating);
+        assert_eq!(model.estimate_runtime(&status), None);
+
+        let percent = status.in_percent(&rating, &quirks);
+        assert_eq!(percent.load_raw, 50.0);
+        let runtime = model.estimate_runtime(&percent).unwrap();
+        let at_full_load = model.estimate_runtime(&UpsStatus {
+            load_raw: 100.0,
+            ..percent.clone()
+        });
+        assert!(runtime > at_full_load.unwrap());
+    }
+}
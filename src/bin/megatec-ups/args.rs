@@ -0,0 +1,1183 @@
+use megatec_ups_control::{
+    HistoryFormat, PowerPolicy, Quantity, Rotation, Schedule, ScheduledTest, TestKind, Threshold,
+    UpsShutdown, DEFAULT_UPS_SHUTDOWN_DELAY_MINUTES,
+};
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+Usage: megatec-ups [OPTIONS] <COMMAND>
+
+Commands:
+  status              Show the UPS status
+  name                Show the UPS name
+  rating              Show the UPS rating information
+  extended            Show the Voltronic/Axpert extended status (QPIGS)
+  mode                Show the operating mode
+  test                Test the UPS for 10 seconds
+  test-low            Test the UPS until the battery is low
+  beep                Toggle the UPS beeper
+  shutdown            Shut the UPS down after 1 minute
+  abort               Abort the current test
+  monitor             Poll the UPS and print events
+      --interval <SECONDS>  Polling interval [default: 5]
+      --os-shutdown         Shut the computer down when the battery is low
+      --os-shutdown-after <MINUTES>
+                            Also shut down after this long on battery
+      --ups-restore <MINUTES>
+                            After the OS shutdown, turn the UPS off and back on
+                            this many minutes after mains returns
+      --hook <EVENT>=<COMMAND>
+                            Run a shell command on an event (on-battery, on-line,
+                            low-battery, comm-lost, comm-restored, test-started,
+                            test-finished, over-temperature, overload or *),
+                            repeatable
+      --stage <MINUTES>=<COMMAND>
+                            Run a shell command once after this long on battery,
+                            such as stopping noncritical services; commands with
+                            the same minutes form a stage, repeatable
+      --stage-restore <MINUTES>=<COMMAND>
+                            Run a shell command when mains returns after that
+                            stage ran, repeatable
+      --alarm <QUANTITY><OP><LIMIT>[,hysteresis=<VALUE>][,for=<SECONDS>]
+                            Print an alarm when input-voltage, output-voltage, load,
+                            input-frequency, battery-voltage or temperature goes
+                            above (>) or below (<) the limit, repeatable
+      --max-temperature <CELSIUS>
+                            Emit an over-temperature event, passed to hooks and
+                            notifications, when the UPS gets hotter
+      --max-load <PERCENT>  Emit an overload event when the load stays above this
+                            percentage for 10 seconds (100 is the rated load)
+      --self-test <KIND>@<CRON>
+                            Run a quick, deep or <MINUTES> battery test on a cron
+                            schedule in UTC, such as 'quick@0 3 * * 0', skipped
+                            while on battery, repeatable
+      --email-to <ADDR>     Mail power failures, restores, low battery, over
+                            temperature and overload to this address, repeatable
+                            (requires the email feature)
+      --email-from <ADDR>   Sender address [default: megatec-ups@localhost]
+      --smtp-server <ADDR>  SMTP relay [default: localhost:25]
+      --email-throttle <MINUTES>
+                            Minimum time between emails for one event [default: 15]
+      --webhook <URL>       POST every event as JSON to this http:// URL, repeatable
+                            (requires the webhook feature)
+      --webhook-relay <HOST:PORT>
+                            Post the preceding https:// --webhook through the local
+                            TLS relay (such as stunnel) listening here
+      --history <PATH>      Append every status sample to this file
+      --history-format <FORMAT>
+                            csv or jsonl [default: from the extension, else csv]
+      --history-max-size <MIB>
+                            Rotate the history file beyond this size
+      --history-max-age <HOURS>
+                            Rotate the history file after this long
+      --history-keep <COUNT>
+                            Rotated history files to keep [default: 5]
+      --history-db <PATH>   Store samples and events in this SQLite database
+                            (requires the sqlite feature)
+      --desktop             Show desktop notifications for power failures, restores
+                            and low battery (requires the desktop feature)
+  tui                 Show a live dashboard with gauges, sparklines and an event log
+      --interval <SECONDS>  Polling interval [default: 5]
+  exporter            Serve Prometheus metrics (requires the exporter feature)
+      --listen <ADDR>       Listen address [default: 0.0.0.0:9402]
+  influxdb            Push samples to InfluxDB (requires the influxdb feature)
+      --url <URL>           Server URL [default: http://localhost:8086]
+      --database <NAME>     InfluxDB 1.x database, with --username and --password
+      --org <ORG>, --bucket <BUCKET>
+                            InfluxDB 2.x organization and bucket, with --token
+      --tag <KEY>=<VALUE>   Extra tag for every sample, repeatable
+      --interval <SECONDS>  Push interval [default: 5]
+  otel                Push OpenTelemetry metrics over OTLP/HTTP (requires the otel
+                      feature)
+      --url <URL>           Collector URL [default: http://localhost:4318]
+      --tag <KEY>=<VALUE>   Extra resource attribute, repeatable
+      --header <NAME>=<VALUE>
+                            Extra request header, such as an API key, repeatable
+      --interval <SECONDS>  Push interval [default: 5]
+  graphite            Push gauges to Graphite or StatsD (requires the graphite feature)
+      --server <ADDR>       Carbon plaintext server [default: localhost:2003, or
+                            localhost:8125 with --statsd]
+      --statsd              Send StatsD gauges over UDP instead
+      --prefix <PREFIX>     Prefix of the metric names [default: ups]
+      --interval <SECONDS>  Push interval [default: 5]
+  nis                 Serve the status over the apcupsd Network Information Server
+                      protocol, for apcaccess and apcupsd clients (requires the nis
+                      feature)
+      --listen <ADDR>       Listen address [default: 0.0.0.0:3551]
+  serve               Serve the JSON REST API (requires the rest feature)
+      --listen <ADDR>       Listen address [default: 127.0.0.1:8080]
+      --token <TOKEN>       Token for the control endpoints [env: MEGATEC_UPS_TOKEN]
+      --history-db <PATH>   Answer GraphQL history queries from this SQLite database,
+                            as written by monitor --history-db (requires the graphql
+                            feature)
+  websocket           Stream status and events over WebSocket (requires the websocket feature)
+      --listen <ADDR>       Listen address [default: 127.0.0.1:8081]
+      --interval <SECONDS>  Polling interval [default: 5]
+  snmp                Serve the UPS-MIB over SNMP v1/v2c (requires the snmp feature)
+      --listen <ADDR>       Listen address [default: 0.0.0.0:161]
+      --community <NAME>    Read community [default: public]
+  modbus              Serve status and commands over Modbus TCP (requires the modbus
+                      feature)
+      --listen <ADDR>       Listen address [default: 127.0.0.1:502]
+      --allow-writes        Accept coil and holding register writes, which run tests
+                            and shutdowns for any client (read-only by default)
+  grpc                Serve the megatec.ups.v1.Ups gRPC service (requires the grpc
+                      feature)
+      --listen <ADDR>       Listen address [default: 127.0.0.1:50051]
+      --token <TOKEN>       Token for RunTest and Shutdown [env: MEGATEC_UPS_TOKEN]
+      --interval <SECONDS>  Polling interval of the event stream [default: 5]
+  agent               Push status and events to a fleet server (requires the fleet
+                      feature)
+      --url <URL>           Fleet server URL [default: http://localhost:8090]
+      --name <NAME>         Name of this UPS on the server, letters, digits, -, _ and .
+      --token <TOKEN>       Token of the fleet server [env: MEGATEC_UPS_TOKEN]
+      --interval <SECONDS>  Push interval [default: 5]
+  fleet-server        Collect the agents' reports and serve the combined view, without
+                      a local UPS (requires the fleet feature)
+      --listen <ADDR>       Listen address [default: 0.0.0.0:8090]
+      --token <TOKEN>       Token required from the agents [env: MEGATEC_UPS_TOKEN]
+      --history-dir <DIR>   Append the samples of each agent to <DIR>/<NAME>.jsonl
+  service <ACTION>    Manage the Windows service running the monitor (requires the
+                      windows-service feature); ACTION is install, uninstall, start, stop or run
+      --name <NAME>         Service name [default: megatec-ups]
+      --interval <SECONDS>  Polling interval [default: 5]
+      --os-shutdown, --os-shutdown-after, --ups-restore, --hook, --stage,
+      --stage-restore, --alarm, --max-temperature, --max-load, --self-test,
+      --email-to, --email-from, --smtp-server, --email-throttle, --webhook,
+      --webhook-relay, --history, --history-format, --history-max-size,
+      --history-max-age, --history-keep, --history-db
+                            As for monitor
+  daemon              Run the monitor in the background (requires the daemon feature,
+                      Unix only); stops on SIGTERM or SIGINT
+      --foreground          Stay in the foreground, for supervisors
+      --pid-file <PATH>     PID file [default: /run/megatec-ups.pid unless --foreground]
+      --log-file <PATH>     Append the output to this file, reopened on SIGHUP
+      --interval, --os-shutdown, --os-shutdown-after, --ups-restore, --hook,
+      --stage, --stage-restore, --alarm, --max-temperature, --max-load,
+      --self-test, --email-to, --email-from, --smtp-server, --email-throttle,
+      --webhook, --webhook-relay, --history, --history-format,
+      --history-max-size, --history-max-age, --history-keep, --history-db
+                            As for monitor
+  nut-driver          Act as a NUT driver for upsd (requires the nut feature)
+      --socket <PATH>       Driver socket [default: /var/run/nut/megatec-ups-ups]
+      --interval <SECONDS>  Polling interval [default: 5]
+  completions <SHELL> Print the completion script for bash, zsh or fish
+  man                 Print the man page
+
+Options:
+      --config <PATH> Read options from this TOML file [default: /etc/megatec-ups.toml]
+      --format <FORMAT>
+                      Output format: plain, json (every one-shot command and
+                      monitor), prometheus (status, rating) or nagios (status,
+                      test, test-low) [default: plain]
+      --vid <ID>      USB vendor ID (hex), requires --pid
+      --pid <ID>      USB product ID (hex), requires --vid
+      --serial <PATH> Serial port of the UPS (requires the serial feature)
+      --usbip <HOST[:PORT]>/<BUSID>
+                      UPS exported by usbipd on another host (requires the
+                      usbip feature)
+      --tcp <HOST:PORT>
+                      Serial port of the UPS behind a raw TCP bridge, such as
+                      ser2net or ESP-Link (requires the tcp-serial feature)
+      --rfc2217 <HOST:PORT>
+                      Serial port of the UPS on an RFC 2217 server, set to
+                      2400 8N1 (requires the tcp-serial feature)
+  -h, --help          Print help
+  -V, --version       Print version
+
+Without --vid/--pid, --serial, --usbip, --tcp or --rfc2217 the first well-known
+Megatec USB device is used.
+
+Each key of the config file is a long option without its dashes, such as
+interval = 10, in any table; true switches an option on, arrays repeat it and
+the [hooks] table maps events to commands. MEGATEC_UPS_<OPTION> environment
+variables, such as MEGATEC_UPS_INTERVAL, override the file and the command
+line overrides both.
+
+Exit codes: 0 on success, 1 on failure and 2 for invalid arguments. With
+--format nagios: 0 OK, 1 WARNING (on battery or bypass, test not started),
+2 CRITICAL (battery low, UPS failed, shutdown active, test failed) and
+3 UNKNOWN (the UPS could not be read).
+";
+
+/// PID file of a daemon without --pid-file or --foreground
+const DEFAULT_PID_FILE: &str = "/run/megatec-ups.pid";
+
+/// Port of usbipd when --usbip names none
+const DEFAULT_USBIP_PORT: u16 = 3240;
+
+/// Output format selected with --format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Plain,
+    Json,
+    Prometheus,
+    Nagios,
+}
+
+impl OutputFormat {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "plain" => Some(OutputFormat::Plain),
+            "json" => Some(OutputFormat::Json),
+            "prometheus" => Some(OutputFormat::Prometheus),
+            "nagios" => Some(OutputFormat::Nagios),
+            _ => None,
+        }
+    }
+
+    /// Check if a subcommand can print in this format
+    fn supports(self, command: &Subcommand) -> bool {
+        match self {
+            OutputFormat::Plain => true,
+            OutputFormat::Json => matches!(
+                command,
+                Subcommand::Status
+                    | Subcommand::Name
+                    | Subcommand::Rating
+                    | Subcommand::Extended
+                    | Subcommand::Mode
+                    | Subcommand::Test
+                    | Subcommand::TestLow
+                    | Subcommand::Beep
+                    | Subcommand::Shutdown
+                    | Subcommand::Abort
+                    | Subcommand::Monitor(_)
+                    | Subcommand::Daemon { .. }
+            ),
+            OutputFormat::Prometheus => matches!(command, Subcommand::Status | Subcommand::Rating),
+            OutputFormat::Nagios => matches!(
+                command,
+                Subcommand::Status | Subcommand::Test | Subcommand::TestLow
+            ),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Plain => "plain",
+            OutputFormat::Json => "json",
+            OutputFormat::Prometheus => "prometheus",
+            OutputFormat::Nagios => "nagios",
+        }
+    }
+}
+
+/// Shells with a completion script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    pub const NAMES: [&'static str; 3] = ["bash", "zsh", "fish"];
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            _ => None,
+        }
+    }
+}
+
+/// How the CLI finds the UPS
+#[derive(Clone)]
+pub enum DeviceSelector {
+    Auto,
+    Usb {
+        vendor_id: u16,
+        product_id: u16,
+    },
+    Serial(PathBuf),
+    UsbIp {
+        address: String,
+        busid: String,
+    },
+    /// Serial port behind a TCP bridge, speaking RFC 2217 or passing the bytes through
+    TcpSerial {
+        address: String,
+        rfc2217: bool,
+    },
+}
+
+/// Options of the monitor loop, shared by the monitor and service subcommands
+pub struct WatchOptions {
+    pub interval: Duration,
+    pub format: OutputFormat,
+    pub power: Option<PowerPolicy>,
+    /// Event names and shell commands given with --hook
+    pub hooks: Vec<(String, String)>,
+    /// Times on battery and shell commands given with --stage
+    pub stages: Vec<(Duration, String)>,
+    /// Times on battery and shell commands given with --stage-restore
+    pub stage_restores: Vec<(Duration, String)>,
+    pub alarms: Vec<Threshold>,
+    /// Limit given with --max-temperature
+    pub max_temperature: Option<f64>,
+    /// Limit given with --max-load
+    pub max_load: Option<f64>,
+    pub self_tests: Vec<ScheduledTest>,
+    pub email: Option<EmailOptions>,
+    pub webhooks: Vec<Webhook>,
+    pub desktop: bool,
+    pub history: Option<HistoryOptions>,
+    pub history_db: Option<PathBuf>,
+}
+
+/// History file options, set when --history is given
+pub struct HistoryOptions {
+    pub path: PathBuf,
+    pub format: HistoryFormat,
+    pub rotation: Rotation,
+}
+
+/// Email notification options, set when --email-to is given
+pub struct EmailOptions {
+    pub server: String,
+    pub from: String,
+    pub to: Vec<String>,
+    pub throttle: Duration,
+}
+
+/// Webhook given with --webhook, and its --webhook-relay
+pub struct Webhook {
+    pub url: String,
+    pub relay: Option<String>,
+}
+
+/// Subcommands of the CLI
+pub enum Subcommand {
+    Status,
+    Name,
+    Rating,
+    Extended,
+    Mode,
+    Test,
+    TestLow,
+    Beep,
+    Shutdown,
+    Abort,
+    Monitor(Box<WatchOptions>),
+    Tui {
+        interval: Duration,
+    },
+    Exporter {
+        listen: String,
+    },
+    Nis {
+        listen: String,
+    },
+    InfluxDb {
+        url: String,
+        target: InfluxTarget,
+        tags: Vec<(String, String)>,
+        interval: Duration,
+    },
+    Graphite {
+        server: String,
+        statsd: bool,
+        prefix: String,
+        interval: Duration,
+    },
+    Otel {
+        url: String,
+        attributes: Vec<(String, String)>,
+        headers: Vec<(String, String)>,
+        interval: Duration,
+    },
+    Serve {
+        listen: String,
+        token: Option<String>,
+        history_db: Option<PathBuf>,
+    },
+    WebSocket {
+        listen: String,
+        interval: Duration,
+    },
+    Snmp {
+        listen: String,
+        community: String,
+    },
+    Modbus {
+        listen: String,
+        allow_writes: bool,
+    },
+    Grpc {
+        listen: String,
+        token: Option<String>,
+        interval: Duration,
+    },
+    Agent {
+        url: String,
+        name: String,
+        token: Option<String>,
+        interval: Duration,
+    },
+    FleetServer {
+        listen: String,
+        token: Option<String>,
+        history_dir: Option<PathBuf>,
+    },
+    NutDriver {
+        socket: PathBuf,
+        interval: Duration,
+    },
+    Service {
+        action: ServiceAction,
+        name: String,
+        watch: Box<WatchOptions>,
+    },
+    Daemon {
+        foreground: bool,
+        pid_file: Option<PathBuf>,
+        log_file: Option<PathBuf>,
+        watch: Box<WatchOptions>,
+    },
+}
+
+/// InfluxDB API selected by the influxdb options
+pub enum InfluxTarget {
+    V1 {
+        database: String,
+        username: Option<String>,
+        password: Option<String>,
+    },
+    V2 {
+        org: String,
+        bucket: String,
+        token: String,
+    },
+}
+
+/// Actions of the service subcommand
+pub enum ServiceAction {
+    Install,
+    Uninstall,
+    Start,
+    Stop,
+    Run,
+}
+
+/// What the CLI was asked to do
+pub enum Action {
+    Help,
+    Version,
+    Completions(Shell),
+    Man,
+    Run {
+        device: DeviceSelector,
+        command: Subcommand,
+        format: OutputFormat,
+    },
+}
+
+/// Parse the command line arguments, without the program name
+pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Action, String> {
+    let mut vendor_id = None;
+    let mut product_id = None;
+    let mut serial = None;
+    let mut usbip = None;
+    let mut bridge = None;
+    let mut command = None;
+    let mut format = OutputFormat::Plain;
+    let mut service_action = None;
+    let mut shell = None;
+    let mut name = None;
+    let mut foreground = false;
+    let mut pid_file = None;
+    let mut log_file = None;
+    let mut os_shutdown = false;
+    let mut os_shutdown_after = None;
+    let mut ups_restore = None;
+    let mut hooks = Vec::new();
+    let mut stages = Vec::new();
+    let mut stage_restores = Vec::new();
+    let mut alarms = Vec::new();
+    let mut max_temperature = None;
+    let mut max_load = None;
+    let mut self_tests = Vec::new();
+    let mut email_to = Vec::new();
+    let mut email_from = "megatec-ups@localhost".to_string();
+    let mut smtp_server = "localhost:25".to_string();
+    let mut email_throttle = Duration::from_secs(15 * 60);
+    let mut webhooks = Vec::new();
+    let mut desktop = false;
+    let mut history = None;
+    let mut history_db = None;
+    let mut history_dir = None;
+    let mut history_format = None;
+    let mut rotation = Rotation::default();
+    let mut interval = Duration::from_secs(5);
+    let mut listen = None;
+    let mut token = std::env::var("MEGATEC_UPS_TOKEN").ok();
+    let mut community = "public".to_string();
+    let mut url = None;
+    let mut database = None;
+    let mut username = None;
+    let mut password = None;
+    let mut org = None;
+    let mut bucket = None;
+    let mut tags = Vec::new();
+    let mut headers = Vec::new();
+    let mut server = None;
+    let mut statsd = false;
+    let mut allow_writes = false;
+    let mut prefix = "ups".to_string();
+    let mut socket = PathBuf::from("/var/run/nut/megatec-ups-ups");
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Action::Help),
+            "-V" | "--version" => return Ok(Action::Version),
+            "--format" => {
+                let name = value(&mut args, &arg)?;
+                format = OutputFormat::from_name(&name)
+                    .ok_or_else(|| format!("unknown format '{}'", name))?;
+            }
+            "--vid" => vendor_id = Some(parse_hex(&value(&mut args, &arg)?)?),
+            "--pid" => product_id = Some(parse_hex(&value(&mut args, &arg)?)?),
+            "--serial" => serial = Some(PathBuf::from(value(&mut args, &arg)?)),
+            "--usbip" => usbip = Some(parse_usbip(&value(&mut args, &arg)?)?),
+            "--tcp" | "--rfc2217" => {
+                let address = parse_address(&value(&mut args, &arg)?)?;
+                bridge = Some(DeviceSelector::TcpSerial {
+                    address,
+                    rfc2217: arg == "--rfc2217",
+                });
+            }
+            "--interval" => {
+                let seconds = value(&mut args, &arg)?;
+                let seconds: f64 = seconds
+                    .parse()
+                    .ok()
+                    .filter(|s: &f64| *s > 0.0 && s.is_finite())
+                    .ok_or_else(|| format!("invalid interval '{}'", seconds))?;
+                interval = Duration::from_secs_f64(seconds);
+            }
+            "--listen" => listen = Some(value(&mut args, &arg)?),
+            "--token" => token = Some(value(&mut args, &arg)?),
+            "--community" => community = value(&mut args, &arg)?,
+            "--url" => url = Some(value(&mut args, &arg)?),
+            "--database" => database = Some(value(&mut args, &arg)?),
+            "--username" => username = Some(value(&mut args, &arg)?),
+            "--password" => password = Some(value(&mut args, &arg)?),
+            "--org" => org = Some(value(&mut args, &arg)?),
+            "--bucket" => bucket = Some(value(&mut args, &arg)?),
+            "--tag" => {
+                let tag = value(&mut args, &arg)?;
+                let (key, value) = tag
+                    .split_once('=')
+                    .filter(|(key, value)| !key.is_empty() && !value.is_empty())
+                    .ok_or_else(|| format!("invalid tag '{}', expected KEY=VALUE", tag))?;
+                tags.push((key.to_string(), value.to_string()));
+            }
+            "--server" => server = Some(value(&mut args, &arg)?),
+            "--statsd" => statsd = true,
+            "--allow-writes" => allow_writes = true,
+            "--prefix" => prefix = value(&mut args, &arg)?,
+            "--header" => {
+                let header = value(&mut args, &arg)?;
+                let (name, value) = header
+                    .split_once('=')
+                    .filter(|(name, value)| !name.is_empty() && !value.is_empty())
+                    .ok_or_else(|| format!("invalid header '{}', expected NAME=VALUE", header))?;
+                headers.push((name.to_string(), value.to_string()));
+            }
+            "--socket" => socket = PathBuf::from(value(&mut args, &arg)?),
+            "--name" => name = Some(value(&mut args, &arg)?),
+            "--foreground" => foreground = true,
+            "--pid-file" => pid_file = Some(PathBuf::from(value(&mut args, &arg)?)),
+            "--log-file" => log_file = Some(PathBuf::from(value(&mut args, &arg)?)),
+            "--os-shutdown" => os_shutdown = true,
+            "--os-shutdown-after" => {
+                let minutes = value(&mut args, &arg)?;
+                let minutes: f64 = minutes
+                    .parse()
+                    .ok()
+                    .filter(|m: &f64| *m >= 0.0 && m.is_finite())
+                    .ok_or_else(|| format!("invalid time '{}'", minutes))?;
+                os_shutdown_after = Some(Duration::from_secs_f64(minutes * 60.0));
+            }
+            "--ups-restore" => {
+                let minutes = value(&mut args, &arg)?;
+                let minutes: u16 = minutes
+                    .parse()
+                    .map_err(|_| format!("invalid time '{}'", minutes))?;
+                ups_restore = Some(minutes);
+            }
+            "--hook" => {
+                let hook = value(&mut args, &arg)?;
+                let (event, command) = hook
+                    .split_once('=')
+                    .filter(|(event, command)| !event.is_empty() && !command.is_empty())
+                    .ok_or_else(|| format!("invalid hook '{}', expected EVENT=COMMAND", hook))?;
+                hooks.push((event.to_string(), command.to_string()));
+            }
+            "--stage" => stages.push(parse_stage(&value(&mut args, &arg)?)?),
+            "--stage-restore" => stage_restores.push(parse_stage(&value(&mut args, &arg)?)?),
+            "--alarm" => alarms.push(parse_alarm(&value(&mut args, &arg)?)?),
+            "--max-temperature" => {
+                let celsius = value(&mut args, &arg)?;
+                let celsius: f64 = celsius
+                    .parse()
+                    .map_err(|_| format!("invalid temperature '{}'", celsius))?;
+                max_temperature = Some(celsius);
+            }
+            "--max-load" => {
+                let percent = value(&mut args, &arg)?;
+                let percent: f64 = percent
+                    .parse()
+                    .map_err(|_| format!("invalid load '{}'", percent))?;
+                max_load = Some(percent);
+            }
+            "--self-test" => self_tests.push(parse_self_test(&value(&mut args, &arg)?)?),
+            "--desktop" => desktop = true,
+            "--history" => history = Some(PathBuf::from(value(&mut args, &arg)?)),
+            "--history-db" => history_db = Some(PathBuf::from(value(&mut args, &arg)?)),
+            "--history-dir" => history_dir = Some(PathBuf::from(value(&mut args, &arg)?)),
+            "--history-format" => {
+                history_format = match value(&mut args, &arg)?.as_str() {
+                    "csv" => Some(HistoryFormat::Csv),
+                    "jsonl" => Some(HistoryFormat::JsonLines),
+                    other => return Err(format!("unknown history format '{}'", other)),
+                }
+            }
+            "--history-max-size" => {
+                let mib = value(&mut args, &arg)?;
+                let mib: f64 = mib
+                    .parse()
+                    .ok()
+                    .filter(|m: &f64| *m > 0.0 && m.is_finite())
+                    .ok_or_else(|| format!("invalid size '{}'", mib))?;
+                rotation.max_size = Some((mib * 1024.0 * 1024.0) as u64);
+            }
+            "--history-max-age" => {
+                let hours = value(&mut args, &arg)?;
+                let hours: f64 = hours
+                    .parse()
+                    .ok()
+                    .filter(|h: &f64| *h > 0.0 && h.is_finite())
+                    .ok_or_else(|| format!("invalid time '{}'", hours))?;
+                rotation.max_age = Some(Duration::from_secs_f64(hours * 3600.0));
+            }
+            "--history-keep" => {
+                let count = value(&mut args, &arg)?;
+                rotation.keep = count
+                    .parse()
+                    .map_err(|_| format!("invalid count '{}'", count))?;
+            }
+            "--webhook" => webhooks.push(Webhook {
+                url: value(&mut args, &arg)?,
+                relay: None,
+            }),
+            "--webhook-relay" => {
+                let relay = value(&mut args, &arg)?;
+                match webhooks.last_mut() {
+                    Some(webhook) if webhook.relay.is_none() => webhook.relay = Some(relay),
+                    _ => return Err("--webhook-relay must follow a --webhook".to_string()),
+                }
+            }
+            "--email-to" => email_to.push(value(&mut args, &arg)?),
+            "--email-from" => email_from = value(&mut args, &arg)?,
+            "--smtp-server" => smtp_server = value(&mut args, &arg)?,
+            "--email-throttle" => {
+                let minutes = value(&mut args, &arg)?;
+                let minutes: f64 = minutes
+                    .parse()
+                    .ok()
+                    .filter(|m: &f64| *m >= 0.0 && m.is_finite())
+                    .ok_or_else(|| format!("invalid time '{}'", minutes))?;
+                email_throttle = Duration::from_secs_f64(minutes * 60.0);
+            }
+            name if command.is_none() && !name.starts_with('-') => command = Some(name.to_string()),
+            name if command.as_deref() == Some("service")
+                && service_action.is_none()
+                && !name.starts_with('-') =>
+            {
+                service_action = Some(name.to_string())
+            }
+            name if command.as_deref() == Some("completions")
+                && shell.is_none()
+                && !name.starts_with('-') =>
+            {
+                shell = Some(name.to_string())
+            }
+            other => return Err(format!("unexpected argument '{}'", other)),
+        }
+    }
+
+    let power = match (os_shutdown || os_shutdown_after.is_some(), ups_restore) {
+        (false, Some(_)) => return Err("--ups-restore requires --os-shutdown".to_string()),
+        (false, None) => None,
+        (true, restore) => Some(PowerPolicy {
+            on_battery_for: os_shutdown_after,
+            ups_shutdown: restore.map(|restore_minutes| UpsShutdown {
+                delay_minutes: DEFAULT_UPS_SHUTDOWN_DELAY_MINUTES,
+                restore_minutes,
+            }),
+            ..PowerPolicy::default()
+        }),
+    };
+
+    if let Some((after, _)) = stage_restores
+        .iter()
+        .find(|(after, _)| !stages.iter().any(|(stage, _)| stage == after))
+    {
+        let minutes = after.as_secs_f64() / 60.0;
+        return Err(format!("--stage-restore {} has no --stage", minutes));
+    }
+
+    let watch = Box::new(WatchOptions {
+        interval,
+        format,
+        power,
+        hooks,
+        stages,
+        stage_restores,
+        alarms,
+        max_temperature,
+        max_load,
+        self_tests,
+        email: (!email_to.is_empty()).then_some(EmailOptions {
+            server: smtp_server,
+            from: email_from,
+            to: email_to,
+            throttle: email_throttle,
+        }),
+        webhooks,
+        desktop,
+        history: history.map(|path| HistoryOptions {
+            format: history_format.unwrap_or_else(|| HistoryFormat::from_path(&path)),
+            path,
+            rotation,
+        }),
+        history_db: history_db.clone(),
+    });
+
+    let command_name = command.clone().unwrap_or_default();
+    let command = match command.as_deref() {
+        Some("status") => Subcommand::Status,
+        Some("name") => Subcommand::Name,
+        Some("rating") => Subcommand::Rating,
+        Some("extended") => Subcommand::Extended,
+        Some("mode") => Subcommand::Mode,
+        Some("test") => Subcommand::Test,
+        Some("test-low") => Subcommand::TestLow,
+        Some("beep") => Subcommand::Beep,
+        Some("shutdown") => Subcommand::Shutdown,
+        Some("abort") => Subcommand::Abort,
+        Some("monitor") => Subcommand::Monitor(watch),
+        Some("tui") => Subcommand::Tui { interval },
+        Some("exporter") => Subcommand::Exporter {
+            listen: listen.unwrap_or_else(|| "0.0.0.0:9402".to_string()),
+        },
+        Some("influxdb") => {
+            let target = match (database, org, bucket) {
+                (Some(database), None, None) => InfluxTarget::V1 {
+                    database,
+                    username,
+                    password,
+                },
+                (None, Some(org), Some(bucket)) => InfluxTarget::V2 {
+                    org,
+                    bucket,
+                    token: token.ok_or("--bucket requires --token")?,
+                },
+                _ => return Err("influxdb needs --database, or --org and --bucket".to_string()),
+            };
+            Subcommand::InfluxDb {
+                url: url.unwrap_or_else(|| "http://localhost:8086".to_string()),
+                target,
+                tags,
+                interval,
+            }
+        }
+        Some("graphite") => Subcommand::Graphite {
+            server: server.unwrap_or_else(|| {
+                let port = if statsd { 8125 } else { 2003 };
+                format!("localhost:{}", port)
+            }),
+            statsd,
+            prefix,
+            interval,
+        },
+        Some("otel") => Subcommand::Otel {
+            url: url.unwrap_or_else(|| "http://localhost:4318".to_string()),
+            attributes: tags,
+            headers,
+            interval,
+        },
+        Some("nis") => Subcommand::Nis {
+            listen: listen.unwrap_or_else(|| "0.0.0.0:3551".to_string()),
+        },
+        Some("serve") => Subcommand::Serve {
+            listen: listen.unwrap_or_else(|| "127.0.0.1:8080".to_string()),
+            token,
+            history_db,
+        },
+        Some("websocket") => Subcommand::WebSocket {
+            listen: listen.unwrap_or_else(|| "127.0.0.1:8081".to_string()),
+            interval,
+        },
+        Some("snmp") => Subcommand::Snmp {
+            listen: listen.unwrap_or_else(|| "0.0.0.0:161".to_string()),
+            community,
+        },
+        Some("modbus") => Subcommand::Modbus {
+            listen: listen.unwrap_or_else(|| "127.0.0.1:502".to_string()),
+            allow_writes,
+        },
+        Some("grpc") => Subcommand::Grpc {
+            listen: listen.unwrap_or_else(|| "127.0.0.1:50051".to_string()),
+            token,
+            interval,
+        },
+        Some("agent") => Subcommand::Agent {
+            url: url.unwrap_or_else(|| "http://localhost:8090".to_string()),
+            name: name.ok_or("agent needs --name")?,
+            token,
+            interval,
+        },
+        Some("fleet-server") => Subcommand::FleetServer {
+            listen: listen.unwrap_or_else(|| "0.0.0.0:8090".to_string()),
+            token,
+            history_dir,
+        },
+        Some("nut-driver") => Subcommand::NutDriver { socket, interval },
+        Some("daemon") => Subcommand::Daemon {
+            foreground,
+            pid_file: pid_file.or_else(|| (!foreground).then(|| PathBuf::from(DEFAULT_PID_FILE))),
+            log_file,
+            watch,
+        },
+        Some("service") => {
+            let action = match service_action.as_deref() {
+                Some("install") => ServiceAction::Install,
+                Some("uninstall") => ServiceAction::Uninstall,
+                Some("start") => ServiceAction::Start,
+                Some("stop") => ServiceAction::Stop,
+                Some("run") => ServiceAction::Run,
+                Some(other) => return Err(format!("unknown service action '{}'", other)),
+                None => return Err("missing service action".to_string()),
+            };
+            Subcommand::Service {
+                action,
+                name: name.unwrap_or_else(|| "megatec-ups".to_string()),
+                watch,
+            }
+        }
+        Some("completions") => {
+            let shell = match shell.as_deref() {
+                Some(name) => {
+                    Shell::from_name(name).ok_or_else(|| format!("unknown shell '{}'", name))?
+                }
+                None => return Err("missing shell".to_string()),
+            };
+            return Ok(Action::Completions(shell));
+        }
+        Some("man") => return Ok(Action::Man),
+        Some(other) => return Err(format!("unknown command '{}'", other)),
+        None => return Err("missing command".to_string()),
+    };
+    if !format.supports(&command) {
+        return Err(format!(
+            "{} does not support the {} format",
+            command_name,
+            format.name()
+        ));
+    }
+
+    let usb = match (vendor_id, product_id) {
+        (None, None) => None,
+        (Some(vendor_id), Some(product_id)) => Some(DeviceSelector::Usb {
+            vendor_id,
+            product_id,
+        }),
+        _ => return Err("--vid and --pid must be given together".to_string()),
+    };
+    let mut devices = usb
+        .into_iter()
+        .chain(serial.map(DeviceSelector::Serial))
+        .chain(usbip.map(|(address, busid)| DeviceSelector::UsbIp { address, busid }))
+        .chain(bridge);
+    let device = match (devices.next(), devices.next()) {
+        (None, _) => DeviceSelector::Auto,
+        (Some(device), None) => device,
+        _ => {
+            return Err(
+                "only one of --vid/--pid, --serial, --usbip, --tcp and --rfc2217 can be given"
+                    .to_string(),
+            )
+        }
+    };
+
+    Ok(Action::Run {
+        device,
+        command,
+        format,
+    })
+}
+
+/// Take the value of an option
+/// Flag of a documented option, such as `--interval` with its `<SECONDS>` placeholder
+pub struct Flag {
+    pub name: &'static str,
+    pub value: Option<&'static str>,
+}
+
+/// Option documented in [`USAGE`]
+pub struct OptionHelp {
+    pub flags: Vec<Flag>,
+    pub description: String,
+}
+
+/// Command documented in [`USAGE`] with its options
+pub struct CommandHelp {
+    pub name: &'static str,
+    pub argument: Option<&'static str>,
+    pub description: String,
+    pub options: Vec<OptionHelp>,
+}
+
+/// [`USAGE`] read back into commands, global options and the closing notes
+pub struct Help {
+    pub commands: Vec<CommandHelp>,
+    pub options: Vec<OptionHelp>,
+    pub notes: Vec<String>,
+}
+
+/// Parse [`USAGE`], so completions and the man page always match the help text
+pub fn help() -> Help {
+    let mut help = Help {
+        commands: Vec::new(),
+        options: Vec::new(),
+        notes: Vec::new(),
+    };
+    let mut section = "";
+    // Set while an option line ends with a comma and its flags go on
+    let mut continued = false;
+
+    for line in USAGE.lines() {
+        let text = line.trim_start();
+        if text.is_empty() || text.starts_with("Usage:") {
+            section = "";
+            continue;
+        }
+        if let Some(heading) = text.strip_suffix(':').filter(|_| text == line) {
+            section = heading;
+            continue;
+        }
+        if !matches!(section, "Commands" | "Options") {
+            if text == line && section != "Notes" {
+                section = "Notes";
+                help.notes.push(text.to_string());
+            } else if let Some(note) = help.notes.last_mut() {
+                note.push(' ');
+                note.push_str(text);
+            }
+            continue;
+        }
+
+        let options = match help.commands.last_mut() {
+            Some(command) if section == "Commands" => &mut command.options,
+            _ => &mut help.options,
+        };
+        if text.starts_with('-') {
+            let (flags, description, more) = parse_flags(text);
+            match options.last_mut() {
+                Some(option) if continued => option.flags.extend(flags),
+                _ => options.push(OptionHelp {
+                    flags,
+                    description: String::new(),
+                }),
+            }
+            continued = more;
+            if let Some(option) = options.last_mut() {
+                append(&mut option.description, description);
+            }
+        } else if line.len() - text.len() == 2 {
+            let (name, rest) = text.split_once(' ').unwrap_or((text, ""));
+            let (argument, description) = match rest.strip_prefix('<') {
+                Some(_) => rest.split_once(' ').unwrap_or((rest, "")),
+                None => ("", rest),
+            };
+            help.commands.push(CommandHelp {
+                name,
+                argument: Some(argument).filter(|argument| !argument.is_empty()),
+                description: description.trim().to_string(),
+                options: Vec::new(),
+            });
+        } else if let Some(option) = options.last_mut() {
+            append(&mut option.description, text);
+        } else if let Some(command) = help.commands.last_mut() {
+            append(&mut command.description, text);
+        }
+    }
+
+    // Options listed "as for monitor" take the value documented there
+    let values: Vec<(&'static str, &'static str)> = help
+        .commands
+        .iter()
+        .flat_map(|command| &command.options)
+        .flat_map(|option| &option.flags)
+        .filter_map(|flag| Some((flag.name, flag.value?)))
+        .collect();
+    for flag in help
+        .commands
+        .iter_mut()
+        .flat_map(|command| &mut command.options)
+        .flat_map(|option| &mut option.flags)
+    {
+        if flag.value.is_none() {
+            flag.value = values
+                .iter()
+                .find(|(name, _)| *name == flag.name)
+                .map(|(_, value)| *value);
+        }
+    }
+    help
+}
+
+/// Split an option line into its flags, the description and whether the flags go on
+fn parse_flags(mut text: &'static str) -> (Vec<Flag>, &'static str, bool) {
+    let mut flags = Vec::new();
+    loop {
+        let end = text.find([' ', ',']).unwrap_or(text.len());
+        let mut flag = Flag {
+            name: &text[..end],
+            value: None,
+        };
+        text = &text[end..];
+        if text.starts_with(" <") {
+            let mut end = text[1..].find(' ').map_or(text.len(), |end| end + 1);
+            if text[..end].ends_with(',') {
+                end -= 1;
+            }
+            flag.value = Some(&text[1..end]);
+            text = &text[end..];
+        }
+        flags.push(flag);
+        match text.strip_prefix(", -") {
+            Some(_) => text = &text[2..],
+            None if text == "," => return (flags, "", true),
+            None => return (flags, text.trim(), false),
+        }
+    }
+}
+
+fn append(description: &mut String, text: &str) {
+    if !description.is_empty() && !text.is_empty() {
+        description.push(' ');
+    }
+    description.push_str(text);
+}
+
+fn value<I: Iterator<Item = String>>(args: &mut I, option: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("missing value for '{}'", option))
+}
+
+/// Parse a USB/IP device such as `gateway/1-1.2` or `10.0.0.5:3240/1-1`, adding the default port
+fn parse_usbip(text: &str) -> Result<(String, String), String> {
+    let (address, busid) = text
+        .rsplit_once('/')
+        .filter(|(address, busid)| !address.is_empty() && !busid.is_empty())
+        .ok_or_else(|| format!("invalid USB/IP device '{}', expected HOST/BUSID", text))?;
+    let has_port = match address.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && !port.contains(']'),
+        None => false,
+    };
+    let address = if has_port {
+        address.to_string()
+    } else {
+        format!("{}:{}", address, DEFAULT_USBIP_PORT)
+    };
+    Ok((address, busid.to_string()))
+}
+
+/// Check that a TCP address such as `bridge.lan:4001` names a port
+fn parse_address(text: &str) -> Result<String, String> {
+    match text.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+            Ok(text.to_string())
+        }
+        _ => Err(format!("invalid address '{}', expected HOST:PORT", text)),
+    }
+}
+
+/// Parse a USB ID written in hex, with or without the 0x prefix
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid USB ID '{}'", text))
+}
+
+/// Parse a scheduled test such as `quick@0 3 * * 0` or `deep@0 4 1 * *`
+fn parse_self_test(text: &str) -> Result<ScheduledTest, String> {
+    let invalid = || format!("invalid self test '{}', expected KIND@CRON", text);
+    let (kind, schedule) = text.split_once('@').ok_or_else(invalid)?;
+    Ok(ScheduledTest {
+        kind: TestKind::from_name(kind.trim()).ok_or_else(invalid)?,
+        schedule: Schedule::parse(schedule).ok_or_else(invalid)?,
+    })
+}
+
+/// Parse a load shedding stage such as `5=systemctl stop backup`
+fn parse_stage(text: &str) -> Result<(Duration, String), String> {
+    let (minutes, command) = text
+        .split_once('=')
+        .filter(|(_, command)| !command.is_empty())
+        .ok_or_else(|| format!("invalid stage '{}', expected MINUTES=COMMAND", text))?;
+    let minutes: f64 = minutes
+        .trim()
+        .parse()
+        .ok()
+        .filter(|m: &f64| *m >= 0.0 && m.is_finite())
+        .ok_or_else(|| format!("invalid time '{}'", minutes))?;
+    Ok((Duration::from_secs_f64(minutes * 60.0), command.to_string()))
+}
+
+/// Parse an alarm threshold such as `load>80` or `input-voltage<190,hysteresis=5,for=30`
+fn parse_alarm(text: &str) -> Result<Threshold, String> {
+    let invalid = || format!("invalid alarm '{}'", text);
+    let mut parts = text.split(',');
+    let condition = parts.next().unwrap_or_default();
+    let (index, operator) = condition
+        .match_indices(['<', '>'])
+        .next()
+        .ok_or_else(invalid)?;
+    let quantity = Quantity::from_name(condition[..index].trim())
+        .ok_or_else(|| format!("unknown alarm quantity in '{}'", text))?;
+    let limit: f64 = condition[index + 1..]
+        .trim()
+        .parse()
+        .map_err(|_| invalid())?;
+    let mut threshold = if operator == ">" {
+        Threshold::above(quantity, limit)
+    } else {
+        Threshold::below(quantity, limit)
+    };
+
+    for part in parts {
+        let (key, value) = part.split_once('=').ok_or_else(invalid)?;
+        let value: f64 = value
+            .trim()
+            .parse()
+            .ok()
+            .filter(|value: &f64| *value >= 0.0 && value.is_finite())
+            .ok_or_else(invalid)?;
+        threshold = match key.trim() {
+            "hysteresis" => threshold.with_hysteresis(value),
+            "for" => threshold.with_min_duration(Duration::from_secs_f64(value)),
+            _ => return Err(invalid()),
+        };
+    }
+    Ok(threshold)
+}
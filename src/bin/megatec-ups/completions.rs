@@ -0,0 +1,296 @@
+//! Shell completion scripts generated from the help text
+
+use crate::args::{self, Flag, Help, OptionHelp, Shell};
+use std::fmt::Write as _;
+
+/// Values offered for an option or for the argument of a command
+const CHOICES: &[(&str, &[&str])] = &[
+    ("--format", &["plain", "json", "prometheus", "nagios"]),
+    ("--history-format", &["csv", "jsonl"]),
+    ("service", &["install", "uninstall", "start", "stop", "run"]),
+    ("completions", &Shell::NAMES),
+];
+
+/// Options completed with file names
+const PATHS: &[&str] = &[
+    "--config",
+    "--serial",
+    "--history",
+    "--history-db",
+    "--history-dir",
+    "--socket",
+];
+
+/// Completion script for a shell
+pub fn script(shell: Shell) -> String {
+    let help = args::help();
+    match shell {
+        Shell::Bash => bash(&help),
+        Shell::Zsh => zsh(&help),
+        Shell::Fish => fish(&help),
+    }
+}
+
+fn bash(help: &Help) -> String {
+    let global = flag_names(&help.options);
+    let mut values: Vec<&str> = help
+        .commands
+        .iter()
+        .flat_map(|command| &command.options)
+        .chain(&help.options)
+        .flat_map(|option| &option.flags)
+        .filter(|flag| flag.value.is_some())
+        .map(|flag| flag.name)
+        .collect();
+    values.sort_unstable();
+    values.dedup();
+
+    let mut out = String::new();
+    let _ = writeln!(out, "_megatec_ups() {{");
+    let _ = writeln!(
+        out,
+        "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\""
+    );
+    let _ = writeln!(out, "    local values=\"{}\"", values.join(" "));
+    out.push_str(
+        r#"    local command="" skip="" word
+    for word in "${COMP_WORDS[@]:1:COMP_CWORD-1}"; do
+        if [[ -n $skip ]]; then
+            skip=""
+        elif [[ " $values " == *" $word "* ]]; then
+            skip=1
+        elif [[ -z $command && $word != -* ]]; then
+            command=$word
+        fi
+    done
+
+    case "$prev" in
+"#,
+    );
+    for (name, choices) in CHOICES.iter().filter(|(name, _)| name.starts_with('-')) {
+        let _ = writeln!(
+            out,
+            "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;",
+            name,
+            choices.join(" ")
+        );
+    }
+    let _ = writeln!(
+        out,
+        "        {}) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;",
+        PATHS.join("|")
+    );
+    let others: Vec<&str> = values
+        .iter()
+        .copied()
+        .filter(|name| choices(name).is_none() && !PATHS.contains(name))
+        .collect();
+    let _ = writeln!(out, "        {}) return ;;", others.join("|"));
+    let _ = writeln!(out, "    esac\n\n    local words");
+    let _ = writeln!(out, "    case \"$command\" in");
+    let commands: Vec<&str> = help.commands.iter().map(|command| command.name).collect();
+    let _ = writeln!(
+        out,
+        "        \"\") words=\"{} {}\" ;;",
+        commands.join(" "),
+        global.join(" ")
+    );
+    for command in &help.commands {
+        let mut words = choices(command.name).unwrap_or_default().to_vec();
+        words.extend(flag_names(&command.options));
+        if !words.is_empty() {
+            words.extend(&global);
+            let _ = writeln!(
+                out,
+                "        {}) words=\"{}\" ;;",
+                command.name,
+                words.join(" ")
+            );
+        }
+    }
+    let _ = writeln!(out, "        *) words=\"{}\" ;;", global.join(" "));
+    out.push_str(
+        r#"    esac
+    COMPREPLY=($(compgen -W "$words" -- "$cur"))
+}
+
+complete -F _megatec_ups megatec-ups
+"#,
+    );
+    out
+}
+
+fn zsh(help: &Help) -> String {
+    let mut out = String::from(
+        "#compdef megatec-ups\n\n_megatec_ups() {\n    local curcontext=\"$curcontext\" state line\n    local -a global commands\n    global=(\n",
+    );
+    for spec in zsh_specs(&help.options) {
+        let _ = writeln!(out, "        {}", spec);
+    }
+    let _ = writeln!(out, "    )\n    commands=(");
+    for command in &help.commands {
+        let description = format!("{}:{}", command.name, command.description);
+        let _ = writeln!(out, "        '{}'", quote(&description));
+    }
+    out.push_str(
+        r#"    )
+
+    _arguments -C $global '1:command:->command' '*::argument:->argument'
+    case $state in
+        command)
+            _describe -t commands 'megatec-ups command' commands
+            ;;
+        argument)
+            case $line[1] in
+"#,
+    );
+    for command in &help.commands {
+        let mut specs = zsh_specs(&command.options);
+        if let Some(choices) = choices(command.name) {
+            let argument = command.argument.map_or("value", placeholder);
+            specs.insert(0, format!("'1:{}:({})'", argument, choices.join(" ")));
+        }
+        if !specs.is_empty() {
+            let _ = writeln!(out, "                {})", command.name);
+            let _ = writeln!(out, "                    _arguments $global \\");
+            for (index, spec) in specs.iter().enumerate() {
+                let end = if index + 1 == specs.len() { "" } else { " \\" };
+                let _ = writeln!(out, "                        {}{}", spec, end);
+            }
+            let _ = writeln!(out, "                    ;;");
+        }
+    }
+    out.push_str(
+        r#"                *)
+                    _arguments $global
+                    ;;
+            esac
+            ;;
+    esac
+}
+
+_megatec_ups "$@"
+"#,
+    );
+    out
+}
+
+/// `_arguments` specs of options, such as `'*--hook[...]:event: '`
+fn zsh_specs(options: &[OptionHelp]) -> Vec<String> {
+    let mut specs = Vec::new();
+    for option in options {
+        let repeatable = if option.description.contains("repeatable") {
+            "*"
+        } else {
+            ""
+        };
+        let description = option
+            .description
+            .replace('\\', "\\\\")
+            .replace('[', "\\[")
+            .replace(']', "\\]");
+        for flag in &option.flags {
+            let value = match flag.value {
+                Some(value) => format!(":{}:{}", placeholder(value), zsh_action(flag)),
+                None => String::new(),
+            };
+            let spec = format!("{}{}[{}]{}", repeatable, flag.name, description, value);
+            specs.push(format!("'{}'", quote(&spec)));
+        }
+    }
+    specs
+}
+
+fn zsh_action(flag: &Flag) -> String {
+    if let Some(choices) = choices(flag.name) {
+        format!("({})", choices.join(" "))
+    } else if PATHS.contains(&flag.name) {
+        "_files".to_string()
+    } else {
+        " ".to_string()
+    }
+}
+
+fn fish(help: &Help) -> String {
+    let mut out = String::from("complete -c megatec-ups -f\n");
+    for command in &help.commands {
+        let _ = writeln!(
+            out,
+            "complete -c megatec-ups -n __fish_use_subcommand -a {} -d '{}'",
+            command.name,
+            fish_quote(&command.description)
+        );
+    }
+    fish_options(&mut out, "", &help.options);
+    for command in &help.commands {
+        let condition = format!("-n '__fish_seen_subcommand_from {}' ", command.name);
+        if let Some(choices) = choices(command.name) {
+            let _ = writeln!(
+                out,
+                "complete -c megatec-ups {}-a '{}'",
+                condition,
+                choices.join(" ")
+            );
+        }
+        fish_options(&mut out, &condition, &command.options);
+    }
+    out
+}
+
+fn fish_options(out: &mut String, condition: &str, options: &[OptionHelp]) {
+    for option in options {
+        for flag in &option.flags {
+            let name = match flag.name.strip_prefix("--") {
+                Some(long) => format!("-l {}", long),
+                None => format!("-s {}", flag.name.trim_start_matches('-')),
+            };
+            let value = match (flag.value, choices(flag.name)) {
+                (None, _) => String::new(),
+                (Some(_), Some(choices)) => format!(" -x -a '{}'", choices.join(" ")),
+                (Some(_), None) if PATHS.contains(&flag.name) => " -r -F".to_string(),
+                (Some(_), None) => " -x".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "complete -c megatec-ups {}{}{} -d '{}'",
+                condition,
+                name,
+                value,
+                fish_quote(&option.description)
+            );
+        }
+    }
+}
+
+fn choices(name: &str) -> Option<&'static [&'static str]> {
+    CHOICES
+        .iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, choices)| *choices)
+}
+
+fn flag_names(options: &[OptionHelp]) -> Vec<&'static str> {
+    options
+        .iter()
+        .flat_map(|option| &option.flags)
+        .map(|flag| flag.name)
+        .collect()
+}
+
+/// First name in a placeholder such as `<QUANTITY><OP><LIMIT>`
+fn placeholder(value: &str) -> &str {
+    value
+        .trim_start_matches('<')
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or(value)
+}
+
+/// Escape text for a single-quoted POSIX shell string
+fn quote(text: &str) -> String {
+    text.replace('\'', "'\\''")
+}
+
+fn fish_quote(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
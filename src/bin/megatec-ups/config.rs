@@ -0,0 +1,355 @@
+//! Configuration file and environment variables turned into command line arguments
+//!
+//! Every key of the TOML file is a long option without its dashes, such as
+//! `interval = 10` or `max_temperature = 45`, grouped in any tables. `true`
+//! switches an option on, arrays repeat it and the `[hooks]` table maps events
+//! to commands. The environment variables `MEGATEC_UPS_<OPTION>`, such as
+//! `MEGATEC_UPS_INTERVAL`, override the file and the command line overrides both.
+
+use crate::args::{self, Flag};
+use std::path::Path;
+
+/// Configuration read when --config is not given, if it exists
+pub const DEFAULT_PATH: &str = "/etc/megatec-ups.toml";
+
+const ENV_PREFIX: &str = "MEGATEC_UPS_";
+
+/// Options that only make sense on the command line
+const COMMAND_LINE_ONLY: &[&str] = &["--config", "--help", "--version"];
+
+/// Value in the configuration file
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    /// Number kept as written, parsed with the option
+    Number(String),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+/// `key = value` line of the configuration file
+struct Entry {
+    line: usize,
+    table: String,
+    key: String,
+    value: Value,
+}
+
+/// Get the command line arguments extended with the configuration file and the environment
+pub fn arguments(command_line: Vec<String>) -> Result<Vec<String>, String> {
+    let mut rest = Vec::with_capacity(command_line.len());
+    let mut path = None;
+    let mut command_line = command_line.into_iter();
+    while let Some(arg) = command_line.next() {
+        if arg == "--config" {
+            let value = command_line
+                .next()
+                .ok_or_else(|| "missing value for '--config'".to_string())?;
+            path = Some(value);
+        } else {
+            rest.push(arg);
+        }
+    }
+
+    let flags = flags();
+    let mut arguments = match path {
+        Some(path) => load(Path::new(&path), &flags)?,
+        None if Path::new(DEFAULT_PATH).exists() => load(Path::new(DEFAULT_PATH), &flags)?,
+        None => Vec::new(),
+    };
+    arguments.extend(environment(&flags)?);
+    arguments.extend(rest);
+    Ok(arguments)
+}
+
+/// Long options documented in the usage text
+fn flags() -> Vec<Flag> {
+    let help = args::help();
+    let mut flags: Vec<Flag> = Vec::new();
+    for flag in help
+        .commands
+        .into_iter()
+        .flat_map(|command| command.options)
+        .chain(help.options)
+        .flat_map(|option| option.flags)
+    {
+        let known = flags.iter().any(|known| known.name == flag.name);
+        if flag.name.starts_with("--") && !known && !COMMAND_LINE_ONLY.contains(&flag.name) {
+            flags.push(flag);
+        }
+    }
+    flags
+}
+
+/// Read a configuration file into arguments
+fn load(path: &Path, flags: &[Flag]) -> Result<Vec<String>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let entries = parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let mut arguments = Vec::new();
+    for entry in entries {
+        let invalid =
+            |message: String| format!("{}: line {}: {}", path.display(), entry.line, message);
+        if entry.table == "hooks" {
+            for command in values(&entry.value) {
+                match command {
+                    Value::String(command) => {
+                        arguments.push("--hook".to_string());
+                        arguments.push(format!("{}={}", entry.key, command));
+                    }
+                    _ => return Err(invalid(format!("hook '{}' must be a string", entry.key))),
+                }
+            }
+            continue;
+        }
+
+        let name = format!("--{}", entry.key);
+        let flag = flags
+            .iter()
+            .find(|flag| flag.name == name)
+            .ok_or_else(|| invalid(format!("unknown option '{}'", entry.key)))?;
+        for value in values(&entry.value) {
+            match (value, flag.value.is_some()) {
+                (Value::Bool(true), false) => arguments.push(name.clone()),
+                (Value::Bool(false), false) => {}
+                (Value::String(text) | Value::Number(text), true) => {
+                    arguments.push(name.clone());
+                    arguments.push(text.clone());
+                }
+                (_, false) => {
+                    return Err(invalid(format!("'{}' must be true or false", entry.key)))
+                }
+                (_, true) => {
+                    return Err(invalid(format!(
+                        "'{}' must be a string or a number",
+                        entry.key
+                    )))
+                }
+            }
+        }
+    }
+    Ok(arguments)
+}
+
+/// Elements of an array, or the value itself
+fn values(value: &Value) -> &[Value] {
+    match value {
+        Value::Array(values) => values,
+        value => std::slice::from_ref(value),
+    }
+}
+
+/// Read the `MEGATEC_UPS_<OPTION>` variables into arguments
+fn environment(flags: &[Flag]) -> Result<Vec<String>, String> {
+    let mut arguments = Vec::new();
+    for flag in flags {
+        let name = format!(
+            "{}{}",
+            ENV_PREFIX,
+            flag.name[2..].to_uppercase().replace('-', "_")
+        );
+        let value = match std::env::var(&name) {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        if flag.value.is_some() {
+            arguments.push(flag.name.to_string());
+            arguments.push(value);
+        } else {
+            match value.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" => arguments.push(flag.name.to_string()),
+                "" | "0" | "false" | "no" => {}
+                _ => return Err(format!("{} must be true or false", name)),
+            }
+        }
+    }
+    Ok(arguments)
+}
+
+/// Parse the subset of TOML used by the configuration: tables, bare or quoted
+/// keys, strings, numbers, booleans and arrays of them
+fn parse(text: &str) -> Result<Vec<Entry>, String> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        position: 0,
+        line: 1,
+    };
+    let mut entries = Vec::new();
+    let mut table = String::new();
+
+    loop {
+        parser.skip_blank(true);
+        let Some(c) = parser.peek() else {
+            return Ok(entries);
+        };
+        let line = parser.line;
+        if c == '[' {
+            parser.position += 1;
+            parser.skip_blank(false);
+            table = parser.key()?;
+            parser.skip_blank(false);
+            parser.expect(']')?;
+        } else {
+            let key = parser.key()?.replace('_', "-");
+            parser.skip_blank(false);
+            parser.expect('=')?;
+            parser.skip_blank(false);
+            let value = parser.value()?;
+            if entries
+                .iter()
+                .any(|entry: &Entry| entry.table == table && entry.key == key)
+            {
+                return Err(format!("line {}: duplicate key '{}'", line, key));
+            }
+            entries.push(Entry {
+                line,
+                table: table.clone(),
+                key,
+                value,
+            });
+        }
+        parser.skip_blank(false);
+        match parser.peek() {
+            None | Some('\n') => {}
+            Some(c) => return Err(parser.error(format!("unexpected '{}'", c))),
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: String) -> String {
+        format!("line {}: {}", self.line, message)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(self.error(format!("expected '{}', found '{}'", expected, c))),
+            None => Err(self.error(format!("expected '{}'", expected))),
+        }
+    }
+
+    /// Skip spaces and comments, and line breaks too when `newlines` is set
+    fn skip_blank(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => self.position += 1,
+                '\n' if newlines => {
+                    self.next();
+                }
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.position += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some('"') | Some('\'') => self.string(),
+            _ => {
+                let start = self.position;
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                {
+                    self.position += 1;
+                }
+                if start == self.position {
+                    return Err(self.error("expected a key".to_string()));
+                }
+                Ok(self.chars[start..self.position].iter().collect())
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') | Some('\'') => Ok(Value::String(self.string()?)),
+            Some('[') => {
+                self.position += 1;
+                let mut values = Vec::new();
+                loop {
+                    self.skip_blank(true);
+                    if self.peek() == Some(']') {
+                        self.position += 1;
+                        return Ok(Value::Array(values));
+                    }
+                    values.push(self.value()?);
+                    self.skip_blank(true);
+                    match self.next() {
+                        Some(',') => {}
+                        Some(']') => return Ok(Value::Array(values)),
+                        _ => return Err(self.error("expected ',' or ']'".to_string())),
+                    }
+                }
+            }
+            _ => {
+                let start = self.position;
+                while self.peek().is_some_and(|c| {
+                    c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-' | '_')
+                }) {
+                    self.position += 1;
+                }
+                let word: String = self.chars[start..self.position].iter().collect();
+                match word.as_str() {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    _ if word.replace('_', "").parse::<f64>().is_ok() => {
+                        Ok(Value::Number(word.replace('_', "")))
+                    }
+                    "" => Err(self.error("expected a value".to_string())),
+                    _ => Err(self.error(format!("invalid value '{}'", word))),
+                }
+            }
+        }
+    }
+
+    /// Basic string with escapes, or literal string between single quotes
+    fn string(&mut self) -> Result<String, String> {
+        let quote = self.next().unwrap_or('"');
+        let mut text = String::new();
+        loop {
+            match self.next() {
+                None | Some('\n') => return Err(self.error("unterminated string".to_string())),
+                Some(c) if c == quote => return Ok(text),
+                Some('\\') if quote == '"' => {
+                    let escaped = match self.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some(c) => return Err(self.error(format!("invalid escape '\\{}'", c))),
+                        None => return Err(self.error("unterminated string".to_string())),
+                    };
+                    text.push(escaped);
+                }
+                Some(c) => text.push(c),
+            }
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Output of the one-shot subcommands in the format chosen with --format
+
+use crate::args::OutputFormat;
+use megatec_ups_control::json::{ToJson, Value};
+use megatec_ups_control::{
+    AlarmEvent, ExtendedStatus, RatingInfo, SheddingStep, StateTransition, TestResult, UpsEvent,
+    UpsMode, UpsStatus,
+};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::process::ExitCode;
+
+/// States of a Nagios plugin, which are also its exit codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NagiosState {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl NagiosState {
+    fn label(self) -> &'static str {
+        match self {
+            NagiosState::Ok => "OK",
+            NagiosState::Warning => "WARNING",
+            NagiosState::Critical => "CRITICAL",
+            NagiosState::Unknown => "UNKNOWN",
+        }
+    }
+
+    /// Print the plugin output line and get the exit code
+    fn report(self, summary: &str, perfdata: &str) -> ExitCode {
+        if perfdata.is_empty() {
+            println!("UPS {} - {}", self.label(), summary);
+        } else {
+            println!("UPS {} - {} | {}", self.label(), summary, perfdata);
+        }
+        ExitCode::from(self as u8)
+    }
+}
+
+/// Print a status reading
+///
+/// In nagios mode a low battery, a failed UPS or an active shutdown is
+/// critical and running on battery or bypass a warning.
+pub fn status(status: &UpsStatus, format: OutputFormat) -> Result<ExitCode, Box<dyn Error>> {
+    match format {
+        OutputFormat::Plain => println!("{:#}", status),
+        OutputFormat::Json => println!("{}", status.to_json_string()),
+        OutputFormat::Prometheus => print!("{}", prometheus_status(status)?),
+        OutputFormat::Nagios => {
+            let flags = status.flags;
+            let state = if flags.battery_low || flags.ups_failed || flags.shutdown_active {
+                NagiosState::Critical
+            } else if flags.utility_fail || flags.bypass_active {
+                NagiosState::Warning
+            } else {
+                NagiosState::Ok
+            };
+            let perfdata = format!(
+                "input_voltage={:.1} output_voltage={:.1} load={:.0}% input_frequency={:.1} \
+                 battery_voltage={:.2} temperature={:.1}",
+                status.input_voltage,
+                status.output_voltage,
+                status.load_raw,
+                status.input_frequency,
+                status.battery_voltage,
+                status.temperature
+            );
+            return Ok(state.report(&status.to_string(), &perfdata));
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(feature = "exporter")]
+fn prometheus_status(status: &UpsStatus) -> Result<String, Box<dyn Error>> {
+    Ok(megatec_ups_control::render_metrics(Some(status)))
+}
+
+#[cfg(not(feature = "exporter"))]
+fn prometheus_status(status: &UpsStatus) -> Result<String, Box<dyn Error>> {
+    let _ = status;
+    Err("the prometheus format of the status is not supported by this build".into())
+}
+
+/// Print the rating, as `megatec_ups_rated_*` gauges in prometheus mode
+pub fn rating(rating: &RatingInfo, format: OutputFormat) -> ExitCode {
+    match format {
+        OutputFormat::Json => println!("{}", rating.to_json_string()),
+        OutputFormat::Prometheus => {
+            let mut out = String::new();
+            for (name, help, value) in [
+                (
+                    "rated_voltage_volts",
+                    "Rated output voltage",
+                    rating.rated_voltage,
+                ),
+                (
+                    "rated_current_amperes",
+                    "Rated output current",
+                    rating.rated_current,
+                ),
+                (
+                    "rated_battery_voltage_volts",
+                    "Nominal battery voltage",
+                    rating.battery_voltage,
+                ),
+                (
+                    "rated_frequency_hertz",
+                    "Rated frequency",
+                    rating.rated_frequency,
+                ),
+            ] {
+                let _ = writeln!(out, "# HELP megatec_ups_{} {}", name, help);
+                let _ = writeln!(out, "# TYPE megatec_ups_{} gauge", name);
+                let _ = writeln!(out, "megatec_ups_{} {}", name, value);
+            }
+            print!("{}", out);
+        }
+        _ => println!("{:#}", rating),
+    }
+    ExitCode::SUCCESS
+}
+
+/// Print the extended status
+pub fn extended(status: &ExtendedStatus, format: OutputFormat) -> ExitCode {
+    match format {
+        OutputFormat::Json => println!("{}", status.to_json_string()),
+        _ => println!("{:#}", status),
+    }
+    ExitCode::SUCCESS
+}
+
+/// Print the UPS name, as `{"name": ...}` in json mode
+pub fn name(name: &str, format: OutputFormat) -> ExitCode {
+    match format {
+        OutputFormat::Json => println!("{}", Value::object(vec![("name", name.into())])),
+        _ => println!("{}", name),
+    }
+    ExitCode::SUCCESS
+}
+
+/// Print the operating mode, as `{"mode": ...}` in json mode
+pub fn mode(mode: UpsMode, format: OutputFormat) -> ExitCode {
+    match format {
+        OutputFormat::Json => println!("{}", Value::object(vec![("mode", mode.name().into())])),
+        _ => println!("{}", mode),
+    }
+    ExitCode::SUCCESS
+}
+
+/// Print the outcome of a requested test
+///
+/// In nagios mode a failure is critical and a test that did not start a warning.
+pub fn test(result: TestResult, format: OutputFormat) -> ExitCode {
+    let name = match result {
+        TestResult::InProgress => "in_progress",
+        TestResult::Passed => "passed",
+        TestResult::Failed => "failed",
+        TestResult::NotStarted => "not_started",
+    };
+    match format {
+        OutputFormat::Json => println!("{}", Value::object(vec![("test", name.into())])),
+        OutputFormat::Nagios => {
+            let state = match result {
+                TestResult::InProgress | TestResult::Passed => NagiosState::Ok,
+                TestResult::NotStarted => NagiosState::Warning,
+                TestResult::Failed => NagiosState::Critical,
+            };
+            return state.report(&format!("test {}", result), "");
+        }
+        _ => println!("test {}", result),
+    }
+    ExitCode::SUCCESS
+}
+
+/// Confirm a command without an answer, as `{"command": ..., "ok": true}` in json mode
+pub fn done(command: &str, format: OutputFormat) -> ExitCode {
+    if format == OutputFormat::Json {
+        let confirmation = Value::object(vec![("command", command.into()), ("ok", true.into())]);
+        println!("{}", confirmation);
+    }
+    ExitCode::SUCCESS
+}
+
+/// Print a failure of a subcommand, as an UNKNOWN result in nagios mode
+pub fn failure(error: &dyn Error, format: OutputFormat) -> ExitCode {
+    match format {
+        OutputFormat::Nagios => NagiosState::Unknown.report(&error.to_string(), ""),
+        OutputFormat::Json => {
+            let error = Value::object(vec![("error", error.to_string().into())]);
+            println!("{}", error);
+            ExitCode::FAILURE
+        }
+        _ => {
+            eprintln!("megatec-ups: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Line of the monitor for a status reading
+pub fn monitor_status(status: &UpsStatus, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Json => Value::object(vec![("status", status.to_json())]).to_string(),
+        _ => status.to_string(),
+    }
+}
+
+/// Line of the monitor for an event
+pub fn monitor_event(event: &UpsEvent, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Json => Value::object(vec![("event", event.to_json())]).to_string(),
+        _ => format!("event: {}", event.name()),
+    }
+}
+
+/// Line of the monitor for a state change
+pub fn monitor_transition(transition: &StateTransition, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Json => {
+            let from = transition
+                .from
+                .map_or(Value::Null, |from| from.name().into());
+            let state = Value::object(vec![("from", from), ("to", transition.to.name().into())]);
+            Value::object(vec![("state", state)]).to_string()
+        }
+        _ => format!("state: {}", transition),
+    }
+}
+
+/// Line of the monitor for an alarm
+pub fn monitor_alarm(alarm: &AlarmEvent, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Json => Value::object(vec![("alarm", alarm.to_string().into())]).to_string(),
+        _ => alarm.to_string(),
+    }
+}
+
+/// Line of the monitor for a load shedding stage or its undoing
+pub fn monitor_shedding(step: &SheddingStep, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Json => {
+            Value::object(vec![("load_shedding", step.to_string().into())]).to_string()
+        }
+        _ => step.to_string(),
+    }
+}
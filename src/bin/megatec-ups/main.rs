@@ -0,0 +1,1075 @@
+mod args;
+mod completions;
+mod config;
+mod format;
+mod man;
+mod tui;
+
+use args::{
+    Action, DeviceSelector, EmailOptions, InfluxTarget, OutputFormat, ServiceAction, Subcommand,
+    WatchOptions, Webhook,
+};
+use megatec_ups_control::{
+    Alarms, HistoryWriter, Hook, Hooks, LoadShedding, MegatecUps, Monitor, SheddingStep, Stage,
+    TestScheduler, Threshold, Transport, UpsEvent, UpsStatus,
+};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type Ups = MegatecUps<Box<dyn Transport + Send>>;
+
+/// Stores a status sample taken by the monitor loop
+type SampleRecorder = Box<dyn FnMut(&UpsStatus)>;
+
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+fn main() -> ExitCode {
+    let arguments = config::arguments(std::env::args().skip(1).collect());
+    let action = match arguments.and_then(|arguments| args::parse(arguments.into_iter())) {
+        Ok(action) => action,
+        Err(e) => {
+            eprintln!("megatec-ups: {}\n\n{}", e, args::USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let (result, format) = match action {
+        Action::Help => {
+            print!("{}", args::USAGE);
+            (Ok(ExitCode::SUCCESS), OutputFormat::Plain)
+        }
+        Action::Version => {
+            println!("megatec-ups {}", env!("CARGO_PKG_VERSION"));
+            (Ok(ExitCode::SUCCESS), OutputFormat::Plain)
+        }
+        Action::Completions(shell) => {
+            print!("{}", completions::script(shell));
+            (Ok(ExitCode::SUCCESS), OutputFormat::Plain)
+        }
+        Action::Man => {
+            print!("{}", man::page());
+            (Ok(ExitCode::SUCCESS), OutputFormat::Plain)
+        }
+        Action::Run {
+            device,
+            command:
+                Subcommand::Service {
+                    action,
+                    name,
+                    watch,
+                },
+            format,
+        } => (
+            service(device, action, &name, *watch).map(|()| ExitCode::SUCCESS),
+            format,
+        ),
+        Action::Run {
+            device,
+            command:
+                Subcommand::Daemon {
+                    foreground,
+                    pid_file,
+                    log_file,
+                    watch,
+                },
+            format,
+        } => (
+            daemon(device, foreground, pid_file, log_file, *watch).map(|()| ExitCode::SUCCESS),
+            format,
+        ),
+        Action::Run {
+            command:
+                Subcommand::FleetServer {
+                    listen,
+                    token,
+                    history_dir,
+                },
+            format,
+            ..
+        } => (
+            fleet_server(&listen, token, history_dir).map(|()| ExitCode::SUCCESS),
+            format,
+        ),
+        Action::Run {
+            device,
+            command,
+            format,
+        } => (
+            connect(device).and_then(|ups| run(ups, command, format)),
+            format,
+        ),
+    };
+
+    result.unwrap_or_else(|e| format::failure(&*e, format))
+}
+
+/// Open the UPS selected on the command line
+fn connect(device: DeviceSelector) -> Result<Ups, Box<dyn Error>> {
+    let transport: Box<dyn Transport + Send> = match device {
+        DeviceSelector::Auto => Box::new(MegatecUps::auto()?.into_transport()),
+        DeviceSelector::Usb {
+            vendor_id,
+            product_id,
+        } => megatec_ups_control::open_usb(vendor_id, product_id)?,
+        #[cfg(all(feature = "serial", unix))]
+        DeviceSelector::Serial(path) => Box::new(megatec_ups_control::SerialTransport::open(path)?),
+        #[cfg(not(all(feature = "serial", unix)))]
+        DeviceSelector::Serial(path) => {
+            let message = format!(
+                "serial port {} is not supported by this build",
+                path.display()
+            );
+            return Err(message.into());
+        }
+        #[cfg(feature = "usbip")]
+        DeviceSelector::UsbIp { address, busid } => Box::new(
+            megatec_ups_control::UsbIpTransport::connect(address.as_str(), &busid)?,
+        ),
+        #[cfg(not(feature = "usbip"))]
+        DeviceSelector::UsbIp { address, busid } => {
+            let message = format!(
+                "USB/IP device {} on {} is not supported by this build",
+                busid, address
+            );
+            return Err(message.into());
+        }
+        #[cfg(feature = "tcp-serial")]
+        DeviceSelector::TcpSerial { address, rfc2217 } => {
+            let mode = if rfc2217 {
+                megatec_ups_control::TcpSerialMode::Rfc2217
+            } else {
+                megatec_ups_control::TcpSerialMode::Raw
+            };
+            Box::new(megatec_ups_control::TcpSerialTransport::connect_with_mode(
+                address.as_str(),
+                mode,
+            )?)
+        }
+        #[cfg(not(feature = "tcp-serial"))]
+        DeviceSelector::TcpSerial { address, rfc2217 } => {
+            let kind = if rfc2217 {
+                "RFC 2217 server"
+            } else {
+                "serial bridge"
+            };
+            let message = format!("{} {} is not supported by this build", kind, address);
+            return Err(message.into());
+        }
+    };
+
+    let mut ups = MegatecUps::with_transport(transport);
+    // A silent device is reported by the first command instead
+    let _ = ups.detect_dialect();
+    Ok(ups)
+}
+
+/// Execute a subcommand against the UPS and get the exit code of its outcome
+fn run(ups: Ups, command: Subcommand, format: OutputFormat) -> Result<ExitCode, Box<dyn Error>> {
+    match command {
+        Subcommand::Status => return format::status(&ups.get_status()?, format),
+        Subcommand::Name => return Ok(format::name(&ups.get_name()?, format)),
+        Subcommand::Rating => return Ok(format::rating(&ups.get_rating()?, format)),
+        Subcommand::Extended => return Ok(format::extended(&ups.get_extended_status()?, format)),
+        Subcommand::Mode => return Ok(format::mode(ups.get_mode()?, format)),
+        Subcommand::Test => return Ok(format::test(ups.test()?, format)),
+        Subcommand::TestLow => return Ok(format::test(ups.test_until_battery_low()?, format)),
+        Subcommand::Beep => {
+            ups.switch_beep()?;
+            return Ok(format::done("beep", format));
+        }
+        Subcommand::Shutdown => {
+            ups.shutdown()?;
+            return Ok(format::done("shutdown", format));
+        }
+        Subcommand::Abort => {
+            ups.abort_test()?;
+            return Ok(format::done("abort", format));
+        }
+        Subcommand::Monitor(options) => watch(ups, *options, &AtomicBool::new(true))?,
+        Subcommand::Tui { interval } => tui::run(ups, interval)?,
+        #[cfg(feature = "exporter")]
+        Subcommand::Exporter { listen } => {
+            let exporter = megatec_ups_control::Exporter::bind(ups, listen.as_str())?;
+            eprintln!(
+                "megatec-ups: serving metrics on http://{}/metrics",
+                exporter.local_addr()?
+            );
+            exporter.serve()?;
+        }
+        #[cfg(not(feature = "exporter"))]
+        Subcommand::Exporter { listen } => {
+            let message = format!("exporter on {} is not supported by this build", listen);
+            return Err(message.into());
+        }
+        #[cfg(feature = "influxdb")]
+        Subcommand::InfluxDb {
+            url,
+            target,
+            tags,
+            interval,
+        } => {
+            use megatec_ups_control::{InfluxApi, InfluxExporter};
+            let api = match target {
+                InfluxTarget::V1 {
+                    database,
+                    username,
+                    password,
+                } => InfluxApi::V1 {
+                    database,
+                    username,
+                    password,
+                },
+                InfluxTarget::V2 { org, bucket, token } => InfluxApi::V2 { org, bucket, token },
+            };
+            let mut exporter = InfluxExporter::new(ups, &url, api, interval)?;
+            for (key, value) in tags {
+                exporter.add_tag(key, value);
+            }
+            eprintln!("megatec-ups: pushing samples to {}", url);
+            loop {
+                let started = Instant::now();
+                if let Err(e) = exporter.push() {
+                    eprintln!("megatec-ups: {}", e);
+                }
+                thread::sleep(interval.saturating_sub(started.elapsed()));
+            }
+        }
+        #[cfg(feature = "graphite")]
+        Subcommand::Graphite {
+            server,
+            statsd,
+            prefix,
+            interval,
+        } => {
+            use megatec_ups_control::{GraphiteProtocol, GraphitePusher};
+            let protocol = if statsd {
+                GraphiteProtocol::StatsD
+            } else {
+                GraphiteProtocol::Plaintext
+            };
+            let mut pusher = GraphitePusher::new(ups, server.as_str(), protocol, interval)?;
+            pusher.set_prefix(prefix);
+            eprintln!("megatec-ups: pushing gauges to {}", server);
+            loop {
+                let started = Instant::now();
+                if let Err(e) = pusher.push() {
+                    eprintln!("megatec-ups: {}", e);
+                }
+                thread::sleep(interval.saturating_sub(started.elapsed()));
+            }
+        }
+        #[cfg(not(feature = "graphite"))]
+        Subcommand::Graphite {
+            server,
+            statsd,
+            prefix,
+            interval,
+        } => {
+            let _ = (prefix, interval);
+            let kind = if statsd { "StatsD" } else { "Graphite" };
+            let message = format!("{} push to {} is not supported by this build", kind, server);
+            return Err(message.into());
+        }
+        #[cfg(feature = "otel")]
+        Subcommand::Otel {
+            url,
+            attributes,
+            headers,
+            interval,
+        } => {
+            let mut exporter =
+                megatec_ups_control::OtelExporter::new(Monitor::new(ups, interval), &url)?;
+            for (key, value) in attributes {
+                exporter.add_attribute(key, value);
+            }
+            for (name, value) in headers {
+                exporter.add_header(name, value);
+            }
+            eprintln!("megatec-ups: exporting metrics to {}", url);
+            loop {
+                let started = Instant::now();
+                if let Err(e) = exporter.push() {
+                    eprintln!("megatec-ups: {}", e);
+                }
+                thread::sleep(interval.saturating_sub(started.elapsed()));
+            }
+        }
+        #[cfg(not(feature = "otel"))]
+        Subcommand::Otel {
+            url,
+            attributes,
+            headers,
+            interval,
+        } => {
+            let _ = (attributes, headers, interval);
+            let message = format!(
+                "OpenTelemetry export to {} is not supported by this build",
+                url
+            );
+            return Err(message.into());
+        }
+        #[cfg(not(feature = "influxdb"))]
+        Subcommand::InfluxDb {
+            url,
+            target,
+            tags,
+            interval,
+        } => {
+            let _ = (tags, interval);
+            let destination = match target {
+                InfluxTarget::V1 {
+                    database,
+                    username,
+                    password,
+                } => {
+                    let _ = (username, password);
+                    database
+                }
+                InfluxTarget::V2 { org, bucket, token } => {
+                    let _ = token;
+                    format!("{}/{}", org, bucket)
+                }
+            };
+            let message = format!(
+                "InfluxDB export to {} at {} is not supported by this build",
+                destination, url
+            );
+            return Err(message.into());
+        }
+        #[cfg(feature = "nis")]
+        Subcommand::Nis { listen } => {
+            let ups_name = ups.get_name().unwrap_or_else(|_| "ups".to_string());
+            let mut server = megatec_ups_control::NisServer::bind(ups, listen.as_str())?;
+            server.set_ups_name(ups_name);
+            eprintln!(
+                "megatec-ups: apcupsd NIS server on {}",
+                server.local_addr()?
+            );
+            server.serve()?;
+        }
+        #[cfg(not(feature = "nis"))]
+        Subcommand::Nis { listen } => {
+            let message = format!(
+                "apcupsd NIS server on {} is not supported by this build",
+                listen
+            );
+            return Err(message.into());
+        }
+        #[cfg(feature = "rest")]
+        Subcommand::Serve {
+            listen,
+            token,
+            history_db,
+        } => {
+            let mut server = megatec_ups_control::RestServer::bind(ups, listen.as_str())?;
+            match token {
+                Some(token) => server.set_token(token),
+                None => eprintln!(
+                    "megatec-ups: no --token given, control and GraphQL endpoints are disabled"
+                ),
+            }
+            #[cfg(feature = "graphql")]
+            if let Some(path) = history_db {
+                let history = megatec_ups_control::SqliteHistory::open(path)?;
+                server.set_history(std::sync::Arc::new(history));
+            }
+            #[cfg(not(feature = "graphql"))]
+            if let Some(path) = history_db {
+                let message = format!(
+                    "GraphQL history queries on {} are not supported by this build",
+                    path.display()
+                );
+                return Err(message.into());
+            }
+            eprintln!(
+                "megatec-ups: serving the API on http://{}",
+                server.local_addr()?
+            );
+            server.serve()?;
+        }
+        #[cfg(not(feature = "rest"))]
+        Subcommand::Serve {
+            listen,
+            token,
+            history_db,
+        } => {
+            let _ = (token, history_db);
+            let message = format!("REST API on {} is not supported by this build", listen);
+            return Err(message.into());
+        }
+        #[cfg(feature = "websocket")]
+        Subcommand::WebSocket { listen, interval } => {
+            let monitor = Monitor::new(ups, interval);
+            let mut server = megatec_ups_control::WebSocketServer::bind(monitor, listen.as_str())?;
+            eprintln!(
+                "megatec-ups: streaming on ws://{}{}",
+                server.local_addr()?,
+                megatec_ups_control::WEBSOCKET_PATH
+            );
+            server.serve()?;
+        }
+        #[cfg(not(feature = "websocket"))]
+        Subcommand::WebSocket { listen, interval } => {
+            let _ = interval;
+            let message = format!(
+                "WebSocket server on {} is not supported by this build",
+                listen
+            );
+            return Err(message.into());
+        }
+        #[cfg(feature = "snmp")]
+        Subcommand::Snmp { listen, community } => {
+            let mut agent = megatec_ups_control::SnmpAgent::bind(ups, listen.as_str(), &community)?;
+            eprintln!("megatec-ups: SNMP agent on {}", agent.local_addr()?);
+            agent.serve()?;
+        }
+        #[cfg(not(feature = "snmp"))]
+        Subcommand::Snmp { listen, community } => {
+            let _ = community;
+            let message = format!("SNMP agent on {} is not supported by this build", listen);
+            return Err(message.into());
+        }
+        #[cfg(feature = "modbus")]
+        Subcommand::Modbus {
+            listen,
+            allow_writes,
+        } => {
+            let mut server = megatec_ups_control::ModbusServer::bind(ups, listen.as_str())?;
+            server.set_allow_writes(allow_writes);
+            eprintln!("megatec-ups: Modbus TCP server on {}", server.local_addr()?);
+            server.serve()?;
+        }
+        #[cfg(not(feature = "modbus"))]
+        Subcommand::Modbus {
+            listen,
+            allow_writes,
+        } => {
+            let _ = allow_writes;
+            let message = format!(
+                "Modbus TCP server on {} is not supported by this build",
+                listen
+            );
+            return Err(message.into());
+        }
+        #[cfg(feature = "grpc")]
+        Subcommand::Grpc {
+            listen,
+            token,
+            interval,
+        } => {
+            let monitor = Monitor::new(ups, interval);
+            let mut server = megatec_ups_control::GrpcServer::bind(monitor, listen.as_str())?;
+            if let Some(token) = token {
+                server.set_token(token);
+            }
+            eprintln!("megatec-ups: gRPC server on {}", server.local_addr()?);
+            server.serve()?;
+        }
+        #[cfg(not(feature = "grpc"))]
+        Subcommand::Grpc {
+            listen,
+            token,
+            interval,
+        } => {
+            let _ = (token, interval);
+            let message = format!("gRPC server on {} is not supported by this build", listen);
+            return Err(message.into());
+        }
+        #[cfg(feature = "fleet")]
+        Subcommand::Agent {
+            url,
+            name,
+            token,
+            interval,
+        } => {
+            let monitor = Monitor::new(ups, interval);
+            let mut agent = megatec_ups_control::FleetAgent::new(monitor, &url, &name)?;
+            if let Some(token) = token {
+                agent.set_token(token);
+            }
+            eprintln!("megatec-ups: reporting to {} as {}", url, name);
+            agent.serve()?;
+        }
+        #[cfg(not(feature = "fleet"))]
+        Subcommand::Agent {
+            url,
+            name,
+            token,
+            interval,
+        } => {
+            let _ = (name, token, interval);
+            let message = format!("fleet agent for {} is not supported by this build", url);
+            return Err(message.into());
+        }
+        Subcommand::Service { .. } | Subcommand::Daemon { .. } | Subcommand::FleetServer { .. } => {
+            unreachable!("services, daemons and fleet servers connect by themselves")
+        }
+        #[cfg(all(feature = "nut", unix))]
+        Subcommand::NutDriver { socket, interval } => {
+            let mut driver = megatec_ups_control::NutDriver::bind(ups, socket, interval)?;
+            driver.serve()?;
+        }
+        #[cfg(not(all(feature = "nut", unix)))]
+        Subcommand::NutDriver { socket, interval } => {
+            let _ = interval;
+            let message = format!(
+                "NUT driver socket {} is not supported by this build",
+                socket.display()
+            );
+            return Err(message.into());
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Poll the UPS, print its status, run hooks and apply the power policy until `running` is cleared
+fn watch(ups: Ups, options: WatchOptions, running: &AtomicBool) -> Result<(), Box<dyn Error>> {
+    let WatchOptions {
+        interval,
+        format,
+        power,
+        hooks,
+        stages,
+        stage_restores,
+        alarms,
+        max_temperature,
+        max_load,
+        self_tests,
+        email,
+        webhooks,
+        desktop,
+        history,
+        history_db,
+    } = options;
+
+    let ups_name = ups.get_name().unwrap_or_else(|_| "ups".to_string());
+    let mut hook_runner = Hooks::new(ups_name.as_str());
+    for (event, command) in &hooks {
+        hook_runner.add(Hook::shell(event, command));
+    }
+
+    let mut monitor = Monitor::new(ups, interval);
+    if !self_tests.is_empty() {
+        let mut scheduler = TestScheduler::new();
+        for test in self_tests {
+            scheduler.add(test.schedule, test.kind);
+        }
+        monitor.set_test_scheduler(scheduler);
+    }
+    if let Some(limit) = max_temperature {
+        monitor.set_temperature_alarm(Threshold::over_temperature(limit));
+    }
+    if let Some(limit) = max_load {
+        monitor.set_overload_alarm(Threshold::overload(limit));
+    }
+    monitor.on_event(move |event| println!("{}", format::monitor_event(event, format)));
+    monitor.on_transition(move |transition| {
+        println!("{}", format::monitor_transition(transition, format))
+    });
+    if !hook_runner.is_empty() {
+        monitor.on_event(move |event| hook_runner.run(event));
+    }
+    if let Some(email) = email {
+        monitor.on_event(email_notifier(email, &ups_name)?);
+    }
+    if !webhooks.is_empty() {
+        monitor.on_event(webhook_notifier(&webhooks, &ups_name)?);
+    }
+    if desktop {
+        monitor.on_event(desktop_notifier()?);
+    }
+    let mut plan = LoadShedding::new(ups_name.as_str());
+    for &(after, _) in &stages {
+        if plan.stages().iter().any(|stage| stage.after == after) {
+            continue;
+        }
+        let mut stage = Stage::new(after);
+        for (_, command) in stages.iter().filter(|(time, _)| *time == after) {
+            stage = stage.with_hook(Hook::shell("*", command));
+        }
+        for (_, command) in stage_restores.iter().filter(|(time, _)| *time == after) {
+            stage = stage.with_restore_hook(Hook::shell("*", command));
+        }
+        plan.add_stage(stage);
+    }
+    if let Some(power) = power {
+        plan.set_shutdown(power);
+    }
+    if !plan.is_empty() {
+        monitor.on_load_shedding(move |step| match step {
+            SheddingStep::Stage(_) | SheddingStep::Restored(_) => {
+                println!("{}", format::monitor_shedding(step, format))
+            }
+            _ => eprintln!("megatec-ups: {}", step),
+        });
+        monitor.set_load_shedding(plan);
+    }
+    let mut alarm_set = Alarms::new();
+    for threshold in alarms {
+        alarm_set.add(threshold);
+    }
+    let mut record_sample = match history_db {
+        Some(path) => Some(history_database(&path, &mut monitor)?),
+        None => None,
+    };
+    let mut history = match history {
+        Some(options) => {
+            let mut writer = HistoryWriter::open(options.path, options.format)?;
+            writer.set_rotation(options.rotation);
+            Some(writer)
+        }
+        None => None,
+    };
+
+    #[cfg(all(feature = "systemd", target_os = "linux"))]
+    let mut watchdog = {
+        use megatec_ups_control::systemd;
+        if let Ok(journal) = systemd::Journal::open("megatec-ups") {
+            monitor.on_event(move |event| {
+                let _ = journal.log_event(event);
+            });
+        }
+        systemd::notify_ready()?;
+        systemd::Watchdog::from_env()
+    };
+
+    while running.load(Ordering::Relaxed) {
+        let started = Instant::now();
+        match monitor.poll() {
+            Ok(status) => {
+                println!("{}", format::monitor_status(&status, format));
+                for alarm in alarm_set.update(&status) {
+                    println!("{}", format::monitor_alarm(&alarm, format));
+                }
+                if let Some(record_sample) = &mut record_sample {
+                    record_sample(&status);
+                }
+                if let Some(history) = &mut history {
+                    if let Err(e) = history.record(&status) {
+                        eprintln!(
+                            "megatec-ups: cannot write {}: {}",
+                            history.path().display(),
+                            e
+                        );
+                    }
+                }
+                #[cfg(all(feature = "systemd", target_os = "linux"))]
+                {
+                    let _ = megatec_ups_control::systemd::notify_status(&status.to_string());
+                    watchdog.poll_succeeded()?;
+                }
+            }
+            Err(e) => eprintln!("megatec-ups: {}", e),
+        }
+
+        pause(interval.saturating_sub(started.elapsed()), running);
+    }
+
+    Ok(())
+}
+
+/// Sleep for `duration`, or less when `running` is cleared meanwhile
+fn pause(duration: Duration, running: &AtomicBool) {
+    let started = Instant::now();
+    while running.load(Ordering::Relaxed) {
+        let remaining = duration.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            break;
+        }
+        thread::sleep(remaining.min(STOP_CHECK_INTERVAL));
+    }
+}
+
+/// Event callback mailing the events selected on the command line
+#[cfg(feature = "email")]
+fn email_notifier(
+    email: EmailOptions,
+    ups_name: &str,
+) -> Result<impl FnMut(&UpsEvent) + Send + 'static, Box<dyn Error>> {
+    let config = megatec_ups_control::EmailConfig {
+        server: email.server,
+        throttle: email.throttle,
+        ..megatec_ups_control::EmailConfig::new(email.from, email.to)
+    };
+    config.validate()?;
+    let mut notifier = megatec_ups_control::EmailNotifier::new(config, ups_name);
+    Ok(move |event: &UpsEvent| {
+        if let Err(e) = notifier.notify(event) {
+            eprintln!("megatec-ups: cannot send email: {}", e);
+        }
+    })
+}
+
+/// Event callback mailing the events selected on the command line
+#[cfg(not(feature = "email"))]
+fn email_notifier(email: EmailOptions, ups_name: &str) -> Result<fn(&UpsEvent), Box<dyn Error>> {
+    let EmailOptions {
+        server,
+        from,
+        to,
+        throttle,
+    } = email;
+    let _ = (ups_name, server, from, throttle);
+    let message = format!(
+        "email notifications to {} are not supported by this build",
+        to.join(", ")
+    );
+    Err(message.into())
+}
+
+/// Event callback posting events to the webhooks given on the command line
+#[cfg(feature = "webhook")]
+fn webhook_notifier(
+    webhooks: &[Webhook],
+    ups_name: &str,
+) -> Result<impl FnMut(&UpsEvent) + Send + 'static, Box<dyn Error>> {
+    let mut notifier = megatec_ups_control::WebhookNotifier::new(ups_name);
+    for webhook in webhooks {
+        match &webhook.relay {
+            Some(relay) => notifier.add_relayed_url(&webhook.url, relay)?,
+            None => notifier.add_url(&webhook.url)?,
+        }
+    }
+    Ok(move |event: &UpsEvent| {
+        let delivery = notifier.notify_in_background(event);
+        thread::spawn(move || {
+            if let Ok(Err(e)) = delivery.join() {
+                eprintln!("megatec-ups: cannot post webhook: {}", e);
+            }
+        });
+    })
+}
+
+/// Event callback posting events to the webhooks given on the command line
+#[cfg(not(feature = "webhook"))]
+fn webhook_notifier(webhooks: &[Webhook], ups_name: &str) -> Result<fn(&UpsEvent), Box<dyn Error>> {
+    let _ = ups_name;
+    let urls: Vec<&str> = webhooks
+        .iter()
+        .map(|webhook| webhook.url.as_str())
+        .collect();
+    let message = format!("webhook {} is not supported by this build", urls.join(", "));
+    Err(message.into())
+}
+
+/// Open the SQLite history, record monitor events in it and get the sample recorder
+#[cfg(feature = "sqlite")]
+fn history_database(
+    path: &Path,
+    monitor: &mut Monitor<Box<dyn Transport + Send>>,
+) -> Result<SampleRecorder, Box<dyn Error>> {
+    let history = std::sync::Arc::new(megatec_ups_control::SqliteHistory::open(path)?);
+    monitor.set_stats(history.outage_stats()?);
+
+    let events = std::sync::Arc::clone(&history);
+    monitor.on_event(move |event| {
+        if let Err(e) = events.record_event(event) {
+            eprintln!("megatec-ups: cannot store event: {}", e);
+        }
+    });
+
+    Ok(Box::new(move |status| {
+        if let Err(e) = history.record(status) {
+            eprintln!("megatec-ups: cannot store sample: {}", e);
+        }
+    }))
+}
+
+/// Open the SQLite history, record monitor events in it and get the sample recorder
+#[cfg(not(feature = "sqlite"))]
+fn history_database(
+    path: &Path,
+    monitor: &mut Monitor<Box<dyn Transport + Send>>,
+) -> Result<SampleRecorder, Box<dyn Error>> {
+    let _ = monitor;
+    let message = format!(
+        "SQLite history {} is not supported by this build",
+        path.display()
+    );
+    Err(message.into())
+}
+
+/// Event callback showing desktop notifications
+#[cfg(feature = "desktop")]
+fn desktop_notifier() -> Result<impl FnMut(&UpsEvent) + Send + 'static, Box<dyn Error>> {
+    let notifier = megatec_ups_control::DesktopNotifier::new("megatec-ups");
+    Ok(move |event: &UpsEvent| {
+        if let Err(e) = notifier.notify(event) {
+            eprintln!("megatec-ups: cannot show notification: {}", e);
+        }
+    })
+}
+
+/// Event callback showing desktop notifications
+#[cfg(not(feature = "desktop"))]
+fn desktop_notifier() -> Result<fn(&UpsEvent), Box<dyn Error>> {
+    Err("desktop notifications are not supported by this build".into())
+}
+
+/// Collect the reports of fleet agents, without a local UPS
+#[cfg(feature = "fleet")]
+fn fleet_server(
+    listen: &str,
+    token: Option<String>,
+    history_dir: Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let mut server = megatec_ups_control::FleetServer::bind(listen)?;
+    if let Some(token) = token {
+        server.set_token(token);
+    }
+    if let Some(dir) = history_dir {
+        std::fs::create_dir_all(&dir)?;
+        server.set_history_dir(dir);
+    }
+    eprintln!(
+        "megatec-ups: fleet server on http://{}",
+        server.local_addr()?
+    );
+    server.serve()?;
+    Ok(())
+}
+
+/// Collect the reports of fleet agents, without a local UPS
+#[cfg(not(feature = "fleet"))]
+fn fleet_server(
+    listen: &str,
+    token: Option<String>,
+    history_dir: Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let _ = (token, history_dir);
+    let message = format!("fleet server on {} is not supported by this build", listen);
+    Err(message.into())
+}
+
+/// Manage or run the Windows service
+#[cfg(all(feature = "windows-service", windows))]
+fn service(
+    device: DeviceSelector,
+    action: ServiceAction,
+    name: &str,
+    watch_options: WatchOptions,
+) -> Result<(), Box<dyn Error>> {
+    use megatec_ups_control::{windows_service, HistoryFormat, Limit};
+
+    match action {
+        ServiceAction::Install => {
+            let device = match &device {
+                DeviceSelector::Auto => String::new(),
+                DeviceSelector::Usb {
+                    vendor_id,
+                    product_id,
+                } => format!("--vid {:04x} --pid {:04x} ", vendor_id, product_id),
+                DeviceSelector::Serial(path) => format!("--serial \"{}\" ", path.display()),
+                DeviceSelector::UsbIp { address, busid } => {
+                    format!("--usbip \"{}/{}\" ", address, busid)
+                }
+                DeviceSelector::TcpSerial { address, rfc2217 } => {
+                    let option = if *rfc2217 { "--rfc2217" } else { "--tcp" };
+                    format!("{} \"{}\" ", option, address)
+                }
+            };
+            let mut power_options = String::new();
+            if let Some(power) = &watch_options.power {
+                power_options.push_str("--os-shutdown ");
+                if let Some(after) = power.on_battery_for {
+                    power_options.push_str(&format!(
+                        "--os-shutdown-after {} ",
+                        after.as_secs_f64() / 60.0
+                    ));
+                }
+                if let Some(ups_shutdown) = power.ups_shutdown {
+                    power_options
+                        .push_str(&format!("--ups-restore {} ", ups_shutdown.restore_minutes));
+                }
+            }
+            for (event, command) in &watch_options.hooks {
+                let hook = format!("{}={}", event, command).replace('"', "\\\"");
+                power_options.push_str(&format!("--hook \"{}\" ", hook));
+            }
+            for (option, stages) in [
+                ("--stage", &watch_options.stages),
+                ("--stage-restore", &watch_options.stage_restores),
+            ] {
+                for (after, command) in stages {
+                    let stage = format!("{}={}", after.as_secs_f64() / 60.0, command);
+                    power_options.push_str(&format!(
+                        "{} \"{}\" ",
+                        option,
+                        stage.replace('"', "\\\"")
+                    ));
+                }
+            }
+            for threshold in &watch_options.alarms {
+                let operator = match threshold.limit {
+                    Limit::Above(limit) => format!(">{}", limit),
+                    Limit::Below(limit) => format!("<{}", limit),
+                };
+                power_options.push_str(&format!(
+                    "--alarm \"{}{},hysteresis={},for={}\" ",
+                    threshold.quantity.name(),
+                    operator,
+                    threshold.hysteresis,
+                    threshold.min_duration.as_secs_f64()
+                ));
+            }
+            if let Some(limit) = watch_options.max_temperature {
+                power_options.push_str(&format!("--max-temperature {} ", limit));
+            }
+            if let Some(limit) = watch_options.max_load {
+                power_options.push_str(&format!("--max-load {} ", limit));
+            }
+            for test in &watch_options.self_tests {
+                power_options
+                    .push_str(&format!("--self-test \"{}@{}\" ", test.kind, test.schedule));
+            }
+            if let Some(email) = &watch_options.email {
+                for to in &email.to {
+                    power_options.push_str(&format!("--email-to \"{}\" ", to));
+                }
+                power_options.push_str(&format!(
+                    "--email-from \"{}\" --smtp-server \"{}\" --email-throttle {} ",
+                    email.from,
+                    email.server,
+                    email.throttle.as_secs_f64() / 60.0
+                ));
+            }
+            for webhook in &watch_options.webhooks {
+                power_options.push_str(&format!("--webhook \"{}\" ", webhook.url));
+                if let Some(relay) = &webhook.relay {
+                    power_options.push_str(&format!("--webhook-relay \"{}\" ", relay));
+                }
+            }
+            if let Some(path) = &watch_options.history_db {
+                power_options.push_str(&format!("--history-db \"{}\" ", path.display()));
+            }
+            if let Some(history) = &watch_options.history {
+                let format = match history.format {
+                    HistoryFormat::Csv => "csv",
+                    HistoryFormat::JsonLines => "jsonl",
+                };
+                power_options.push_str(&format!(
+                    "--history \"{}\" --history-format {} --history-keep {} ",
+                    history.path.display(),
+                    format,
+                    history.rotation.keep
+                ));
+                if let Some(max_size) = history.rotation.max_size {
+                    power_options.push_str(&format!(
+                        "--history-max-size {} ",
+                        max_size as f64 / 1024.0 / 1024.0
+                    ));
+                }
+                if let Some(max_age) = history.rotation.max_age {
+                    power_options.push_str(&format!(
+                        "--history-max-age {} ",
+                        max_age.as_secs_f64() / 3600.0
+                    ));
+                }
+            }
+            let command_line = format!(
+                "{}{}--interval {} service run --name \"{}\"",
+                device,
+                power_options,
+                watch_options.interval.as_secs_f64(),
+                name
+            );
+            windows_service::install(name, "Megatec UPS monitor", &command_line)?;
+        }
+        ServiceAction::Uninstall => windows_service::uninstall(name)?,
+        ServiceAction::Start => windows_service::start(name)?,
+        ServiceAction::Stop => windows_service::stop(name)?,
+        ServiceAction::Run => windows_service::run(name, move |running| {
+            if let Ok(ups) = connect(device) {
+                let _ = watch(ups, watch_options, running);
+            }
+        })?,
+    }
+
+    Ok(())
+}
+
+/// Manage or run the Windows service
+#[cfg(not(all(feature = "windows-service", windows)))]
+fn service(
+    device: DeviceSelector,
+    action: ServiceAction,
+    name: &str,
+    watch_options: WatchOptions,
+) -> Result<(), Box<dyn Error>> {
+    let _ = (device, action, watch_options);
+    let message = format!("Windows service {} is not supported by this build", name);
+    Err(message.into())
+}
+
+/// Run the monitor as a daemon until SIGTERM or SIGINT, waiting for the UPS if it is missing
+#[cfg(all(feature = "daemon", unix))]
+fn daemon(
+    device: DeviceSelector,
+    foreground: bool,
+    pid_file: Option<PathBuf>,
+    log_file: Option<PathBuf>,
+    watch_options: WatchOptions,
+) -> Result<(), Box<dyn Error>> {
+    use megatec_ups_control::daemon::{self, LogFile, PidFile};
+
+    // Both files are checked while errors still reach the terminal
+    let log = log_file.map(LogFile::open).transpose()?;
+    let pid_file = pid_file.map(PidFile::create).transpose()?;
+    if !foreground {
+        daemon::detach()?;
+        if let Some(pid_file) = &pid_file {
+            pid_file.update()?;
+        }
+    }
+    if let Some(log) = &log {
+        log.redirect()?;
+    }
+
+    let running = daemon::handle_signals()?;
+    if let Some(mut log) = log {
+        thread::spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                if daemon::take_hangup() {
+                    if let Err(e) = log.reopen() {
+                        eprintln!("megatec-ups: cannot reopen {}: {}", log.path().display(), e);
+                    }
+                }
+                thread::sleep(STOP_CHECK_INTERVAL);
+            }
+        });
+    }
+    eprintln!("megatec-ups: daemon started, pid {}", std::process::id());
+
+    while running.load(Ordering::Relaxed) {
+        match connect(device.clone()) {
+            Ok(ups) => {
+                watch(ups, watch_options, running)?;
+                break;
+            }
+            Err(e) => {
+                eprintln!("megatec-ups: {}, retrying", e);
+                pause(watch_options.interval, running);
+            }
+        }
+    }
+    eprintln!("megatec-ups: daemon stopped");
+    Ok(())
+}
+
+/// Run the monitor as a daemon until SIGTERM or SIGINT, waiting for the UPS if it is missing
+#[cfg(not(all(feature = "daemon", unix)))]
+fn daemon(
+    device: DeviceSelector,
+    foreground: bool,
+    pid_file: Option<PathBuf>,
+    log_file: Option<PathBuf>,
+    watch_options: WatchOptions,
+) -> Result<(), Box<dyn Error>> {
+    let _ = (device, foreground, pid_file, log_file, watch_options);
+    Err("daemon mode is not supported by this build".into())
+}
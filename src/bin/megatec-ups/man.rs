@@ -0,0 +1,91 @@
+//! Man page generated from the help text
+
+use crate::args::{self, OptionHelp};
+use std::fmt::Write as _;
+
+/// Man page in roff, for `man -l -` or installing as megatec-ups.1
+pub fn page() -> String {
+    let help = args::help();
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        ".TH MEGATEC-UPS 1 \"\" \"megatec-ups {}\" \"User Commands\"",
+        env!("CARGO_PKG_VERSION")
+    );
+    let _ = writeln!(
+        out,
+        ".SH NAME\nmegatec-ups \\- monitor and control a Megatec protocol UPS"
+    );
+    let _ = writeln!(
+        out,
+        ".SH SYNOPSIS\n.B megatec-ups\n[\\fIOPTIONS\\fR] \\fICOMMAND\\fR"
+    );
+    let _ = writeln!(
+        out,
+        ".SH DESCRIPTION\n{}",
+        escape(
+            "megatec-ups reads the status of a UPS speaking the Megatec protocol over USB or \
+             a serial port, runs its battery tests and shutdowns and monitors it for power events."
+        )
+    );
+
+    let (exit_codes, notes): (Vec<&String>, Vec<&String>) = help
+        .notes
+        .iter()
+        .partition(|note| note.starts_with("Exit codes:"));
+    for note in notes {
+        let _ = writeln!(out, ".PP\n{}", escape(note));
+    }
+
+    let _ = writeln!(out, ".SH COMMANDS");
+    for command in &help.commands {
+        let _ = write!(out, ".TP\n\\fB{}\\fR", escape(command.name));
+        if let Some(argument) = command.argument {
+            let _ = write!(out, " \\fI{}\\fR", escape(argument));
+        }
+        let _ = writeln!(out, "\n{}", escape(&command.description));
+        if !command.options.is_empty() {
+            let _ = writeln!(out, ".RS");
+            options(&mut out, &command.options);
+            let _ = writeln!(out, ".RE");
+        }
+    }
+
+    let _ = writeln!(out, ".SH OPTIONS");
+    options(&mut out, &help.options);
+
+    if let Some(exit_codes) = exit_codes.first() {
+        let text = exit_codes.trim_start_matches("Exit codes:").trim();
+        let _ = writeln!(out, ".SH EXIT STATUS\n{}", escape(text));
+    }
+    out
+}
+
+fn options(out: &mut String, options: &[OptionHelp]) {
+    for option in options {
+        let flags: Vec<String> = option
+            .flags
+            .iter()
+            .map(|flag| match flag.value {
+                Some(value) => format!("\\fB{}\\fR \\fI{}\\fR", escape(flag.name), escape(value)),
+                None => format!("\\fB{}\\fR", escape(flag.name)),
+            })
+            .collect();
+        let _ = writeln!(
+            out,
+            ".TP\n{}\n{}",
+            flags.join(", "),
+            escape(&option.description)
+        );
+    }
+}
+
+/// Escape text for roff, so dashes stay hyphens and no line starts a request
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with(['.', '\'']) {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
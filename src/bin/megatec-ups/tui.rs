@@ -0,0 +1,217 @@
+//! Full-screen terminal dashboard drawn with ANSI escape sequences
+
+use crate::Ups;
+use megatec_ups_control::{BatteryModel, Monitor, Quantity, RatingInfo, Transport, UpsStatus};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Columns used when the terminal does not report its width through COLUMNS
+const DEFAULT_WIDTH: usize = 80;
+
+/// Characters of a gauge bar
+const GAUGE_WIDTH: usize = 24;
+
+/// Events kept in the log pane
+const EVENT_LOG_LINES: usize = 8;
+
+/// Levels of a sparkline, from the lowest reading to the highest
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Move the cursor home and clear the screen
+const CLEAR: &str = "\x1b[H\x1b[2J";
+
+/// Poll the UPS every `interval` and redraw the dashboard until interrupted
+pub fn run(ups: Ups, interval: Duration) -> Result<(), Box<dyn Error>> {
+    let name = ups.get_name().unwrap_or_else(|_| "ups".to_string());
+    let rating = ups.get_rating().ok();
+
+    let mut monitor = Monitor::new(ups, interval);
+    let events = monitor.subscribe();
+    let mut log = VecDeque::with_capacity(EVENT_LOG_LINES);
+    let mut stdout = io::stdout();
+
+    loop {
+        let started = Instant::now();
+        let reading = monitor.poll();
+        for event in events.try_iter() {
+            if log.len() == EVENT_LOG_LINES {
+                log.pop_front();
+            }
+            log.push_back(format!("{} {}", clock(SystemTime::now()), event));
+        }
+
+        let width = std::env::var("COLUMNS")
+            .ok()
+            .and_then(|columns| columns.parse().ok())
+            .unwrap_or(DEFAULT_WIDTH);
+        let mut frame = String::from(CLEAR);
+        let _ = writeln!(
+            frame,
+            "megatec-ups: {}    {} UTC    every {} s",
+            name,
+            clock(SystemTime::now()),
+            interval.as_secs_f64()
+        );
+        let state = monitor
+            .current_state()
+            .map_or_else(|| "unknown".to_string(), |state| state.to_string());
+        match &reading {
+            Ok(status) => {
+                let _ = writeln!(frame, "State: {}    Flags: {}\n", state, status.flags);
+                draw_gauges(&mut frame, &monitor, status, rating.as_ref(), width);
+            }
+            Err(e) => {
+                let _ = writeln!(frame, "State: {}    Error: {}\n", state, e);
+            }
+        }
+        let _ = writeln!(frame, "\nEvents");
+        if log.is_empty() {
+            let _ = writeln!(frame, "  none yet");
+        }
+        for line in &log {
+            let _ = writeln!(frame, "  {}", line);
+        }
+        let _ = writeln!(frame, "\nPress Ctrl-C to quit");
+
+        stdout.write_all(frame.as_bytes())?;
+        stdout.flush()?;
+        thread::sleep(interval.saturating_sub(started.elapsed()));
+    }
+}
+
+/// Draw one gauge line with its sparkline per measured value
+fn draw_gauges(
+    frame: &mut String,
+    monitor: &Monitor<Box<dyn Transport + Send>>,
+    status: &UpsStatus,
+    rating: Option<&RatingInfo>,
+    width: usize,
+) {
+    let rated_voltage = rating
+        .map(|rating| rating.rated_voltage)
+        .filter(|voltage| *voltage > 0.0)
+        .unwrap_or(230.0);
+    let charge =
+        rating.and_then(|rating| BatteryModel::from_rating(rating).state_of_charge(status));
+    let battery = match charge {
+        Some(charge) => format!("{:.2} V {:>3.0}%", status.battery_voltage, charge * 100.0),
+        None => format!("{:.2} V", status.battery_voltage),
+    };
+
+    let rows = [
+        (
+            Quantity::InputVoltage,
+            format!("{:.1} V", status.input_voltage),
+            status.input_voltage / (rated_voltage * 1.25),
+        ),
+        (
+            Quantity::OutputVoltage,
+            format!("{:.1} V", status.output_voltage),
+            status.output_voltage / (rated_voltage * 1.25),
+        ),
+        (
+            Quantity::Load,
+            format!("{:.0}%", status.load_raw),
+            status.load_raw / 100.0,
+        ),
+        (
+            Quantity::BatteryVoltage,
+            battery,
+            charge.unwrap_or(f64::NAN),
+        ),
+        (
+            Quantity::Temperature,
+            format!("{:.1} °C", status.temperature),
+            status.temperature / 60.0,
+        ),
+        (
+            Quantity::InputFrequency,
+            format!("{:.1} Hz", status.input_frequency),
+            f64::NAN,
+        ),
+    ];
+
+    // Label, value and gauge take 61 columns, the sparkline gets the rest
+    let spark_width = width.saturating_sub(GAUGE_WIDTH + 37).max(8);
+    let recent = monitor.recent(spark_width);
+    for (quantity, value, fraction) in rows {
+        let history: Vec<f64> = recent
+            .iter()
+            .map(|(_, status)| quantity.value(status))
+            .collect();
+        let _ = writeln!(
+            frame,
+            "{:<16} {:>14}  {}  {}",
+            label(quantity),
+            value,
+            gauge(fraction),
+            sparkline(&history)
+        );
+    }
+}
+
+fn label(quantity: Quantity) -> &'static str {
+    match quantity {
+        Quantity::InputVoltage => "Input voltage",
+        Quantity::OutputVoltage => "Output voltage",
+        Quantity::Load => "Load",
+        Quantity::InputFrequency => "Frequency",
+        Quantity::BatteryVoltage => "Battery",
+        Quantity::Temperature => "Temperature",
+    }
+}
+
+/// Bar filled to `fraction` of its width, blank when unknown
+fn gauge(fraction: f64) -> String {
+    if fraction.is_nan() {
+        return " ".repeat(GAUGE_WIDTH + 2);
+    }
+    let filled = (fraction.clamp(0.0, 1.0) * GAUGE_WIDTH as f64).round() as usize;
+    format!(
+        "[{}{}]",
+        "█".repeat(filled),
+        "░".repeat(GAUGE_WIDTH - filled)
+    )
+}
+
+/// Spark characters scaled between the lowest and highest value, blank for missing values
+fn sparkline(values: &[f64]) -> String {
+    let (min, max) = values
+        .iter()
+        .filter(|value| !value.is_nan())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+            (min.min(*value), max.max(*value))
+        });
+    values
+        .iter()
+        .map(|value| {
+            if value.is_nan() {
+                ' '
+            } else if max > min {
+                let level = (value - min) / (max - min) * (SPARKS.len() - 1) as f64;
+                SPARKS[level.round() as usize]
+            } else {
+                SPARKS[SPARKS.len() / 2]
+            }
+        })
+        .collect()
+}
+
+/// Time of day in UTC as HH:MM:SS
+fn clock(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        % 86_400;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
@@ -0,0 +1,179 @@
+use crate::dialect::Dialect;
+use crate::discovery::KNOWN_DEVICES;
+use crate::error::{Result, UpsError};
+use crate::quirks::Quirks;
+use crate::retry::RetryPolicy;
+use crate::transport::{Command, Transport};
+use crate::usb::UsbTransport;
+use crate::MegatecUps;
+use std::time::Duration;
+
+/// Builder used to configure a MegatecUps connection
+#[derive(Debug, Clone)]
+pub struct MegatecUpsBuilder {
+    device: Option<(u16, u16)>,
+    timeout: Duration,
+    retry_policy: RetryPolicy,
+    ack_delay: Duration,
+    status_cache_ttl: Duration,
+    quirks: Option<Quirks>,
+    claim_interface: bool,
+    strict_parsing: bool,
+    dialect: Option<Dialect>,
+}
+
+impl MegatecUpsBuilder {
+    /// Create a builder with the default settings
+    pub fn new() -> Self {
+        Self {
+            device: None,
+            timeout: Duration::from_secs(1),
+            retry_policy: RetryPolicy::default(),
+            ack_delay: Duration::from_secs(1),
+            status_cache_ttl: Duration::ZERO,
+            quirks: None,
+            claim_interface: false,
+            strict_parsing: false,
+            dialect: None,
+        }
+    }
+
+    /// Connect to the device with the given vendor_id and product_id instead of auto-detecting it
+    pub fn device(mut self, vendor_id: u16, product_id: u16) -> Self {
+        self.device = Some((vendor_id, product_id));
+        self
+    }
+
+    /// Set the USB control transfer timeout
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the number of times a failed transaction is retried
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retry_policy.retries = retries;
+        self
+    }
+
+    /// Set the policy used to retry failed transactions
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Set the delay between the acknowledgment and the actual status read
+    pub fn ack_delay(mut self, ack_delay: Duration) -> Self {
+        self.ack_delay = ack_delay;
+        self
+    }
+
+    /// Serve status readings younger than `ttl` from a cache, disabled by default
+    pub fn status_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.status_cache_ttl = ttl;
+        self
+    }
+
+    /// Use the given quirks instead of looking them up in the registry
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    /// Reject status answers deviating from the protocol instead of tolerating them
+    pub fn strict_parsing(mut self, strict: bool) -> Self {
+        self.strict_parsing = strict;
+        self
+    }
+
+    /// Use the given status query instead of probing the device for it
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = Some(dialect);
+        self
+    }
+
+    /// Detach the kernel driver and claim the interface when opening the device
+    ///
+    /// See [`UsbTransport::claim_interface`].
+    pub fn claim_interface(mut self, claim_interface: bool) -> Self {
+        self.claim_interface = claim_interface;
+        self
+    }
+
+    /// Open the configured USB device and apply the quirks registered for it
+    pub fn build(self) -> Result<MegatecUps<UsbTransport>> {
+        let mut transport = match self.device {
+            Some((vendor_id, product_id)) => self.open(vendor_id, product_id)?,
+            None => self.find_known_device()?,
+        };
+
+        let quirks = match self.quirks {
+            Some(quirks) => quirks,
+            None => {
+                let model = transport.send_command(Command::Name).ok();
+                Quirks::lookup(
+                    transport.vendor_id(),
+                    transport.product_id(),
+                    model.as_ref().map(|response| response.text.as_str()),
+                )
+            }
+        };
+        transport.set_quirks(quirks);
+
+        let detect = self.dialect.is_none();
+        let mut ups = self.build_with_transport(transport);
+        ups.set_quirks(quirks);
+        if detect {
+            // A silent device is reported by the first status query instead
+            let _ = ups.detect_dialect();
+        }
+        Ok(ups)
+    }
+
+    /// Apply the settings to a connection over an existing transport
+    pub fn build_with_transport<T: Transport>(self, transport: T) -> MegatecUps<T> {
+        let mut ups = MegatecUps::with_transport(transport);
+        ups.set_retry_policy(self.retry_policy);
+        ups.set_ack_delay(self.ack_delay);
+        ups.set_status_cache_ttl(self.status_cache_ttl);
+        ups.set_strict_parsing(self.strict_parsing);
+        if let Some(quirks) = self.quirks {
+            ups.set_quirks(quirks);
+        }
+        if let Some(dialect) = self.dialect {
+            ups.set_dialect(dialect);
+        }
+        ups
+    }
+
+    /// Open a USB device, claiming its interface if configured
+    fn open(&self, vendor_id: u16, product_id: u16) -> Result<UsbTransport> {
+        let mut transport = UsbTransport::open_with_timeout(vendor_id, product_id, self.timeout)?;
+        if self.claim_interface {
+            transport.claim_interface()?;
+        }
+        Ok(transport)
+    }
+
+    /// Open the first well-known Megatec device that answers a status query
+    fn find_known_device(&self) -> Result<UsbTransport> {
+        for known in KNOWN_DEVICES {
+            let transport = match self.open(known.vendor_id, known.product_id) {
+                Ok(transport) => transport,
+                Err(_) => continue,
+            };
+
+            if transport.send_command(Command::Status).is_ok() {
+                return Ok(transport);
+            }
+        }
+
+        Err(UpsError::DeviceNotFound)
+    }
+}
+
+impl Default for MegatecUpsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
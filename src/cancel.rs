@@ -0,0 +1,65 @@
+use crate::error::{Result, UpsError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Shared flag that interrupts waits and long-running operations
+///
+/// Clones share the same flag: hand one to a [`MegatecUps`](crate::MegatecUps)
+/// or a [`Monitor`](crate::Monitor) and call [`cancel`](Self::cancel) on
+/// another to stop it right away instead of after the acknowledgment delay,
+/// a retry backoff or a polling interval. Cancelling cannot be undone.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    shared: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl CancellationToken {
+    /// Create a token that is not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the operations watching this token and wake their waits
+    pub fn cancel(&self) {
+        let (cancelled, wakeup) = &*self.shared;
+        *lock(cancelled) = true;
+        wakeup.notify_all();
+    }
+
+    /// Check if the token was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        *lock(&self.shared.0)
+    }
+
+    /// Return [`UpsError::Cancelled`] if the token was cancelled
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(UpsError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sleep for `duration`, returning [`UpsError::Cancelled`] as soon as the token is cancelled
+    pub fn sleep(&self, duration: Duration) -> Result<()> {
+        let (cancelled, wakeup) = &*self.shared;
+        let deadline = Instant::now() + duration;
+        let mut guard = lock(cancelled);
+        while !*guard {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(());
+            }
+            guard = wakeup
+                .wait_timeout(guard, remaining)
+                .map(|(guard, _)| guard)
+                .unwrap_or_else(|poisoned| poisoned.into_inner().0);
+        }
+        Err(UpsError::Cancelled)
+    }
+}
+
+/// Lock the flag, recovering from a poisoned mutex
+fn lock(flag: &Mutex<bool>) -> MutexGuard<'_, bool> {
+    flag.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
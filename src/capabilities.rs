@@ -0,0 +1,63 @@
+use std::ops::{BitOr, BitOrAssign};
+
+/// Set of features supported by a UPS device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// UPS name query
+    pub const NAME: Self = Self(1 << 0);
+    /// Status query (Q1)
+    pub const STATUS: Self = Self(1 << 1);
+    /// Rating information query (F)
+    pub const RATING: Self = Self(1 << 2);
+    /// Manufacturer information query (I)
+    pub const INFO: Self = Self(1 << 3);
+    /// Battery test commands (T, TL, T<n>, CT)
+    pub const TEST: Self = Self(1 << 4);
+    /// Beeper toggle (Q)
+    pub const BEEPER: Self = Self(1 << 5);
+    /// Voltronic/Axpert extended status query (QPIGS)
+    pub const EXTENDED_STATUS: Self = Self(1 << 6);
+    /// Voltronic operating mode query (QMOD)
+    pub const MODE: Self = Self(1 << 7);
+
+    /// Create an empty set
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Get the raw bits of the set
+    pub const fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// Check if every capability of `other` is in the set
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Check if the set is empty
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Add the capabilities of `other` to the set
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Capabilities {
+    fn bitor_assign(&mut self, other: Self) {
+        self.insert(other);
+    }
+}
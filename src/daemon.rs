@@ -0,0 +1,171 @@
+//! Running the monitor as a classic Unix daemon
+//!
+//! [`detach`] forks into the background, [`PidFile`] guards against a second
+//! instance, [`LogFile`] sends standard output and error to a file that can be
+//! reopened after log rotation, and [`handle_signals`] turns SIGTERM and SIGINT
+//! into a stop flag and SIGHUP into [`take_hangup`].
+
+use crate::error::Result;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static RUNNING: AtomicBool = AtomicBool::new(true);
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+/// Fork into the background, detached from the terminal
+///
+/// The calling process exits, the daemon continues in a new session with `/`
+/// as working directory and standard input, output and error on `/dev/null`.
+/// Call it before starting any thread.
+pub fn detach() -> Result<()> {
+    fork_and_exit_parent()?;
+    if unsafe { libc::setsid() } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    // A second fork makes sure the daemon never gets a controlling terminal
+    fork_and_exit_parent()?;
+
+    std::env::set_current_dir("/")?;
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for target in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        redirect(&null, target)?;
+    }
+    Ok(())
+}
+
+fn fork_and_exit_parent() -> Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error().into()),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+fn redirect(file: &File, target: libc::c_int) -> Result<()> {
+    if unsafe { libc::dup2(file.as_raw_fd(), target) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Clear the returned flag on SIGTERM or SIGINT and record SIGHUP for [`take_hangup`]
+pub fn handle_signals() -> Result<&'static AtomicBool> {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+    Ok(&RUNNING)
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    if signal == libc::SIGHUP {
+        HANGUP.store(true, Ordering::Relaxed);
+    } else {
+        RUNNING.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Check if SIGHUP arrived since the last call
+pub fn take_hangup() -> bool {
+    HANGUP.swap(false, Ordering::Relaxed)
+}
+
+/// File holding the process ID of the running daemon, removed when dropped
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the ID of this process to `path`
+    ///
+    /// Fails when the file names another process that is still running; a
+    /// file left behind by a crashed daemon is replaced.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = std::path::absolute(path.as_ref())?;
+        if let Some(pid) = read_pid(&path) {
+            let alive =
+                pid != std::process::id() as libc::pid_t && unsafe { libc::kill(pid, 0) } == 0;
+            if alive {
+                let message = format!("{} belongs to running process {}", path.display(), pid);
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, message).into());
+            }
+        }
+        let pid_file = Self { path };
+        pid_file.update()?;
+        Ok(pid_file)
+    }
+
+    /// Write the ID of this process again, after [`detach`] changed it
+    pub fn update(&self) -> Result<()> {
+        let mut file = File::create(&self.path)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(())
+    }
+
+    /// Get the path the process ID is written to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if read_pid(&self.path) == Some(std::process::id() as libc::pid_t) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn read_pid(path: &Path) -> Option<libc::pid_t> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Log file taking over standard output and error
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl LogFile {
+    /// Open `path` for appending, creating it if needed
+    ///
+    /// Output is only redirected by [`LogFile::redirect`], so the file can be
+    /// checked before [`detach`].
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = std::path::absolute(path.as_ref())?;
+        let file = open_log(&path)?;
+        Ok(Self { path, file })
+    }
+
+    /// Send standard output and error to the file
+    pub fn redirect(&self) -> Result<()> {
+        io::stdout().flush()?;
+        redirect(&self.file, libc::STDOUT_FILENO)?;
+        redirect(&self.file, libc::STDERR_FILENO)
+    }
+
+    /// Open the path again and redirect to it, after logrotate moved the old file away
+    pub fn reopen(&mut self) -> Result<()> {
+        self.file = open_log(&self.path)?;
+        self.redirect()
+    }
+
+    /// Get the path output is appended to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+fn open_log(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
@@ -0,0 +1,146 @@
+use crate::error::{Result, UpsError};
+use crate::event::UpsEvent;
+use std::io;
+use std::process::{Command as Process, Stdio};
+
+/// Events shown by default
+const DEFAULT_EVENTS: [&str; 5] = [
+    "power-failure",
+    "power-restored",
+    "low-battery",
+    "over-temperature",
+    "overload",
+];
+
+/// PowerShell script showing a toast, reading the texts from the environment
+///
+/// Toasts need a registered application ID; the one of PowerShell is used so
+/// no shortcut has to be installed.
+#[cfg(windows)]
+const TOAST_SCRIPT: &str = "\
+[Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] > $null
+$template = [Windows.UI.Notifications.ToastNotificationManager]::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02)
+$texts = $template.GetElementsByTagName('text')
+$texts.Item(0).AppendChild($template.CreateTextNode($env:UPS_NOTIFICATION_TITLE)) > $null
+$texts.Item(1).AppendChild($template.CreateTextNode($env:UPS_NOTIFICATION_BODY)) > $null
+$toast = [Windows.UI.Notifications.ToastNotification]::new($template)
+$app = '{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}\\WindowsPowerShell\\v1.0\\powershell.exe'
+[Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier($app).Show($toast)
+";
+
+/// Shows native desktop notifications for monitor events
+///
+/// Notifications are shown through the tool each desktop ships with:
+/// `notify-send` (libnotify) on Linux and the BSDs, `osascript` on macOS and a
+/// PowerShell toast on Windows. Low battery is shown as a critical notification
+/// where the desktop supports urgency levels.
+#[derive(Debug, Clone)]
+pub struct DesktopNotifier {
+    app_name: String,
+    events: Vec<String>,
+}
+
+impl DesktopNotifier {
+    /// Create a notifier showing power failures, restores, low battery, over temperature and overload
+    pub fn new<S: Into<String>>(app_name: S) -> Self {
+        Self {
+            app_name: app_name.into(),
+            events: DEFAULT_EVENTS.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    /// Only show the events with these names (as returned by [`UpsEvent::name`])
+    pub fn set_events(&mut self, events: Vec<String>) {
+        self.events = events;
+    }
+
+    /// Show a notification for an event, returns `false` when it is not selected
+    pub fn notify(&self, event: &UpsEvent) -> Result<bool> {
+        if !self.events.iter().any(|name| name == event.name()) {
+            return Ok(false);
+        }
+
+        let title = match event {
+            UpsEvent::PowerFailure(_) => "UPS on battery",
+            UpsEvent::PowerRestored(_) => "UPS power restored",
+            UpsEvent::LowBattery(_) => "UPS battery low",
+            UpsEvent::TestStarted(_) => "UPS test started",
+            UpsEvent::TestFinished(_) => "UPS test finished",
+            UpsEvent::TestSkipped(_) => "UPS test skipped",
+            UpsEvent::OverTemperature(_) => "UPS overheating",
+            UpsEvent::Overload(_) => "UPS overloaded",
+            UpsEvent::CommunicationLost => "UPS not responding",
+            UpsEvent::CommunicationRestored(_) => "UPS responding again",
+        };
+        let body = match event.status() {
+            Some(status) => status.to_string(),
+            None => "Communication with the UPS was lost.".to_string(),
+        };
+        let critical = matches!(event, UpsEvent::LowBattery(_));
+
+        self.show(title, &body, critical)?;
+        Ok(true)
+    }
+
+    /// Show a notification with any text
+    pub fn show(&self, title: &str, body: &str, critical: bool) -> Result<()> {
+        let mut process = self.process(title, body, critical);
+        let status = process
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .status()
+            .map_err(UpsError::Io)?;
+        if status.success() {
+            Ok(())
+        } else {
+            let message = format!("notification command exited with {}", status);
+            Err(UpsError::Io(io::Error::other(message)))
+        }
+    }
+
+    #[cfg(windows)]
+    fn process(&self, title: &str, body: &str, _critical: bool) -> Process {
+        let mut process = Process::new("powershell");
+        process
+            .args(["-NoProfile", "-NonInteractive", "-Command", TOAST_SCRIPT])
+            .env(
+                "UPS_NOTIFICATION_TITLE",
+                format!("{}: {}", self.app_name, title),
+            )
+            .env("UPS_NOTIFICATION_BODY", body);
+        process
+    }
+
+    #[cfg(target_os = "macos")]
+    fn process(&self, title: &str, body: &str, critical: bool) -> Process {
+        let sound = if critical {
+            " sound name \"Sosumi\""
+        } else {
+            ""
+        };
+        let script = format!(
+            "on run argv\ndisplay notification (item 2 of argv) with title (item 1 of argv){}\nend run",
+            sound
+        );
+        let mut process = Process::new("osascript");
+        process
+            .args(["-e", &script])
+            .arg(format!("{}: {}", self.app_name, title))
+            .arg(body);
+        process
+    }
+
+    #[cfg(not(any(windows, target_os = "macos")))]
+    fn process(&self, title: &str, body: &str, critical: bool) -> Process {
+        let mut process = Process::new("notify-send");
+        process
+            .arg(format!("--app-name={}", self.app_name))
+            .arg(format!(
+                "--urgency={}",
+                if critical { "critical" } else { "normal" }
+            ))
+            .arg("--")
+            .args([title, body]);
+        process
+    }
+}
@@ -0,0 +1,68 @@
+use crate::error::Result;
+use crate::extended::ExtendedStatus;
+use crate::protocol;
+use crate::quirks::Quirks;
+use crate::status::UpsStatus;
+use crate::transport::Command;
+use std::fmt;
+
+/// Status query understood by a device
+///
+/// Megatec devices answer Q1, some clones only QS with the same layout,
+/// Voltronic UPSes QGS and Voltronic/Axpert inverters QPIGS. Every dialect is
+/// mapped to an [`UpsStatus`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Dialect {
+    /// Megatec status query (Q1)
+    #[default]
+    Q1,
+    /// Clone status query with the Q1 layout (QS)
+    Qs,
+    /// Voltronic general status query (QGS)
+    Qgs,
+    /// Voltronic/Axpert inverter status query (QPIGS)
+    Qpigs,
+}
+
+impl Dialect {
+    /// The dialects in the order they are probed
+    pub const ALL: [Dialect; 4] = [Dialect::Q1, Dialect::Qs, Dialect::Qgs, Dialect::Qpigs];
+
+    /// Get the command querying the status in this dialect
+    pub fn command(&self) -> Command {
+        match self {
+            Dialect::Q1 => Command::Status,
+            Dialect::Qs => Command::StatusQs,
+            Dialect::Qgs => Command::GeneralStatus,
+            Dialect::Qpigs => Command::ExtendedStatus,
+        }
+    }
+
+    /// Get the dialect name, the mnemonic of its status query
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dialect::Q1 => "Q1",
+            Dialect::Qs => "QS",
+            Dialect::Qgs => "QGS",
+            Dialect::Qpigs => "QPIGS",
+        }
+    }
+
+    /// Parse a status answer of this dialect
+    ///
+    /// The strict flag and the quirks only apply to the Q1 layout.
+    pub(crate) fn parse(&self, text: &str, strict: bool, quirks: &Quirks) -> Result<UpsStatus> {
+        match self {
+            Dialect::Q1 | Dialect::Qs if strict => UpsStatus::parse(text, quirks),
+            Dialect::Q1 | Dialect::Qs => UpsStatus::parse_tolerant(text, quirks),
+            Dialect::Qgs => Ok(protocol::parse_general_status(text)?),
+            Dialect::Qpigs => ExtendedStatus::from_str(text).map(UpsStatus::from),
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
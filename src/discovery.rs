@@ -0,0 +1,166 @@
+use crate::error::Result;
+use rusb::{Context, Device, DeviceDescriptor, DeviceHandle, UsbContext};
+use std::time::Duration;
+
+pub(crate) const USB_CLASS_HUB: u8 = 0x09;
+
+/// Structure describing a well-known Megatec-compatible USB device
+#[derive(Debug, Clone, Copy)]
+pub struct KnownDevice {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub description: &'static str,
+}
+
+/// Well-known Megatec/Phoenixtec USB IDs, in the order they are tried by `MegatecUps::auto()`
+pub const KNOWN_DEVICES: &[KnownDevice] = &[
+    KnownDevice {
+        vendor_id: 0x0001,
+        product_id: 0x0000,
+        description: "Mega System Technologies (Krauler, Fiscal)",
+    },
+    KnownDevice {
+        vendor_id: 0xffff,
+        product_id: 0x0000,
+        description: "Mega System Technologies (Ablerex)",
+    },
+    KnownDevice {
+        vendor_id: 0x0665,
+        product_id: 0x5161,
+        description: "Cypress USB to serial (Voltronic Power)",
+    },
+    KnownDevice {
+        vendor_id: 0x06da,
+        product_id: 0x0002,
+        description: "Phoenixtec Power",
+    },
+    KnownDevice {
+        vendor_id: 0x06da,
+        product_id: 0x0003,
+        description: "Phoenixtec Power",
+    },
+    KnownDevice {
+        vendor_id: 0x06da,
+        product_id: 0x0004,
+        description: "Phoenixtec Power",
+    },
+    KnownDevice {
+        vendor_id: 0x06da,
+        product_id: 0x0005,
+        description: "Phoenixtec Power",
+    },
+    KnownDevice {
+        vendor_id: 0x06da,
+        product_id: 0x0201,
+        description: "Phoenixtec Power",
+    },
+    KnownDevice {
+        vendor_id: 0x06da,
+        product_id: 0x0601,
+        description: "Phoenixtec Power",
+    },
+    KnownDevice {
+        vendor_id: 0x05b8,
+        product_id: 0x0000,
+        description: "Agiler",
+    },
+    KnownDevice {
+        vendor_id: 0x0f03,
+        product_id: 0x0001,
+        description: "Unitek",
+    },
+    KnownDevice {
+        vendor_id: 0x14f0,
+        product_id: 0x00c9,
+        description: "Phoenix",
+    },
+];
+
+/// Structure describing a USB device that may be a UPS
+#[derive(Debug, Clone)]
+pub struct UpsDeviceInfo {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bus_number: u8,
+    pub address: u8,
+    /// Ports from the root hub to the device, stable across reboots unlike the address
+    pub port_numbers: Vec<u8>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+impl UpsDeviceInfo {
+    /// Read the descriptors of a USB device into UpsDeviceInfo struct
+    fn from_device(device: &Device<Context>) -> Result<Option<Self>> {
+        let descriptor = device.device_descriptor()?;
+        if descriptor.class_code() == USB_CLASS_HUB {
+            return Ok(None);
+        }
+
+        // Strings can only be read when we are allowed to open the device
+        let (manufacturer, product, serial_number) = match device.open() {
+            Ok(handle) => {
+                let timeout = Duration::from_secs(1);
+                let language = handle
+                    .read_languages(timeout)
+                    .ok()
+                    .and_then(|languages| languages.first().copied());
+
+                match language {
+                    Some(language) => (
+                        handle
+                            .read_manufacturer_string(language, &descriptor, timeout)
+                            .ok(),
+                        handle
+                            .read_product_string(language, &descriptor, timeout)
+                            .ok(),
+                        handle
+                            .read_serial_number_string(language, &descriptor, timeout)
+                            .ok(),
+                    ),
+                    None => (None, None, None),
+                }
+            }
+            Err(_) => (None, None, None),
+        };
+
+        Ok(Some(Self {
+            vendor_id: descriptor.vendor_id(),
+            product_id: descriptor.product_id(),
+            bus_number: device.bus_number(),
+            address: device.address(),
+            port_numbers: device.port_numbers().unwrap_or_default(),
+            manufacturer,
+            product,
+            serial_number,
+        }))
+    }
+}
+
+/// Read the serial number string of an opened device
+pub(crate) fn read_serial_number(
+    handle: &DeviceHandle<Context>,
+    descriptor: &DeviceDescriptor,
+    timeout: Duration,
+) -> Option<String> {
+    descriptor.serial_number_string_index()?;
+    let language = *handle.read_languages(timeout).ok()?.first()?;
+    handle
+        .read_serial_number_string(language, descriptor, timeout)
+        .ok()
+}
+
+/// List all USB devices that are candidates for a UPS connection
+pub fn list_devices() -> Result<Vec<UpsDeviceInfo>> {
+    let context = Context::new()?;
+    let mut devices = Vec::new();
+
+    for device in context.devices()?.iter() {
+        if let Some(info) = UpsDeviceInfo::from_device(&device)? {
+            devices.push(info);
+        }
+    }
+
+    Ok(devices)
+}
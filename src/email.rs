@@ -0,0 +1,323 @@
+use crate::error::{Result, UpsError};
+use crate::event::UpsEvent;
+use crate::status::UpsStatus;
+use crate::timestamp;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Mail relay used when none is configured
+pub const DEFAULT_SMTP_SERVER: &str = "localhost:25";
+
+/// Minimum time between two emails for the same kind of event
+pub const DEFAULT_EMAIL_THROTTLE: Duration = Duration::from_secs(15 * 60);
+
+/// Subject used when none is configured
+pub const DEFAULT_EMAIL_SUBJECT: &str = "[{ups}] {event}";
+
+/// Body used when none is configured
+pub const DEFAULT_EMAIL_BODY: &str = "UPS {ups} reported {event}.\n\n{status}\n";
+
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Events mailed by default
+const DEFAULT_EVENTS: [&str; 5] = [
+    "power-failure",
+    "power-restored",
+    "low-battery",
+    "over-temperature",
+    "overload",
+];
+
+/// Where and how event emails are sent
+///
+/// `subject` and `body` are templates where `{ups}`, `{event}`, `{status}`
+/// (one value per line), `{input_voltage}`, `{output_voltage}`, `{load}`
+/// (with its unit, `%` or `A`), `{battery_voltage}` and `{temperature}` are
+/// replaced by the event values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailConfig {
+    /// SMTP relay as `host:port`, accepting mail without authentication
+    pub server: String,
+    /// Envelope and header sender
+    pub from: String,
+    /// Recipients
+    pub to: Vec<String>,
+    /// Subject template
+    pub subject: String,
+    /// Body template
+    pub body: String,
+    /// Names of the events to mail, as returned by [`UpsEvent::name`]
+    pub events: Vec<String>,
+    /// Minimum time between two emails for the same event
+    pub throttle: Duration,
+}
+
+impl EmailConfig {
+    /// Configuration mailing power failures, restores and low battery to `to`
+    pub fn new<S: Into<String>>(from: S, to: Vec<String>) -> Self {
+        Self {
+            server: DEFAULT_SMTP_SERVER.to_string(),
+            from: from.into(),
+            to,
+            subject: DEFAULT_EMAIL_SUBJECT.to_string(),
+            body: DEFAULT_EMAIL_BODY.to_string(),
+            events: DEFAULT_EVENTS.iter().map(|name| name.to_string()).collect(),
+            throttle: DEFAULT_EMAIL_THROTTLE,
+        }
+    }
+
+    /// Check that the addresses cannot break out of the SMTP commands and headers
+    ///
+    /// `from` and `to` are written inside `MAIL FROM:<..>`, `RCPT TO:<..>` and
+    /// the From and To headers, so line breaks and angle brackets are refused.
+    pub fn validate(&self) -> Result<()> {
+        for address in std::iter::once(&self.from).chain(&self.to) {
+            if address.is_empty() || address.contains(['\r', '\n', '<', '>']) {
+                return Err(UpsError::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid email address {:?}", address),
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sends an email for monitor events over SMTP
+///
+/// Events of a kind mailed less than `throttle` ago are not sent but counted,
+/// and the next email of that kind mentions how many were suppressed, so a
+/// flapping mains line does not flood the inbox. Sending blocks for up to 30
+/// seconds per SMTP exchange; the relay is expected to be reachable without
+/// TLS, like a local MTA.
+#[derive(Debug)]
+pub struct EmailNotifier {
+    config: EmailConfig,
+    ups_name: String,
+    last_sent: HashMap<&'static str, Instant>,
+    suppressed: HashMap<&'static str, u32>,
+}
+
+impl EmailNotifier {
+    /// Create a notifier for the UPS called `ups_name`
+    pub fn new<S: Into<String>>(config: EmailConfig, ups_name: S) -> Self {
+        Self {
+            config,
+            ups_name: ups_name.into(),
+            last_sent: HashMap::new(),
+            suppressed: HashMap::new(),
+        }
+    }
+
+    /// Get the configuration
+    pub fn config(&self) -> &EmailConfig {
+        &self.config
+    }
+
+    /// Mail an event, returns `false` when it is not configured or throttled
+    ///
+    /// Fits [`Monitor::on_event`](crate::Monitor::on_event):
+    /// `monitor.on_event(move |event| { let _ = notifier.notify(event); })`.
+    pub fn notify(&mut self, event: &UpsEvent) -> Result<bool> {
+        let name = event.name();
+        if !self.config.events.iter().any(|event| event == name) {
+            return Ok(false);
+        }
+        if self
+            .last_sent
+            .get(name)
+            .is_some_and(|sent| sent.elapsed() < self.config.throttle)
+        {
+            *self.suppressed.entry(name).or_default() += 1;
+            return Ok(false);
+        }
+
+        let subject = self.render(&self.config.subject, event);
+        let mut body = self.render(&self.config.body, event);
+        if let Some(count) = self.suppressed.get(name).filter(|count| **count > 0) {
+            body.push_str(&format!(
+                "\n{} more {} events were suppressed since the last email.\n",
+                count, name
+            ));
+        }
+
+        self.send(&subject, &body)?;
+        self.last_sent.insert(name, Instant::now());
+        self.suppressed.remove(name);
+        Ok(true)
+    }
+
+    /// Send an email to every recipient
+    ///
+    /// Fails without connecting when [`EmailConfig::validate`] rejects the addresses.
+    pub fn send(&self, subject: &str, body: &str) -> Result<()> {
+        self.config.validate()?;
+        let address = self
+            .config
+            .server
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| smtp_error(format!("cannot resolve {}", self.config.server)))?;
+        let stream = TcpStream::connect_timeout(&address, SMTP_TIMEOUT)?;
+        stream.set_read_timeout(Some(SMTP_TIMEOUT))?;
+        stream.set_write_timeout(Some(SMTP_TIMEOUT))?;
+        let mut session = Session {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+
+        session.expect(220)?;
+        session.command("EHLO localhost", 250)?;
+        session.command(&format!("MAIL FROM:<{}>", self.config.from), 250)?;
+        for recipient in &self.config.to {
+            session.command(&format!("RCPT TO:<{}>", recipient), 250)?;
+        }
+        session.command("DATA", 354)?;
+        session
+            .writer
+            .write_all(self.message(subject, body).as_bytes())?;
+        session.command(".", 250)?;
+        session.command("QUIT", 221)
+    }
+
+    /// Build the message headers and the dot-stuffed body
+    fn message(&self, subject: &str, body: &str) -> String {
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\n\
+             MIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Transfer-Encoding: 8bit\r\n\r\n",
+            self.config.from,
+            self.config.to.join(", "),
+            subject.replace(['\r', '\n'], " "),
+            timestamp::rfc5322(SystemTime::now()),
+        );
+        for line in body.lines() {
+            if line.starts_with('.') {
+                message.push('.');
+            }
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message
+    }
+
+    /// Replace the placeholders of a template
+    fn render(&self, template: &str, event: &UpsEvent) -> String {
+        let mut values = vec![
+            ("{ups}", self.ups_name.clone()),
+            ("{event}", event.name().to_string()),
+        ];
+        match event.status() {
+            Some(status) => values.extend([
+                ("{status}", format!("{:#}", status)),
+                ("{input_voltage}", status.input_voltage.to_string()),
+                ("{output_voltage}", status.output_voltage.to_string()),
+                ("{load}", load(status)),
+                ("{battery_voltage}", status.battery_voltage.to_string()),
+                ("{temperature}", status.temperature.to_string()),
+            ]),
+            None => values.push(("{status}", "No status available.".to_string())),
+        }
+
+        values
+            .iter()
+            .fold(template.to_string(), |text, (placeholder, value)| {
+                text.replace(placeholder, value)
+            })
+    }
+}
+
+/// Connection to the SMTP relay
+struct Session {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Session {
+    /// Send a command line and check the reply code
+    fn command(&mut self, line: &str, code: u16) -> Result<()> {
+        self.writer.write_all(format!("{}\r\n", line).as_bytes())?;
+        self.expect(code)
+    }
+
+    /// Read a possibly multi-line reply and check its code
+    fn expect(&mut self, code: u16) -> Result<()> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(UpsError::Disconnected);
+            }
+            let reply: Option<u16> = line.get(..3).and_then(|digits| digits.parse().ok());
+            if reply != Some(code) {
+                return Err(smtp_error(format!(
+                    "SMTP server replied {}",
+                    line.trim_end()
+                )));
+            }
+            // "250-" continues the reply, "250 " ends it
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Format the load field with its unit
+fn load(status: &UpsStatus) -> String {
+    if status.load_in_amps {
+        format!("{:.1} A", status.load_raw)
+    } else {
+        format!("{:.0}%", status.load_raw)
+    }
+}
+
+fn smtp_error(message: String) -> UpsError {
+    UpsError::Io(io::Error::other(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notifier(config: EmailConfig) -> EmailNotifier {
+        EmailNotifier::new(config, "rack")
+    }
+
+    #[test]
+    fn addresses_breaking_the_envelope_are_rejected() {
+        let config = EmailConfig::new("ups@example.com", vec!["ops@example.com".to_string()]);
+        assert!(config.validate().is_ok());
+
+        for address in ["a@b\r\nRCPT TO:<x@y>", "a@b\n", "a@b>", "<a@b", ""] {
+            let from = EmailConfig::new(address, vec!["ops@example.com".to_string()]);
+            assert!(from.validate().is_err(), "{:?}", address);
+            let to = EmailConfig::new("ups@example.com", vec![address.to_string()]);
+            assert!(to.validate().is_err(), "{:?}", address);
+            assert!(notifier(to).send("subject", "body").is_err());
+        }
+    }
+
+    #[test]
+    fn load_is_rendered_with_its_unit() {
+        let config = EmailConfig {
+            body: "{load}".to_string(),
+            ..EmailConfig::new("ups@example.com", Vec::new())
+        };
+        let mut status =
+            UpsStatus::parse_strict("229.5 229.5 230.1 012 50.1 27.3 30.5 00001000").unwrap();
+        let notifier = notifier(config);
+        let render = |status: &UpsStatus| {
+            notifier.render(
+                &notifier.config.body,
+                &UpsEvent::PowerFailure(status.clone()),
+            )
+        };
+        assert_eq!(render(&status), "12%");
+
+        status.load_in_amps = true;
+        status.load_raw = 1.5;
+        assert_eq!(render(&status), "1.5 A");
+    }
+}
@@ -0,0 +1,69 @@
+use crate::protocol::ProtocolError;
+use rusb::Error as UsbError;
+use std::io;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum UpsError {
+    #[error("USB error: {0}")]
+    Usb(UsbError),
+    #[error("I/O error: {0}")]
+    Io(io::Error),
+    #[error("UPS device not found")]
+    DeviceNotFound,
+    #[error("Permission denied while accessing the UPS device (check the udev rules or the kernel driver bound to the device)")]
+    PermissionDenied,
+    #[error("Timed out waiting for the UPS")]
+    Timeout,
+    #[error("UPS device disconnected")]
+    Disconnected,
+    #[error("Invalid response")]
+    InvalidResponse,
+    #[error("Cannot parse response {decoded:?} (raw bytes: {raw:02x?})")]
+    ParseError { raw: Vec<u8>, decoded: String },
+    #[error("Invalid time value")]
+    InvalidTime,
+    #[error("Command not supported by this transport")]
+    Unsupported,
+    #[error("No shutdown is pending")]
+    NoShutdownPending,
+    #[error("Operation cancelled")]
+    Cancelled,
+}
+
+impl From<UsbError> for UpsError {
+    fn from(error: UsbError) -> Self {
+        match error {
+            UsbError::NotFound => UpsError::DeviceNotFound,
+            UsbError::Access => UpsError::PermissionDenied,
+            UsbError::Timeout => UpsError::Timeout,
+            UsbError::NoDevice => UpsError::Disconnected,
+            other => UpsError::Usb(other),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<ProtocolError> for UpsError {
+    fn from(error: ProtocolError) -> Self {
+        match error {
+            ProtocolError::InvalidResponse => UpsError::InvalidResponse,
+            ProtocolError::InvalidTime => UpsError::InvalidTime,
+        }
+    }
+}
+
+impl From<io::Error> for UpsError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::NotFound => UpsError::DeviceNotFound,
+            io::ErrorKind::PermissionDenied => UpsError::PermissionDenied,
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => UpsError::Timeout,
+            io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof => UpsError::Disconnected,
+            _ => UpsError::Io(error),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, UpsError>;
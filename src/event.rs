@@ -0,0 +1,98 @@
+use crate::status::{StatusFlags, UpsStatus};
+use std::fmt;
+
+/// Events emitted when the state of the UPS changes
+#[derive(Debug, Clone)]
+pub enum UpsEvent {
+    /// Utility power failed and the UPS switched to battery
+    PowerFailure(UpsStatus),
+    /// Utility power came back
+    PowerRestored(UpsStatus),
+    /// Battery became low
+    LowBattery(UpsStatus),
+    /// A battery test started
+    TestStarted(UpsStatus),
+    /// A battery test finished
+    TestFinished(UpsStatus),
+    /// A scheduled battery test was not started, because the UPS was on
+    /// battery, busy with a test or shutdown, or did not accept the command
+    TestSkipped(UpsStatus),
+    /// The internal temperature rose above the configured limit, the only
+    /// early warning of a failed fan
+    OverTemperature(UpsStatus),
+    /// The load stayed above the configured limit, a cue to shed
+    /// non-critical loads
+    Overload(UpsStatus),
+    /// The UPS stopped answering
+    CommunicationLost,
+    /// The UPS answers again after a communication loss
+    CommunicationRestored(UpsStatus),
+}
+
+impl UpsEvent {
+    /// Get the status snapshot that triggered the event, if any
+    pub fn status(&self) -> Option<&UpsStatus> {
+        match self {
+            UpsEvent::PowerFailure(status)
+            | UpsEvent::PowerRestored(status)
+            | UpsEvent::LowBattery(status)
+            | UpsEvent::TestStarted(status)
+            | UpsEvent::TestFinished(status)
+            | UpsEvent::TestSkipped(status)
+            | UpsEvent::OverTemperature(status)
+            | UpsEvent::Overload(status)
+            | UpsEvent::CommunicationRestored(status) => Some(status),
+            UpsEvent::CommunicationLost => None,
+        }
+    }
+
+    /// Get the event name as used in logs and notifications
+    pub fn name(&self) -> &'static str {
+        match self {
+            UpsEvent::PowerFailure(_) => "power-failure",
+            UpsEvent::PowerRestored(_) => "power-restored",
+            UpsEvent::LowBattery(_) => "low-battery",
+            UpsEvent::TestStarted(_) => "test-started",
+            UpsEvent::TestFinished(_) => "test-finished",
+            UpsEvent::TestSkipped(_) => "test-skipped",
+            UpsEvent::OverTemperature(_) => "over-temperature",
+            UpsEvent::Overload(_) => "overload",
+            UpsEvent::CommunicationLost => "communication-lost",
+            UpsEvent::CommunicationRestored(_) => "communication-restored",
+        }
+    }
+
+    /// Derive the events of the flags that changed since `previous`
+    pub(crate) fn flag_changes(previous: &StatusFlags, status: &UpsStatus) -> Vec<UpsEvent> {
+        let current = status.flags;
+        let mut events = Vec::new();
+
+        if current.utility_fail && !previous.utility_fail {
+            events.push(UpsEvent::PowerFailure(status.clone()));
+        }
+        if !current.utility_fail && previous.utility_fail {
+            events.push(UpsEvent::PowerRestored(status.clone()));
+        }
+        if current.battery_low && !previous.battery_low {
+            events.push(UpsEvent::LowBattery(status.clone()));
+        }
+        if current.test_in_progress && !previous.test_in_progress {
+            events.push(UpsEvent::TestStarted(status.clone()));
+        }
+        if !current.test_in_progress && previous.test_in_progress {
+            events.push(UpsEvent::TestFinished(status.clone()));
+        }
+        events
+    }
+}
+
+/// Event name followed by the status snapshot, formatted like the status itself
+impl fmt::Display for UpsEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.status() {
+            Some(status) if f.alternate() => write!(f, "{}\n{:#}", self.name(), status),
+            Some(status) => write!(f, "{}: {}", self.name(), status),
+            None => f.write_str(self.name()),
+        }
+    }
+}
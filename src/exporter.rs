@@ -0,0 +1,236 @@
+use crate::aggregate::Aggregator;
+use crate::alarms::Quantity;
+use crate::error::Result;
+use crate::http::{self, Request};
+use crate::listener;
+use crate::status::UpsStatus;
+use crate::transport::Transport;
+use crate::MegatecUps;
+use std::fmt::Write;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Default listen address of the exporter (9402 is not taken in the Prometheus port registry)
+pub const DEFAULT_EXPORTER_ADDRESS: &str = "0.0.0.0:9402";
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Metric name suffix, help text and value of a gauge
+type Gauge = (&'static str, &'static str, fn(&UpsStatus) -> f64);
+
+const GAUGES: [Gauge; 7] = [
+    ("input_voltage_volts", "Input voltage", |s| s.input_voltage),
+    ("input_fault_voltage_volts", "Input fault voltage", |s| {
+        s.input_fault_voltage
+    }),
+    ("output_voltage_volts", "Output voltage", |s| {
+        s.output_voltage
+    }),
+    (
+        "load_percent",
+        "Output load in percent of the rating",
+        |s| s.load_raw,
+    ),
+    ("input_frequency_hertz", "Input frequency", |s| {
+        s.input_frequency
+    }),
+    ("battery_voltage_volts", "Battery voltage", |s| {
+        s.battery_voltage
+    }),
+    ("temperature_celsius", "Internal temperature", |s| {
+        s.temperature
+    }),
+];
+
+/// Serves the UPS status as Prometheus metrics on `/metrics`
+///
+/// Every scrape reads a fresh status from the UPS. When the UPS does not answer
+/// only `megatec_ups_up 0` is reported, so alerting can key on it.
+pub struct Exporter<T: Transport> {
+    ups: MegatecUps<T>,
+    listener: TcpListener,
+    aggregator: Option<Mutex<Aggregator>>,
+}
+
+impl<T: Transport> Exporter<T> {
+    /// Bind the exporter to an address such as [`DEFAULT_EXPORTER_ADDRESS`]
+    pub fn bind<A: ToSocketAddrs>(ups: MegatecUps<T>, address: A) -> Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        Ok(Self {
+            ups,
+            listener,
+            aggregator: None,
+        })
+    }
+
+    /// Also publish the minimum, maximum and mean of the scraped readings
+    ///
+    /// The windows should be a multiple of the scrape interval; see
+    /// [`render_summary_metrics`].
+    pub fn set_aggregation(&mut self, aggregator: Aggregator) {
+        self.aggregator = Some(Mutex::new(aggregator));
+    }
+
+    /// Get the address the exporter is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Get a reference to the exported UPS
+    pub fn ups(&self) -> &MegatecUps<T> {
+        &self.ups
+    }
+
+    /// Read the status and render it in the Prometheus text format
+    pub fn scrape(&self) -> String {
+        let status = self.ups.get_status().ok();
+        let mut out = render_metrics(status.as_ref());
+        if let Some(aggregator) = &self.aggregator {
+            let mut aggregator = aggregator.lock().unwrap_or_else(|p| p.into_inner());
+            if let Some(status) = &status {
+                aggregator.record(status);
+            }
+            out.push_str(&render_summary_metrics(&aggregator));
+        }
+        out
+    }
+
+    /// Handle scrapes, reporting failed accepts on stderr without stopping
+    pub fn serve(&self) -> Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    listener::accept_failed("Prometheus exporter", &e);
+                    continue;
+                }
+            };
+            // A client that goes away mid-request is not an exporter failure
+            let _ = self.handle(&stream);
+        }
+        Ok(())
+    }
+
+    /// Answer a single HTTP request
+    fn handle(&self, stream: &TcpStream) -> std::io::Result<()> {
+        let request = match Request::read(stream) {
+            Ok(request) => request,
+            Err(_) => return http::respond(stream, 400, "text/plain", b"bad request\n"),
+        };
+
+        match (request.method.as_str(), request.route()) {
+            ("GET", "/metrics") => {
+                http::respond(stream, 200, CONTENT_TYPE, self.scrape().as_bytes())
+            }
+            ("GET", "/") => http::respond(
+                stream,
+                200,
+                "text/html",
+                b"<html><body><a href=\"/metrics\">Metrics</a></body></html>\n",
+            ),
+            (_, "/metrics") | (_, "/") => {
+                http::respond(stream, 405, "text/plain", b"method not allowed\n")
+            }
+            _ => http::respond(stream, 404, "text/plain", b"not found\n"),
+        }
+    }
+}
+
+/// Render a status snapshot in the Prometheus text exposition format
+///
+/// `None` means the UPS could not be read and renders `megatec_ups_up 0` only.
+pub fn render_metrics(status: Option<&UpsStatus>) -> String {
+    let mut out = String::new();
+    gauge(&mut out, "up", "Whether the UPS answered the status query");
+    let _ = writeln!(out, "megatec_ups_up {}", u8::from(status.is_some()));
+
+    let status = match status {
+        Some(status) => status,
+        None => return out,
+    };
+
+    for (name, help, value) in GAUGES {
+        gauge(&mut out, name, help);
+        let _ = writeln!(out, "megatec_ups_{} {}", name, value(status));
+    }
+
+    let flags = status.flags;
+    gauge(&mut out, "status", "Status flags reported by the UPS");
+    for (flag, active) in [
+        ("utility_fail", flags.utility_fail),
+        ("battery_low", flags.battery_low),
+        ("bypass_active", flags.bypass_active),
+        ("ups_failed", flags.ups_failed),
+        ("standby", flags.standby),
+        ("test_in_progress", flags.test_in_progress),
+        ("shutdown_active", flags.shutdown_active),
+        ("beeper_on", flags.beeper_on),
+    ] {
+        let _ = writeln!(
+            out,
+            "megatec_ups_status{{flag=\"{}\"}} {}",
+            flag,
+            u8::from(active)
+        );
+    }
+
+    out
+}
+
+/// Render the window summaries of an aggregator in the Prometheus text format
+///
+/// Each quantity gets `_min`, `_max` and `_mean` gauges, such as
+/// `megatec_ups_battery_voltage_mean{window="15m"}`.
+pub fn render_summary_metrics(aggregator: &Aggregator) -> String {
+    let mut out = String::new();
+    for quantity in Quantity::ALL {
+        let name = quantity.name().replace('-', "_");
+        for (stat, help) in [("min", "Minimum"), ("max", "Maximum"), ("mean", "Mean")] {
+            let mut wrote_header = false;
+            for window in aggregator.windows() {
+                let summary = match aggregator.summary(*window, quantity) {
+                    Some(summary) => summary,
+                    None => continue,
+                };
+                if !wrote_header {
+                    let help = format!("{} {} over the window", help, name.replace('_', " "));
+                    gauge(&mut out, &format!("{}_{}", name, stat), &help);
+                    wrote_header = true;
+                }
+                let value = match stat {
+                    "min" => summary.min,
+                    "max" => summary.max,
+                    _ => summary.mean,
+                };
+                let _ = writeln!(
+                    out,
+                    "megatec_ups_{}_{}{{window=\"{}\"}} {}",
+                    name,
+                    stat,
+                    window_label(*window),
+                    value
+                );
+            }
+        }
+    }
+    out
+}
+
+/// Format a window as `30s`, `15m` or `1h`
+fn window_label(window: Duration) -> String {
+    let seconds = window.as_secs();
+    if seconds > 0 && seconds.is_multiple_of(3600) {
+        format!("{}h", seconds / 3600)
+    } else if seconds > 0 && seconds.is_multiple_of(60) {
+        format!("{}m", seconds / 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// Write the HELP and TYPE lines of a gauge
+fn gauge(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP megatec_ups_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE megatec_ups_{} gauge", name);
+}
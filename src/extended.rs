@@ -0,0 +1,132 @@
+use crate::error::Result;
+use crate::protocol;
+use crate::status::{StatusFlags, UpsStatus};
+use std::fmt;
+
+/// Structure representing the Voltronic/Axpert QPIGS status values
+#[derive(Debug, Clone)]
+pub struct ExtendedStatus {
+    pub grid_voltage: f64,
+    pub grid_frequency: f64,
+    pub output_voltage: f64,
+    pub output_frequency: f64,
+    /// Output apparent power (VA)
+    pub output_apparent_power: f64,
+    /// Output active power (W)
+    pub output_active_power: f64,
+    pub load_percent: f64,
+    pub bus_voltage: f64,
+    pub battery_voltage: f64,
+    pub battery_charging_current: f64,
+    /// Battery capacity (%)
+    pub battery_capacity: f64,
+    /// Inverter heat sink temperature (°C)
+    pub heat_sink_temperature: f64,
+    pub pv_input_current: f64,
+    pub pv_input_voltage: f64,
+    /// Battery voltage measured by the solar charge controller
+    pub scc_battery_voltage: f64,
+    pub battery_discharge_current: f64,
+    /// Charging power of the PV input, sent by newer firmware only (W)
+    pub pv_charging_power: Option<f64>,
+    /// Load is powered
+    pub load_on: bool,
+    /// Battery is charging
+    pub charging: bool,
+    /// Battery is charged by the solar charge controller
+    pub scc_charging: bool,
+    /// Battery is charged from the grid
+    pub ac_charging: bool,
+}
+
+impl ExtendedStatus {
+    /// Parse the QPIGS answer into ExtendedStatus struct
+    pub(crate) fn from_str(status: &str) -> Result<Self> {
+        Ok(protocol::parse_extended_status(status)?)
+    }
+}
+
+/// Map the inverter status to the Q1 values
+///
+/// QPIGS has no utility fail bit, so a grid without voltage counts as failed.
+impl From<ExtendedStatus> for UpsStatus {
+    #[allow(deprecated)]
+    fn from(status: ExtendedStatus) -> Self {
+        Self {
+            input_voltage: status.grid_voltage,
+            input_fault_voltage: status.grid_voltage,
+            output_voltage: status.output_voltage,
+            load_raw: status.load_percent,
+            output_current: status.load_percent,
+            load_in_amps: false,
+            input_frequency: status.grid_frequency,
+            battery_voltage: status.battery_voltage,
+            temperature: status.heat_sink_temperature,
+            flags: StatusFlags {
+                utility_fail: status.grid_voltage < 1.0,
+                ..StatusFlags::default()
+            },
+        }
+    }
+}
+
+/// Compact one-line summary, or one line per value with the alternate flag (`{:#}`)
+impl fmt::Display for ExtendedStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            writeln!(f, "Grid Voltage: {:.1} V", self.grid_voltage)?;
+            writeln!(f, "Grid Frequency: {:.1} Hz", self.grid_frequency)?;
+            writeln!(f, "Output Voltage: {:.1} V", self.output_voltage)?;
+            writeln!(f, "Output Frequency: {:.1} Hz", self.output_frequency)?;
+            writeln!(
+                f,
+                "Output Power: {} VA / {} W",
+                self.output_apparent_power, self.output_active_power
+            )?;
+            writeln!(f, "Load: {}%", self.load_percent)?;
+            writeln!(f, "Bus Voltage: {} V", self.bus_voltage)?;
+            writeln!(f, "Battery Voltage: {:.2} V", self.battery_voltage)?;
+            writeln!(
+                f,
+                "Battery Charging Current: {} A",
+                self.battery_charging_current
+            )?;
+            writeln!(
+                f,
+                "Battery Discharge Current: {} A",
+                self.battery_discharge_current
+            )?;
+            writeln!(f, "Battery Capacity: {}%", self.battery_capacity)?;
+            writeln!(
+                f,
+                "Heat Sink Temperature: {:.1} °C",
+                self.heat_sink_temperature
+            )?;
+            writeln!(
+                f,
+                "PV Input: {:.1} V {} A",
+                self.pv_input_voltage, self.pv_input_current
+            )?;
+            if let Some(power) = self.pv_charging_power {
+                writeln!(f, "PV Charging Power: {} W", power)?;
+            }
+            write!(f, "SCC Battery Voltage: {:.2} V", self.scc_battery_voltage)
+        } else {
+            write!(
+                f,
+                "GRID {:.1}V OUT {:.1}V {}W LOAD {}% BAT {:.2}V {}% PV {:.1}V",
+                self.grid_voltage,
+                self.output_voltage,
+                self.output_active_power,
+                self.load_percent,
+                self.battery_voltage,
+                self.battery_capacity,
+                self.pv_input_voltage
+            )?;
+            if self.charging {
+                f.write_str(" CHARGING")?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! C bindings for the core API
+//!
+//! Connections are opaque `MegatecUps` pointers created by
+//! [`megatec_ups_connect`] or [`megatec_ups_auto`] and released with
+//! [`megatec_ups_free`]. Functions return 0 on success or a negative
+//! `MEGATEC_UPS_ERROR_*` code, and [`megatec_ups_last_error`] describes the
+//! last failure of the calling thread. The declarations are mirrored in
+//! `include/megatec_ups.h`; build the library with
+//! `cargo rustc --release --lib --features ffi --crate-type cdylib` (or
+//! `staticlib`).
+//!
+//! ```c
+//! MegatecUps *ups = megatec_ups_auto();
+//! MegatecUpsStatus status;
+//! if (ups && megatec_ups_get_status(ups, &status) == MEGATEC_UPS_OK)
+//!     printf("%.1f V, %s\n", status.input_voltage,
+//!            status.utility_fail ? "on battery" : "online");
+//! megatec_ups_free(ups);
+//! ```
+
+use crate::error::{Result, UpsError};
+use crate::status::{TestResult, UpsStatus};
+use crate::MegatecUps;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CString};
+use std::ptr;
+
+pub const MEGATEC_UPS_OK: c_int = 0;
+pub const MEGATEC_UPS_ERROR_USB: c_int = -1;
+pub const MEGATEC_UPS_ERROR_IO: c_int = -2;
+pub const MEGATEC_UPS_ERROR_NOT_FOUND: c_int = -3;
+pub const MEGATEC_UPS_ERROR_PERMISSION_DENIED: c_int = -4;
+pub const MEGATEC_UPS_ERROR_TIMEOUT: c_int = -5;
+pub const MEGATEC_UPS_ERROR_DISCONNECTED: c_int = -6;
+pub const MEGATEC_UPS_ERROR_INVALID_RESPONSE: c_int = -7;
+pub const MEGATEC_UPS_ERROR_INVALID_TIME: c_int = -8;
+pub const MEGATEC_UPS_ERROR_UNSUPPORTED: c_int = -9;
+pub const MEGATEC_UPS_ERROR_NO_SHUTDOWN_PENDING: c_int = -10;
+pub const MEGATEC_UPS_ERROR_CANCELLED: c_int = -11;
+/// A required pointer argument was null
+pub const MEGATEC_UPS_ERROR_NULL_ARGUMENT: c_int = -12;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Status reading laid out for C
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MegatecUpsStatus {
+    pub input_voltage: f64,
+    pub input_fault_voltage: f64,
+    pub output_voltage: f64,
+    pub load_raw: f64,
+    pub input_frequency: f64,
+    pub battery_voltage: f64,
+    pub temperature: f64,
+    pub utility_fail: bool,
+    pub battery_low: bool,
+    pub bypass_active: bool,
+    pub ups_failed: bool,
+    pub standby: bool,
+    pub test_in_progress: bool,
+    pub shutdown_active: bool,
+    pub beeper_on: bool,
+}
+
+impl From<&UpsStatus> for MegatecUpsStatus {
+    fn from(status: &UpsStatus) -> Self {
+        let flags = status.flags;
+        Self {
+            input_voltage: status.input_voltage,
+            input_fault_voltage: status.input_fault_voltage,
+            output_voltage: status.output_voltage,
+            load_raw: status.load_raw,
+            input_frequency: status.input_frequency,
+            battery_voltage: status.battery_voltage,
+            temperature: status.temperature,
+            utility_fail: flags.utility_fail,
+            battery_low: flags.battery_low,
+            bypass_active: flags.bypass_active,
+            ups_failed: flags.ups_failed,
+            standby: flags.standby,
+            test_in_progress: flags.test_in_progress,
+            shutdown_active: flags.shutdown_active,
+            beeper_on: flags.beeper_on,
+        }
+    }
+}
+
+/// Outcome of a battery test for C, see [`TestResult`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MegatecUpsTestResult {
+    InProgress = 0,
+    Passed = 1,
+    Failed = 2,
+    NotStarted = 3,
+}
+
+impl From<TestResult> for MegatecUpsTestResult {
+    fn from(result: TestResult) -> Self {
+        match result {
+            TestResult::InProgress => MegatecUpsTestResult::InProgress,
+            TestResult::Passed => MegatecUpsTestResult::Passed,
+            TestResult::Failed => MegatecUpsTestResult::Failed,
+            TestResult::NotStarted => MegatecUpsTestResult::NotStarted,
+        }
+    }
+}
+
+/// Connect to the USB device with the given vendor and product ID
+///
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn megatec_ups_connect(vendor_id: u16, product_id: u16) -> *mut MegatecUps {
+    into_handle(MegatecUps::new(vendor_id, product_id))
+}
+
+/// Connect to the first well-known Megatec device that answers
+///
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn megatec_ups_auto() -> *mut MegatecUps {
+    into_handle(MegatecUps::auto())
+}
+
+/// Close a connection and release it
+///
+/// # Safety
+///
+/// `ups` must be null or a pointer returned by a connect function that was
+/// not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn megatec_ups_free(ups: *mut MegatecUps) {
+    if !ups.is_null() {
+        let ups = unsafe { Box::from_raw(ups) };
+        let _ = ups.close();
+    }
+}
+
+/// Read the status into `status`
+///
+/// # Safety
+///
+/// `ups` must be a live connection and `status` must point to writable
+/// memory for a `MegatecUpsStatus`.
+#[no_mangle]
+pub unsafe extern "C" fn megatec_ups_get_status(
+    ups: *const MegatecUps,
+    status: *mut MegatecUpsStatus,
+) -> c_int {
+    if status.is_null() {
+        return null_argument();
+    }
+    call(unsafe { ups.as_ref() }, |ups| {
+        let reading = ups.get_status()?;
+        unsafe { status.write(MegatecUpsStatus::from(&reading)) };
+        Ok(())
+    })
+}
+
+/// Copy the UPS name into `buffer` as a NUL-terminated string, truncated to fit
+///
+/// # Safety
+///
+/// `ups` must be a live connection and `buffer` must point to `length`
+/// writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn megatec_ups_get_name(
+    ups: *const MegatecUps,
+    buffer: *mut c_char,
+    length: usize,
+) -> c_int {
+    if buffer.is_null() || length == 0 {
+        return null_argument();
+    }
+    call(unsafe { ups.as_ref() }, |ups| {
+        let name = ups.get_name()?;
+        let copied = name.len().min(length - 1);
+        unsafe {
+            ptr::copy_nonoverlapping(name.as_ptr().cast::<c_char>(), buffer, copied);
+            buffer.add(copied).write(0);
+        }
+        Ok(())
+    })
+}
+
+/// Test the UPS for 10 seconds, storing the outcome in `result` unless it is null
+///
+/// # Safety
+///
+/// `ups` must be a live connection and `result` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn megatec_ups_test(
+    ups: *const MegatecUps,
+    result: *mut MegatecUpsTestResult,
+) -> c_int {
+    call(unsafe { ups.as_ref() }, |ups| {
+        let outcome = ups.test()?;
+        if !result.is_null() {
+            unsafe { result.write(outcome.into()) };
+        }
+        Ok(())
+    })
+}
+
+/// Abort the running test
+///
+/// # Safety
+///
+/// `ups` must be a live connection.
+#[no_mangle]
+pub unsafe extern "C" fn megatec_ups_abort_test(ups: *const MegatecUps) -> c_int {
+    call(unsafe { ups.as_ref() }, MegatecUps::abort_test)
+}
+
+/// Toggle the beeper
+///
+/// # Safety
+///
+/// `ups` must be a live connection.
+#[no_mangle]
+pub unsafe extern "C" fn megatec_ups_switch_beep(ups: *const MegatecUps) -> c_int {
+    call(unsafe { ups.as_ref() }, MegatecUps::switch_beep)
+}
+
+/// Shut the UPS down after 1 minute
+///
+/// # Safety
+///
+/// `ups` must be a live connection.
+#[no_mangle]
+pub unsafe extern "C" fn megatec_ups_shutdown(ups: *const MegatecUps) -> c_int {
+    call(unsafe { ups.as_ref() }, MegatecUps::shutdown)
+}
+
+/// Shut the UPS down after .2 to .9 minutes or 1 to 10 whole minutes
+///
+/// # Safety
+///
+/// `ups` must be a live connection.
+#[no_mangle]
+pub unsafe extern "C" fn megatec_ups_shutdown_after(ups: *const MegatecUps, minutes: f32) -> c_int {
+    call(unsafe { ups.as_ref() }, |ups| ups.shutdown_after(minutes))
+}
+
+/// Cancel a pending shutdown
+///
+/// # Safety
+///
+/// `ups` must be a live connection.
+#[no_mangle]
+pub unsafe extern "C" fn megatec_ups_cancel_shutdown(ups: *const MegatecUps) -> c_int {
+    call(unsafe { ups.as_ref() }, MegatecUps::cancel_shutdown)
+}
+
+/// Get the message of the last failure on this thread, null when none
+///
+/// The string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn megatec_ups_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Map an error to its `MEGATEC_UPS_ERROR_*` code
+fn error_code(error: &UpsError) -> c_int {
+    match error {
+        UpsError::Usb(_) => MEGATEC_UPS_ERROR_USB,
+        UpsError::Io(_) => MEGATEC_UPS_ERROR_IO,
+        UpsError::DeviceNotFound => MEGATEC_UPS_ERROR_NOT_FOUND,
+        UpsError::PermissionDenied => MEGATEC_UPS_ERROR_PERMISSION_DENIED,
+        UpsError::Timeout => MEGATEC_UPS_ERROR_TIMEOUT,
+        UpsError::Disconnected => MEGATEC_UPS_ERROR_DISCONNECTED,
+        UpsError::InvalidResponse | UpsError::ParseError { .. } => {
+            MEGATEC_UPS_ERROR_INVALID_RESPONSE
+        }
+        UpsError::InvalidTime => MEGATEC_UPS_ERROR_INVALID_TIME,
+        UpsError::Unsupported => MEGATEC_UPS_ERROR_UNSUPPORTED,
+        UpsError::NoShutdownPending => MEGATEC_UPS_ERROR_NO_SHUTDOWN_PENDING,
+        UpsError::Cancelled => MEGATEC_UPS_ERROR_CANCELLED,
+    }
+}
+
+/// Remember the message of a failure for [`megatec_ups_last_error`]
+fn set_last_error(message: String) {
+    // Messages never contain NUL, but drop any rather than the whole message
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn null_argument() -> c_int {
+    set_last_error("Null argument".to_string());
+    MEGATEC_UPS_ERROR_NULL_ARGUMENT
+}
+
+fn into_handle(ups: Result<MegatecUps>) -> *mut MegatecUps {
+    match ups {
+        Ok(ups) => Box::into_raw(Box::new(ups)),
+        Err(error) => {
+            set_last_error(error.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Run an operation on a connection and turn its outcome into a code
+fn call(ups: Option<&MegatecUps>, operation: impl FnOnce(&MegatecUps) -> Result<()>) -> c_int {
+    let ups = match ups {
+        Some(ups) => ups,
+        None => return null_argument(),
+    };
+    match operation(ups) {
+        Ok(()) => MEGATEC_UPS_OK,
+        Err(error) => {
+            let code = error_code(&error);
+            set_last_error(error.to_string());
+            code
+        }
+    }
+}
@@ -0,0 +1,581 @@
+use crate::error::{Result, UpsError};
+use crate::event::UpsEvent;
+use crate::history::{HistoryFormat, HistoryWriter};
+use crate::http::{self, error, Reply, Request, Url};
+use crate::json::{FromJson, ToJson, Value};
+use crate::listener;
+use crate::manager::{AggregateStatus, DeviceStatus};
+use crate::monitor::Monitor;
+use crate::status::UpsStatus;
+use crate::timestamp;
+use crate::transport::Transport;
+use crate::websocket;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, MutexGuard, Once};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Default listen address of the fleet server
+pub const DEFAULT_FLEET_ADDRESS: &str = "0.0.0.0:8090";
+
+/// Time without a report after which an agent counts as lost by default
+pub const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(60);
+
+const CONTENT_TYPE: &str = "application/json";
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+const STALE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Samples kept in memory per agent, a day at one report per minute
+const HISTORY_LENGTH: usize = 1440;
+/// Events kept in memory per agent
+const EVENT_HISTORY_LENGTH: usize = 100;
+/// Events an agent keeps while the server cannot be reached
+const MAX_PENDING_EVENTS: usize = 100;
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Pushes the readings and events of a local [`Monitor`] to a [`FleetServer`]
+///
+/// Each [`push`](Self::push) polls the monitor once and posts
+/// `{"status": {..}, "events": [..]}`, or `{"error": "..", "events": [..]}`
+/// when the UPS did not answer, to `<server>/agents/<name>`. Events are kept
+/// until a push succeeds, so an unreachable server does not lose them.
+pub struct FleetAgent<T: Transport> {
+    monitor: Monitor<T>,
+    events: Receiver<UpsEvent>,
+    url: Url,
+    token: Option<String>,
+    pending: VecDeque<UpsEvent>,
+}
+
+impl<T: Transport> FleetAgent<T> {
+    /// Create an agent reporting as `name` to a server such as `http://fleet:8090`
+    ///
+    /// Names are made of letters, digits, `-`, `_` and `.`, as they name the
+    /// history files on the server.
+    pub fn new(mut monitor: Monitor<T>, server: &str, name: &str) -> Result<Self> {
+        if !valid_name(name) {
+            let message = format!("invalid agent name '{}'", name);
+            return Err(UpsError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                message,
+            )));
+        }
+        let base = Url::parse(server.trim_end_matches('/')).map_err(UpsError::Io)?;
+        let path = format!("{}/agents/{}", base.path.trim_end_matches('/'), name);
+        Ok(Self {
+            events: monitor.subscribe(),
+            monitor,
+            url: Url { path, ..base },
+            token: None,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Set the token the server requires for reports
+    pub fn set_token<S: Into<String>>(&mut self, token: S) {
+        self.token = Some(token.into());
+    }
+
+    /// Get a mutable reference to the monitor, to register callbacks
+    pub fn monitor(&mut self) -> &mut Monitor<T> {
+        &mut self.monitor
+    }
+
+    /// Poll the UPS and report the reading with the events since the last report
+    pub fn push(&mut self) -> Result<()> {
+        let status = self.monitor.poll();
+        self.pending.extend(self.events.try_iter());
+        while self.pending.len() > MAX_PENDING_EVENTS {
+            self.pending.pop_front();
+        }
+
+        let events = Value::Array(self.pending.iter().map(ToJson::to_json).collect());
+        let body = match &status {
+            Ok(status) => Value::object(vec![("status", status.to_json()), ("events", events)]),
+            Err(e) => Value::object(vec![("error", e.to_string().into()), ("events", events)]),
+        };
+        let headers: Vec<(&str, String)> = self
+            .token
+            .iter()
+            .map(|token| ("Authorization", format!("Bearer {}", token)))
+            .collect();
+        let code = http::post(
+            &self.url,
+            CONTENT_TYPE,
+            &headers,
+            body.to_string().as_bytes(),
+            PUSH_TIMEOUT,
+        )?;
+        if !(200..300).contains(&code) {
+            let message = format!("fleet server replied {}", code);
+            return Err(UpsError::Io(io::Error::other(message)));
+        }
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Push every interval of the monitor, forever
+    ///
+    /// Failed pushes are retried with the next reading; use
+    /// [`push`](Self::push) in a custom loop to report them.
+    pub fn serve(&mut self) -> Result<()> {
+        loop {
+            let started = Instant::now();
+            let _ = self.push();
+            thread::sleep(self.monitor.interval().saturating_sub(started.elapsed()));
+        }
+    }
+}
+
+/// Collects the reports of [`FleetAgent`]s and serves the combined view
+///
+/// | Method   | Path                    | Action                                          |
+/// |----------|-------------------------|-------------------------------------------------|
+/// | `POST`   | `/agents/<name>`        | Report of an agent                              |
+/// | `DELETE` | `/agents/<name>`        | Forget an agent                                 |
+/// | `GET`    | `/fleet`                | [`AggregateStatus`] and the [`DeviceStatus`] of every agent |
+/// | `GET`    | `/fleet/<name>`         | [`DeviceStatus`] of one agent                   |
+/// | `GET`    | `/fleet/<name>/history` | Samples and events kept in memory, `?limit=n` newest |
+/// | `GET`    | `/ws`                   | WebSocket stream of the reports and events      |
+///
+/// `POST` and `DELETE` requests need an `Authorization: Bearer <token>` header
+/// matching the configured token. Without a token they are refused.
+///
+/// WebSocket clients receive `{"type":"status","agent":..,"status":{..}}` for
+/// every report and `{"type":"event","agent":..,"event":..}` for every event.
+/// An agent that does not report for [`DEFAULT_STALE_AFTER`] becomes
+/// unreachable with a `CommunicationLost` event, and its next report brings a
+/// `CommunicationRestored` event.
+pub struct FleetServer {
+    listener: TcpListener,
+    token: Option<String>,
+    history_dir: Option<PathBuf>,
+    stale_after: Duration,
+    fleet: Arc<Mutex<Fleet>>,
+    stale_check: Once,
+}
+
+#[derive(Default)]
+struct Fleet {
+    agents: Vec<Agent>,
+    clients: Vec<Client>,
+}
+
+/// Connection of a WebSocket client, locked on its own so a slow client does not block the fleet
+type Client = Arc<Mutex<TcpStream>>;
+
+struct Agent {
+    device: DeviceStatus,
+    received: Instant,
+    lost: bool,
+    samples: VecDeque<(SystemTime, UpsStatus)>,
+    events: VecDeque<(SystemTime, UpsEvent)>,
+    history: Option<HistoryWriter>,
+}
+
+impl FleetServer {
+    /// Bind the server to an address such as [`DEFAULT_FLEET_ADDRESS`]
+    pub fn bind<A: ToSocketAddrs>(address: A) -> Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        Ok(Self {
+            listener,
+            token: None,
+            history_dir: None,
+            stale_after: DEFAULT_STALE_AFTER,
+            fleet: Arc::new(Mutex::new(Fleet::default())),
+            stale_check: Once::new(),
+        })
+    }
+
+    /// Set the token required for reports
+    pub fn set_token<S: Into<String>>(&mut self, token: S) {
+        self.token = Some(token.into());
+    }
+
+    /// Append the samples of every agent to `<dir>/<name>.jsonl`
+    pub fn set_history_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.history_dir = Some(dir.into());
+    }
+
+    /// Set the time without a report after which an agent counts as lost
+    pub fn set_stale_after(&mut self, stale_after: Duration) {
+        self.stale_after = stale_after;
+    }
+
+    /// Get the address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Get the latest report of every agent, in the order they first reported
+    pub fn statuses(&self) -> Vec<DeviceStatus> {
+        self.lock()
+            .agents
+            .iter()
+            .map(|agent| agent.device.clone())
+            .collect()
+    }
+
+    /// Summarize the latest reports
+    pub fn aggregate(&self) -> AggregateStatus {
+        AggregateStatus::from_devices(&self.statuses())
+    }
+
+    /// Watch for lost agents in the background and handle requests, reporting
+    /// failed accepts on stderr without stopping
+    ///
+    /// The watching thread is started by the first call only.
+    pub fn serve(&self) -> Result<()> {
+        self.stale_check.call_once(|| {
+            let fleet = Arc::clone(&self.fleet);
+            let stale_after = self.stale_after;
+            thread::spawn(move || loop {
+                thread::sleep(STALE_CHECK_INTERVAL);
+                let messages = lock(&fleet).expire(stale_after);
+                broadcast(&fleet, &messages);
+            });
+        });
+
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    listener::accept_failed("fleet server", &e);
+                    continue;
+                }
+            };
+            let _ = self.handle(stream);
+        }
+        Ok(())
+    }
+
+    /// Answer a single HTTP request, or keep the connection of a WebSocket client
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        let (status, body) = match Request::read(&stream) {
+            Ok(request) if request.route() == websocket::WEBSOCKET_PATH => {
+                websocket::upgrade(&stream, &request)?;
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                self.lock().clients.push(Arc::new(Mutex::new(stream)));
+                return Ok(());
+            }
+            Ok(request) => self.route(&request),
+            Err(_) => error(400, "bad request"),
+        };
+
+        let mut body = body.to_string();
+        body.push('\n');
+        http::respond(&stream, status, CONTENT_TYPE, body.as_bytes())
+    }
+
+    /// Dispatch a request to its endpoint
+    fn route(&self, request: &Request) -> Reply {
+        let method = request.method.as_str();
+        let path = request.route();
+
+        if let Some(name) = path.strip_prefix("/agents/") {
+            if !matches!(method, "POST" | "DELETE") {
+                return error(405, "method not allowed");
+            }
+            if !http::authorized(request.header("authorization"), self.token.as_deref()) {
+                return error(401, "missing or invalid token");
+            }
+            if !valid_name(name) {
+                return error(400, "invalid agent name");
+            }
+            return match method {
+                "POST" => match report(&request.body) {
+                    Ok((status, events)) => {
+                        self.report(name, status, events);
+                        (200, Value::object(vec![("ok", true.into())]))
+                    }
+                    Err(message) => error(400, &message),
+                },
+                _ => {
+                    let mut fleet = self.lock();
+                    let count = fleet.agents.len();
+                    fleet.agents.retain(|agent| agent.device.name != name);
+                    if fleet.agents.len() == count {
+                        return error(404, "unknown agent");
+                    }
+                    (200, Value::object(vec![("ok", true.into())]))
+                }
+            };
+        }
+
+        if method != "GET" {
+            return match path {
+                "/fleet" => error(405, "method not allowed"),
+                _ if path.starts_with("/fleet/") => error(405, "method not allowed"),
+                _ => error(404, "not found"),
+            };
+        }
+        let fleet = self.lock();
+        let find = |name: &str| fleet.agents.iter().find(|agent| agent.device.name == name);
+        match path.strip_prefix("/fleet") {
+            Some("" | "/") => {
+                let devices: Vec<DeviceStatus> =
+                    fleet.agents.iter().map(|a| a.device.clone()).collect();
+                let body = Value::object(vec![
+                    (
+                        "aggregate",
+                        AggregateStatus::from_devices(&devices).to_json(),
+                    ),
+                    (
+                        "agents",
+                        Value::Array(devices.iter().map(ToJson::to_json).collect()),
+                    ),
+                ]);
+                (200, body)
+            }
+            Some(rest) if rest.starts_with('/') => {
+                let rest = &rest[1..];
+                let (name, history) = match rest.strip_suffix("/history") {
+                    Some(name) => (name, true),
+                    None => (rest, false),
+                };
+                let agent = match find(name) {
+                    Some(agent) => agent,
+                    None => return error(404, "unknown agent"),
+                };
+                if !history {
+                    return (200, agent.device.to_json());
+                }
+                let limit = match request.query("limit").map(|limit| limit.parse::<usize>()) {
+                    Some(Ok(limit)) => limit,
+                    Some(Err(_)) => return error(400, "invalid limit"),
+                    None => usize::MAX,
+                };
+                (200, agent.history(limit))
+            }
+            _ => error(404, "not found"),
+        }
+    }
+
+    /// Record the report of an agent and stream it to the WebSocket clients
+    fn report(
+        &self,
+        name: &str,
+        status: std::result::Result<UpsStatus, String>,
+        events: Vec<UpsEvent>,
+    ) {
+        let now = SystemTime::now();
+        let mut fleet = self.lock();
+        let index = match fleet.agents.iter().position(|a| a.device.name == name) {
+            Some(index) => index,
+            None => {
+                let history = self.history_dir.as_ref().and_then(|dir| {
+                    let path = dir.join(format!("{}.jsonl", name));
+                    // A server without a writable directory still serves the reports
+                    HistoryWriter::open(path, HistoryFormat::JsonLines).ok()
+                });
+                fleet.agents.push(Agent {
+                    device: DeviceStatus {
+                        name: name.to_string(),
+                        ..DeviceStatus::default()
+                    },
+                    received: Instant::now(),
+                    lost: false,
+                    samples: VecDeque::new(),
+                    events: VecDeque::new(),
+                    history,
+                });
+                fleet.agents.len() - 1
+            }
+        };
+
+        let agent = &mut fleet.agents[index];
+        let mut events = events;
+        if agent.lost {
+            if let Ok(status) = &status {
+                events.push(UpsEvent::CommunicationRestored(status.clone()));
+            }
+        }
+        agent.received = Instant::now();
+        agent.lost = false;
+        agent.device.time = Some(now);
+        match &status {
+            Ok(reading) => {
+                agent.device.status = Some(reading.clone());
+                agent.device.error = None;
+                if let Some(history) = &mut agent.history {
+                    let _ = history.record_at(now, reading);
+                }
+                agent.samples.push_back((now, reading.clone()));
+                if agent.samples.len() > HISTORY_LENGTH {
+                    agent.samples.pop_front();
+                }
+            }
+            Err(message) => agent.device.error = Some(message.clone()),
+        }
+        for event in &events {
+            agent.push_event(now, event.clone());
+        }
+
+        drop(fleet);
+
+        let mut messages: Vec<Value> = events
+            .iter()
+            .map(|event| message("event", name, event.to_json()))
+            .collect();
+        if let Ok(status) = &status {
+            messages.push(message("status", name, status.to_json()));
+        }
+        broadcast(&self.fleet, &messages);
+    }
+    fn lock(&self) -> MutexGuard<'_, Fleet> {
+        lock(&self.fleet)
+    }
+}
+
+impl Fleet {
+    /// Mark the agents without a recent report as lost, returning the messages to broadcast
+    fn expire(&mut self, stale_after: Duration) -> Vec<Value> {
+        let now = SystemTime::now();
+        let mut lost = Vec::new();
+        for agent in &mut self.agents {
+            if !agent.lost && agent.received.elapsed() > stale_after {
+                agent.lost = true;
+                let seconds = agent.received.elapsed().as_secs();
+                agent.device.error = Some(format!("no report for {} seconds", seconds));
+                agent.push_event(now, UpsEvent::CommunicationLost);
+                lost.push(agent.device.name.clone());
+            }
+        }
+        lost.iter()
+            .map(|name| message("event", name, UpsEvent::CommunicationLost.to_json()))
+            .collect()
+    }
+}
+
+fn lock(fleet: &Mutex<Fleet>) -> MutexGuard<'_, Fleet> {
+    fleet
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Send messages to every WebSocket client, dropping the ones that went away
+///
+/// The clients are written after the fleet is unlocked, so a client slow to
+/// read holds up neither the reports nor the queries.
+fn broadcast(fleet: &Mutex<Fleet>, messages: &[Value]) {
+    if messages.is_empty() {
+        return;
+    }
+    let clients = lock(fleet).clients.clone();
+    let frames: Vec<Vec<u8>> = messages
+        .iter()
+        .map(|message| websocket::text_frame(&message.to_string()))
+        .collect();
+    let gone: Vec<Client> = clients
+        .into_iter()
+        .filter(|client| {
+            let mut stream = client
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            frames.iter().any(|frame| stream.write_all(frame).is_err())
+        })
+        .collect();
+    if !gone.is_empty() {
+        lock(fleet)
+            .clients
+            .retain(|client| !gone.iter().any(|gone| Arc::ptr_eq(client, gone)));
+    }
+}
+
+impl Agent {
+    fn push_event(&mut self, time: SystemTime, event: UpsEvent) {
+        self.events.push_back((time, event));
+        if self.events.len() > EVENT_HISTORY_LENGTH {
+            self.events.pop_front();
+        }
+    }
+
+    /// Newest samples and events, oldest first
+    fn history(&self, limit: usize) -> Value {
+        let samples = self
+            .samples
+            .iter()
+            .skip(self.samples.len().saturating_sub(limit));
+        let events = self
+            .events
+            .iter()
+            .skip(self.events.len().saturating_sub(limit));
+        let entry = |key: &str, time: &SystemTime, value: Value| {
+            Value::object(vec![
+                ("time", timestamp::rfc3339(*time).into()),
+                (key, value),
+            ])
+        };
+        Value::object(vec![
+            (
+                "samples",
+                Value::Array(
+                    samples
+                        .map(|(time, status)| entry("status", time, status.to_json()))
+                        .collect(),
+                ),
+            ),
+            (
+                "events",
+                Value::Array(
+                    events
+                        .map(|(time, event)| entry("event", time, event.to_json()))
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+}
+
+/// Parse the body of a report
+fn report(
+    body: &[u8],
+) -> std::result::Result<(std::result::Result<UpsStatus, String>, Vec<UpsEvent>), String> {
+    let text = std::str::from_utf8(body).map_err(|_| "body is not UTF-8".to_string())?;
+    let body = Value::parse(text).map_err(|e| e.to_string())?;
+    let status = match (
+        body.get("status"),
+        body.get("error").and_then(Value::as_str),
+    ) {
+        (Some(status), _) if *status != Value::Null => {
+            Ok(UpsStatus::from_json(status).map_err(|e| e.to_string())?)
+        }
+        (_, Some(error)) => Err(error.to_string()),
+        _ => return Err("report needs a status or an error".to_string()),
+    };
+    let events = match body.get("events") {
+        Some(events) => events
+            .as_array()
+            .ok_or("events must be an array")?
+            .iter()
+            .map(UpsEvent::from_json)
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|e| e.to_string())?,
+        None => Vec::new(),
+    };
+    Ok((status, events))
+}
+
+/// WebSocket message about an agent
+fn message(kind: &str, agent: &str, value: Value) -> Value {
+    Value::object(vec![
+        ("type", kind.into()),
+        ("agent", agent.into()),
+        (kind, value),
+    ])
+}
+
+/// Check that an agent name is safe as a URL segment and a file name
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
@@ -0,0 +1,157 @@
+use crate::error::Result;
+use crate::status::UpsStatus;
+use crate::transport::Transport;
+use crate::MegatecUps;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Prefix of the metric names by default
+pub const DEFAULT_GRAPHITE_PREFIX: &str = "ups";
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wire format of the metrics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphiteProtocol {
+    /// Carbon plaintext protocol over TCP, `<path> <value> <timestamp>` (port 2003)
+    Plaintext,
+    /// StatsD gauges over UDP, `<name>:<value>|g` (port 8125)
+    StatsD,
+}
+
+/// Pushes status samples as gauges to Graphite (carbon) or a StatsD daemon
+///
+/// Every sample sends `<prefix>.input_voltage`, `.output_voltage`,
+/// `.load_percent`, `.battery_voltage` and the other status values, plus the
+/// flags as 0 or 1 (`<prefix>.on_battery`, `.battery_low`, ...). The prefix is
+/// `ups` unless set with [`set_prefix`](Self::set_prefix).
+pub struct GraphitePusher<T: Transport> {
+    ups: MegatecUps<T>,
+    address: SocketAddr,
+    protocol: GraphiteProtocol,
+    prefix: String,
+    interval: Duration,
+}
+
+impl<T: Transport> GraphitePusher<T> {
+    /// Create a pusher sending to the carbon or StatsD server at `address`
+    pub fn new<A: ToSocketAddrs>(
+        ups: MegatecUps<T>,
+        address: A,
+        protocol: GraphiteProtocol,
+        interval: Duration,
+    ) -> Result<Self> {
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host not found"))?;
+        Ok(Self {
+            ups,
+            address,
+            protocol,
+            prefix: DEFAULT_GRAPHITE_PREFIX.to_string(),
+            interval,
+        })
+    }
+
+    /// Set the prefix of the metric names, such as `power.garage.ups`
+    pub fn set_prefix<S: Into<String>>(&mut self, prefix: S) {
+        self.prefix = prefix.into().trim_end_matches('.').to_string();
+    }
+
+    /// Get the UPS the samples are read from
+    pub fn ups(&self) -> &MegatecUps<T> {
+        &self.ups
+    }
+
+    /// Get the time between two samples
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Read the status and send it
+    pub fn push(&self) -> Result<()> {
+        let status = self.ups.get_status()?;
+        self.send(SystemTime::now(), &status)
+    }
+
+    /// Send a sample taken at the given time
+    pub fn send(&self, time: SystemTime, status: &UpsStatus) -> Result<()> {
+        let payload = self.payload(time, status);
+        match self.protocol {
+            GraphiteProtocol::Plaintext => {
+                let mut stream = TcpStream::connect_timeout(&self.address, SEND_TIMEOUT)?;
+                stream.set_write_timeout(Some(SEND_TIMEOUT))?;
+                stream.write_all(payload.as_bytes())?;
+            }
+            GraphiteProtocol::StatsD => {
+                let local = if self.address.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                let socket = UdpSocket::bind(local)?;
+                // One datagram with a metric per line, as StatsD accepts
+                socket.send_to(payload.trim_end().as_bytes(), self.address)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Push a sample every interval, forever
+    ///
+    /// Failed reads and sends skip the sample; use [`push`](Self::push) in a
+    /// custom loop to handle them.
+    pub fn serve(&self) -> Result<()> {
+        loop {
+            let started = Instant::now();
+            let _ = self.push();
+            thread::sleep(self.interval.saturating_sub(started.elapsed()));
+        }
+    }
+
+    /// Format a sample in the wire format, one metric per line
+    pub fn payload(&self, time: SystemTime, status: &UpsStatus) -> String {
+        let flags = &status.flags;
+        let flag = |set: bool| if set { 1.0 } else { 0.0 };
+        let values = [
+            ("input_voltage", status.input_voltage),
+            ("input_fault_voltage", status.input_fault_voltage),
+            ("output_voltage", status.output_voltage),
+            ("load_percent", status.load_raw),
+            ("input_frequency", status.input_frequency),
+            ("battery_voltage", status.battery_voltage),
+            ("temperature", status.temperature),
+            ("on_battery", flag(flags.on_battery())),
+            ("battery_low", flag(flags.battery_low)),
+            ("bypass_active", flag(flags.bypass_active)),
+            ("ups_failed", flag(flags.ups_failed)),
+            ("test_in_progress", flag(flags.test_in_progress)),
+            ("shutdown_active", flag(flags.shutdown_active)),
+            ("beeper_on", flag(flags.beeper_on)),
+        ];
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut payload = String::new();
+        // Values missing from truncated replies are left out
+        for (name, value) in values.iter().filter(|(_, value)| value.is_finite()) {
+            let line = match self.protocol {
+                GraphiteProtocol::Plaintext => {
+                    format!("{}.{} {} {}\n", self.prefix, name, value, seconds)
+                }
+                // A leading sign would make the gauge relative, so negative values start from 0
+                GraphiteProtocol::StatsD if *value < 0.0 => {
+                    format!("{0}.{1}:0|g\n{0}.{1}:{2}|g\n", self.prefix, name, value)
+                }
+                GraphiteProtocol::StatsD => format!("{}.{}:{}|g\n", self.prefix, name, value),
+            };
+            payload.push_str(&line);
+        }
+        payload
+    }
+}
@@ -0,0 +1,1205 @@
+use crate::http::Request;
+use crate::json::{ToJson, Value};
+use crate::sqlite::SqliteHistory;
+use crate::timestamp::{parse_rfc3339, rfc3339};
+use crate::transport::Transport;
+use crate::MegatecUps;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Deepest nesting of selection sets and input values a query may use
+const MAX_DEPTH: usize = 32;
+
+/// Field of an object type, with its arguments and GraphQL type
+struct Field {
+    name: &'static str,
+    arguments: &'static [(&'static str, &'static str)],
+    ty: &'static str,
+}
+
+const fn field(name: &'static str, ty: &'static str) -> Field {
+    Field {
+        name,
+        arguments: &[],
+        ty,
+    }
+}
+
+/// Object types of the schema; resolved objects are the JSON form of the values,
+/// so the field names follow the REST API
+const TYPES: &[(&str, &[Field])] = &[
+    (
+        "Query",
+        &[
+            field("status", "Status"),
+            field("rating", "Rating"),
+            field("info", "Info"),
+            field("name", "String"),
+            Field {
+                name: "history",
+                arguments: &[("from", "Time"), ("to", "Time"), ("limit", "Int")],
+                ty: "[Sample!]",
+            },
+            Field {
+                name: "events",
+                arguments: &[
+                    ("from", "Time"),
+                    ("to", "Time"),
+                    ("limit", "Int"),
+                    ("name", "String"),
+                ],
+                ty: "[Event!]",
+            },
+        ],
+    ),
+    (
+        "Status",
+        &[
+            field("input_voltage", "Float"),
+            field("input_fault_voltage", "Float"),
+            field("output_voltage", "Float"),
+            field("output_current", "Float"),
+            field("input_frequency", "Float"),
+            field("battery_voltage", "Float"),
+            field("temperature", "Float"),
+            field("flags", "Flags!"),
+        ],
+    ),
+    (
+        "Flags",
+        &[
+            field("utility_fail", "Boolean!"),
+            field("battery_low", "Boolean!"),
+            field("bypass_active", "Boolean!"),
+            field("ups_failed", "Boolean!"),
+            field("standby", "Boolean!"),
+            field("test_in_progress", "Boolean!"),
+            field("shutdown_active", "Boolean!"),
+            field("beeper_on", "Boolean!"),
+        ],
+    ),
+    (
+        "Rating",
+        &[
+            field("rated_voltage", "Float"),
+            field("rated_current", "Float"),
+            field("battery_voltage", "Float"),
+            field("rated_frequency", "Float"),
+        ],
+    ),
+    (
+        "Info",
+        &[
+            field("company", "String!"),
+            field("model", "String!"),
+            field("firmware_version", "String!"),
+        ],
+    ),
+    (
+        "Sample",
+        &[
+            field("time", "Time!"),
+            field("timestamp", "Float!"),
+            field("status", "Status!"),
+        ],
+    ),
+    (
+        "Event",
+        &[
+            field("time", "Time!"),
+            field("timestamp", "Float!"),
+            field("name", "String!"),
+            field("status", "Status"),
+        ],
+    ),
+];
+
+/// Schema served at `/graphql`, in the GraphQL schema definition language
+pub(crate) fn schema() -> String {
+    let mut schema = String::from(
+        "\"RFC 3339 time such as 2024-05-01T12:00:00Z, or Unix seconds as input\"\nscalar Time\n",
+    );
+    for (name, fields) in TYPES {
+        schema.push_str(&format!("\ntype {} {{\n", name));
+        for field in fields.iter() {
+            let arguments: Vec<String> = field
+                .arguments
+                .iter()
+                .map(|(name, ty)| format!("{}: {}", name, ty))
+                .collect();
+            if arguments.is_empty() {
+                schema.push_str(&format!("  {}: {}\n", field.name, field.ty));
+            } else {
+                let arguments = arguments.join(", ");
+                schema.push_str(&format!("  {}({}): {}\n", field.name, arguments, field.ty));
+            }
+        }
+        schema.push_str("}\n");
+    }
+    schema
+}
+
+/// Answer a GraphQL request sent as `GET /graphql?query=...` or `POST /graphql`
+///
+/// POST bodies are `{"query": ..., "variables": ..., "operationName": ...}`
+/// objects, or the bare query with the `application/graphql` content type.
+pub(crate) fn handle<T: Transport>(
+    ups: &MegatecUps<T>,
+    history: Option<&SqliteHistory>,
+    request: &Request,
+) -> (u16, Value) {
+    let content_type = request.header("content-type").unwrap_or("");
+    let (query, operation, variables) = if request.method == "GET" {
+        let variables = match request
+            .query("variables")
+            .map(|text| Value::parse_with_depth(&text, MAX_DEPTH))
+        {
+            Some(Ok(variables)) => variables,
+            Some(Err(e)) => return failure(&format!("invalid variables: {}", e)),
+            None => Value::Null,
+        };
+        (
+            request.query("query"),
+            request.query("operationName"),
+            variables,
+        )
+    } else if content_type.starts_with("application/graphql") {
+        let query = String::from_utf8_lossy(&request.body).into_owned();
+        (Some(query), None, Value::Null)
+    } else {
+        let body = match std::str::from_utf8(&request.body)
+            .map(|text| Value::parse_with_depth(text, MAX_DEPTH))
+        {
+            Ok(Ok(body)) => body,
+            Ok(Err(e)) => return failure(&format!("invalid body: {}", e)),
+            Err(_) => return failure("body is not UTF-8"),
+        };
+        let text = |key: &str| body.get(key).and_then(Value::as_str).map(str::to_string);
+        let variables = body.get("variables").cloned().unwrap_or(Value::Null);
+        (text("query"), text("operationName"), variables)
+    };
+
+    let query = match query {
+        Some(query) => query,
+        None => return failure("missing query"),
+    };
+    let variables = match variables {
+        Value::Object(variables) => variables,
+        Value::Null => Vec::new(),
+        _ => return failure("variables must be an object"),
+    };
+    match execute(ups, history, &query, operation.as_deref(), variables) {
+        Ok(response) => (200, response),
+        Err(message) => failure(&message),
+    }
+}
+
+/// Response of a request that could not be executed
+fn failure(message: &str) -> (u16, Value) {
+    let error = Value::object(vec![("message", message.into())]);
+    (
+        400,
+        Value::object(vec![("errors", Value::Array(vec![error]))]),
+    )
+}
+
+/// Parse, validate and run a query, returning the `data` and `errors` of the response
+fn execute<T: Transport>(
+    ups: &MegatecUps<T>,
+    history: Option<&SqliteHistory>,
+    query: &str,
+    operation_name: Option<&str>,
+    mut variables: Vec<(String, Value)>,
+) -> Result<Value, String> {
+    let document = Parser::new(query)?.document()?;
+    let mut operations = document.operations.iter();
+    let operation = match operation_name {
+        Some(name) => operations
+            .find(|operation| operation.name.as_deref() == Some(name))
+            .ok_or_else(|| format!("unknown operation \"{}\"", name))?,
+        None if document.operations.len() == 1 => &document.operations[0],
+        None => return Err("operationName is required with several operations".to_string()),
+    };
+    if operation.kind != "query" {
+        return Err(format!(
+            "{} operations are not supported, use the REST endpoints to control the UPS",
+            operation.kind
+        ));
+    }
+
+    // Variables not given take their default, or null
+    variables.retain(|(name, _)| operation.variables.iter().any(|(known, _)| known == name));
+    for (name, default) in &operation.variables {
+        if !variables.iter().any(|(given, _)| given == name) {
+            let value = default.as_ref().map_or(Value::Null, Input::constant);
+            variables.push((name.clone(), value));
+        }
+    }
+
+    let context = Context {
+        fragments: &document.fragments,
+        variables,
+    };
+    context.validate("Query", &operation.selections, &mut Vec::new())?;
+
+    let mut data = Vec::new();
+    let mut errors = Vec::new();
+    for (key, fields) in context.collect(&operation.selections) {
+        let field = fields[0];
+        let arguments: Vec<(&str, Value)> = field
+            .arguments
+            .iter()
+            .map(|(name, input)| (name.as_str(), context.value(input)))
+            .collect();
+        let resolved = match field.name.as_str() {
+            "__typename" => Ok("Query".into()),
+            "status" => ups
+                .get_status()
+                .map(|s| s.to_json())
+                .map_err(|e| e.to_string()),
+            "rating" => ups
+                .get_rating()
+                .map(|r| r.to_json())
+                .map_err(|e| e.to_string()),
+            "info" => ups
+                .get_info()
+                .map(|i| i.to_json())
+                .map_err(|e| e.to_string()),
+            "name" => ups
+                .get_name()
+                .map(|name| name.trim().into())
+                .map_err(|e| e.to_string()),
+            "history" => history_samples(history, &arguments),
+            _ => history_events(history, &arguments),
+        };
+        match resolved {
+            Ok(value) => {
+                let ty = root_field(&field.name).map_or("String", |f| f.ty);
+                data.push((key, context.complete(value, ty, &fields)));
+            }
+            Err(message) => {
+                let path = Value::Array(vec![key.into()]);
+                errors.push(Value::object(vec![
+                    ("message", message.into()),
+                    ("path", path),
+                ]));
+                data.push((key, Value::Null));
+            }
+        }
+    }
+
+    let mut response = vec![("data", Value::object(data))];
+    if !errors.is_empty() {
+        response.push(("errors", Value::Array(errors)));
+    }
+    Ok(Value::object(response))
+}
+
+/// Samples of the history database in the range of the arguments
+fn history_samples(
+    history: Option<&SqliteHistory>,
+    arguments: &[(&str, Value)],
+) -> Result<Value, String> {
+    let history = history.ok_or("no history database is configured")?;
+    let (from, to) = time_range(arguments)?;
+    let limit = limit(argument(arguments, "limit"))?;
+
+    let samples = history.range(from, to).map_err(|e| e.to_string())?;
+    let skip = samples.len().saturating_sub(limit);
+    let samples = samples
+        .into_iter()
+        .skip(skip)
+        .map(|sample| {
+            Value::object(vec![
+                ("time", rfc3339(sample.time).into()),
+                ("timestamp", unix_seconds(sample.time).into()),
+                ("status", sample.status.to_json()),
+            ])
+        })
+        .collect();
+    Ok(Value::Array(samples))
+}
+
+/// Events of the history database in the range of the arguments, optionally of one name
+fn history_events(
+    history: Option<&SqliteHistory>,
+    arguments: &[(&str, Value)],
+) -> Result<Value, String> {
+    let history = history.ok_or("no history database is configured")?;
+    let (from, to) = time_range(arguments)?;
+    let limit = limit(argument(arguments, "limit"))?;
+    let name = match argument(arguments, "name") {
+        Value::Null => None,
+        Value::String(name) => Some(name.as_str()),
+        _ => return Err("name must be a string".to_string()),
+    };
+
+    let mut events = history.events_range(from, to).map_err(|e| e.to_string())?;
+    events.retain(|record| name.is_none_or(|name| record.event.name() == name));
+    let skip = events.len().saturating_sub(limit);
+    let events = events
+        .into_iter()
+        .skip(skip)
+        .map(|record| {
+            let status = record.event.status().map_or(Value::Null, |s| s.to_json());
+            Value::object(vec![
+                ("time", rfc3339(record.time).into()),
+                ("timestamp", unix_seconds(record.time).into()),
+                ("name", record.event.name().into()),
+                ("status", status),
+            ])
+        })
+        .collect();
+    Ok(Value::Array(events))
+}
+
+/// `from` (inclusive) and `to` (exclusive) arguments, the whole history by default
+fn time_range(arguments: &[(&str, Value)]) -> Result<(SystemTime, SystemTime), String> {
+    let time = |name: &str| -> Result<Option<SystemTime>, String> {
+        match argument(arguments, name) {
+            Value::Null => Ok(None),
+            Value::String(text) => parse_rfc3339(text)
+                .map(Some)
+                .ok_or_else(|| format!("{} is not an RFC 3339 time: {}", name, text)),
+            Value::Number(seconds) if seconds.is_finite() && *seconds >= 0.0 => {
+                Ok(Some(UNIX_EPOCH + Duration::from_secs_f64(*seconds)))
+            }
+            _ => Err(format!("{} must be an RFC 3339 time or Unix seconds", name)),
+        }
+    };
+    let from = time("from")?.unwrap_or(UNIX_EPOCH);
+    let to = time("to")?.unwrap_or(UNIX_EPOCH + Duration::from_millis(i64::MAX as u64));
+    Ok((from, to))
+}
+
+/// Value of an argument, null when not given
+fn argument<'v>(arguments: &'v [(&str, Value)], name: &str) -> &'v Value {
+    arguments
+        .iter()
+        .find(|(given, _)| *given == name)
+        .map_or(&Value::Null, |(_, value)| value)
+}
+
+/// `limit` argument: how many of the newest entries to return
+fn limit(value: &Value) -> Result<usize, String> {
+    match value {
+        Value::Null => Ok(usize::MAX),
+        Value::Number(limit) if limit.fract() == 0.0 && *limit >= 0.0 => Ok(*limit as usize),
+        _ => Err("limit must be a non-negative integer".to_string()),
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn object_type(name: &str) -> Option<&'static [Field]> {
+    TYPES
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, fields)| *fields)
+}
+
+fn root_field(name: &str) -> Option<&'static Field> {
+    object_type("Query")?
+        .iter()
+        .find(|field| field.name == name)
+}
+
+/// Name of the type without list and non-null wrappers
+fn named_type(ty: &str) -> &str {
+    ty.trim_matches(|c| matches!(c, '[' | ']' | '!'))
+}
+
+/// Variables and fragments shared by the validation and execution of an operation
+struct Context<'a> {
+    fragments: &'a [Fragment],
+    variables: Vec<(String, Value)>,
+}
+
+impl<'a> Context<'a> {
+    /// Check the selections against the type before anything is resolved
+    fn validate(
+        &self,
+        type_name: &str,
+        selections: &'a [Selection],
+        spreads: &mut Vec<&'a str>,
+    ) -> Result<(), String> {
+        let fields = object_type(type_name).unwrap_or(&[]);
+        for selection in selections {
+            match selection {
+                Selection::Field(selected) => {
+                    self.condition(&selected.directives)?;
+                    let name = selected.name.as_str();
+                    if name == "__typename" {
+                        if !selected.selections.is_empty() {
+                            return Err("__typename has no subfields".to_string());
+                        }
+                        continue;
+                    }
+                    if name.starts_with("__") {
+                        return Err(format!(
+                            "introspection field \"{}\" is not supported, see RestServer::graphql_schema",
+                            name
+                        ));
+                    }
+                    let field =
+                        fields
+                            .iter()
+                            .find(|field| field.name == name)
+                            .ok_or_else(|| {
+                                format!("cannot query field \"{}\" on type \"{}\"", name, type_name)
+                            })?;
+                    for (argument, _) in &selected.arguments {
+                        if !field.arguments.iter().any(|(known, _)| known == argument) {
+                            return Err(format!(
+                                "unknown argument \"{}\" on field \"{}.{}\"",
+                                argument, type_name, name
+                            ));
+                        }
+                    }
+                    let ty = named_type(field.ty);
+                    match (object_type(ty), selected.selections.is_empty()) {
+                        (Some(_), true) => {
+                            return Err(format!(
+                                "field \"{}\" of type \"{}\" needs a selection of subfields",
+                                name, field.ty
+                            ))
+                        }
+                        (Some(_), false) => self.validate(ty, &selected.selections, spreads)?,
+                        (None, false) => {
+                            return Err(format!(
+                                "field \"{}\" of type \"{}\" has no subfields",
+                                name, field.ty
+                            ))
+                        }
+                        (None, true) => {}
+                    }
+                }
+                Selection::Spread { name, directives } => {
+                    self.condition(directives)?;
+                    let fragment = self
+                        .fragments
+                        .iter()
+                        .find(|fragment| fragment.name == *name)
+                        .ok_or_else(|| format!("unknown fragment \"{}\"", name))?;
+                    if spreads.contains(&name.as_str()) {
+                        return Err(format!("fragment \"{}\" spreads itself", name));
+                    }
+                    check_type_condition(Some(&fragment.type_condition), type_name)?;
+                    spreads.push(name);
+                    self.validate(type_name, &fragment.selections, spreads)?;
+                    spreads.pop();
+                }
+                Selection::Inline {
+                    type_condition,
+                    directives,
+                    selections,
+                } => {
+                    self.condition(directives)?;
+                    check_type_condition(type_condition.as_deref(), type_name)?;
+                    self.validate(type_name, selections, spreads)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Group the included fields of the selections by response key, in query order
+    ///
+    /// Validation already checked that every fragment applies to the type.
+    fn collect(&self, selections: &'a [Selection]) -> Vec<(&'a str, Vec<&'a SelectedField>)> {
+        let mut grouped = Vec::new();
+        self.collect_into(selections, &mut grouped);
+        grouped
+    }
+
+    fn collect_into(
+        &self,
+        selections: &'a [Selection],
+        grouped: &mut Vec<(&'a str, Vec<&'a SelectedField>)>,
+    ) {
+        for selection in selections {
+            match selection {
+                Selection::Field(field) if self.included(&field.directives) => {
+                    let key = field.alias.as_deref().unwrap_or(&field.name);
+                    match grouped.iter_mut().find(|(known, _)| *known == key) {
+                        Some((_, fields)) => fields.push(field),
+                        None => grouped.push((key, vec![field])),
+                    }
+                }
+                Selection::Spread { name, directives } if self.included(directives) => {
+                    if let Some(fragment) = self.fragments.iter().find(|f| f.name == *name) {
+                        self.collect_into(&fragment.selections, grouped);
+                    }
+                }
+                Selection::Inline {
+                    selections,
+                    directives,
+                    ..
+                } if self.included(directives) => {
+                    self.collect_into(selections, grouped);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Shape a resolved value by the subfields selected on it
+    fn complete(&self, value: Value, ty: &str, fields: &[&'a SelectedField]) -> Value {
+        if let (Some(inner), Value::Array(items)) = (list_item_type(ty), &value) {
+            let items = items
+                .iter()
+                .map(|item| self.complete(item.clone(), inner, fields))
+                .collect();
+            return Value::Array(items);
+        }
+        let type_name = named_type(ty);
+        let object_fields = match (object_type(type_name), &value) {
+            (Some(object_fields), Value::Object(_)) => object_fields,
+            _ => return value,
+        };
+
+        // Subfields of fields selected more than once are merged
+        let mut grouped = Vec::new();
+        for field in fields {
+            self.collect_into(&field.selections, &mut grouped);
+        }
+        let members = grouped
+            .into_iter()
+            .map(|(key, selected)| {
+                let name = selected[0].name.as_str();
+                let member = match object_fields.iter().find(|field| field.name == name) {
+                    Some(field) => {
+                        let member = value.get(name).cloned().unwrap_or(Value::Null);
+                        self.complete(member, field.ty, &selected)
+                    }
+                    None => type_name.into(),
+                };
+                (key, member)
+            })
+            .collect();
+        Value::object(members)
+    }
+
+    /// Evaluate the `@skip` and `@include` directives of a selection
+    fn condition(&self, directives: &[Directive]) -> Result<bool, String> {
+        let mut included = true;
+        for directive in directives {
+            let condition = directive
+                .arguments
+                .iter()
+                .find(|(name, _)| name == "if")
+                .map(|(_, input)| self.value(input));
+            let condition = match condition {
+                Some(Value::Bool(condition)) => condition,
+                _ => return Err(format!("@{} needs a Boolean \"if\"", directive.name)),
+            };
+            match directive.name.as_str() {
+                "skip" => included &= !condition,
+                "include" => included &= condition,
+                name => return Err(format!("unknown directive \"@{}\"", name)),
+            }
+        }
+        Ok(included)
+    }
+
+    fn included(&self, directives: &[Directive]) -> bool {
+        // Validation already rejected directives that do not evaluate
+        self.condition(directives).unwrap_or(false)
+    }
+
+    /// Value of an argument, with the variables substituted
+    fn value(&self, input: &Input) -> Value {
+        match input {
+            Input::Variable(name) => self
+                .variables
+                .iter()
+                .find(|(known, _)| known == name)
+                .map_or(Value::Null, |(_, value)| value.clone()),
+            Input::Constant(value) => value.clone(),
+            Input::List(items) => Value::Array(items.iter().map(|i| self.value(i)).collect()),
+            Input::Object(members) => Value::Object(
+                members
+                    .iter()
+                    .map(|(name, input)| (name.clone(), self.value(input)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Check that a fragment with a type condition can apply to an object of `type_name`
+fn check_type_condition(condition: Option<&str>, type_name: &str) -> Result<(), String> {
+    match condition {
+        Some(condition) if object_type(condition).is_none() => {
+            Err(format!("unknown type \"{}\"", condition))
+        }
+        Some(condition) if condition != type_name => Err(format!(
+            "fragment on \"{}\" can never apply to \"{}\"",
+            condition, type_name
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Type of the items of a list type, `None` for other types
+fn list_item_type(ty: &str) -> Option<&str> {
+    ty.trim_end_matches('!')
+        .strip_prefix('[')
+        .and_then(|inner| inner.strip_suffix(']'))
+}
+
+struct Document {
+    operations: Vec<Operation>,
+    fragments: Vec<Fragment>,
+}
+
+struct Operation {
+    /// `query`, `mutation` or `subscription`
+    kind: String,
+    name: Option<String>,
+    /// Variable names and default values
+    variables: Vec<(String, Option<Input>)>,
+    selections: Vec<Selection>,
+}
+
+struct Fragment {
+    name: String,
+    type_condition: String,
+    selections: Vec<Selection>,
+}
+
+enum Selection {
+    Field(SelectedField),
+    Spread {
+        name: String,
+        directives: Vec<Directive>,
+    },
+    Inline {
+        type_condition: Option<String>,
+        directives: Vec<Directive>,
+        selections: Vec<Selection>,
+    },
+}
+
+struct SelectedField {
+    alias: Option<String>,
+    name: String,
+    arguments: Vec<(String, Input)>,
+    directives: Vec<Directive>,
+    selections: Vec<Selection>,
+}
+
+struct Directive {
+    name: String,
+    arguments: Vec<(String, Input)>,
+}
+
+/// Argument value, which may refer to variables
+enum Input {
+    Variable(String),
+    Constant(Value),
+    List(Vec<Input>),
+    Object(Vec<(String, Input)>),
+}
+
+impl Input {
+    /// Value of a variable default, which cannot refer to other variables
+    fn constant(&self) -> Value {
+        match self {
+            Input::Variable(_) => Value::Null,
+            Input::Constant(value) => value.clone(),
+            Input::List(items) => Value::Array(items.iter().map(Input::constant).collect()),
+            Input::Object(members) => Value::Object(
+                members
+                    .iter()
+                    .map(|(name, input)| (name.clone(), input.constant()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Punctuator(char),
+    Spread,
+    Name(String),
+    Number(f64),
+    String(String),
+}
+
+/// Recursive descent parser of executable GraphQL documents
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn new(source: &str) -> Result<Self, String> {
+        Ok(Self {
+            tokens: tokenize(source)?,
+            position: 0,
+            depth: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or("unexpected end of query")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn accept(&mut self, punctuator: char) -> bool {
+        if self.peek() == Some(&Token::Punctuator(punctuator)) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punctuator: char) -> Result<(), String> {
+        match self.next()? {
+            Token::Punctuator(c) if c == punctuator => Ok(()),
+            token => Err(format!(
+                "expected '{}', found {}",
+                punctuator,
+                describe(&token)
+            )),
+        }
+    }
+
+    fn peek_name(&self) -> Option<&str> {
+        match self.peek() {
+            Some(Token::Name(name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            token => Err(format!("expected a name, found {}", describe(&token))),
+        }
+    }
+
+    fn document(mut self) -> Result<Document, String> {
+        let mut document = Document {
+            operations: Vec::new(),
+            fragments: Vec::new(),
+        };
+        while self.peek().is_some() {
+            match self.peek_name() {
+                Some("fragment") => {
+                    self.position += 1;
+                    let name = self.name()?;
+                    if self.name()? != "on" {
+                        return Err(format!("expected 'on' after fragment \"{}\"", name));
+                    }
+                    let type_condition = self.name()?;
+                    let selections = self.selection_set()?;
+                    document.fragments.push(Fragment {
+                        name,
+                        type_condition,
+                        selections,
+                    });
+                }
+                Some("query" | "mutation" | "subscription") => {
+                    let kind = self.name()?;
+                    let name = match self.peek_name() {
+                        Some(_) => Some(self.name()?),
+                        None => None,
+                    };
+                    let variables = self.variable_definitions()?;
+                    // Operation directives have no meaning here
+                    self.directives()?;
+                    let selections = self.selection_set()?;
+                    document.operations.push(Operation {
+                        kind,
+                        name,
+                        variables,
+                        selections,
+                    });
+                }
+                _ => {
+                    let selections = self.selection_set()?;
+                    document.operations.push(Operation {
+                        kind: "query".to_string(),
+                        name: None,
+                        variables: Vec::new(),
+                        selections,
+                    });
+                }
+            }
+        }
+        if document.operations.is_empty() {
+            return Err("the document has no operation".to_string());
+        }
+        Ok(document)
+    }
+
+    fn variable_definitions(&mut self) -> Result<Vec<(String, Option<Input>)>, String> {
+        let mut variables = Vec::new();
+        if !self.accept('(') {
+            return Ok(variables);
+        }
+        while !self.accept(')') {
+            self.expect('$')?;
+            let name = self.name()?;
+            self.expect(':')?;
+            self.skip_type()?;
+            let default = if self.accept('=') {
+                Some(self.value()?)
+            } else {
+                None
+            };
+            self.directives()?;
+            variables.push((name, default));
+        }
+        Ok(variables)
+    }
+
+    /// Skip a variable type such as `[String!]!`; arguments are checked when resolved
+    fn skip_type(&mut self) -> Result<(), String> {
+        if self.accept('[') {
+            self.skip_type()?;
+            self.expect(']')?;
+        } else {
+            self.name()?;
+        }
+        self.accept('!');
+        Ok(())
+    }
+
+    fn selection_set(&mut self) -> Result<Vec<Selection>, String> {
+        self.expect('{')?;
+        self.enter()?;
+        let mut selections = Vec::new();
+        while !self.accept('}') {
+            selections.push(self.selection()?);
+        }
+        self.depth -= 1;
+        if selections.is_empty() {
+            return Err("empty selection set".to_string());
+        }
+        Ok(selections)
+    }
+
+    fn selection(&mut self) -> Result<Selection, String> {
+        if self.peek() == Some(&Token::Spread) {
+            self.position += 1;
+            return match self.peek_name() {
+                Some("on") => {
+                    self.position += 1;
+                    let type_condition = Some(self.name()?);
+                    let directives = self.directives()?;
+                    let selections = self.selection_set()?;
+                    Ok(Selection::Inline {
+                        type_condition,
+                        directives,
+                        selections,
+                    })
+                }
+                Some(_) => {
+                    let name = self.name()?;
+                    let directives = self.directives()?;
+                    Ok(Selection::Spread { name, directives })
+                }
+                None => {
+                    let directives = self.directives()?;
+                    let selections = self.selection_set()?;
+                    Ok(Selection::Inline {
+                        type_condition: None,
+                        directives,
+                        selections,
+                    })
+                }
+            };
+        }
+
+        let mut name = self.name()?;
+        let mut alias = None;
+        if self.accept(':') {
+            alias = Some(name);
+            name = self.name()?;
+        }
+        let arguments = self.arguments()?;
+        let directives = self.directives()?;
+        let selections = if self.peek() == Some(&Token::Punctuator('{')) {
+            self.selection_set()?
+        } else {
+            Vec::new()
+        };
+        Ok(Selection::Field(SelectedField {
+            alias,
+            name,
+            arguments,
+            directives,
+            selections,
+        }))
+    }
+
+    fn arguments(&mut self) -> Result<Vec<(String, Input)>, String> {
+        let mut arguments = Vec::new();
+        if !self.accept('(') {
+            return Ok(arguments);
+        }
+        while !self.accept(')') {
+            let name = self.name()?;
+            self.expect(':')?;
+            arguments.push((name, self.value()?));
+        }
+        Ok(arguments)
+    }
+
+    fn directives(&mut self) -> Result<Vec<Directive>, String> {
+        let mut directives = Vec::new();
+        while self.accept('@') {
+            let name = self.name()?;
+            let arguments = self.arguments()?;
+            directives.push(Directive { name, arguments });
+        }
+        Ok(directives)
+    }
+
+    fn value(&mut self) -> Result<Input, String> {
+        let input = match self.next()? {
+            Token::Punctuator('$') => Input::Variable(self.name()?),
+            Token::Number(number) => Input::Constant(number.into()),
+            Token::String(text) => Input::Constant(text.into()),
+            Token::Name(name) => Input::Constant(match name.as_str() {
+                "true" => true.into(),
+                "false" => false.into(),
+                "null" => Value::Null,
+                // Enum values are passed on as their name
+                _ => name.into(),
+            }),
+            Token::Punctuator('[') => {
+                self.enter()?;
+                let mut items = Vec::new();
+                while !self.accept(']') {
+                    items.push(self.value()?);
+                }
+                self.depth -= 1;
+                Input::List(items)
+            }
+            Token::Punctuator('{') => {
+                self.enter()?;
+                let mut members = Vec::new();
+                while !self.accept('}') {
+                    let name = self.name()?;
+                    self.expect(':')?;
+                    members.push((name, self.value()?));
+                }
+                self.depth -= 1;
+                Input::Object(members)
+            }
+            token => return Err(format!("expected a value, found {}", describe(&token))),
+        };
+        Ok(input)
+    }
+
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            Err("query is nested too deeply".to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Punctuator(c) => format!("'{}'", c),
+        Token::Spread => "'...'".to_string(),
+        Token::Name(name) => format!("\"{}\"", name),
+        Token::Number(number) => number.to_string(),
+        Token::String(_) => "a string".to_string(),
+    }
+}
+
+/// Split a document into tokens, dropping whitespace, commas and comments
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => {}
+            '#' => while chars.next_if(|&(_, c)| c != '\n' && c != '\r').is_some() {},
+            '!' | '$' | '&' | '(' | ')' | ':' | '=' | '@' | '[' | ']' | '{' | '|' | '}' => {
+                tokens.push(Token::Punctuator(c))
+            }
+            '.' => {
+                if !source[start..].starts_with("...") {
+                    return Err("unexpected '.'".to_string());
+                }
+                chars.next();
+                chars.next();
+                tokens.push(Token::Spread);
+            }
+            '"' if source[start..].starts_with("\"\"\"") => {
+                let body = &source[start + 3..];
+                let end = body.find("\"\"\"").ok_or("unterminated block string")?;
+                // Block strings are taken verbatim, without removing the indentation
+                tokens.push(Token::String(body[..end].trim().to_string()));
+                let skip = body[..end + 3].chars().count() + 2;
+                for _ in 0..skip {
+                    chars.next();
+                }
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next().map(|(_, c)| c) {
+                        None | Some('\n' | '\r') => return Err("unterminated string".to_string()),
+                        Some('"') => break,
+                        Some('\\') => {
+                            let escaped = match chars.next().map(|(_, c)| c) {
+                                Some('"') => '"',
+                                Some('\\') => '\\',
+                                Some('/') => '/',
+                                Some('b') => '\u{8}',
+                                Some('f') => '\u{c}',
+                                Some('n') => '\n',
+                                Some('r') => '\r',
+                                Some('t') => '\t',
+                                Some('u') => {
+                                    let hex: String = (0..4)
+                                        .filter_map(|_| chars.next())
+                                        .map(|(_, c)| c)
+                                        .collect();
+                                    u32::from_str_radix(&hex, 16)
+                                        .ok()
+                                        .and_then(char::from_u32)
+                                        .ok_or("invalid unicode escape")?
+                                }
+                                _ => return Err("invalid escape in string".to_string()),
+                            };
+                            text.push(escaped);
+                        }
+                        Some(c) => text.push(c),
+                    }
+                }
+                tokens.push(Token::String(text));
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(index, c)) = chars.peek() {
+                    let exponent_sign = matches!(c, '+' | '-')
+                        && matches!(source[..index].chars().last(), Some('e' | 'E'));
+                    if c.is_ascii_alphanumeric() || c == '.' || exponent_sign {
+                        end = index + c.len_utf8();
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let number = &source[start..end];
+                let valid = number
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || matches!(b, b'-' | b'+' | b'.' | b'e' | b'E'));
+                match number.parse::<f64>() {
+                    Ok(value) if valid && value.is_finite() => tokens.push(Token::Number(value)),
+                    _ => return Err(format!("invalid number {}", number)),
+                }
+            }
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let mut name = String::from(c);
+                while let Some((_, c)) =
+                    chars.next_if(|&(_, c)| c == '_' || c.is_ascii_alphanumeric())
+                {
+                    name.push(c);
+                }
+                tokens.push(Token::Name(name));
+            }
+            c => return Err(format!("unexpected character '{}'", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockTransport;
+
+    fn run(query: &str) -> Result<Value, String> {
+        let ups = MegatecUps::with_transport(MockTransport::online());
+        execute(&ups, None, query, None, Vec::new())
+    }
+
+    #[test]
+    fn queries_select_the_requested_fields() {
+        let response = run("{ status { input_voltage flags { utility_fail } } name }").unwrap();
+        let data = response.get("data").unwrap();
+        let status = data.get("status").unwrap();
+        assert!(status
+            .get("input_voltage")
+            .and_then(Value::as_f64)
+            .is_some());
+        assert!(status.get("output_voltage").is_none());
+        assert_eq!(
+            status.get("flags").and_then(|f| f.get("utility_fail")),
+            Some(&Value::Bool(false))
+        );
+        assert_eq!(data.get("name").and_then(Value::as_str), Some("MOCK UPS"));
+    }
+
+    #[test]
+    fn failing_fields_are_reported_with_their_path() {
+        let response = run("{ name history(limit: 10) { time } }").unwrap();
+        let data = response.get("data").unwrap();
+        assert_eq!(data.get("name").and_then(Value::as_str), Some("MOCK UPS"));
+        assert_eq!(data.get("history"), Some(&Value::Null));
+        let errors = response.get("errors").and_then(Value::as_array).unwrap();
+        assert_eq!(
+            errors[0].get("path"),
+            Some(&Value::Array(vec!["history".into()]))
+        );
+    }
+
+    #[test]
+    fn malformed_queries_are_errors() {
+        for query in [
+            "",
+            "{",
+            "{ status { input_voltage }",
+            "{ status { } }",
+            "{ unknown }",
+            "{ status { unknown } }",
+            "{ status }",
+            "{ name { length } }",
+            "\"unterminated",
+            "{ status @include { input_voltage } }",
+            "fragment F on Status { input_voltage } { status { ...G } }",
+        ] {
+            assert!(run(query).is_err(), "{:?}", query);
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        let query = format!("{}{}", "{ status ".repeat(10_000), "}".repeat(10_000));
+        assert_eq!(run(&query).unwrap_err(), "query is nested too deeply");
+
+        let query = format!(
+            "{{ events(name: {}\"x\"{}) {{ name }} }}",
+            "[".repeat(10_000),
+            "]".repeat(10_000)
+        );
+        assert_eq!(run(&query).unwrap_err(), "query is nested too deeply");
+    }
+}
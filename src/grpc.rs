@@ -0,0 +1,1470 @@
+use crate::error::{Result, UpsError};
+use crate::event::UpsEvent;
+use crate::http::{self, ConnectionLimit};
+use crate::monitor::Monitor;
+use crate::status::{TestResult, UpsStatus};
+use crate::transport::Transport;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Default listen address of the gRPC server, reachable from this host only
+pub const DEFAULT_GRPC_ADDRESS: &str = "127.0.0.1:50051";
+
+/// Protocol buffers definition of the service, to generate clients from
+pub const GRPC_PROTO: &str = include_str!("../proto/megatec_ups.proto");
+
+const SERVICE_PATH: &str = "/megatec.ups.v1.Ups/";
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(300);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a call waits for its turn on the UPS
+const CALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+const MAX_FRAME_SIZE: usize = 16384;
+const MAX_MESSAGE_LENGTH: usize = 64 * 1024;
+/// Undelivered event data after which a stream that does not read is dropped
+const MAX_PENDING_LENGTH: usize = 256 * 1024;
+const MAX_CONCURRENT_STREAMS: u32 = 100;
+/// Clients served at the same time, each on its own thread
+const MAX_CONNECTIONS: usize = 64;
+const DEFAULT_WINDOW_SIZE: i64 = 65535;
+const HEADER_TABLE_SIZE: usize = 4096;
+
+const FRAME_DATA: u8 = 0x0;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_RST_STREAM: u8 = 0x3;
+const FRAME_SETTINGS: u8 = 0x4;
+const FRAME_PING: u8 = 0x6;
+const FRAME_GOAWAY: u8 = 0x7;
+const FRAME_WINDOW_UPDATE: u8 = 0x8;
+const FRAME_CONTINUATION: u8 = 0x9;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+const PROTOCOL_ERROR: u32 = 0x1;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const CANCEL: u32 = 0x8;
+const COMPRESSION_ERROR: u32 = 0x9;
+
+const STATUS_CANCELLED: u32 = 1;
+const STATUS_INVALID_ARGUMENT: u32 = 3;
+const STATUS_DEADLINE_EXCEEDED: u32 = 4;
+const STATUS_RESOURCE_EXHAUSTED: u32 = 8;
+const STATUS_FAILED_PRECONDITION: u32 = 9;
+const STATUS_UNIMPLEMENTED: u32 = 12;
+const STATUS_INTERNAL: u32 = 13;
+const STATUS_UNAVAILABLE: u32 = 14;
+const STATUS_UNAUTHENTICATED: u32 = 16;
+
+/// Serves the `megatec.ups.v1.Ups` gRPC service of [`GRPC_PROTO`] for a [`Monitor`]
+///
+/// | Method         | Action                                                    |
+/// |----------------|-----------------------------------------------------------|
+/// | `GetStatus`    | [`MegatecUps::get_status`](crate::MegatecUps::get_status) |
+/// | `StreamEvents` | Every event of the monitor, optionally only some names    |
+/// | `RunTest`      | Quick, timed or until battery low test, or abort the test |
+/// | `Shutdown`     | Shutdown with optional restore, or cancel it              |
+///
+/// Clients connect over HTTP/2 without TLS (insecure channel credentials);
+/// put a TLS terminating proxy in front for remote networks. `RunTest` and
+/// `Shutdown` need an `authorization: Bearer <token>` metadata entry matching
+/// the configured token and are refused without one. Calls are run one at a
+/// time between the polls of the monitor, so the bus is never shared. At
+/// most 64 clients are served at the same time.
+///
+/// The HTTP/2 and HPACK implementation is experimental: it covers what
+/// grpcurl and the official gRPC clients send, but has not been run against
+/// a conformance suite. Keep the server on trusted networks.
+pub struct GrpcServer<T: Transport> {
+    monitor: Monitor<T>,
+    listener: TcpListener,
+    token: Option<String>,
+}
+
+impl<T: Transport> GrpcServer<T> {
+    /// Bind the server to an address such as [`DEFAULT_GRPC_ADDRESS`]
+    pub fn bind<A: ToSocketAddrs>(monitor: Monitor<T>, address: A) -> Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        Ok(Self {
+            monitor,
+            listener,
+            token: None,
+        })
+    }
+
+    /// Set the token required by `RunTest` and `Shutdown`
+    pub fn set_token<S: Into<String>>(&mut self, token: S) {
+        self.token = Some(token.into());
+    }
+
+    /// Get the address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Get a mutable reference to the monitor, to register callbacks
+    pub fn monitor(&mut self) -> &mut Monitor<T> {
+        &mut self.monitor
+    }
+
+    /// Accept clients in the background, poll the monitor and answer calls, forever
+    pub fn serve(&mut self) -> Result<()> {
+        let listener = self.listener.try_clone()?;
+        let shared = Arc::new(Shared {
+            token: self.token.clone(),
+            subscribers: Mutex::new(Vec::new()),
+        });
+        let (calls, requests) = mpsc::channel();
+        let accepted = Arc::clone(&shared);
+        let limit = ConnectionLimit::new(MAX_CONNECTIONS);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // Clients beyond the limit are disconnected right away
+                let Some(slot) = limit.acquire() else {
+                    continue;
+                };
+                let calls = calls.clone();
+                let shared = Arc::clone(&accepted);
+                // A client that goes away is not a server failure
+                thread::spawn(move || {
+                    let _slot = slot;
+                    Connection::serve(stream, calls, shared)
+                });
+            }
+        });
+
+        let events = self.monitor.subscribe();
+        let mut next_poll = Instant::now();
+        loop {
+            if Instant::now() >= next_poll {
+                next_poll = Instant::now() + self.monitor.interval();
+                let _ = self.monitor.poll();
+                for event in events.try_iter() {
+                    shared.broadcast(&event);
+                }
+            }
+            match requests.recv_timeout(next_poll.saturating_duration_since(Instant::now())) {
+                Ok(Call {
+                    method,
+                    message,
+                    reply,
+                }) => {
+                    let _ = reply.send(self.call(method, &message));
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+
+    /// Run a unary call on the UPS and encode its response message
+    fn call(&self, method: Method, message: &[u8]) -> Reply {
+        let ups = self.monitor.ups();
+        let fields = decode_message(message).ok_or_else(|| {
+            (
+                STATUS_INVALID_ARGUMENT,
+                "invalid request message".to_string(),
+            )
+        })?;
+        match method {
+            Method::GetStatus => ups
+                .get_status()
+                .map(|status| encode_status(&status))
+                .map_err(status_of),
+            Method::RunTest => {
+                let minutes = varint_field(&fields, 2);
+                let result = match varint_field(&fields, 1) {
+                    0 => ups.test(),
+                    1 if (1..=99).contains(&minutes) => ups.test_with_time(minutes as u8),
+                    1 => {
+                        let message = "minutes must be 1-99 for a timed test".to_string();
+                        return Err((STATUS_INVALID_ARGUMENT, message));
+                    }
+                    2 => ups.test_until_battery_low(),
+                    3 => return ups.abort_test().map(|()| Vec::new()).map_err(status_of),
+                    kind => {
+                        let message = format!("unknown test kind {}", kind);
+                        return Err((STATUS_INVALID_ARGUMENT, message));
+                    }
+                };
+                let result = match result.map_err(status_of)? {
+                    TestResult::InProgress => 1,
+                    TestResult::Passed => 2,
+                    TestResult::Failed => 3,
+                    TestResult::NotStarted => 4,
+                };
+                let mut response = Vec::new();
+                put_varint_field(&mut response, 1, result);
+                Ok(response)
+            }
+            Method::Shutdown => {
+                if varint_field(&fields, 3) != 0 {
+                    return ups
+                        .cancel_shutdown()
+                        .map(|()| Vec::new())
+                        .map_err(status_of);
+                }
+                let minutes = match double_field(&fields, 1) {
+                    0.0 => 1.0,
+                    minutes => minutes as f32,
+                };
+                let result = match u16::try_from(varint_field(&fields, 2)) {
+                    Ok(0) => ups.shutdown_after(minutes),
+                    Ok(restore) => ups.shutdown_and_restore(minutes, restore),
+                    Err(_) => Err(UpsError::InvalidTime),
+                };
+                result.map(|()| Vec::new()).map_err(status_of)
+            }
+        }
+    }
+}
+
+/// Unary methods, run by the serving thread
+#[derive(Debug, Clone, Copy)]
+enum Method {
+    GetStatus,
+    RunTest,
+    Shutdown,
+}
+
+/// Response message, or the gRPC status code and message of the failure
+type Reply = std::result::Result<Vec<u8>, (u32, String)>;
+
+struct Call {
+    method: Method,
+    message: Vec<u8>,
+    reply: Sender<Reply>,
+}
+
+/// State shared by the serving thread and the connections
+struct Shared {
+    token: Option<String>,
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+/// Open `StreamEvents` call
+struct Subscriber {
+    writer: Arc<Mutex<Writer>>,
+    stream: u32,
+    /// Event names asked for, empty for every event
+    names: Vec<String>,
+}
+
+impl Shared {
+    /// Send an event to the streams asking for it, dropping the closed ones
+    fn broadcast(&self, event: &UpsEvent) {
+        let message = grpc_message(&encode_event(event, SystemTime::now()));
+        lock(&self.subscribers).retain(|subscriber| {
+            let mut writer = lock(&subscriber.writer);
+            if !writer.is_open(subscriber.stream) {
+                return false;
+            }
+            if !subscriber.names.is_empty() && !subscriber.names.iter().any(|n| n == event.name()) {
+                return true;
+            }
+            writer.data(subscriber.stream, &message).unwrap_or(false)
+        });
+    }
+
+    /// Check the bearer token of a control call
+    fn authorized(&self, authorization: Option<&str>) -> bool {
+        http::authorized(authorization, self.token.as_deref())
+    }
+}
+
+/// HTTP/2 connection of a client, read by its own thread
+struct Connection {
+    stream: TcpStream,
+    writer: Arc<Mutex<Writer>>,
+    calls: Sender<Call>,
+    shared: Arc<Shared>,
+    decoder: HpackDecoder,
+    requests: Vec<Incoming>,
+    /// Stream whose header block continues in CONTINUATION frames
+    continuation: Option<u32>,
+    last_stream: u32,
+}
+
+/// Request stream still being received
+struct Incoming {
+    id: u32,
+    block: Vec<u8>,
+    headers: Option<Vec<(String, String)>>,
+    body: Vec<u8>,
+    end_stream: bool,
+}
+
+impl Connection {
+    /// Exchange the connection preface and settings, then handle frames until the client leaves
+    fn serve(stream: TcpStream, calls: Sender<Call>, shared: Arc<Shared>) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let mut preface = [0u8; 24];
+        (&stream).read_exact(&mut preface)?;
+        if preface != PREFACE {
+            // HTTP/1.1 clients and TLS handshakes
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        let writer = Arc::new(Mutex::new(Writer {
+            stream: stream.try_clone()?,
+            closed: false,
+            window: DEFAULT_WINDOW_SIZE,
+            initial_window: DEFAULT_WINDOW_SIZE,
+            max_frame: MAX_FRAME_SIZE,
+            streams: Vec::new(),
+        }));
+        let mut settings = Vec::new();
+        settings.extend_from_slice(&SETTINGS_MAX_CONCURRENT_STREAMS.to_be_bytes());
+        settings.extend_from_slice(&MAX_CONCURRENT_STREAMS.to_be_bytes());
+        lock(&writer).write(&frame(FRAME_SETTINGS, 0, 0, &settings))?;
+
+        let mut connection = Connection {
+            stream,
+            writer,
+            calls,
+            shared,
+            decoder: HpackDecoder::new(),
+            requests: Vec::new(),
+            continuation: None,
+            last_stream: 0,
+        };
+        let result = connection.run();
+        lock(&connection.writer).closed = true;
+        result
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            let mut header = [0u8; 9];
+            (&self.stream).read_exact(&mut header)?;
+            let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+            let (kind, flags) = (header[3], header[4]);
+            let id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+            if length > MAX_FRAME_SIZE {
+                return self.go_away(FRAME_SIZE_ERROR);
+            }
+            let mut payload = vec![0u8; length];
+            (&self.stream).read_exact(&mut payload)?;
+
+            if self
+                .continuation
+                .is_some_and(|continued| kind != FRAME_CONTINUATION || id != continued)
+            {
+                return self.go_away(PROTOCOL_ERROR);
+            }
+            match kind {
+                FRAME_SETTINGS if flags & FLAG_ACK == 0 => self.settings(&payload)?,
+                FRAME_PING if flags & FLAG_ACK == 0 && length == 8 => {
+                    lock(&self.writer).write(&frame(FRAME_PING, FLAG_ACK, 0, &payload))?
+                }
+                FRAME_WINDOW_UPDATE if length == 4 => {
+                    let increment =
+                        u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                    lock(&self.writer).window_update(id, i64::from(increment & 0x7fff_ffff))?;
+                }
+                FRAME_HEADERS => self.headers(id, flags, &payload)?,
+                FRAME_CONTINUATION => self.continuation(id, flags, &payload)?,
+                FRAME_DATA => self.data(id, flags, &payload)?,
+                FRAME_RST_STREAM => {
+                    self.requests.retain(|request| request.id != id);
+                    lock(&self.writer).reset(id);
+                }
+                FRAME_GOAWAY => return Ok(()),
+                // Priorities, acknowledgements and unknown frames
+                _ => {}
+            }
+        }
+    }
+
+    fn settings(&mut self, payload: &[u8]) -> io::Result<()> {
+        let mut writer = lock(&self.writer);
+        for setting in payload.chunks_exact(6) {
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match u16::from_be_bytes([setting[0], setting[1]]) {
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let delta = i64::from(value) - writer.initial_window;
+                    writer.initial_window = i64::from(value);
+                    for stream in &mut writer.streams {
+                        stream.window += delta;
+                    }
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    writer.max_frame = (value as usize).clamp(MAX_FRAME_SIZE, 0xff_ffff)
+                }
+                _ => {}
+            }
+        }
+        writer.write(&frame(FRAME_SETTINGS, FLAG_ACK, 0, &[]))?;
+        writer.flush()
+    }
+
+    fn headers(&mut self, id: u32, flags: u8, payload: &[u8]) -> io::Result<()> {
+        let mut block = match unpad(flags, payload) {
+            Some(block) => block,
+            None => return self.go_away(PROTOCOL_ERROR),
+        };
+        if flags & FLAG_PRIORITY != 0 {
+            block = match block.get(5..) {
+                Some(block) => block,
+                None => return self.go_away(PROTOCOL_ERROR),
+            };
+        }
+        if id.is_multiple_of(2) {
+            return self.go_away(PROTOCOL_ERROR);
+        }
+
+        let end_stream = flags & FLAG_END_STREAM != 0;
+        match self.requests.iter_mut().find(|request| request.id == id) {
+            // Trailers of a request, which only end it
+            Some(request) => {
+                request.block = block.to_vec();
+                request.end_stream = end_stream;
+            }
+            None => {
+                if id <= self.last_stream {
+                    return self.go_away(PROTOCOL_ERROR);
+                }
+                self.last_stream = id;
+                if self.requests.len() >= MAX_CONCURRENT_STREAMS as usize {
+                    lock(&self.writer).write(&frame(
+                        FRAME_RST_STREAM,
+                        0,
+                        id,
+                        &REFUSED_STREAM.to_be_bytes(),
+                    ))?;
+                    return Ok(());
+                }
+                self.requests.push(Incoming {
+                    id,
+                    block: block.to_vec(),
+                    headers: None,
+                    body: Vec::new(),
+                    end_stream,
+                });
+                lock(&self.writer).open(id);
+            }
+        }
+        if flags & FLAG_END_HEADERS != 0 {
+            self.end_headers(id)
+        } else {
+            self.continuation = Some(id);
+            Ok(())
+        }
+    }
+
+    fn continuation(&mut self, id: u32, flags: u8, payload: &[u8]) -> io::Result<()> {
+        let request = match self.requests.iter_mut().find(|request| request.id == id) {
+            Some(request) if self.continuation == Some(id) => request,
+            _ => return self.go_away(PROTOCOL_ERROR),
+        };
+        if request.block.len() + payload.len() > MAX_MESSAGE_LENGTH {
+            return self.go_away(PROTOCOL_ERROR);
+        }
+        request.block.extend_from_slice(payload);
+        if flags & FLAG_END_HEADERS != 0 {
+            self.continuation = None;
+            self.end_headers(id)?;
+        }
+        Ok(())
+    }
+
+    /// Decode a complete header block, with the decoder state it changes
+    fn end_headers(&mut self, id: u32) -> io::Result<()> {
+        let index = match self.requests.iter().position(|request| request.id == id) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let block = std::mem::take(&mut self.requests[index].block);
+        let headers = match self.decoder.decode(&block) {
+            Some(headers) => headers,
+            None => return self.go_away(COMPRESSION_ERROR),
+        };
+        let request = &mut self.requests[index];
+        if request.headers.is_none() {
+            request.headers = Some(headers);
+        }
+        if request.end_stream {
+            let request = self.requests.remove(index);
+            self.dispatch(request)?;
+        }
+        Ok(())
+    }
+
+    fn data(&mut self, id: u32, flags: u8, payload: &[u8]) -> io::Result<()> {
+        let data = match unpad(flags, payload) {
+            Some(data) => data,
+            None => return self.go_away(PROTOCOL_ERROR),
+        };
+        let end_stream = flags & FLAG_END_STREAM != 0;
+        if !payload.is_empty() {
+            // Request messages are small, so the credit is given back right away
+            let increment = (payload.len() as u32).to_be_bytes();
+            let mut updates = frame(FRAME_WINDOW_UPDATE, 0, 0, &increment);
+            if !end_stream {
+                updates.extend(frame(FRAME_WINDOW_UPDATE, 0, id, &increment));
+            }
+            lock(&self.writer).write(&updates)?;
+        }
+
+        let index = match self.requests.iter().position(|request| request.id == id) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let request = &mut self.requests[index];
+        if request.body.len() + data.len() > MAX_MESSAGE_LENGTH + 5 {
+            self.requests.remove(index);
+            let message = "request message is too large";
+            return lock(&self.writer).failure(id, STATUS_RESOURCE_EXHAUSTED, message);
+        }
+        request.body.extend_from_slice(data);
+        if end_stream {
+            let request = self.requests.remove(index);
+            self.dispatch(request)?;
+        }
+        Ok(())
+    }
+
+    /// Start the call of a complete request
+    fn dispatch(&mut self, request: Incoming) -> io::Result<()> {
+        let id = request.id;
+        let headers = request.headers.unwrap_or_default();
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(known, _)| known == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let grpc = header("content-type").is_some_and(|t| t.starts_with("application/grpc"));
+        if header(":method") != Some("POST") || !grpc {
+            return lock(&self.writer).headers(id, &[(":status", "415")], true);
+        }
+
+        let path = header(":path").unwrap_or("");
+        let method = match path.strip_prefix(SERVICE_PATH) {
+            Some("GetStatus") => Method::GetStatus,
+            Some("RunTest") => Method::RunTest,
+            Some("Shutdown") => Method::Shutdown,
+            Some("StreamEvents") => {
+                let names = match unframe(&request.body).map(string_fields) {
+                    Ok(Some(names)) => names,
+                    Ok(None) => {
+                        let message = "invalid request message";
+                        return lock(&self.writer).failure(id, STATUS_INVALID_ARGUMENT, message);
+                    }
+                    Err((code, message)) => return lock(&self.writer).failure(id, code, &message),
+                };
+                let response = [(":status", "200"), ("content-type", "application/grpc")];
+                lock(&self.writer).headers(id, &response, false)?;
+                // The client may stay silent for as long as it listens
+                self.stream.set_read_timeout(None)?;
+                lock(&self.shared.subscribers).push(Subscriber {
+                    writer: Arc::clone(&self.writer),
+                    stream: id,
+                    names,
+                });
+                return Ok(());
+            }
+            _ => {
+                let message = format!("unknown method {}", path);
+                return lock(&self.writer).failure(id, STATUS_UNIMPLEMENTED, &message);
+            }
+        };
+
+        if matches!(method, Method::RunTest | Method::Shutdown)
+            && !self.shared.authorized(header("authorization"))
+        {
+            let message = "missing or invalid token";
+            return lock(&self.writer).failure(id, STATUS_UNAUTHENTICATED, message);
+        }
+        let message = match unframe(&request.body) {
+            Ok(message) => message.to_vec(),
+            Err((code, message)) => return lock(&self.writer).failure(id, code, &message),
+        };
+
+        let (reply, replies) = mpsc::channel();
+        let call = Call {
+            method,
+            message,
+            reply,
+        };
+        if self.calls.send(call).is_err() {
+            let message = "server is shutting down";
+            return lock(&self.writer).failure(id, STATUS_UNAVAILABLE, message);
+        }
+        let writer = Arc::clone(&self.writer);
+        thread::spawn(move || {
+            let reply = replies.recv_timeout(CALL_TIMEOUT).unwrap_or_else(|_| {
+                let message = "the UPS did not answer in time".to_string();
+                Err((STATUS_DEADLINE_EXCEEDED, message))
+            });
+            let _ = lock(&writer).respond(id, reply);
+        });
+        Ok(())
+    }
+
+    /// Tell the client about a connection error and close the connection
+    fn go_away(&mut self, code: u32) -> io::Result<()> {
+        let mut payload = self.last_stream.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.to_be_bytes());
+        let _ = lock(&self.writer).write(&frame(FRAME_GOAWAY, 0, 0, &payload));
+        Err(io::ErrorKind::InvalidData.into())
+    }
+}
+
+/// Sending side of a connection, shared by its reader, the calls and the event streams
+struct Writer {
+    stream: TcpStream,
+    closed: bool,
+    /// Flow control window of the connection
+    window: i64,
+    initial_window: i64,
+    max_frame: usize,
+    streams: Vec<Outgoing>,
+}
+
+/// Response stream, with the data and trailers waiting for flow control credit
+struct Outgoing {
+    id: u32,
+    window: i64,
+    pending: Vec<u8>,
+    trailers: Option<Vec<u8>>,
+}
+
+impl Writer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let result = self.stream.write_all(bytes);
+        if result.is_err() {
+            self.closed = true;
+        }
+        result
+    }
+
+    fn open(&mut self, id: u32) {
+        self.streams.push(Outgoing {
+            id,
+            window: self.initial_window,
+            pending: Vec::new(),
+            trailers: None,
+        });
+    }
+
+    fn is_open(&self, id: u32) -> bool {
+        !self.closed && self.streams.iter().any(|stream| stream.id == id)
+    }
+
+    fn reset(&mut self, id: u32) {
+        self.streams.retain(|stream| stream.id != id);
+    }
+
+    fn window_update(&mut self, id: u32, increment: i64) -> io::Result<()> {
+        if id == 0 {
+            self.window += increment;
+        } else if let Some(stream) = self.streams.iter_mut().find(|stream| stream.id == id) {
+            stream.window += increment;
+        }
+        self.flush()
+    }
+
+    /// Send a header block; trailers wait for the data queued before them
+    fn headers(&mut self, id: u32, fields: &[(&str, &str)], end_stream: bool) -> io::Result<()> {
+        let block = encode_headers(fields);
+        if end_stream {
+            match self.streams.iter_mut().find(|stream| stream.id == id) {
+                Some(stream) if !stream.pending.is_empty() => {
+                    stream.trailers = Some(block);
+                    return Ok(());
+                }
+                _ => self.reset(id),
+            }
+        }
+        let flags = FLAG_END_HEADERS | if end_stream { FLAG_END_STREAM } else { 0 };
+        self.write(&frame(FRAME_HEADERS, flags, id, &block))
+    }
+
+    /// Queue data on a stream and send what the windows allow, false if the stream is gone
+    fn data(&mut self, id: u32, data: &[u8]) -> io::Result<bool> {
+        let stream = match self.streams.iter_mut().find(|stream| stream.id == id) {
+            Some(stream) if !self.closed => stream,
+            _ => return Ok(false),
+        };
+        if stream.pending.len() + data.len() > MAX_PENDING_LENGTH {
+            self.reset(id);
+            self.write(&frame(FRAME_RST_STREAM, 0, id, &CANCEL.to_be_bytes()))?;
+            return Ok(false);
+        }
+        stream.pending.extend_from_slice(data);
+        self.flush()?;
+        Ok(true)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut output = Vec::new();
+        let max_frame = self.max_frame;
+        let window = &mut self.window;
+        self.streams.retain_mut(|stream| {
+            while !stream.pending.is_empty() && stream.window > 0 && *window > 0 {
+                let length = stream
+                    .pending
+                    .len()
+                    .min(max_frame)
+                    .min(stream.window as usize)
+                    .min(*window as usize);
+                output.extend(frame(FRAME_DATA, 0, stream.id, &stream.pending[..length]));
+                stream.pending.drain(..length);
+                stream.window -= length as i64;
+                *window -= length as i64;
+            }
+            match &stream.trailers {
+                Some(trailers) if stream.pending.is_empty() => {
+                    let flags = FLAG_END_HEADERS | FLAG_END_STREAM;
+                    output.extend(frame(FRAME_HEADERS, flags, stream.id, trailers));
+                    false
+                }
+                _ => true,
+            }
+        });
+        self.write(&output)
+    }
+
+    /// Answer a unary call with its message and an OK status, or with its failure
+    fn respond(&mut self, id: u32, reply: Reply) -> io::Result<()> {
+        if !self.is_open(id) {
+            // Cancelled by the client meanwhile
+            return Ok(());
+        }
+        match reply {
+            Ok(message) => {
+                let response = [(":status", "200"), ("content-type", "application/grpc")];
+                self.headers(id, &response, false)?;
+                self.data(id, &grpc_message(&message))?;
+                self.headers(id, &[("grpc-status", "0")], true)
+            }
+            Err((code, message)) => self.failure(id, code, &message),
+        }
+    }
+
+    /// End a call with a trailers-only response carrying a gRPC status
+    fn failure(&mut self, id: u32, code: u32, message: &str) -> io::Result<()> {
+        let code = code.to_string();
+        let message = percent_encode(message);
+        let response = [
+            (":status", "200"),
+            ("content-type", "application/grpc"),
+            ("grpc-status", code.as_str()),
+            ("grpc-message", message.as_str()),
+        ];
+        self.headers(id, &response, true)
+    }
+}
+
+fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(9 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    frame.push(kind);
+    frame.push(flags);
+    frame.extend_from_slice(&stream.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Strip the padding of a DATA or HEADERS frame
+fn unpad(flags: u8, payload: &[u8]) -> Option<&[u8]> {
+    if flags & FLAG_PADDED == 0 {
+        return Some(payload);
+    }
+    let padding = *payload.first()? as usize;
+    payload.get(1..payload.len().checked_sub(padding)?)
+}
+
+/// Get the message of a length-prefixed gRPC request body
+fn unframe(body: &[u8]) -> std::result::Result<&[u8], (u32, String)> {
+    match body {
+        [0, a, b, c, d, message @ ..] => {
+            if message.len() == u32::from_be_bytes([*a, *b, *c, *d]) as usize {
+                Ok(message)
+            } else {
+                Err((STATUS_INTERNAL, "truncated request message".to_string()))
+            }
+        }
+        [1, ..] => Err((
+            STATUS_UNIMPLEMENTED,
+            "compressed messages are not supported".to_string(),
+        )),
+        _ => Err((STATUS_INTERNAL, "missing request message".to_string())),
+    }
+}
+
+/// Prefix a message with its compression flag and length
+fn grpc_message(message: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(5 + message.len());
+    framed.push(0);
+    framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    framed.extend_from_slice(message);
+    framed
+}
+
+fn status_of(error: UpsError) -> (u32, String) {
+    let code = match error {
+        UpsError::InvalidTime => STATUS_INVALID_ARGUMENT,
+        UpsError::NoShutdownPending => STATUS_FAILED_PRECONDITION,
+        UpsError::Unsupported => STATUS_UNIMPLEMENTED,
+        UpsError::Cancelled => STATUS_CANCELLED,
+        _ => STATUS_UNAVAILABLE,
+    };
+    (code, error.to_string())
+}
+
+/// Percent-encode a `grpc-message` value
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::new();
+    for byte in message.bytes().take(1024) {
+        if (0x20..=0x7e).contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// `Status` message of the proto file
+fn encode_status(status: &UpsStatus) -> Vec<u8> {
+    let mut message = Vec::new();
+    for (field, value) in [
+        (1, status.input_voltage),
+        (2, status.input_fault_voltage),
+        (3, status.output_voltage),
+        (4, status.load_raw),
+        (5, status.input_frequency),
+        (6, status.battery_voltage),
+        (7, status.temperature),
+    ] {
+        // Zero is the default and left out, as by every protobuf encoder
+        if value != 0.0 {
+            put_key(&mut message, field, 1);
+            message.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    let flags = &status.flags;
+    for (field, set) in [
+        (8, flags.utility_fail),
+        (9, flags.battery_low),
+        (10, flags.bypass_active),
+        (11, flags.ups_failed),
+        (12, flags.standby),
+        (13, flags.test_in_progress),
+        (14, flags.shutdown_active),
+        (15, flags.beeper_on),
+    ] {
+        if set {
+            put_varint_field(&mut message, field, 1);
+        }
+    }
+    message
+}
+
+/// `Event` message of the proto file
+fn encode_event(event: &UpsEvent, time: SystemTime) -> Vec<u8> {
+    let mut message = Vec::new();
+    put_bytes_field(&mut message, 1, event.name().as_bytes());
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    put_varint_field(&mut message, 2, millis as u64);
+    if let Some(status) = event.status() {
+        put_bytes_field(&mut message, 3, &encode_status(status));
+    }
+    message
+}
+
+/// Value of a protobuf field, by wire type
+enum Wire<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32,
+}
+
+/// Split a protobuf message into its fields, `None` if it is malformed
+fn decode_message(message: &[u8]) -> Option<Vec<(u64, Wire<'_>)>> {
+    let mut fields = Vec::new();
+    let mut position = 0;
+    while position < message.len() {
+        let key = varint(message, &mut position)?;
+        let value = match key & 7 {
+            0 => Wire::Varint(varint(message, &mut position)?),
+            1 => {
+                let bytes = message.get(position..position + 8)?;
+                position += 8;
+                Wire::Fixed64(u64::from_le_bytes(bytes.try_into().ok()?))
+            }
+            2 => {
+                let length = usize::try_from(varint(message, &mut position)?).ok()?;
+                let bytes = message.get(position..position.checked_add(length)?)?;
+                position += length;
+                Wire::Bytes(bytes)
+            }
+            5 => {
+                message.get(position..position + 4)?;
+                position += 4;
+                Wire::Fixed32
+            }
+            _ => return None,
+        };
+        fields.push((key >> 3, value));
+    }
+    Some(fields)
+}
+
+/// Last value of a varint field, 0 when absent as in proto3
+fn varint_field(fields: &[(u64, Wire)], number: u64) -> u64 {
+    fields
+        .iter()
+        .rev()
+        .find_map(|(field, value)| match value {
+            Wire::Varint(value) if *field == number => Some(*value),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+/// Last value of a double field, 0 when absent as in proto3
+fn double_field(fields: &[(u64, Wire)], number: u64) -> f64 {
+    fields
+        .iter()
+        .rev()
+        .find_map(|(field, value)| match value {
+            Wire::Fixed64(bits) if *field == number => Some(f64::from_bits(*bits)),
+            _ => None,
+        })
+        .unwrap_or(0.0)
+}
+
+/// Values of the repeated string field 1, `None` if the message is malformed
+fn string_fields(message: &[u8]) -> Option<Vec<String>> {
+    let mut strings = Vec::new();
+    for (field, value) in decode_message(message)? {
+        if let (1, Wire::Bytes(bytes)) = (field, value) {
+            strings.push(String::from_utf8(bytes.to_vec()).ok()?);
+        }
+    }
+    Some(strings)
+}
+
+fn varint(data: &[u8], position: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*position)?;
+        *position += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_key(out: &mut Vec<u8>, field: u64, wire_type: u64) {
+    put_varint(out, field << 3 | wire_type);
+}
+
+fn put_varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+    put_key(out, field, 0);
+    put_varint(out, value);
+}
+
+fn put_bytes_field(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_key(out, field, 2);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Encode response headers as literals that the decoder does not index
+fn encode_headers(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in fields {
+        if (*name, *value) == (":status", "200") {
+            // Index 8 of the static table
+            block.push(0x88);
+            continue;
+        }
+        block.push(0x00);
+        for text in [name, value] {
+            put_integer(&mut block, 0x00, 7, text.len());
+            block.extend_from_slice(text.as_bytes());
+        }
+    }
+    block
+}
+
+/// HPACK integer with an N-bit prefix, sharing its first byte with `flags`
+fn put_integer(out: &mut Vec<u8>, flags: u8, prefix: u32, mut value: usize) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// HPACK decoder with the dynamic table of the client's header blocks
+struct HpackDecoder {
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl HpackDecoder {
+    fn new() -> Self {
+        Self {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: HEADER_TABLE_SIZE,
+        }
+    }
+
+    /// Decode a header block, `None` if it is malformed
+    fn decode(&mut self, block: &[u8]) -> Option<Vec<(String, String)>> {
+        let mut headers = Vec::new();
+        let mut position = 0;
+        while position < block.len() {
+            let byte = block[position];
+            if byte & 0x80 != 0 {
+                let index = integer(block, &mut position, 7)?;
+                headers.push(self.entry(index)?);
+            } else if byte & 0x40 != 0 {
+                let header = self.literal(block, &mut position, 6)?;
+                self.insert(header.clone());
+                headers.push(header);
+            } else if byte & 0x20 != 0 {
+                let size = integer(block, &mut position, 5)?;
+                if size > HEADER_TABLE_SIZE {
+                    return None;
+                }
+                self.max_size = size;
+                self.evict();
+            } else {
+                // Literals without indexing and never indexed
+                headers.push(self.literal(block, &mut position, 4)?);
+            }
+        }
+        Some(headers)
+    }
+
+    fn literal(&self, block: &[u8], position: &mut usize, prefix: u32) -> Option<(String, String)> {
+        let name = match integer(block, position, prefix)? {
+            0 => string(block, position)?,
+            index => self.entry(index)?.0,
+        };
+        Some((name, string(block, position)?))
+    }
+
+    fn entry(&self, index: usize) -> Option<(String, String)> {
+        match index {
+            0 => None,
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Some((name.to_string(), value.to_string()))
+            }
+            _ => self.table.get(index - 62).cloned(),
+        }
+    }
+
+    fn insert(&mut self, header: (String, String)) {
+        self.size += header.0.len() + header.1.len() + 32;
+        self.table.push_front(header);
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            match self.table.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + 32,
+                None => break,
+            }
+        }
+    }
+}
+
+/// HPACK integer with an N-bit prefix
+fn integer(block: &[u8], position: &mut usize, prefix: u32) -> Option<usize> {
+    let max = (1usize << prefix) - 1;
+    let mut value = *block.get(*position)? as usize & max;
+    *position += 1;
+    if value < max {
+        return Some(value);
+    }
+    for shift in (0..=28).step_by(7) {
+        let byte = *block.get(*position)?;
+        *position += 1;
+        value += ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// HPACK string literal, Huffman coded or raw
+fn string(block: &[u8], position: &mut usize) -> Option<String> {
+    let huffman = *block.get(*position)? & 0x80 != 0;
+    let length = integer(block, position, 7)?;
+    let bytes = block.get(*position..position.checked_add(length)?)?;
+    *position += length;
+    let bytes = if huffman {
+        huffman_decode(bytes)?
+    } else {
+        bytes.to_vec()
+    };
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Decode the canonical Huffman code of HPACK, given by its code lengths
+fn huffman_decode(data: &[u8]) -> Option<Vec<u8>> {
+    // Canonical codes count up within a length, shorter codes first
+    let mut symbols: Vec<u16> = (0..=256).collect();
+    symbols.sort_by_key(|&symbol| HUFFMAN_CODE_LENGTHS[symbol as usize]);
+    let mut count = [0u32; 31];
+    for &length in HUFFMAN_CODE_LENGTHS.iter() {
+        count[length as usize] += 1;
+    }
+    let mut first_code = [0u32; 31];
+    let mut first_index = [0u32; 31];
+    let mut code = 0;
+    for length in 1..31 {
+        code = (code + count[length - 1]) << 1;
+        first_code[length] = code;
+        first_index[length] = first_index[length - 1] + count[length - 1];
+    }
+
+    let mut decoded = Vec::new();
+    let (mut code, mut length) = (0u32, 0usize);
+    for byte in data {
+        for bit in (0..8).rev() {
+            code = code << 1 | u32::from(byte >> bit & 1);
+            length += 1;
+            if length > 30 {
+                return None;
+            }
+            if code >= first_code[length] && code - first_code[length] < count[length] {
+                let index = first_index[length] + code - first_code[length];
+                match symbols[index as usize] {
+                    256 => return None, // EOS must not appear in the data
+                    symbol => decoded.push(symbol as u8),
+                }
+                code = 0;
+                length = 0;
+            }
+        }
+    }
+    // Padding is a prefix of EOS, all ones and shorter than a byte
+    if length > 7 || code != (1 << length) - 1 {
+        return None;
+    }
+    Some(decoded)
+}
+
+/// HPACK static table, RFC 7541 appendix A
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Bit length of the Huffman code of every byte and EOS, RFC 7541 appendix B
+const HUFFMAN_CODE_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, //
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28, //
+    6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, //
+    5, 5, 5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, //
+    13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, //
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, //
+    15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6, 6, 5, //
+    6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, //
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23, //
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, //
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, //
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23, //
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, //
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, //
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23, //
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, //
+    30,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MegatecUps, MockTransport};
+
+    fn hex(text: &str) -> Vec<u8> {
+        let digits: Vec<u8> = text.bytes().filter(u8::is_ascii_hexdigit).collect();
+        digits
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn hpack_integers_follow_rfc_7541() {
+        for (bytes, prefix, value) in [
+            (vec![0x0a], 5, 10),
+            (vec![0x1f, 0x9a, 0x0a], 5, 1337),
+            (vec![0x2a], 8, 42),
+        ] {
+            let mut position = 0;
+            assert_eq!(integer(&bytes, &mut position, prefix), Some(value));
+            assert_eq!(position, bytes.len());
+
+            let mut encoded = Vec::new();
+            put_integer(&mut encoded, 0, prefix, value);
+            assert_eq!(encoded, bytes);
+        }
+    }
+
+    #[test]
+    fn hpack_decodes_the_rfc_7541_requests() {
+        let mut decoder = HpackDecoder::new();
+        let first = hex("8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d");
+        assert_eq!(
+            decoder.decode(&first),
+            Some(headers(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ]))
+        );
+
+        // Huffman coded, reusing the :authority of the dynamic table
+        let mut decoder = HpackDecoder::new();
+        let first = hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff");
+        assert_eq!(decoder.decode(&first).unwrap()[3].1, "www.example.com");
+        let second = hex("8286 84be 5886 a8eb 1064 9cbf");
+        assert_eq!(
+            decoder.decode(&second),
+            Some(headers(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn hpack_round_trips_the_encoded_headers() {
+        let fields = [(":status", "200"), ("content-type", "application/grpc")];
+        let block = encode_headers(&fields);
+        assert_eq!(HpackDecoder::new().decode(&block), Some(headers(&fields)));
+    }
+
+    #[test]
+    fn malformed_header_blocks_are_rejected() {
+        for block in [
+            // Index 0 and an index past the dynamic table
+            vec![0x80],
+            vec![0xc0],
+            // Integer continuing past the limit
+            vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+            vec![0xff, 0x80],
+            // String longer than the block
+            vec![0x40, 0x05, b'a'],
+            vec![0x00, 0x01, b'a', 0x7f],
+            // Huffman string with a bad padding, then with EOS
+            vec![0x00, 0x81, 0x00, 0x00],
+            vec![0x00, 0x84, 0xff, 0xff, 0xff, 0xff, 0x00],
+            // Table size above the advertised one
+            vec![0x3f, 0xe2, 0x1f],
+        ] {
+            assert_eq!(HpackDecoder::new().decode(&block), None, "{:02x?}", block);
+        }
+    }
+
+    #[test]
+    fn dynamic_table_evicts_beyond_its_size() {
+        let mut decoder = HpackDecoder::new();
+        // Table size update to 40, one 32 + 2 byte entry fits
+        assert_eq!(decoder.decode(&[0x3f, 0x09]), Some(Vec::new()));
+        decoder.decode(&[0x40, 0x01, b'a', 0x01, b'b']).unwrap();
+        assert_eq!(decoder.entry(62), Some(("a".to_string(), "b".to_string())));
+        decoder.decode(&[0x40, 0x01, b'c', 0x01, b'd']).unwrap();
+        assert_eq!(decoder.entry(62), Some(("c".to_string(), "d".to_string())));
+        assert_eq!(decoder.entry(63), None);
+    }
+
+    #[test]
+    fn protobuf_fields_round_trip() {
+        let mut message = Vec::new();
+        put_varint_field(&mut message, 1, 300);
+        put_bytes_field(&mut message, 2, b"minutes");
+        put_key(&mut message, 3, 1);
+        message.extend_from_slice(&2.5f64.to_le_bytes());
+        put_varint_field(&mut message, 1, u64::MAX);
+
+        let fields = decode_message(&message).unwrap();
+        assert_eq!(fields.len(), 4);
+        assert_eq!(varint_field(&fields, 1), u64::MAX);
+        assert_eq!(varint_field(&fields, 9), 0);
+        assert_eq!(double_field(&fields, 3), 2.5);
+        assert!(matches!(fields[1], (2, Wire::Bytes(b"minutes"))));
+    }
+
+    #[test]
+    fn status_messages_decode_to_the_reading() {
+        let ups = MegatecUps::with_transport(MockTransport::online());
+        let status = ups.get_status().unwrap();
+        let message = encode_status(&status);
+        let fields = decode_message(&message).unwrap();
+        assert_eq!(double_field(&fields, 1), status.input_voltage);
+        assert_eq!(double_field(&fields, 4), status.load_raw);
+        assert_eq!(varint_field(&fields, 8), 0);
+        assert_eq!(varint_field(&fields, 12), 1);
+    }
+
+    #[test]
+    fn malformed_protobuf_messages_are_rejected() {
+        for message in [
+            // Truncated varint, and one longer than ten bytes
+            vec![0x08, 0x80],
+            vec![
+                0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+            ],
+            // Bytes longer than the message, with a huge length
+            vec![0x12, 0x05, b'a'],
+            vec![0x12, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
+            // Truncated fixed64 and fixed32
+            vec![0x19, 0x00, 0x00],
+            vec![0x1d, 0x00],
+            // Group wire types
+            vec![0x0b],
+            vec![0x0c],
+        ] {
+            assert!(decode_message(&message).is_none(), "{:02x?}", message);
+        }
+        assert_eq!(string_fields(&[0x0a, 0x01, 0xff]), None);
+        assert_eq!(
+            string_fields(&[0x0a, 0x02, b'o', b'k']),
+            Some(vec!["ok".to_string()])
+        );
+    }
+
+    #[test]
+    fn request_bodies_are_unframed() {
+        assert_eq!(unframe(&[0, 0, 0, 0, 2, 8, 1]), Ok(&[8u8, 1][..]));
+        assert!(unframe(&[0, 0, 0, 0, 3, 8, 1]).is_err());
+        assert_eq!(
+            unframe(&[1, 0, 0, 0, 0]).unwrap_err().0,
+            STATUS_UNIMPLEMENTED
+        );
+        assert!(unframe(&[0, 0]).is_err());
+        assert!(unframe(&[]).is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use crate::battery::BatteryModel;
+use crate::status::UpsStatus;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// Longest gap between two samples still counted as the same discharge
+const MAX_SAMPLE_GAP: Duration = Duration::from_secs(10 * 60);
+
+/// Smallest charge drop a discharge needs to project its runtime
+const MIN_PROJECTED_DEPTH: f64 = 0.05;
+
+/// Resting voltage drop per cell over the history that hints at ageing cells
+const RESTING_DECLINE_PER_CELL: f64 = 0.05;
+
+/// Growth of the full-load sag between the first and last discharge
+const SAG_GROWTH_LIMIT: f64 = 1.5;
+
+/// Remaining capacity below which the battery is considered worn out
+const CAPACITY_LIMIT: f64 = 0.8;
+
+/// One stretch of samples taken on battery
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discharge {
+    /// Time of the first sample on battery
+    pub start: SystemTime,
+    /// Time between the first and last sample on battery
+    pub duration: Duration,
+    /// Battery voltage of the last sample before the discharge, if any
+    pub resting_voltage: Option<f64>,
+    /// Battery voltage of the first sample on battery
+    pub start_voltage: f64,
+    /// Battery voltage of the last sample on battery
+    pub end_voltage: f64,
+    /// Average load in percent, `NaN` when the UPS reported it in amps
+    pub average_load: f64,
+    /// Drop of the state of charge, from 0.0 to 1.0
+    pub depth: f64,
+}
+
+impl Discharge {
+    /// Voltage drop per cell when the load moved to the battery, scaled to 100% load
+    pub fn full_load_sag(&self, model: &BatteryModel) -> Option<f64> {
+        let load = self.average_load / 100.0;
+        let sag = model.per_cell(self.resting_voltage?) - model.per_cell(self.start_voltage);
+        (load > 0.0 && sag.is_finite()).then(|| sag.max(0.0) / load)
+    }
+
+    /// Runtime at 100% load the discharge projects for a full battery
+    ///
+    /// Returns `None` for discharges too shallow to extrapolate, such as
+    /// 10-second tests, and without a load in percent.
+    pub fn projected_runtime(&self, model: &BatteryModel) -> Option<Duration> {
+        if self.depth < MIN_PROJECTED_DEPTH
+            || self.duration.is_zero()
+            || !self.average_load.is_finite()
+        {
+            return None;
+        }
+        let load = (self.average_load / 100.0).max(0.05);
+        let factor = load.powf(model.chemistry.peukert_exponent()) / self.depth;
+        Some(self.duration.mul_f64(factor))
+    }
+}
+
+/// Signs that the battery is wearing out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthWarning {
+    /// The battery voltage on utility power keeps falling
+    RestingVoltageDeclining,
+    /// The voltage drops more and more when the load moves to the battery
+    VoltageSagIncreasing,
+    /// Discharges last noticeably shorter than the battery model expects
+    RuntimeShrinking,
+}
+
+impl fmt::Display for HealthWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HealthWarning::RestingVoltageDeclining => "resting battery voltage is declining",
+            HealthWarning::VoltageSagIncreasing => "voltage sag under load is increasing",
+            HealthWarning::RuntimeShrinking => "runtime per discharge is shrinking",
+        })
+    }
+}
+
+/// Battery health indicators computed from recorded samples
+///
+/// Samples on utility power give the resting voltage trend, and every stretch
+/// of samples on battery is a [`Discharge`] giving the voltage sag and the
+/// runtime it projects at full load. The battery is likely due for
+/// replacement when any [`HealthWarning`] is raised, which needs a history
+/// spanning a few discharges to be meaningful.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatteryHealthReport {
+    /// Number of samples analyzed
+    pub samples: usize,
+    /// Resting battery voltage change in volts per cell and day, from a least
+    /// squares fit
+    pub resting_voltage_trend: Option<f64>,
+    /// Discharges found in the samples, oldest first
+    pub discharges: Vec<Discharge>,
+    /// Full-load sag per cell of the first and last discharge with one
+    pub voltage_sag: Option<(f64, f64)>,
+    /// Projected full-load runtime of the last deep enough discharge
+    /// compared to the runtime of the battery model
+    pub capacity: Option<f64>,
+    /// Warnings raised by the indicators
+    pub warnings: Vec<HealthWarning>,
+}
+
+impl BatteryHealthReport {
+    /// Analyze samples sorted by time
+    pub fn from_samples(samples: &[(SystemTime, UpsStatus)], model: &BatteryModel) -> Self {
+        let resting: Vec<(SystemTime, f64)> = samples
+            .iter()
+            .filter(|(_, status)| !status.flags.on_battery() && !status.flags.test_in_progress)
+            .map(|(time, status)| (*time, model.per_cell(status.battery_voltage)))
+            .filter(|(_, voltage)| voltage.is_finite())
+            .collect();
+        let resting_voltage_trend = trend(&resting);
+        let discharges = discharges(samples, model);
+
+        let sags: Vec<f64> = discharges
+            .iter()
+            .filter_map(|discharge| discharge.full_load_sag(model))
+            .collect();
+        let voltage_sag = match (sags.first(), sags.last()) {
+            (Some(first), Some(last)) if sags.len() > 1 => Some((*first, *last)),
+            _ => None,
+        };
+        let capacity = discharges
+            .iter()
+            .rev()
+            .find_map(|discharge| discharge.projected_runtime(model))
+            .map(|runtime| runtime.as_secs_f64() / model.full_load_runtime.as_secs_f64());
+
+        let mut warnings = Vec::new();
+        let span_days = match (resting.first(), resting.last()) {
+            (Some((first, _)), Some((last, _))) => last
+                .duration_since(*first)
+                .map(|span| span.as_secs_f64() / 86400.0)
+                .unwrap_or(0.0),
+            _ => 0.0,
+        };
+        if resting_voltage_trend.is_some_and(|trend| -trend * span_days > RESTING_DECLINE_PER_CELL)
+        {
+            warnings.push(HealthWarning::RestingVoltageDeclining);
+        }
+        if voltage_sag.is_some_and(|(first, last)| last > first * SAG_GROWTH_LIMIT) {
+            warnings.push(HealthWarning::VoltageSagIncreasing);
+        }
+        if capacity.is_some_and(|capacity| capacity < CAPACITY_LIMIT) {
+            warnings.push(HealthWarning::RuntimeShrinking);
+        }
+
+        Self {
+            samples: samples.len(),
+            resting_voltage_trend,
+            discharges,
+            voltage_sag,
+            capacity,
+            warnings,
+        }
+    }
+
+    /// Check if the battery is likely due for replacement
+    pub fn replacement_due(&self) -> bool {
+        !self.warnings.is_empty()
+    }
+}
+
+impl fmt::Display for BatteryHealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Samples: {}", self.samples)?;
+        match self.resting_voltage_trend {
+            Some(trend) => writeln!(f, "Resting Voltage Trend: {:+.3} V/cell/day", trend)?,
+            None => writeln!(f, "Resting Voltage Trend: unknown")?,
+        }
+        writeln!(f, "Discharges: {}", self.discharges.len())?;
+        match self.voltage_sag {
+            Some((first, last)) => writeln!(f, "Voltage Sag: {:.3} -> {:.3} V/cell", first, last)?,
+            None => writeln!(f, "Voltage Sag: unknown")?,
+        }
+        match self.capacity {
+            Some(capacity) => writeln!(f, "Capacity: {:.0}%", capacity * 100.0)?,
+            None => writeln!(f, "Capacity: unknown")?,
+        }
+        if self.warnings.is_empty() {
+            write!(f, "Battery: OK")
+        } else {
+            let warnings: Vec<String> = self.warnings.iter().map(|w| w.to_string()).collect();
+            write!(f, "Battery: replacement due ({})", warnings.join(", "))
+        }
+    }
+}
+
+/// Split the samples into discharges
+fn discharges(samples: &[(SystemTime, UpsStatus)], model: &BatteryModel) -> Vec<Discharge> {
+    let mut discharges = Vec::new();
+    let mut resting_voltage = None;
+    let mut run: Vec<&(SystemTime, UpsStatus)> = Vec::new();
+
+    for sample in samples {
+        let (time, status) = sample;
+        let gap = run
+            .last()
+            .and_then(|(last, _)| time.duration_since(*last).ok())
+            .is_some_and(|gap| gap > MAX_SAMPLE_GAP);
+        if !status.flags.on_battery() || gap {
+            if let Some(discharge) = discharge(&run, resting_voltage, model) {
+                discharges.push(discharge);
+            }
+            run.clear();
+        }
+        if status.flags.on_battery() {
+            run.push(sample);
+        } else if status.battery_voltage.is_finite() {
+            resting_voltage = Some(status.battery_voltage);
+        } else {
+            resting_voltage = None;
+        }
+    }
+    if let Some(discharge) = discharge(&run, resting_voltage, model) {
+        discharges.push(discharge);
+    }
+    discharges
+}
+
+/// Summarize a run of samples on battery
+fn discharge(
+    run: &[&(SystemTime, UpsStatus)],
+    resting_voltage: Option<f64>,
+    model: &BatteryModel,
+) -> Option<Discharge> {
+    let (start, first) = run.first()?;
+    let (end, last) = run.last()?;
+    // A load in amps says nothing about the percentage without the rating
+    let loads: Vec<f64> = run
+        .iter()
+        .filter(|(_, status)| !status.load_in_amps)
+        .map(|(_, status)| status.load_raw)
+        .filter(|load| load.is_finite())
+        .collect();
+    let average_load = match loads.len() {
+        0 => f64::NAN,
+        count => loads.iter().sum::<f64>() / count as f64,
+    };
+
+    let depth = match (model.state_of_charge(first), model.state_of_charge(last)) {
+        (Some(from), Some(to)) => (from - to).max(0.0),
+        _ => 0.0,
+    };
+
+    Some(Discharge {
+        start: *start,
+        duration: end.duration_since(*start).unwrap_or_default(),
+        resting_voltage,
+        start_voltage: first.battery_voltage,
+        end_voltage: last.battery_voltage,
+        average_load,
+        depth,
+    })
+}
+
+/// Slope of a least squares fit in units per day
+fn trend(points: &[(SystemTime, f64)]) -> Option<f64> {
+    let (origin, _) = points.first()?;
+    let days: Vec<(f64, f64)> = points
+        .iter()
+        .map(|(time, value)| {
+            let offset = time.duration_since(*origin).unwrap_or_default();
+            (offset.as_secs_f64() / 86400.0, *value)
+        })
+        .collect();
+
+    let count = days.len() as f64;
+    let mean_x = days.iter().map(|(x, _)| x).sum::<f64>() / count;
+    let mean_y = days.iter().map(|(_, y)| y).sum::<f64>() / count;
+    let covariance: f64 = days.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    let variance: f64 = days.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    (variance > 0.0).then(|| covariance / variance)
+}
@@ -0,0 +1,285 @@
+use crate::error::{Result, UpsError};
+use crate::protocol::{command_text, decode_response, expects_response};
+use crate::transport::{Command, Response, Transport};
+use crate::usb::UsbTransport;
+use crate::wire::WireLog;
+use rusb::{Context, Device, DeviceHandle, Direction, TransferType, UsbContext};
+use std::io;
+use std::time::Duration;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+const USB_CLASS_HID: u8 = 0x03;
+const HID_SET_REPORT: u8 = 0x09;
+/// Output report 0, as written by the Cypress and Phoenixtec bridges
+const HID_OUTPUT_REPORT: u16 = 0x0200;
+const REPORT_SIZE: usize = 8;
+const MAX_RESPONSE_LENGTH: usize = 256;
+const CHAR_CR: u8 = b'\r';
+/// String descriptor answering the status query in the Mega(USB) protocol
+const STATUS_DESCRIPTOR: u8 = 3;
+
+/// How a USB bridge exchanges Megatec commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbProtocol {
+    /// Commands mapped to string descriptor reads (Mega(USB), [`UsbTransport`])
+    StringDescriptor,
+    /// Serial commands written as HID output reports and answered on the
+    /// interrupt IN endpoint ([`HidTransport`])
+    HidReport,
+}
+
+impl UsbProtocol {
+    /// Find out the protocol of a device from its descriptors
+    ///
+    /// Devices with an HID interface and an interrupt IN endpoint that have no
+    /// status string descriptor speak [`HidReport`](Self::HidReport).
+    pub fn detect(vendor_id: u16, product_id: u16) -> Result<Self> {
+        let context = Context::new()?;
+        let device = find_device(&context, vendor_id, product_id)?;
+        if hid_endpoint(&device).is_none() {
+            return Ok(UsbProtocol::StringDescriptor);
+        }
+
+        let handle = device.open()?;
+        match handle.read_string_descriptor_ascii(STATUS_DESCRIPTOR) {
+            Ok(text) if text.len() >= 3 => Ok(UsbProtocol::StringDescriptor),
+            _ => Ok(UsbProtocol::HidReport),
+        }
+    }
+}
+
+/// Open a USB device with the transport matching its [`UsbProtocol`]
+pub fn open_usb(vendor_id: u16, product_id: u16) -> Result<Box<dyn Transport + Send>> {
+    Ok(match UsbProtocol::detect(vendor_id, product_id)? {
+        UsbProtocol::StringDescriptor => Box::new(UsbTransport::open(vendor_id, product_id)?),
+        UsbProtocol::HidReport => Box::new(HidTransport::open(vendor_id, product_id)?),
+    })
+}
+
+/// Transport writing serial commands as HID output reports
+///
+/// Used by Cypress style USB to serial bridges: the command and its carriage
+/// return are sent in 8-byte SET_REPORT control transfers and the answer is
+/// read from the interrupt IN endpoint until the carriage return arrives.
+/// The HID interface is claimed, detaching the kernel driver where libusb
+/// supports it, and released when the transport is dropped.
+pub struct HidTransport {
+    handle: DeviceHandle<Context>,
+    interface: u8,
+    endpoint: u8,
+    claimed: bool,
+    timeout: Duration,
+    vendor_id: u16,
+    product_id: u16,
+    wire_log: Option<WireLog>,
+}
+
+impl HidTransport {
+    /// Open the USB device with the given vendor_id and product_id
+    pub fn open(vendor_id: u16, product_id: u16) -> Result<Self> {
+        Self::open_with_timeout(vendor_id, product_id, DEFAULT_TIMEOUT)
+    }
+
+    /// Open the USB device using the given transfer timeout
+    pub fn open_with_timeout(vendor_id: u16, product_id: u16, timeout: Duration) -> Result<Self> {
+        let context = Context::new()?;
+        let device = find_device(&context, vendor_id, product_id)?;
+        let (interface, endpoint) = hid_endpoint(&device).ok_or(UpsError::Unsupported)?;
+
+        let handle = device.open()?;
+        // Not supported on every platform, claiming reports the actual failure
+        let _ = handle.set_auto_detach_kernel_driver(true);
+        handle.claim_interface(interface)?;
+
+        Ok(Self {
+            handle,
+            interface,
+            endpoint,
+            claimed: true,
+            timeout,
+            vendor_id,
+            product_id,
+            wire_log: None,
+        })
+    }
+
+    /// Get the vendor ID of the device
+    pub fn vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+
+    /// Get the product ID of the device
+    pub fn product_id(&self) -> u16 {
+        self.product_id
+    }
+
+    /// Get the transfer timeout
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Set the transfer timeout
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Log every output report written and the bytes read back to `log`
+    pub fn set_wire_log(&mut self, log: WireLog) {
+        self.wire_log = Some(log);
+    }
+
+    /// Send a command line that the crate does not wrap, such as a vendor specific query
+    ///
+    /// The carriage return is appended. With `expect_response` the answer line
+    /// is read back, otherwise an empty response is returned right away.
+    pub fn send_raw_command(&self, text: &str, expect_response: bool) -> Result<Response> {
+        self.exchange(text, expect_response, self.timeout)
+    }
+
+    /// Write a command line and read the answer, waiting up to `timeout` per transfer
+    fn exchange(&self, text: &str, expect_response: bool, timeout: Duration) -> Result<Response> {
+        if text.contains(char::from(CHAR_CR)) {
+            let error = io::Error::new(io::ErrorKind::InvalidInput, "command contains CR");
+            return Err(UpsError::Io(error));
+        }
+        self.write_command(text, timeout)?;
+
+        if !expect_response {
+            return Ok(Response::default());
+        }
+
+        let raw = self.read_line(timeout);
+        if let Some(log) = &self.wire_log {
+            match &raw {
+                Ok(raw) => log.response(raw),
+                Err(e) => log.error(e),
+            }
+        }
+        let raw = raw?;
+        let text = decode_response(&raw);
+        Ok(Response { raw, text })
+    }
+
+    /// Release the interface, reattach the kernel driver and close the device
+    ///
+    /// Dropping the transport does the same but cannot report failures.
+    pub fn close(mut self) -> Result<()> {
+        self.release_interface()
+    }
+
+    fn release_interface(&mut self) -> Result<()> {
+        if self.claimed {
+            self.claimed = false;
+            // Releasing reattaches the kernel driver detached when claiming
+            self.handle.release_interface(self.interface)?;
+        }
+        Ok(())
+    }
+
+    /// Write the command in 8-byte output reports
+    fn write_command(&self, text: &str, timeout: Duration) -> Result<()> {
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.push(CHAR_CR);
+
+        for chunk in bytes.chunks(REPORT_SIZE) {
+            let mut report = [0u8; REPORT_SIZE];
+            report[..chunk.len()].copy_from_slice(chunk);
+            if let Some(log) = &self.wire_log {
+                log.request(format_args!(
+                    "SET_REPORT interface {} {:02x?}",
+                    self.interface, report
+                ));
+            }
+            self.handle.write_control(
+                rusb::request_type(
+                    Direction::Out,
+                    rusb::RequestType::Class,
+                    rusb::Recipient::Interface,
+                ),
+                HID_SET_REPORT,
+                HID_OUTPUT_REPORT,
+                self.interface as u16,
+                &report,
+                timeout,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Read interrupt reports until the carriage return
+    fn read_line(&self, timeout: Duration) -> Result<Vec<u8>> {
+        let mut line = Vec::new();
+        let mut report = [0u8; REPORT_SIZE];
+
+        while line.len() < MAX_RESPONSE_LENGTH {
+            let length = self
+                .handle
+                .read_interrupt(self.endpoint, &mut report, timeout)?;
+            let data = &report[..length];
+            if let Some(end) = data.iter().position(|&byte| byte == CHAR_CR) {
+                line.extend_from_slice(&data[..end]);
+                return Ok(line);
+            }
+            line.extend_from_slice(data);
+        }
+
+        Err(UpsError::InvalidResponse)
+    }
+}
+
+impl Transport for HidTransport {
+    fn send_command(&self, command: Command) -> Result<Response> {
+        self.send_raw_command(&command_text(command)?, expects_response(command))
+    }
+
+    fn send_command_with_timeout(&self, command: Command, timeout: Duration) -> Result<Response> {
+        self.exchange(&command_text(command)?, expects_response(command), timeout)
+    }
+
+    /// Clear the halt of the interrupt endpoint and reset the device
+    fn reset(&self) -> Result<()> {
+        let _ = self.handle.clear_halt(self.endpoint);
+        self.handle.reset()?;
+        Ok(())
+    }
+}
+
+impl Drop for HidTransport {
+    fn drop(&mut self) {
+        let _ = self.release_interface();
+    }
+}
+
+/// Find the first device matching vendor_id and product_id
+fn find_device(context: &Context, vendor_id: u16, product_id: u16) -> Result<Device<Context>> {
+    for device in context.devices()?.iter() {
+        let matches = device.device_descriptor().is_ok_and(|descriptor| {
+            descriptor.vendor_id() == vendor_id && descriptor.product_id() == product_id
+        });
+        if matches {
+            return Ok(device);
+        }
+    }
+
+    Err(UpsError::DeviceNotFound)
+}
+
+/// Find the HID interface and its interrupt IN endpoint
+fn hid_endpoint<T: UsbContext>(device: &Device<T>) -> Option<(u8, u8)> {
+    let config = device.active_config_descriptor().ok()?;
+    for interface in config.interfaces() {
+        for descriptor in interface.descriptors() {
+            if descriptor.class_code() != USB_CLASS_HID {
+                continue;
+            }
+            let endpoint = descriptor.endpoint_descriptors().find(|endpoint| {
+                endpoint.transfer_type() == TransferType::Interrupt
+                    && endpoint.direction() == Direction::In
+            });
+            if let Some(endpoint) = endpoint {
+                return Some((descriptor.interface_number(), endpoint.address()));
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,233 @@
+use crate::error::Result;
+use crate::json::{ToJson, Value};
+use crate::status::UpsStatus;
+use crate::timestamp;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Number of rotated files kept next to the current one by default
+pub const DEFAULT_HISTORY_KEEP: usize = 5;
+
+const CSV_HEADER: &str = "timestamp,input_voltage,input_fault_voltage,output_voltage,\
+output_current,input_frequency,battery_voltage,temperature,utility_fail,battery_low,\
+bypass_active,ups_failed,standby,test_in_progress,shutdown_active,beeper_on";
+
+/// File format of the history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryFormat {
+    /// Comma separated values with a header line, flags as `0`/`1`
+    Csv,
+    /// One JSON object per line, with the [`ToJson`] layout of [`UpsStatus`]
+    /// plus a `timestamp` member
+    JsonLines,
+}
+
+impl HistoryFormat {
+    /// Guess the format from the file extension, JSON Lines for `.jsonl`/`.json`
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl" | "ndjson" | "json") => HistoryFormat::JsonLines,
+            _ => HistoryFormat::Csv,
+        }
+    }
+}
+
+/// When the history file is rotated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    /// Rotate once the file grows beyond this many bytes
+    pub max_size: Option<u64>,
+    /// Rotate once the file was started this long ago
+    pub max_age: Option<Duration>,
+    /// Rotated files kept as `<path>.1` (newest) to `<path>.<keep>`
+    pub keep: usize,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Self {
+            max_size: None,
+            max_age: None,
+            keep: DEFAULT_HISTORY_KEEP,
+        }
+    }
+}
+
+/// Appends timestamped status samples to a CSV or JSON Lines file
+///
+/// Timestamps are RFC 3339 in UTC. When the [`Rotation`] limits are reached
+/// the file is renamed to `<path>.1`, older rotations are shifted up and the
+/// oldest beyond `keep` is deleted.
+#[derive(Debug)]
+pub struct HistoryWriter {
+    path: PathBuf,
+    format: HistoryFormat,
+    rotation: Rotation,
+    file: BufWriter<File>,
+    size: u64,
+    started: SystemTime,
+}
+
+impl HistoryWriter {
+    /// Open or create the history file, appending to existing samples
+    pub fn open<P: Into<PathBuf>>(path: P, format: HistoryFormat) -> Result<Self> {
+        let path = path.into();
+        let (file, size) = open_file(&path)?;
+        let started = fs::metadata(&path)
+            .and_then(|metadata| metadata.created().or_else(|_| metadata.modified()))
+            .unwrap_or_else(|_| SystemTime::now());
+        let mut writer = Self {
+            path,
+            format,
+            rotation: Rotation::default(),
+            file,
+            size,
+            started,
+        };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    /// Set when the file is rotated
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+    }
+
+    /// Get the path of the current file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the format
+    pub fn format(&self) -> HistoryFormat {
+        self.format
+    }
+
+    /// Append a sample taken now
+    pub fn record(&mut self, status: &UpsStatus) -> Result<()> {
+        self.record_at(SystemTime::now(), status)
+    }
+
+    /// Append a sample taken at `time`, rotating the file first when it is due
+    pub fn record_at(&mut self, time: SystemTime, status: &UpsStatus) -> Result<()> {
+        if self.rotation_due(time) {
+            self.rotate(time)?;
+        }
+
+        let timestamp = timestamp::rfc3339(time);
+        let line = match self.format {
+            HistoryFormat::Csv => csv_line(&timestamp, status),
+            HistoryFormat::JsonLines => {
+                let mut members = vec![("timestamp".to_string(), Value::from(timestamp))];
+                if let Value::Object(fields) = status.to_json() {
+                    members.extend(fields);
+                }
+                Value::Object(members).to_string()
+            }
+        };
+        self.write_line(&line)?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Check if a size or age limit is reached
+    fn rotation_due(&self, time: SystemTime) -> bool {
+        let too_large = self
+            .rotation
+            .max_size
+            .is_some_and(|max_size| self.size >= max_size);
+        let too_old = self.rotation.max_age.is_some_and(|max_age| {
+            time.duration_since(self.started)
+                .is_ok_and(|age| age >= max_age)
+        });
+        too_large || too_old
+    }
+
+    /// Shift the rotated files and start a new one
+    fn rotate(&mut self, time: SystemTime) -> Result<()> {
+        self.file.flush()?;
+        let rotated = |index: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", index));
+            PathBuf::from(name)
+        };
+
+        if self.rotation.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            match fs::remove_file(rotated(self.rotation.keep)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+            for index in (1..self.rotation.keep).rev() {
+                match fs::rename(rotated(index), rotated(index + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+
+        let (file, size) = open_file(&self.path)?;
+        self.file = file;
+        self.size = size;
+        self.started = time;
+        self.write_header()
+    }
+
+    /// Write the CSV header to an empty file
+    fn write_header(&mut self) -> Result<()> {
+        if self.format == HistoryFormat::Csv && self.size == 0 {
+            self.write_line(CSV_HEADER)?;
+            self.file.flush()?;
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+}
+
+/// Open a file for appending and get its current size
+fn open_file(path: &Path) -> Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((BufWriter::new(file), size))
+}
+
+/// Format a sample as a CSV line matching [`CSV_HEADER`]
+fn csv_line(timestamp: &str, status: &UpsStatus) -> String {
+    let flags = &status.flags;
+    let bits = [
+        flags.utility_fail,
+        flags.battery_low,
+        flags.bypass_active,
+        flags.ups_failed,
+        flags.standby,
+        flags.test_in_progress,
+        flags.shutdown_active,
+        flags.beeper_on,
+    ];
+
+    let mut fields = vec![timestamp.to_string()];
+    fields.extend(
+        [
+            status.input_voltage,
+            status.input_fault_voltage,
+            status.output_voltage,
+            status.load_raw,
+            status.input_frequency,
+            status.battery_voltage,
+            status.temperature,
+        ]
+        .iter()
+        .map(|value| value.to_string()),
+    );
+    fields.extend(bits.iter().map(|bit| u8::from(*bit).to_string()));
+    fields.join(",")
+}
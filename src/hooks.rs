@@ -0,0 +1,154 @@
+use crate::event::UpsEvent;
+use crate::status::UpsStatus;
+use std::process::{Command as Process, Stdio};
+use std::thread;
+
+/// External command run when a UPS event happens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hook {
+    /// Event name as returned by [`UpsEvent::name`], `None` for every event
+    pub event: Option<String>,
+    /// Program to run
+    pub program: String,
+    /// Arguments of the program
+    pub args: Vec<String>,
+}
+
+impl Hook {
+    /// Hook running `program` with `args` for `event`
+    ///
+    /// `event` is an [`UpsEvent::name`] such as `power-failure`, the apcupsd
+    /// style aliases `on-battery`, `on-line`, `comm-lost` and `comm-restored`,
+    /// or `*` for every event.
+    pub fn new<S: Into<String>>(event: &str, program: S, args: Vec<String>) -> Self {
+        Self {
+            event: canonical_event(event),
+            program: program.into(),
+            args,
+        }
+    }
+
+    /// Hook running a command line through the system shell
+    pub fn shell(event: &str, command: &str) -> Self {
+        let (shell, flag) = if cfg!(windows) {
+            ("cmd", "/C")
+        } else {
+            ("sh", "-c")
+        };
+        Self::new(event, shell, vec![flag.to_string(), command.to_string()])
+    }
+
+    /// Check if the hook runs for an event
+    pub fn matches(&self, event: &UpsEvent) -> bool {
+        self.event
+            .as_deref()
+            .is_none_or(|name| name == event.name())
+    }
+}
+
+/// Runs hooks with the event and the status passed in environment variables
+///
+/// Every hook gets `UPS_NAME` and `EVENT`, and, when the event carries a status,
+/// `INPUT_VOLTAGE`, `INPUT_FAULT_VOLTAGE`, `OUTPUT_VOLTAGE`, `LOAD`,
+/// `INPUT_FREQUENCY`, `BATTERY_VOLTAGE`, `TEMPERATURE`, `ON_BATTERY` and
+/// `BATTERY_LOW` (`1` or `0`). Hooks run in the background so a slow script does
+/// not delay polling.
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    ups_name: String,
+    hooks: Vec<Hook>,
+}
+
+impl Hooks {
+    /// Create an empty set of hooks for the UPS called `ups_name`
+    pub fn new<S: Into<String>>(ups_name: S) -> Self {
+        Self {
+            ups_name: ups_name.into(),
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Add a hook
+    pub fn add(&mut self, hook: Hook) {
+        self.hooks.push(hook);
+    }
+
+    /// Get the registered hooks
+    pub fn hooks(&self) -> &[Hook] {
+        &self.hooks
+    }
+
+    /// Check if no hook is registered
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Start every hook registered for `event`
+    ///
+    /// Fits [`Monitor::on_event`](crate::Monitor::on_event):
+    /// `monitor.on_event(move |event| hooks.run(event))`.
+    pub fn run(&self, event: &UpsEvent) {
+        let environment = self.environment(event);
+        for hook in self.hooks.iter().filter(|hook| hook.matches(event)) {
+            spawn(hook, &environment);
+        }
+    }
+
+    /// Environment variables describing an event
+    fn environment(&self, event: &UpsEvent) -> Vec<(&'static str, String)> {
+        let mut environment = vec![
+            ("UPS_NAME", self.ups_name.clone()),
+            ("EVENT", event.name().to_string()),
+        ];
+        if let Some(status) = event.status() {
+            environment.extend(status_environment(status));
+        }
+        environment
+    }
+}
+
+/// Start a hook in the background with the given environment variables
+pub(crate) fn spawn(hook: &Hook, environment: &[(&'static str, String)]) {
+    let mut process = Process::new(&hook.program);
+    process
+        .args(&hook.args)
+        .envs(environment.iter().map(|(name, value)| (*name, value)))
+        .stdin(Stdio::null());
+
+    // Waiting on a thread reaps the child without blocking the caller
+    thread::spawn(move || {
+        let _ = process.status();
+    });
+}
+
+/// Environment variables describing a status reading
+pub(crate) fn status_environment(status: &UpsStatus) -> Vec<(&'static str, String)> {
+    let flag = |active: bool| if active { "1" } else { "0" }.to_string();
+    vec![
+        ("INPUT_VOLTAGE", status.input_voltage.to_string()),
+        (
+            "INPUT_FAULT_VOLTAGE",
+            status.input_fault_voltage.to_string(),
+        ),
+        ("OUTPUT_VOLTAGE", status.output_voltage.to_string()),
+        ("LOAD", status.load_raw.to_string()),
+        ("INPUT_FREQUENCY", status.input_frequency.to_string()),
+        ("BATTERY_VOLTAGE", status.battery_voltage.to_string()),
+        ("TEMPERATURE", status.temperature.to_string()),
+        ("ON_BATTERY", flag(status.flags.on_battery())),
+        ("BATTERY_LOW", flag(status.flags.battery_low)),
+    ]
+}
+
+/// Map aliases to event names, `None` for the wildcard
+fn canonical_event(event: &str) -> Option<String> {
+    let name = match event {
+        "*" | "any" => return None,
+        "on-battery" => "power-failure",
+        "on-line" => "power-restored",
+        "comm-lost" => "communication-lost",
+        "comm-restored" => "communication-restored",
+        other => other,
+    };
+    Some(name.to_string())
+}
@@ -0,0 +1,326 @@
+use crate::error::{Result, UpsError};
+use crate::quirks::Quirks;
+use crate::transport::{Command, Response, Transport};
+use crate::usb::{Selection, UsbTransport};
+use crate::wire::WireLog;
+use rusb::{Context, Device, Hotplug, HotplugBuilder, Registration, UsbContext};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Changes of the USB link reported by ReconnectingTransport
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The device went away
+    Disconnected,
+    /// The device was opened again after a disconnection
+    Reconnected,
+}
+
+/// USB transport that transparently reopens the device after it re-enumerates
+///
+/// The device is reopened the way it was first selected, by vendor and
+/// product ID, serial number or port path. The timeout, quirks, claimed
+/// interface and wire log of the transport are applied again every time.
+pub struct ReconnectingTransport {
+    shared: Arc<Shared>,
+    watcher: Option<HotplugWatcher>,
+}
+
+struct Shared {
+    vendor_id: u16,
+    product_id: u16,
+    selection: Selection,
+    transport: Mutex<Option<UsbTransport>>,
+    settings: Mutex<Settings>,
+    listeners: Mutex<Vec<Sender<ConnectionEvent>>>,
+}
+
+/// Configuration of the USB transport, applied again when the device is reopened
+struct Settings {
+    timeout: Duration,
+    quirks: Quirks,
+    claim_interface: bool,
+    wire_log: Option<Arc<WireLog>>,
+}
+
+impl ReconnectingTransport {
+    /// Open the USB device and watch it for hotplug events when libusb supports them
+    pub fn open(vendor_id: u16, product_id: u16) -> Result<Self> {
+        Self::from_transport(UsbTransport::open(vendor_id, product_id)?)
+    }
+
+    /// Watch an already configured USB transport, reopening it with the same configuration
+    pub fn from_transport(transport: UsbTransport) -> Result<Self> {
+        let settings = Settings {
+            timeout: transport.timeout(),
+            quirks: transport.quirks(),
+            claim_interface: transport.is_interface_claimed(),
+            wire_log: transport.wire_log(),
+        };
+        let shared = Arc::new(Shared {
+            vendor_id: transport.vendor_id(),
+            product_id: transport.product_id(),
+            selection: transport.selection().clone(),
+            transport: Mutex::new(Some(transport)),
+            settings: Mutex::new(settings),
+            listeners: Mutex::new(Vec::new()),
+        });
+
+        let watcher = if rusb::has_hotplug() {
+            Some(HotplugWatcher::spawn(Arc::clone(&shared))?)
+        } else {
+            None
+        };
+
+        Ok(Self { shared, watcher })
+    }
+
+    /// Subscribe to connection events
+    pub fn subscribe(&self) -> Receiver<ConnectionEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.shared.lock_listeners().push(sender);
+        receiver
+    }
+
+    /// Check whether the device is currently open
+    pub fn is_connected(&self) -> bool {
+        self.shared.lock_transport().is_some()
+    }
+
+    /// Check whether disconnections are detected through libusb hotplug callbacks
+    pub fn has_hotplug(&self) -> bool {
+        self.watcher.is_some()
+    }
+
+    /// Set the control transfer timeout, now and after reconnections
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.shared.lock_settings().timeout = timeout;
+        if let Some(usb) = self.shared.lock_transport().as_mut() {
+            usb.set_timeout(timeout);
+        }
+    }
+
+    /// Use the descriptor indices overridden by the given quirks, now and after reconnections
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.shared.lock_settings().quirks = quirks;
+        if let Some(usb) = self.shared.lock_transport().as_mut() {
+            usb.set_quirks(quirks);
+        }
+    }
+
+    /// Detach the kernel driver and claim the interface, now and after reconnections
+    pub fn claim_interface(&mut self) -> Result<()> {
+        self.shared.lock_settings().claim_interface = true;
+        match self.shared.lock_transport().as_mut() {
+            Some(usb) => usb.claim_interface(),
+            None => Ok(()),
+        }
+    }
+
+    /// Log every descriptor request to `log`, also on the reopened device
+    pub fn set_wire_log(&mut self, log: WireLog) {
+        let log = Arc::new(log);
+        self.shared.lock_settings().wire_log = Some(Arc::clone(&log));
+        if let Some(usb) = self.shared.lock_transport().as_mut() {
+            usb.set_shared_wire_log(log);
+        }
+    }
+
+    /// Send on the open device, reopening it first or after a disconnection
+    fn exchange(&self, send: impl Fn(&UsbTransport) -> Result<Response>) -> Result<Response> {
+        let mut transport = self.shared.lock_transport();
+
+        if transport.is_none() {
+            *transport = Some(self.shared.reconnect()?);
+        }
+
+        let result = match transport.as_ref() {
+            Some(usb) => send(usb),
+            None => return Err(UpsError::Disconnected),
+        };
+
+        match result {
+            Err(UpsError::Disconnected) => {
+                *transport = None;
+                self.shared.notify(ConnectionEvent::Disconnected);
+
+                let usb = self.shared.reconnect()?;
+                let result = send(&usb);
+                *transport = Some(usb);
+                result
+            }
+            result => result,
+        }
+    }
+}
+
+impl Transport for ReconnectingTransport {
+    fn send_command(&self, command: Command) -> Result<Response> {
+        self.exchange(|usb| usb.send_command(command))
+    }
+
+    fn send_command_with_timeout(&self, command: Command, timeout: Duration) -> Result<Response> {
+        self.exchange(|usb| usb.send_command_with_timeout(command, timeout))
+    }
+
+    /// Reset the device, reopening it on the next command if it re-enumerated
+    fn reset(&self) -> Result<()> {
+        let mut transport = self.shared.lock_transport();
+        let result = match transport.as_ref() {
+            Some(usb) => usb.reset(),
+            None => return Ok(()),
+        };
+
+        match result {
+            Err(UpsError::DeviceNotFound | UpsError::Disconnected) => {
+                *transport = None;
+                self.shared.notify(ConnectionEvent::Disconnected);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+impl Shared {
+    /// Lock the current transport, recovering from a poisoned mutex
+    fn lock_transport(&self) -> std::sync::MutexGuard<'_, Option<UsbTransport>> {
+        self.transport
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Lock the list of subscribers, recovering from a poisoned mutex
+    fn lock_listeners(&self) -> std::sync::MutexGuard<'_, Vec<Sender<ConnectionEvent>>> {
+        self.listeners
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Lock the transport settings, recovering from a poisoned mutex
+    fn lock_settings(&self) -> std::sync::MutexGuard<'_, Settings> {
+        self.settings
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Open the device again with the recorded settings and report the reconnection
+    fn reconnect(&self) -> Result<UsbTransport> {
+        let settings = self.lock_settings();
+        let mut transport = UsbTransport::reopen(&self.selection, settings.timeout)?;
+        transport.set_quirks(settings.quirks);
+        if let Some(log) = &settings.wire_log {
+            transport.set_shared_wire_log(Arc::clone(log));
+        }
+        if settings.claim_interface {
+            transport.claim_interface()?;
+        }
+        drop(settings);
+        self.notify(ConnectionEvent::Reconnected);
+        Ok(transport)
+    }
+
+    /// Send an event to every subscriber, forgetting the ones that went away
+    fn notify(&self, event: ConnectionEvent) {
+        self.lock_listeners()
+            .retain(|listener| listener.send(event).is_ok());
+    }
+}
+
+/// Background thread dispatching libusb hotplug events
+struct HotplugWatcher {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HotplugWatcher {
+    /// Register for hotplug events of the shared device and start the event thread
+    fn spawn(shared: Arc<Shared>) -> Result<Self> {
+        let context = Context::new()?;
+        let (sender, receiver) = mpsc::channel();
+
+        let mut builder = HotplugBuilder::new();
+        builder
+            .vendor_id(shared.vendor_id)
+            .product_id(shared.product_id)
+            .enumerate(false);
+        let registration = builder.register(&context, Box::new(HotplugCallback { sender }))?;
+
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = {
+            let running = Arc::clone(&running);
+            thread::spawn(move || Self::run(context, registration, receiver, shared, running))
+        };
+
+        Ok(Self {
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// Handle libusb events and apply the recorded changes outside of the callback
+    fn run(
+        context: Context,
+        _registration: Registration<Context>,
+        receiver: Receiver<HotplugChange>,
+        shared: Arc<Shared>,
+        running: Arc<AtomicBool>,
+    ) {
+        while running.load(Ordering::Relaxed) {
+            if context.handle_events(Some(EVENT_POLL_INTERVAL)).is_err() {
+                thread::sleep(EVENT_POLL_INTERVAL);
+            }
+
+            for change in receiver.try_iter() {
+                let mut transport = shared.lock_transport();
+                match change {
+                    HotplugChange::Left => {
+                        if transport.take().is_some() {
+                            shared.notify(ConnectionEvent::Disconnected);
+                        }
+                    }
+                    HotplugChange::Arrived => {
+                        if transport.is_none() {
+                            *transport = shared.reconnect().ok();
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for HotplugWatcher {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Change recorded by the hotplug callback
+enum HotplugChange {
+    Arrived,
+    Left,
+}
+
+/// Hotplug callback forwarding changes to the event thread
+struct HotplugCallback {
+    sender: Sender<HotplugChange>,
+}
+
+impl Hotplug<Context> for HotplugCallback {
+    fn device_arrived(&mut self, _device: Device<Context>) {
+        let _ = self.sender.send(HotplugChange::Arrived);
+    }
+
+    fn device_left(&mut self, _device: Device<Context>) {
+        let _ = self.sender.send(HotplugChange::Left);
+    }
+}
@@ -0,0 +1,575 @@
+#[cfg(any(feature = "fleet", feature = "rest"))]
+use crate::json::Value;
+#[cfg(any(
+    feature = "fleet",
+    feature = "influxdb",
+    feature = "otel",
+    feature = "webhook"
+))]
+use std::fmt;
+#[cfg(any(feature = "exporter", feature = "rest", feature = "websocket"))]
+use std::io::Read;
+#[cfg(any(
+    feature = "exporter",
+    feature = "fleet",
+    feature = "influxdb",
+    feature = "otel",
+    feature = "rest",
+    feature = "websocket",
+    feature = "webhook"
+))]
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(any(
+    feature = "exporter",
+    feature = "fleet",
+    feature = "influxdb",
+    feature = "otel",
+    feature = "rest",
+    feature = "websocket",
+    feature = "webhook"
+))]
+use std::net::TcpStream;
+#[cfg(any(
+    feature = "fleet",
+    feature = "influxdb",
+    feature = "otel",
+    feature = "webhook"
+))]
+use std::net::ToSocketAddrs;
+#[cfg(any(feature = "grpc", feature = "rest"))]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(any(feature = "grpc", feature = "rest"))]
+use std::sync::Arc;
+#[cfg(any(
+    feature = "exporter",
+    feature = "fleet",
+    feature = "influxdb",
+    feature = "otel",
+    feature = "rest",
+    feature = "websocket",
+    feature = "webhook"
+))]
+use std::time::Duration;
+#[cfg(any(feature = "exporter", feature = "rest", feature = "websocket"))]
+use std::time::Instant;
+
+/// Time a client has to send its whole request
+#[cfg(any(feature = "exporter", feature = "rest", feature = "websocket"))]
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+#[cfg(any(feature = "exporter", feature = "rest", feature = "websocket"))]
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+#[cfg(any(feature = "exporter", feature = "rest", feature = "websocket"))]
+const MAX_HEADER_LINES: usize = 64;
+#[cfg(any(feature = "exporter", feature = "rest", feature = "websocket"))]
+const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+#[cfg(any(feature = "exporter", feature = "rest", feature = "websocket"))]
+/// Minimal HTTP/1.x request, enough for the scrape and control endpoints
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+#[cfg(any(feature = "exporter", feature = "rest", feature = "websocket"))]
+impl Request {
+    /// Read a request from a client connection
+    ///
+    /// The whole request must arrive within [`REQUEST_TIMEOUT`], so a client
+    /// trickling bytes cannot hold the connection.
+    pub(crate) fn read(stream: &TcpStream) -> io::Result<Self> {
+        let mut reader = BufReader::new(Deadline {
+            stream,
+            deadline: Instant::now() + REQUEST_TIMEOUT,
+        });
+
+        let mut line = String::new();
+        read_line(&mut reader, &mut line)?;
+        let mut parts = line.split_whitespace();
+        let (method, path) = match (parts.next(), parts.next()) {
+            (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "bad request line",
+                ))
+            }
+        };
+
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            if read_line(&mut reader, &mut line)? == 0 {
+                break;
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADER_LINES {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "too many headers",
+                ));
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        let mut request = Self {
+            method,
+            path,
+            headers,
+            body: Vec::new(),
+        };
+
+        let length = request
+            .header("content-length")
+            .and_then(|length| length.parse::<usize>().ok())
+            .unwrap_or(0);
+        if length > MAX_BODY_LENGTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "body too large"));
+        }
+        request.body = vec![0; length];
+        reader.read_exact(&mut request.body)?;
+
+        Ok(request)
+    }
+
+    /// Get a header by its lowercase name
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Request path without the query string
+    pub(crate) fn route(&self) -> &str {
+        self.path.split('?').next().unwrap_or("")
+    }
+
+    /// Decoded value of a query string parameter
+    #[cfg(any(feature = "fleet", feature = "graphql"))]
+    pub(crate) fn query(&self, name: &str) -> Option<String> {
+        let (_, query) = self.path.split_once('?')?;
+        query.split('&').find_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key) == name).then(|| percent_decode(value))
+        })
+    }
+}
+
+/// Read a line of at most [`MAX_LINE_LENGTH`] bytes
+#[cfg(any(feature = "exporter", feature = "rest", feature = "websocket"))]
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    let length = reader.take(MAX_LINE_LENGTH as u64).read_line(line)?;
+    if length == MAX_LINE_LENGTH && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+    Ok(length)
+}
+
+/// Connection whose reads fail once the deadline has passed
+#[cfg(any(feature = "exporter", feature = "rest", feature = "websocket"))]
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+#[cfg(any(feature = "exporter", feature = "rest", feature = "websocket"))]
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "request too slow"));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+/// Decode a form-encoded query string component
+#[cfg(any(feature = "fleet", feature = "graphql"))]
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(any(feature = "exporter", feature = "rest", feature = "websocket"))]
+/// Write a complete response and close the exchange
+pub(crate) fn respond(
+    mut stream: &TcpStream,
+    status: u16,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason(status),
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+#[cfg(any(feature = "exporter", feature = "rest", feature = "websocket"))]
+/// Reason phrase of the status codes used by the servers in this crate
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        426 => "Upgrade Required",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Plain `http://` URL split into the parts a request needs
+///
+/// An `https://` URL is reached through a relay, a local TLS tunnel such as
+/// stunnel that forwards plain HTTP to the host over TLS.
+#[cfg(any(
+    feature = "fleet",
+    feature = "influxdb",
+    feature = "otel",
+    feature = "webhook"
+))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Url {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) path: String,
+    /// Address connected to instead of the host, for `https://` URLs
+    pub(crate) relay: Option<(String, u16)>,
+}
+
+#[cfg(any(
+    feature = "fleet",
+    feature = "influxdb",
+    feature = "otel",
+    feature = "webhook"
+))]
+impl Url {
+    /// Parse an `http://host[:port][/path]` URL
+    pub(crate) fn parse(url: &str) -> io::Result<Self> {
+        let invalid = |message: &str| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", message, url))
+        };
+        let rest = url.strip_prefix("http://").ok_or_else(|| {
+            invalid("only http:// URLs are supported, https:// ones need a relay")
+        })?;
+        Self::split(rest, 80).map_err(invalid)
+    }
+
+    /// Parse an `https://host[:port][/path]` URL reached through the relay at `host:port`
+    #[cfg(feature = "webhook")]
+    pub(crate) fn parse_relayed(url: &str, relay: &str) -> io::Result<Self> {
+        let invalid = |message: &str, text: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: {}", message, text),
+            )
+        };
+        let rest = url
+            .strip_prefix("https://")
+            .ok_or_else(|| invalid("only https:// URLs are relayed", url))?;
+        let mut parsed = Self::split(rest, 443).map_err(|message| invalid(message, url))?;
+        let (host, port) = relay
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+            .filter(|(host, _)| !host.is_empty())
+            .ok_or_else(|| invalid("relay must be host:port", relay))?;
+        parsed.relay = Some((host.to_string(), port));
+        Ok(parsed)
+    }
+
+    /// Split `host[:port][/path]`
+    fn split(rest: &str, default_port: u16) -> std::result::Result<Self, &'static str> {
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| "invalid port")?),
+            None => (authority, default_port),
+        };
+        if host.is_empty() {
+            return Err("missing host");
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+            relay: None,
+        })
+    }
+
+    /// Value of the `Host` header, without the default port of the scheme
+    fn authority(&self) -> String {
+        let default_port = if self.relay.is_some() { 443 } else { 80 };
+        if self.port == default_port {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "fleet",
+    feature = "influxdb",
+    feature = "otel",
+    feature = "webhook"
+))]
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.relay {
+            Some((host, port)) => write!(
+                f,
+                "https://{}:{}{} via {}:{}",
+                self.host, self.port, self.path, host, port
+            ),
+            None => write!(f, "http://{}:{}{}", self.host, self.port, self.path),
+        }
+    }
+}
+
+/// Send a POST request and return the status code of the response
+#[cfg(any(
+    feature = "fleet",
+    feature = "influxdb",
+    feature = "otel",
+    feature = "webhook"
+))]
+pub(crate) fn post(
+    url: &Url,
+    content_type: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+    timeout: Duration,
+) -> io::Result<u16> {
+    let (host, port) = match &url.relay {
+        Some((host, port)) => (host.as_str(), *port),
+        None => (url.host.as_str(), url.port),
+    };
+    let address = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host not found"))?;
+    let mut stream = TcpStream::connect_timeout(&address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        url.path,
+        url.authority(),
+        content_type,
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    line.split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad status line"))
+}
+
+/// Reply of an endpoint: status code and JSON body
+#[cfg(any(feature = "fleet", feature = "rest"))]
+pub(crate) type Reply = (u16, Value);
+
+/// Build an error reply
+#[cfg(any(feature = "fleet", feature = "rest"))]
+pub(crate) fn error(status: u16, message: &str) -> Reply {
+    (status, Value::object(vec![("error", message.into())]))
+}
+
+/// Check an `Authorization: Bearer <token>` header, always refused without a token
+#[cfg(any(feature = "fleet", feature = "grpc", feature = "rest"))]
+pub(crate) fn authorized(authorization: Option<&str>, token: Option<&str>) -> bool {
+    let token = match token {
+        Some(token) => token,
+        None => return false,
+    };
+    authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()))
+}
+
+/// Compare two secrets without leaking the position of the first difference
+#[cfg(any(feature = "fleet", feature = "grpc", feature = "rest"))]
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Number of clients a server handles at the same time
+#[cfg(any(feature = "grpc", feature = "rest"))]
+pub(crate) struct ConnectionLimit {
+    active: AtomicUsize,
+    max: usize,
+}
+
+#[cfg(any(feature = "grpc", feature = "rest"))]
+impl ConnectionLimit {
+    pub(crate) fn new(max: usize) -> Arc<Self> {
+        Arc::new(Self {
+            active: AtomicUsize::new(0),
+            max,
+        })
+    }
+
+    /// Take a slot for a new client, `None` when every slot is in use
+    pub(crate) fn acquire(self: &Arc<Self>) -> Option<ConnectionSlot> {
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+                (active < self.max).then_some(active + 1)
+            })
+            .ok()
+            .map(|_| ConnectionSlot(Arc::clone(self)))
+    }
+}
+
+/// Slot of a client, given back when dropped
+#[cfg(any(feature = "grpc", feature = "rest"))]
+pub(crate) struct ConnectionSlot(Arc<ConnectionLimit>);
+
+#[cfg(any(feature = "grpc", feature = "rest"))]
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(all(
+    test,
+    any(
+        feature = "fleet",
+        feature = "grpc",
+        feature = "rest",
+        feature = "webhook"
+    )
+))]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "webhook")]
+    #[test]
+    fn relayed_urls_connect_to_the_relay_and_name_the_host() {
+        use std::net::TcpListener;
+
+        assert!(Url::parse("https://hooks.slack.com/x").is_err());
+        assert!(Url::parse_relayed("http://hooks.slack.com/x", "127.0.0.1:8443").is_err());
+        assert!(Url::parse_relayed("https://hooks.slack.com/x", "127.0.0.1").is_err());
+
+        let relay = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = relay.local_addr().unwrap().to_string();
+        let url = Url::parse_relayed("https://hooks.slack.com/services/T/B/X", &address).unwrap();
+        assert_eq!(url.port, 443);
+        assert_eq!(url.path, "/services/T/B/X");
+
+        let server = std::thread::spawn(move || {
+            let (stream, _) = relay.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut head = String::new();
+            while reader.read_line(&mut head).unwrap() > 2 {}
+            (&stream)
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            head
+        });
+        let status = post(&url, "application/json", &[], b"", Duration::from_secs(5)).unwrap();
+        assert_eq!(status, 204);
+        let head = server.join().unwrap();
+        assert!(head.starts_with("POST /services/T/B/X HTTP/1.1\r\nHost: hooks.slack.com\r\n"));
+    }
+
+    #[cfg(any(feature = "fleet", feature = "grpc", feature = "rest"))]
+    #[test]
+    fn secrets_compare_equal_only_when_identical() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"token2"));
+        assert!(!constant_time_eq(b"", b"token"));
+    }
+
+    #[cfg(any(feature = "fleet", feature = "grpc", feature = "rest"))]
+    #[test]
+    fn bearer_tokens_are_checked() {
+        assert!(authorized(Some("Bearer secret"), Some("secret")));
+        assert!(!authorized(Some("Bearer wrong"), Some("secret")));
+        assert!(!authorized(Some("secret"), Some("secret")));
+        assert!(!authorized(None, Some("secret")));
+        assert!(!authorized(Some("Bearer secret"), None));
+    }
+
+    #[cfg(any(feature = "fleet", feature = "rest"))]
+    #[test]
+    fn overlong_request_lines_are_refused() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let path = "a".repeat(MAX_LINE_LENGTH);
+        // The server may close before reading everything
+        let _ = write!(client, "GET /{} HTTP/1.1\r\n\r\n", path);
+        let error = Request::read(&server).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        write!(client, "GET /status HTTP/1.1\r\nHost: ups\r\n\r\n").unwrap();
+        assert_eq!(Request::read(&server).unwrap().route(), "/status");
+    }
+
+    #[cfg(any(feature = "grpc", feature = "rest"))]
+    #[test]
+    fn connection_slots_are_given_back() {
+        let limit = ConnectionLimit::new(2);
+        let first = limit.acquire().unwrap();
+        let _second = limit.acquire().unwrap();
+        assert!(limit.acquire().is_none());
+        drop(first);
+        assert!(limit.acquire().is_some());
+    }
+}
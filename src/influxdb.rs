@@ -0,0 +1,236 @@
+use crate::error::{Result, UpsError};
+use crate::http::{self, Url};
+use crate::status::UpsStatus;
+use crate::transport::Transport;
+use crate::MegatecUps;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Measurement the samples are written to by default
+pub const DEFAULT_INFLUXDB_MEASUREMENT: &str = "ups";
+
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// InfluxDB write API and its credentials
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InfluxApi {
+    /// InfluxDB 1.x `/write` endpoint
+    V1 {
+        database: String,
+        username: Option<String>,
+        password: Option<String>,
+    },
+    /// InfluxDB 2.x `/api/v2/write` endpoint, also served by InfluxDB 3 and Cloud
+    V2 {
+        org: String,
+        bucket: String,
+        token: String,
+    },
+}
+
+/// Pushes status samples to InfluxDB in line protocol
+///
+/// Every sample is written as one point of the measurement (`ups` by default)
+/// with the status values and flags as fields, tagged with the UPS name
+/// (`ups`) and model (`model`) read when the exporter is created, plus any
+/// tags added with [`add_tag`](Self::add_tag). Only plain `http://` URLs are
+/// supported.
+pub struct InfluxExporter<T: Transport> {
+    ups: MegatecUps<T>,
+    write_url: Url,
+    token: Option<String>,
+    measurement: String,
+    tags: Vec<(String, String)>,
+    interval: Duration,
+}
+
+impl<T: Transport> InfluxExporter<T> {
+    /// Create an exporter writing to the server at `url`, such as `http://localhost:8086`
+    pub fn new(ups: MegatecUps<T>, url: &str, api: InfluxApi, interval: Duration) -> Result<Self> {
+        let base = Url::parse(url.trim_end_matches('/')).map_err(UpsError::Io)?;
+        let base_path = base.path.trim_end_matches('/');
+        let (path, token) = match api {
+            InfluxApi::V1 {
+                database,
+                username,
+                password,
+            } => {
+                let mut path = format!("{}/write?db={}&precision=ms", base_path, encode(&database));
+                if let Some(username) = username {
+                    path.push_str(&format!("&u={}", encode(&username)));
+                }
+                if let Some(password) = password {
+                    path.push_str(&format!("&p={}", encode(&password)));
+                }
+                (path, None)
+            }
+            InfluxApi::V2 { org, bucket, token } => {
+                let path = format!(
+                    "{}/api/v2/write?org={}&bucket={}&precision=ms",
+                    base_path,
+                    encode(&org),
+                    encode(&bucket)
+                );
+                (path, Some(token))
+            }
+        };
+
+        let mut tags = Vec::new();
+        if let Ok(name) = ups.get_name() {
+            tags.push(("ups".to_string(), name.trim().to_string()));
+        }
+        if let Ok(info) = ups.get_info() {
+            tags.push(("model".to_string(), info.model.trim().to_string()));
+        }
+        tags.retain(|(_, value)| !value.is_empty());
+
+        Ok(Self {
+            ups,
+            write_url: Url { path, ..base },
+            token,
+            measurement: DEFAULT_INFLUXDB_MEASUREMENT.to_string(),
+            tags,
+            interval,
+        })
+    }
+
+    /// Add a tag written with every sample, such as the site or rack
+    pub fn add_tag<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.tags.push((key.into(), value.into()));
+    }
+
+    /// Set the measurement name
+    pub fn set_measurement<S: Into<String>>(&mut self, measurement: S) {
+        self.measurement = measurement.into();
+    }
+
+    /// Get the UPS the samples are read from
+    pub fn ups(&self) -> &MegatecUps<T> {
+        &self.ups
+    }
+
+    /// Get the time between two samples
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Read the status and write it to InfluxDB
+    pub fn push(&self) -> Result<()> {
+        let status = self.ups.get_status()?;
+        self.write(&[(SystemTime::now(), status)])
+    }
+
+    /// Write samples taken at the given times
+    pub fn write(&self, samples: &[(SystemTime, UpsStatus)]) -> Result<()> {
+        let body: String = samples
+            .iter()
+            .map(|(time, status)| self.line(*time, status) + "\n")
+            .collect();
+        let headers: Vec<(&str, String)> = self
+            .token
+            .iter()
+            .map(|token| ("Authorization", format!("Token {}", token)))
+            .collect();
+
+        let status = http::post(
+            &self.write_url,
+            "text/plain; charset=utf-8",
+            &headers,
+            body.as_bytes(),
+            WRITE_TIMEOUT,
+        )?;
+        if (200..300).contains(&status) {
+            Ok(())
+        } else {
+            let message = format!("InfluxDB replied {}", status);
+            Err(UpsError::Io(io::Error::other(message)))
+        }
+    }
+
+    /// Push a sample every interval, forever
+    ///
+    /// Failed reads and writes skip the sample; use [`push`](Self::push) in a
+    /// custom loop to handle them.
+    pub fn serve(&self) -> Result<()> {
+        loop {
+            let started = Instant::now();
+            let _ = self.push();
+            thread::sleep(self.interval.saturating_sub(started.elapsed()));
+        }
+    }
+
+    /// Format a sample as a line protocol point
+    pub fn line(&self, time: SystemTime, status: &UpsStatus) -> String {
+        let mut line = escape(&self.measurement, &[',', ' ']);
+        for (key, value) in &self.tags {
+            line.push_str(&format!(
+                ",{}={}",
+                escape(key, &[',', '=', ' ']),
+                escape(value, &[',', '=', ' '])
+            ));
+        }
+
+        let values = [
+            ("input_voltage", status.input_voltage),
+            ("input_fault_voltage", status.input_fault_voltage),
+            ("output_voltage", status.output_voltage),
+            ("load_percent", status.load_raw),
+            ("input_frequency", status.input_frequency),
+            ("battery_voltage", status.battery_voltage),
+            ("temperature", status.temperature),
+        ];
+        let flags = &status.flags;
+        let flags = [
+            ("on_battery", flags.on_battery()),
+            ("battery_low", flags.battery_low),
+            ("bypass_active", flags.bypass_active),
+            ("ups_failed", flags.ups_failed),
+            ("test_in_progress", flags.test_in_progress),
+            ("shutdown_active", flags.shutdown_active),
+            ("beeper_on", flags.beeper_on),
+        ];
+        // Values missing from truncated replies are left out of the point
+        let fields: Vec<String> = values
+            .iter()
+            .filter(|(_, value)| value.is_finite())
+            .map(|(name, value)| format!("{}={}", name, value))
+            .chain(
+                flags
+                    .iter()
+                    .map(|(name, flag)| format!("{}={}", name, flag)),
+            )
+            .collect();
+
+        let millis = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        format!("{} {} {}", line, fields.join(","), millis)
+    }
+}
+
+/// Escape the characters line protocol requires for measurements and tags
+fn escape(text: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Percent-encode a query parameter value
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -0,0 +1,35 @@
+use crate::error::Result;
+use crate::protocol;
+use std::fmt;
+
+/// Structure representing the UPS manufacturer information
+#[derive(Debug, Clone)]
+pub struct UpsInfo {
+    pub company: String,
+    pub model: String,
+    pub firmware_version: String,
+}
+
+impl UpsInfo {
+    /// Parse the fixed-width info string into UpsInfo struct
+    pub(crate) fn from_str(info: &str) -> Result<Self> {
+        Ok(protocol::parse_info(info)?)
+    }
+}
+
+/// Compact one-line summary, or one line per value with the alternate flag (`{:#}`)
+impl fmt::Display for UpsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            writeln!(f, "Company: {}", self.company)?;
+            writeln!(f, "Model: {}", self.model)?;
+            write!(f, "Firmware Version: {}", self.firmware_version)
+        } else {
+            write!(
+                f,
+                "{} {} {}",
+                self.company, self.model, self.firmware_version
+            )
+        }
+    }
+}
@@ -0,0 +1,737 @@
+use crate::event::UpsEvent;
+use crate::extended::ExtendedStatus;
+use crate::info::UpsInfo;
+use crate::manager::{AggregateStatus, DeviceStatus};
+use crate::rating::RatingInfo;
+use crate::status::{StatusFlags, UpsStatus};
+use crate::timestamp;
+use std::fmt::{self, Write};
+use thiserror::Error;
+
+/// Deepest nesting of arrays and objects [`Value::parse`] accepts
+pub const MAX_DEPTH: usize = 128;
+
+/// Error raised when JSON cannot be parsed or does not match the expected shape
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("JSON error: {0}")]
+pub struct JsonError(pub String);
+
+/// JSON value, with object members kept in insertion order
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Parse a JSON document nested at most [`MAX_DEPTH`] levels deep
+    pub fn parse(text: &str) -> Result<Self, JsonError> {
+        Self::parse_with_depth(text, MAX_DEPTH)
+    }
+
+    /// Parse a JSON document, rejecting arrays and objects nested deeper than `max_depth`
+    pub fn parse_with_depth(text: &str, max_depth: usize) -> Result<Self, JsonError> {
+        let mut parser = Parser {
+            chars: text.char_indices().peekable(),
+            text,
+            depth: 0,
+            max_depth,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+
+        match parser.chars.next() {
+            None => Ok(value),
+            Some((position, _)) => Err(JsonError(format!("trailing data at {}", position))),
+        }
+    }
+
+    /// Get a member of an object
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a number, treating null as NaN
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            Value::Null => Some(f64::NAN),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a boolean
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a string
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get the value as an array
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// Build an object from its members
+    pub fn object<K: Into<String>>(members: Vec<(K, Value)>) -> Self {
+        Value::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(value) => write!(f, "{}", value),
+            // JSON has no representation for NaN and infinities
+            Value::Number(number) if !number.is_finite() => f.write_str("null"),
+            Value::Number(number) => write!(f, "{}", number),
+            Value::String(value) => write_string(f, value),
+            Value::Array(values) => {
+                f.write_char('[')?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_char(']')
+            }
+            Value::Object(members) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+/// Write a string literal with JSON escaping
+fn write_string(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// Recursive descent JSON parser
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    text: &'a str,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Parser<'_> {
+    fn error<T>(&mut self, message: &str) -> Result<T, JsonError> {
+        let position = self
+            .chars
+            .peek()
+            .map_or(self.text.len(), |&(position, _)| position);
+        Err(JsonError(format!("{} at {}", message, position)))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .chars
+            .peek()
+            .is_some_and(|&(_, c)| c.is_ascii_whitespace())
+        {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            _ => self.error(&format!("expected '{}'", expected)),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, JsonError> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Value, JsonError> {
+        self.skip_whitespace();
+        match self.chars.peek().map(|&(_, c)| c) {
+            Some('n') => self.literal("null", Value::Null),
+            Some('t') => self.literal("true", Value::Bool(true)),
+            Some('f') => self.literal("false", Value::Bool(false)),
+            Some('"') => self.string().map(Value::String),
+            Some('[') => self.array(),
+            Some('{') => self.object(),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => self.error("unexpected character"),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, JsonError> {
+        let mut text = String::new();
+        while let Some(&(_, c)) = self.chars.peek() {
+            if c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E') {
+                text.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+
+        match text.parse() {
+            Ok(number) => Ok(Value::Number(number)),
+            Err(_) => self.error("invalid number"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut value = String::new();
+
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(value),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, '"')) => value.push('"'),
+                    Some((_, '\\')) => value.push('\\'),
+                    Some((_, '/')) => value.push('/'),
+                    Some((_, 'b')) => value.push('\u{8}'),
+                    Some((_, 'f')) => value.push('\u{c}'),
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 'r')) => value.push('\r'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, 'u')) => {
+                        let high = self.hex_escape()?;
+                        let code = if (0xd800..0xdc00).contains(&high) {
+                            self.expect('\\')?;
+                            self.expect('u')?;
+                            let low = self.hex_escape()?;
+                            0x10000 + ((high - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff)
+                        } else {
+                            high
+                        };
+                        value.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    }
+                    _ => return self.error("invalid escape"),
+                },
+                Some((_, c)) => value.push(c),
+                None => return self.error("unterminated string"),
+            }
+        }
+    }
+
+    fn hex_escape(&mut self) -> Result<u32, JsonError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.chars.next().and_then(|(_, c)| c.to_digit(16)) {
+                Some(digit) => code = code * 16 + digit,
+                None => return self.error("invalid unicode escape"),
+            }
+        }
+        Ok(code)
+    }
+
+    fn array(&mut self) -> Result<Value, JsonError> {
+        self.expect('[')?;
+        self.enter()?;
+        let mut values = Vec::new();
+
+        self.skip_whitespace();
+        if self.chars.peek().is_some_and(|&(_, c)| c == ']') {
+            self.chars.next();
+            self.depth -= 1;
+            return Ok(Value::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, ']')) => break,
+                _ => return self.error("expected ',' or ']'"),
+            }
+        }
+        self.depth -= 1;
+        Ok(Value::Array(values))
+    }
+
+    fn object(&mut self) -> Result<Value, JsonError> {
+        self.expect('{')?;
+        self.enter()?;
+        let mut members = Vec::new();
+
+        self.skip_whitespace();
+        if self.chars.peek().is_some_and(|&(_, c)| c == '}') {
+            self.chars.next();
+            self.depth -= 1;
+            return Ok(Value::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, '}')) => break,
+                _ => return self.error("expected ',' or '}'"),
+            }
+        }
+        self.depth -= 1;
+        Ok(Value::Object(members))
+    }
+
+    fn enter(&mut self) -> Result<(), JsonError> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            self.error("document is nested too deeply")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Conversion of a data type into JSON
+pub trait ToJson {
+    /// Convert into a JSON value
+    fn to_json(&self) -> Value;
+
+    /// Convert into a compact JSON document
+    fn to_json_string(&self) -> String {
+        self.to_json().to_string()
+    }
+}
+
+/// Conversion of JSON into a data type
+pub trait FromJson: Sized {
+    /// Convert from a JSON value
+    fn from_json(value: &Value) -> Result<Self, JsonError>;
+
+    /// Parse a JSON document
+    fn from_json_str(text: &str) -> Result<Self, JsonError> {
+        Self::from_json(&Value::parse(text)?)
+    }
+}
+
+/// Get a numeric member of an object
+fn number(value: &Value, key: &str) -> Result<f64, JsonError> {
+    value
+        .get(key)
+        .and_then(Value::as_f64)
+        .ok_or_else(|| JsonError(format!("missing number '{}'", key)))
+}
+
+/// Get a boolean member of an object
+fn boolean(value: &Value, key: &str) -> Result<bool, JsonError> {
+    value
+        .get(key)
+        .and_then(Value::as_bool)
+        .ok_or_else(|| JsonError(format!("missing boolean '{}'", key)))
+}
+
+/// Get a string member of an object
+fn string(value: &Value, key: &str) -> Result<String, JsonError> {
+    value
+        .get(key)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| JsonError(format!("missing string '{}'", key)))
+}
+
+impl ToJson for StatusFlags {
+    fn to_json(&self) -> Value {
+        Value::object(vec![
+            ("utility_fail", self.utility_fail.into()),
+            ("battery_low", self.battery_low.into()),
+            ("bypass_active", self.bypass_active.into()),
+            ("ups_failed", self.ups_failed.into()),
+            ("standby", self.standby.into()),
+            ("test_in_progress", self.test_in_progress.into()),
+            ("shutdown_active", self.shutdown_active.into()),
+            ("beeper_on", self.beeper_on.into()),
+        ])
+    }
+}
+
+impl FromJson for StatusFlags {
+    fn from_json(value: &Value) -> Result<Self, JsonError> {
+        Ok(Self {
+            utility_fail: boolean(value, "utility_fail")?,
+            battery_low: boolean(value, "battery_low")?,
+            bypass_active: boolean(value, "bypass_active")?,
+            ups_failed: boolean(value, "ups_failed")?,
+            standby: boolean(value, "standby")?,
+            test_in_progress: boolean(value, "test_in_progress")?,
+            shutdown_active: boolean(value, "shutdown_active")?,
+            beeper_on: boolean(value, "beeper_on")?,
+        })
+    }
+}
+
+impl ToJson for UpsStatus {
+    fn to_json(&self) -> Value {
+        Value::object(vec![
+            ("input_voltage", self.input_voltage.into()),
+            ("input_fault_voltage", self.input_fault_voltage.into()),
+            ("output_voltage", self.output_voltage.into()),
+            ("output_current", self.load_raw.into()),
+            ("load_in_amps", self.load_in_amps.into()),
+            ("input_frequency", self.input_frequency.into()),
+            ("battery_voltage", self.battery_voltage.into()),
+            ("temperature", self.temperature.into()),
+            ("flags", self.flags.to_json()),
+        ])
+    }
+}
+
+impl FromJson for UpsStatus {
+    fn from_json(value: &Value) -> Result<Self, JsonError> {
+        let flags = value
+            .get("flags")
+            .ok_or_else(|| JsonError("missing object 'flags'".to_string()))?;
+
+        let load = number(value, "output_current")?;
+        #[allow(deprecated)]
+        Ok(Self {
+            input_voltage: number(value, "input_voltage")?,
+            input_fault_voltage: number(value, "input_fault_voltage")?,
+            output_voltage: number(value, "output_voltage")?,
+            load_raw: load,
+            output_current: load,
+            // Reports of older agents lack the unit
+            load_in_amps: value
+                .get("load_in_amps")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            input_frequency: number(value, "input_frequency")?,
+            battery_voltage: number(value, "battery_voltage")?,
+            temperature: number(value, "temperature")?,
+            flags: StatusFlags::from_json(flags)?,
+        })
+    }
+}
+
+impl ToJson for RatingInfo {
+    fn to_json(&self) -> Value {
+        Value::object(vec![
+            ("rated_voltage", self.rated_voltage.into()),
+            ("rated_current", self.rated_current.into()),
+            ("battery_voltage", self.battery_voltage.into()),
+            ("rated_frequency", self.rated_frequency.into()),
+        ])
+    }
+}
+
+impl FromJson for RatingInfo {
+    fn from_json(value: &Value) -> Result<Self, JsonError> {
+        Ok(Self {
+            rated_voltage: number(value, "rated_voltage")?,
+            rated_current: number(value, "rated_current")?,
+            battery_voltage: number(value, "battery_voltage")?,
+            rated_frequency: number(value, "rated_frequency")?,
+        })
+    }
+}
+
+impl ToJson for UpsInfo {
+    fn to_json(&self) -> Value {
+        Value::object(vec![
+            ("company", self.company.as_str().into()),
+            ("model", self.model.as_str().into()),
+            ("firmware_version", self.firmware_version.as_str().into()),
+        ])
+    }
+}
+
+impl FromJson for UpsInfo {
+    fn from_json(value: &Value) -> Result<Self, JsonError> {
+        Ok(Self {
+            company: string(value, "company")?,
+            model: string(value, "model")?,
+            firmware_version: string(value, "firmware_version")?,
+        })
+    }
+}
+
+impl ToJson for ExtendedStatus {
+    fn to_json(&self) -> Value {
+        Value::object(vec![
+            ("grid_voltage", self.grid_voltage.into()),
+            ("grid_frequency", self.grid_frequency.into()),
+            ("output_voltage", self.output_voltage.into()),
+            ("output_frequency", self.output_frequency.into()),
+            ("output_apparent_power", self.output_apparent_power.into()),
+            ("output_active_power", self.output_active_power.into()),
+            ("load_percent", self.load_percent.into()),
+            ("bus_voltage", self.bus_voltage.into()),
+            ("battery_voltage", self.battery_voltage.into()),
+            (
+                "battery_charging_current",
+                self.battery_charging_current.into(),
+            ),
+            ("battery_capacity", self.battery_capacity.into()),
+            ("heat_sink_temperature", self.heat_sink_temperature.into()),
+            ("pv_input_current", self.pv_input_current.into()),
+            ("pv_input_voltage", self.pv_input_voltage.into()),
+            ("scc_battery_voltage", self.scc_battery_voltage.into()),
+            (
+                "battery_discharge_current",
+                self.battery_discharge_current.into(),
+            ),
+            (
+                "pv_charging_power",
+                self.pv_charging_power.map_or(Value::Null, Value::from),
+            ),
+            ("load_on", self.load_on.into()),
+            ("charging", self.charging.into()),
+            ("scc_charging", self.scc_charging.into()),
+            ("ac_charging", self.ac_charging.into()),
+        ])
+    }
+}
+
+impl FromJson for ExtendedStatus {
+    fn from_json(value: &Value) -> Result<Self, JsonError> {
+        Ok(Self {
+            grid_voltage: number(value, "grid_voltage")?,
+            grid_frequency: number(value, "grid_frequency")?,
+            output_voltage: number(value, "output_voltage")?,
+            output_frequency: number(value, "output_frequency")?,
+            output_apparent_power: number(value, "output_apparent_power")?,
+            output_active_power: number(value, "output_active_power")?,
+            load_percent: number(value, "load_percent")?,
+            bus_voltage: number(value, "bus_voltage")?,
+            battery_voltage: number(value, "battery_voltage")?,
+            battery_charging_current: number(value, "battery_charging_current")?,
+            battery_capacity: number(value, "battery_capacity")?,
+            heat_sink_temperature: number(value, "heat_sink_temperature")?,
+            pv_input_current: number(value, "pv_input_current")?,
+            pv_input_voltage: number(value, "pv_input_voltage")?,
+            scc_battery_voltage: number(value, "scc_battery_voltage")?,
+            battery_discharge_current: number(value, "battery_discharge_current")?,
+            pv_charging_power: value
+                .get("pv_charging_power")
+                .and_then(Value::as_f64)
+                .filter(|power| !power.is_nan()),
+            load_on: boolean(value, "load_on")?,
+            charging: boolean(value, "charging")?,
+            scc_charging: boolean(value, "scc_charging")?,
+            ac_charging: boolean(value, "ac_charging")?,
+        })
+    }
+}
+
+/// Times are RFC 3339 strings, missing values `null`
+impl ToJson for DeviceStatus {
+    fn to_json(&self) -> Value {
+        let optional = |value: Option<Value>| value.unwrap_or(Value::Null);
+        Value::object(vec![
+            ("name", self.name.as_str().into()),
+            (
+                "time",
+                optional(self.time.map(|t| timestamp::rfc3339(t).into())),
+            ),
+            ("reachable", self.is_reachable().into()),
+            (
+                "status",
+                optional(self.status.as_ref().map(ToJson::to_json)),
+            ),
+            ("error", optional(self.error.as_deref().map(Value::from))),
+        ])
+    }
+}
+
+impl ToJson for AggregateStatus {
+    fn to_json(&self) -> Value {
+        let optional = |value: Option<f64>| value.map(Value::from).unwrap_or(Value::Null);
+        Value::object(vec![
+            ("devices", (self.devices as f64).into()),
+            ("reachable", (self.reachable as f64).into()),
+            ("on_battery", (self.on_battery as f64).into()),
+            ("battery_low", (self.battery_low as f64).into()),
+            ("failed", (self.failed as f64).into()),
+            ("average_load", optional(self.average_load)),
+            ("min_battery_voltage", optional(self.min_battery_voltage)),
+            ("all_nominal", self.all_nominal().into()),
+        ])
+    }
+}
+
+/// Events use the externally tagged layout: `{"PowerFailure": {...}}` or `"CommunicationLost"`
+impl ToJson for UpsEvent {
+    fn to_json(&self) -> Value {
+        let tag = match self {
+            UpsEvent::PowerFailure(_) => "PowerFailure",
+            UpsEvent::PowerRestored(_) => "PowerRestored",
+            UpsEvent::LowBattery(_) => "LowBattery",
+            UpsEvent::TestStarted(_) => "TestStarted",
+            UpsEvent::TestFinished(_) => "TestFinished",
+            UpsEvent::TestSkipped(_) => "TestSkipped",
+            UpsEvent::OverTemperature(_) => "OverTemperature",
+            UpsEvent::Overload(_) => "Overload",
+            UpsEvent::CommunicationLost => "CommunicationLost",
+            UpsEvent::CommunicationRestored(_) => "CommunicationRestored",
+        };
+
+        match self.status() {
+            Some(status) => Value::object(vec![(tag, status.to_json())]),
+            None => tag.into(),
+        }
+    }
+}
+
+impl FromJson for UpsEvent {
+    fn from_json(value: &Value) -> Result<Self, JsonError> {
+        if value.as_str() == Some("CommunicationLost") {
+            return Ok(UpsEvent::CommunicationLost);
+        }
+
+        let (tag, status) = match value {
+            Value::Object(members) if members.len() == 1 => {
+                (members[0].0.as_str(), UpsStatus::from_json(&members[0].1)?)
+            }
+            _ => return Err(JsonError("expected an event".to_string())),
+        };
+
+        match tag {
+            "PowerFailure" => Ok(UpsEvent::PowerFailure(status)),
+            "PowerRestored" => Ok(UpsEvent::PowerRestored(status)),
+            "LowBattery" => Ok(UpsEvent::LowBattery(status)),
+            "TestStarted" => Ok(UpsEvent::TestStarted(status)),
+            "TestFinished" => Ok(UpsEvent::TestFinished(status)),
+            "TestSkipped" => Ok(UpsEvent::TestSkipped(status)),
+            "OverTemperature" => Ok(UpsEvent::OverTemperature(status)),
+            "Overload" => Ok(UpsEvent::Overload(status)),
+            "CommunicationRestored" => Ok(UpsEvent::CommunicationRestored(status)),
+            other => Err(JsonError(format!("unknown event '{}'", other))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_are_parsed_and_printed_back() {
+        let text = r#"{"a":[1,-2.5,1e3],"b":"x\"\u00e9\n","c":{"d":null,"e":true}}"#;
+        let value = Value::parse(text).unwrap();
+        assert_eq!(value.get("a").unwrap().as_array().unwrap().len(), 3);
+        assert_eq!(value.get("b").unwrap().as_str(), Some("x\"\u{e9}\n"));
+        assert_eq!(
+            value.get("c").unwrap().get("e").unwrap().as_bool(),
+            Some(true)
+        );
+        assert_eq!(Value::parse(&value.to_string()).unwrap(), value);
+    }
+
+    #[test]
+    fn malformed_documents_are_errors() {
+        for text in [
+            "",
+            "   ",
+            "{",
+            "[1,]",
+            "[1 2]",
+            "{\"a\"}",
+            "{\"a\":1,}",
+            "{a:1}",
+            "\"unterminated",
+            "\"\\x\"",
+            "\"\\u12\"",
+            "-",
+            "tru",
+            "nul",
+            "[1] 2",
+        ] {
+            assert!(Value::parse(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn nesting_up_to_the_limit_parses() {
+        let text = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(Value::parse(&text).is_ok());
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        let text = "[".repeat(60_000);
+        assert!(Value::parse(&text).is_err());
+        let text = "{\"a\":".repeat(60_000);
+        assert!(Value::parse(&text).is_err());
+        assert!(Value::parse_with_depth("[[[1]]]", 2).is_err());
+    }
+}
@@ -1,201 +1,1027 @@
-use rusb::{Context, DeviceHandle, Error as UsbError, UsbContext};
-use std::time::Duration;
-use thiserror::Error;
-
-#[derive(Debug, Error)]
-pub enum UpsError {
-    #[error("USB error: {0}")]
-    Usb(#[from] UsbError),
-    #[error("Invalid response")]
-    InvalidResponse,
-    #[error("Invalid time value")]
-    InvalidTime,
-}
+extern crate alloc;
+
+mod aggregate;
+mod alarms;
+#[cfg(feature = "async")]
+mod async_ups;
+mod battery;
+mod builder;
+mod cancel;
+mod capabilities;
+#[cfg(all(feature = "daemon", unix))]
+pub mod daemon;
+#[cfg(feature = "desktop")]
+mod desktop;
+mod dialect;
+mod discovery;
+#[cfg(feature = "email")]
+mod email;
+mod error;
+mod event;
+#[cfg(feature = "exporter")]
+mod exporter;
+mod extended;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fleet")]
+mod fleet;
+#[cfg(feature = "graphite")]
+mod graphite;
+#[cfg(feature = "graphql")]
+mod graphql;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod health;
+mod hid;
+mod history;
+mod hooks;
+mod hotplug;
+#[cfg(any(
+    feature = "exporter",
+    feature = "fleet",
+    feature = "grpc",
+    feature = "rest",
+    feature = "influxdb",
+    feature = "otel",
+    feature = "websocket",
+    feature = "webhook"
+))]
+mod http;
+#[cfg(feature = "influxdb")]
+mod influxdb;
+mod info;
+pub mod json;
+#[cfg(any(
+    feature = "exporter",
+    feature = "fleet",
+    feature = "modbus",
+    feature = "nis",
+    feature = "rest"
+))]
+mod listener;
+mod load_shedding;
+mod manager;
+mod mock;
+#[cfg(feature = "modbus")]
+mod modbus;
+mod mode;
+mod monitor;
+#[cfg(feature = "napi")]
+mod napi;
+#[cfg(feature = "nis")]
+mod nis;
+#[cfg(all(feature = "nut", unix))]
+mod nut;
+#[cfg(feature = "otel")]
+mod otel;
+mod outage;
+mod poller;
+mod power_action;
+mod power_quality;
+pub mod protocol;
+mod quirks;
+mod rating;
+mod redundancy;
+mod replay;
+#[cfg(feature = "rest")]
+mod rest;
+mod retry;
+mod schedule;
+#[cfg(all(feature = "serial", unix))]
+mod serial;
+mod smoothing;
+#[cfg(feature = "snmp")]
+mod snmp;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod state;
+mod status;
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+pub mod systemd;
+#[cfg(feature = "tcp-serial")]
+mod tcp_serial;
+mod timestamp;
+#[cfg(feature = "tracing")]
+mod trace;
+mod transport;
+#[cfg(feature = "units")]
+mod units;
+mod usb;
+#[cfg(feature = "usbip")]
+mod usbip;
+#[cfg(feature = "webhook")]
+mod webhook;
+#[cfg(feature = "websocket")]
+mod websocket;
+#[cfg(all(feature = "windows-service", windows))]
+pub mod windows_service;
+mod wire;
 
-pub type Result<T> = std::result::Result<T, UpsError>;
+pub use aggregate::{Aggregator, Summary, DEFAULT_WINDOWS};
+pub use alarms::{
+    AlarmEvent, Alarms, Limit, Quantity, Threshold, DEFAULT_OVERLOAD_DURATION,
+    OVER_TEMPERATURE_HYSTERESIS, RATED_LOAD_PERCENT,
+};
+#[cfg(feature = "async")]
+pub use async_ups::{
+    AsyncMegatecUps, AsyncRuntime, Blocking, BlockingWork, Delay, EventStream, Sleep, StatusStream,
+    Task, ThreadRuntime,
+};
+pub use battery::{BatteryChemistry, BatteryModel, BatteryThresholds, DEFAULT_FULL_LOAD_RUNTIME};
+pub use builder::MegatecUpsBuilder;
+pub use cancel::CancellationToken;
+pub use capabilities::Capabilities;
+#[cfg(feature = "desktop")]
+pub use desktop::DesktopNotifier;
+pub use dialect::Dialect;
+pub use discovery::{list_devices, KnownDevice, UpsDeviceInfo, KNOWN_DEVICES};
+#[cfg(feature = "email")]
+pub use email::{
+    EmailConfig, EmailNotifier, DEFAULT_EMAIL_BODY, DEFAULT_EMAIL_SUBJECT, DEFAULT_EMAIL_THROTTLE,
+    DEFAULT_SMTP_SERVER,
+};
+pub use error::{Result, UpsError};
+pub use event::UpsEvent;
+#[cfg(feature = "exporter")]
+pub use exporter::{render_metrics, render_summary_metrics, Exporter, DEFAULT_EXPORTER_ADDRESS};
+pub use extended::ExtendedStatus;
+#[cfg(feature = "fleet")]
+pub use fleet::{FleetAgent, FleetServer, DEFAULT_FLEET_ADDRESS, DEFAULT_STALE_AFTER};
+#[cfg(feature = "graphite")]
+pub use graphite::{GraphiteProtocol, GraphitePusher, DEFAULT_GRAPHITE_PREFIX};
+#[cfg(feature = "grpc")]
+pub use grpc::{GrpcServer, DEFAULT_GRPC_ADDRESS, GRPC_PROTO};
+pub use health::{BatteryHealthReport, Discharge, HealthWarning};
+pub use hid::{open_usb, HidTransport, UsbProtocol};
+pub use history::{HistoryFormat, HistoryWriter, Rotation, DEFAULT_HISTORY_KEEP};
+pub use hooks::{Hook, Hooks};
+pub use hotplug::{ConnectionEvent, ReconnectingTransport};
+#[cfg(feature = "influxdb")]
+pub use influxdb::{InfluxApi, InfluxExporter, DEFAULT_INFLUXDB_MEASUREMENT};
+pub use info::UpsInfo;
+pub use load_shedding::{LoadShedding, SheddingStep, Stage};
+pub use manager::{AggregateStatus, DeviceStatus, UpsManager};
+pub use mock::{Fault, MockTransport, DEFAULT_INFO, DEFAULT_RATING, ONLINE_STATUS};
+#[cfg(feature = "modbus")]
+pub use modbus::{ModbusServer, DEFAULT_MODBUS_ADDRESS, DEFAULT_MODBUS_CACHE_TTL};
+pub use mode::UpsMode;
+pub use monitor::{Monitor, MonitorHandle, DEFAULT_RECENT_SAMPLES};
+#[cfg(feature = "nis")]
+pub use nis::{NisServer, DEFAULT_NIS_ADDRESS};
+#[cfg(all(feature = "nut", unix))]
+pub use nut::{
+    nut_info_variables, nut_rating_variables, nut_status_variables, NutDriver,
+    DEFAULT_NUT_STATE_PATH,
+};
+#[cfg(feature = "otel")]
+pub use otel::{OtelExporter, DEFAULT_OTLP_ENDPOINT};
+pub use outage::OutageStats;
+pub use poller::{Poller, PollerHandle, Snapshot, DEFAULT_MIN_COMMAND_GAP};
+pub use power_action::{
+    default_shutdown_command, PowerAction, PowerPolicy, ShutdownReason, UpsShutdown,
+    DEFAULT_UPS_SHUTDOWN_DELAY_MINUTES,
+};
+pub use power_quality::{
+    Excursion, ExcursionKind, PowerQuality, PowerQualityStats, VoltageBand,
+    DEFAULT_VOLTAGE_TOLERANCE,
+};
+pub use quirks::{QuirkEntry, Quirks, KNOWN_QUIRKS};
+pub use rating::RatingInfo;
+pub use redundancy::{QuorumVote, RedundancyPolicy, RedundantPowerAction, DEFAULT_FAILED_WEIGHT};
+pub use replay::{RecordingTransport, ReplayTransport};
+#[cfg(feature = "rest")]
+pub use rest::{RestServer, DEFAULT_REST_ADDRESS};
+pub use retry::RetryPolicy;
+pub use schedule::{Schedule, ScheduledTest, TestKind, TestScheduler};
+#[cfg(all(feature = "serial", unix))]
+pub use serial::SerialTransport;
+pub use smoothing::{Smoother, Smoothing};
+#[cfg(feature = "snmp")]
+pub use snmp::{SnmpAgent, DEFAULT_SNMP_ADDRESS, DEFAULT_SNMP_CACHE_TTL};
+#[cfg(feature = "sqlite")]
+pub use sqlite::{EventRecord, Sample, SqliteHistory};
+pub use state::{StateTransition, UpsState, UpsStateMachine};
+pub use status::{StatusFlags, TestResult, UpsStatus};
+#[cfg(feature = "tcp-serial")]
+pub use tcp_serial::{TcpSerialMode, TcpSerialTransport};
+#[cfg(feature = "tracing")]
+pub use trace::Transaction;
+pub use transport::{Command, Response, ShutdownDelay, Transport, MAX_RESTORE_MINUTES};
+#[cfg(feature = "units")]
+pub use units::{ElectricPotential, Frequency, Ratio, ThermodynamicTemperature, UpsStatusTyped};
+pub use usb::UsbTransport;
+#[cfg(feature = "usbip")]
+pub use usbip::{UsbIpDevice, UsbIpTransport, DEFAULT_USBIP_PORT};
+#[cfg(feature = "webhook")]
+pub use webhook::{WebhookNotifier, DEFAULT_WEBHOOK_TIMEOUT};
+#[cfg(feature = "websocket")]
+pub use websocket::{WebSocketServer, DEFAULT_WEBSOCKET_ADDRESS, WEBSOCKET_PATH};
+pub use wire::{hex_dump, WireLog};
 
-const ASCII_MIN: u8 = 32;
-const ASCII_MAX: u8 = 126;
-const CHAR_QUOTE: u8 = 34;
-const CHAR_BACKTICK: u8 = 96;
-const CHAR_PAREN: u8 = 40;
+use std::mem::{self, Discriminant};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+const DEFAULT_ACK_DELAY: Duration = Duration::from_secs(1);
+pub(crate) const TEST_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Main structure for interacting with a Megatec UPS device
-pub struct MegatecUps {
-    handle: DeviceHandle<Context>,
-    context: Context,
+///
+/// Commands are serialized on the wire through an internal lock, so a single
+/// connection can be shared between threads (for example through an `Arc`).
+pub struct MegatecUps<T: Transport = UsbTransport> {
+    transport: Mutex<T>,
+    retry_policy: RetryPolicy,
+    ack_delay: Duration,
+    quirks: Quirks,
+    strict_parsing: bool,
+    dialect: Dialect,
+    status_cache_ttl: Duration,
+    status_cache: Mutex<Option<(Instant, UpsStatus)>>,
+    command_timeouts: Vec<(Discriminant<Command>, Duration)>,
+    cancellation: CancellationToken,
+    #[cfg(feature = "tracing")]
+    tracer: Option<trace::Tracer>,
 }
 
-impl MegatecUps {
+impl MegatecUps<UsbTransport> {
     /// Create a new UPS connection using vendor_id and product_id
     pub fn new(vendor_id: u16, product_id: u16) -> Result<Self> {
-        let context = Context::new()?;
-        let handle = context
-            .open_device_with_vid_pid(vendor_id, product_id)
-            .ok_or(UpsError::InvalidResponse)?;
-
-        Ok(Self { handle, context })
-    }
-
-    /// Get a string descriptor from the device
-    fn get_string_descriptor(&self, index: u8, length: u16) -> Result<String> {
-        let mut data = vec![0u8; length as usize];
-        let result = self.handle.read_control(
-            rusb::request_type(
-                rusb::Direction::In,
-                rusb::RequestType::Standard,
-                rusb::Recipient::Device,
-            ),
-            rusb::constants::LIBUSB_REQUEST_GET_DESCRIPTOR,
-            (rusb::constants::LIBUSB_DT_STRING as u16) << 8 | index as u16,
-            0,
-            &mut data,
-            Duration::from_secs(1),
-        )?;
-
-        if result >= 3 {
-            let filtered: String = data
-                .into_iter()
-                .filter(|&c| Self::is_valid_char(c))
-                .map(|c| c as char)
-                .collect();
-            Ok(filtered)
-        } else {
-            Err(UpsError::InvalidResponse)
-        }
-    }
-
-    /// Check if a character is valid according to protocol rules
-    fn is_valid_char(c: u8) -> bool {
-        c >= ASCII_MIN && c <= ASCII_MAX && c != CHAR_QUOTE && c != CHAR_BACKTICK && c != CHAR_PAREN
+        let transport = UsbTransport::open(vendor_id, product_id)?;
+        Ok(Self::with_transport(transport))
+    }
+
+    /// Create a new UPS connection to the USB device reporting the given serial number
+    pub fn open_by_serial(serial_number: &str) -> Result<Self> {
+        let transport = UsbTransport::open_by_serial(serial_number)?;
+        Ok(Self::with_transport(transport))
+    }
+
+    /// Create a new UPS connection to the USB device at the given bus and port path
+    pub fn open_by_port_path(bus_number: u8, port_numbers: &[u8]) -> Result<Self> {
+        let transport = UsbTransport::open_by_port_path(bus_number, port_numbers)?;
+        Ok(Self::with_transport(transport))
+    }
+
+    /// Connect to the first well-known Megatec device that answers a status query
+    pub fn auto() -> Result<Self> {
+        Self::builder().build()
+    }
+
+    /// Create a builder to configure the connection
+    pub fn builder() -> MegatecUpsBuilder {
+        MegatecUpsBuilder::new()
+    }
+
+    /// Release the USB interface, reattach the kernel driver and close the device
+    pub fn close(self) -> Result<()> {
+        self.into_transport().close()
+    }
+
+    /// List all USB devices that are candidates for a UPS connection
+    pub fn enumerate() -> Result<Vec<UpsDeviceInfo>> {
+        list_devices()
+    }
+
+    /// Read a string descriptor the crate does not wrap, see [`UsbTransport::send_raw`]
+    ///
+    /// The cached status is dropped, as vendor commands may change it.
+    pub fn send_raw(&self, index: u8, length: u16) -> Result<Vec<u8>> {
+        self.cache_status(None);
+        self.transport().send_raw(index, length)
+    }
+}
+
+#[cfg(feature = "usbip")]
+impl MegatecUps<UsbIpTransport> {
+    /// Create a new UPS connection to the device `busid` exported by usbipd at `address`
+    pub fn open_usbip(address: impl std::net::ToSocketAddrs, busid: &str) -> Result<Self> {
+        Ok(Self::with_transport(UsbIpTransport::connect(
+            address, busid,
+        )?))
+    }
+}
+
+#[cfg(feature = "tcp-serial")]
+impl MegatecUps<TcpSerialTransport> {
+    /// Create a new UPS connection over a raw serial-to-TCP bridge at `address`
+    pub fn open_tcp_serial(address: impl std::net::ToSocketAddrs) -> Result<Self> {
+        Self::open_bridge(TcpSerialTransport::connect(address)?)
+    }
+
+    /// Create a new UPS connection over the RFC 2217 server at `address`
+    pub fn open_rfc2217(address: impl std::net::ToSocketAddrs) -> Result<Self> {
+        Self::open_bridge(TcpSerialTransport::connect_rfc2217(address)?)
+    }
+
+    fn open_bridge(transport: TcpSerialTransport) -> Result<Self> {
+        let mut ups = Self::with_transport(transport);
+        // A silent device is reported by the first status query instead
+        let _ = ups.detect_dialect();
+        Ok(ups)
+    }
+
+    /// Send a command line the crate does not wrap, see [`TcpSerialTransport::send_raw_command`]
+    ///
+    /// The cached status is dropped, as vendor commands may change it.
+    pub fn send_raw_command(&self, text: &str, expect_response: bool) -> Result<Response> {
+        self.cache_status(None);
+        self.transport().send_raw_command(text, expect_response)
+    }
+}
+
+#[cfg(all(feature = "serial", unix))]
+impl MegatecUps<SerialTransport> {
+    /// Create a new UPS connection over the serial port at the given path
+    pub fn open_serial<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let transport = SerialTransport::open(path)?;
+        let mut ups = Self::with_transport(transport);
+        // A silent device is reported by the first status query instead
+        let _ = ups.detect_dialect();
+        Ok(ups)
+    }
+
+    /// Send a command line the crate does not wrap, see [`SerialTransport::send_raw_command`]
+    ///
+    /// The cached status is dropped, as vendor commands may change it.
+    pub fn send_raw_command(&self, text: &str, expect_response: bool) -> Result<Response> {
+        self.cache_status(None);
+        self.transport().send_raw_command(text, expect_response)
+    }
+}
+
+impl<T: Transport> MegatecUps<T> {
+    /// Create a new UPS connection on top of an existing transport
+    pub fn with_transport(transport: T) -> Self {
+        Self {
+            transport: Mutex::new(transport),
+            retry_policy: RetryPolicy::default(),
+            ack_delay: DEFAULT_ACK_DELAY,
+            quirks: Quirks::NONE,
+            strict_parsing: false,
+            dialect: Dialect::Q1,
+            status_cache_ttl: Duration::ZERO,
+            status_cache: Mutex::new(None),
+            command_timeouts: Vec::new(),
+            cancellation: CancellationToken::new(),
+            #[cfg(feature = "tracing")]
+            tracer: None,
+        }
+    }
+
+    /// Lock and get the underlying transport
+    pub fn transport(&self) -> MutexGuard<'_, T> {
+        self.transport
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Consume the connection and return the underlying transport
+    pub fn into_transport(self) -> T {
+        self.transport
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Get the policy used to retry failed transactions
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    /// Set the policy used to retry failed transactions
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Get the delay between the acknowledgment and the actual status read
+    pub fn ack_delay(&self) -> Duration {
+        self.ack_delay
+    }
+
+    /// Set the delay between the acknowledgment and the actual status read
+    pub fn set_ack_delay(&mut self, ack_delay: Duration) {
+        self.ack_delay = ack_delay;
+    }
+
+    /// Get the model quirks applied to the protocol
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Set the model quirks applied to the protocol
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Check if status answers are parsed strictly
+    pub fn strict_parsing(&self) -> bool {
+        self.strict_parsing
+    }
+
+    /// Reject status answers deviating from the protocol
+    ///
+    /// By default a leading '(', comma decimal separators and missing trailing
+    /// fields are tolerated, as several clones answer that way; in strict mode
+    /// only the model quirks are applied.
+    pub fn set_strict_parsing(&mut self, strict: bool) {
+        self.strict_parsing = strict;
+    }
+
+    /// Parse a status answer according to the dialect and the parsing mode
+    fn parse_status(&self, text: &str) -> Result<UpsStatus> {
+        self.dialect.parse(text, self.strict_parsing, &self.quirks)
+    }
+
+    /// Get the status query used by the device
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// Use the given status query instead of Q1
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    /// Find out which status query the device answers and use it from now on
+    ///
+    /// The dialects are probed in the order of [`Dialect::ALL`], twice each as
+    /// the first answer may only be the acknowledgment, without retries. The
+    /// dialect is kept when none answers and the error of the Q1 probe returned.
+    pub fn detect_dialect(&mut self) -> Result<Dialect> {
+        let mut first_error = None;
+        for dialect in Dialect::ALL {
+            for attempt in 1..=2 {
+                let status = self.transact(
+                    &self.transport(),
+                    dialect.command(),
+                    attempt,
+                    None,
+                    |response| dialect.parse(&response.text, self.strict_parsing, &self.quirks),
+                );
+                match status {
+                    Ok(_) => {
+                        self.dialect = dialect;
+                        return Ok(dialect);
+                    }
+                    Err(error) => {
+                        first_error.get_or_insert(error);
+                    }
+                }
+            }
+        }
+        Err(first_error.unwrap_or(UpsError::InvalidResponse))
+    }
+
+    /// Get how long a status reading is served to later `get_status` calls
+    pub fn status_cache_ttl(&self) -> Duration {
+        self.status_cache_ttl
+    }
+
+    /// Serve status readings younger than `ttl` from a cache instead of the UPS
+    ///
+    /// Concurrent readers waiting for the same reading share it, so a web UI
+    /// and a metrics scraper polling together cost one Q1 transaction. A zero
+    /// TTL, the default, disables the cache.
+    pub fn set_status_cache_ttl(&mut self, ttl: Duration) {
+        self.status_cache_ttl = ttl;
+    }
+
+    /// Get the cached status if it is still fresh
+    pub(crate) fn cached_status(&self) -> Option<UpsStatus> {
+        if self.status_cache_ttl.is_zero() {
+            return None;
+        }
+        let cache = self
+            .status_cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        cache
+            .as_ref()
+            .filter(|(time, _)| time.elapsed() < self.status_cache_ttl)
+            .map(|(_, status)| status.clone())
+    }
+
+    /// Replace or drop the cached status
+    pub(crate) fn cache_status(&self, status: Option<&UpsStatus>) {
+        if !self.status_cache_ttl.is_zero() {
+            *self
+                .status_cache
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()) =
+                status.map(|status| (Instant::now(), status.clone()));
+        }
+    }
+
+    /// Report every protocol transaction to `tracer`
+    ///
+    /// The callback runs on the thread sending the command, while the
+    /// transport is locked, so it should not block.
+    #[cfg(feature = "tracing")]
+    pub fn set_tracer<F>(&mut self, tracer: F)
+    where
+        F: Fn(&Transaction<'_>) + Send + Sync + 'static,
+    {
+        self.tracer = Some(std::sync::Arc::new(tracer));
+    }
+
+    /// Get the response timeout used for a kind of command, if overridden
+    pub fn command_timeout(&self, command: Command) -> Option<Duration> {
+        let kind = mem::discriminant(&command);
+        self.command_timeouts
+            .iter()
+            .find(|(other, _)| *other == kind)
+            .map(|(_, timeout)| *timeout)
+    }
+
+    /// Wait up to `timeout` for the response to every command of this kind
+    ///
+    /// Shutdowns and tests take much longer to acknowledge than queries on
+    /// some units. The override applies to all parameters of the command, for
+    /// example to every [`Command::Shutdown`] delay, and is ignored by
+    /// transports without a configurable timeout.
+    pub fn set_command_timeout(&mut self, command: Command, timeout: Duration) {
+        let kind = mem::discriminant(&command);
+        self.command_timeouts.retain(|(other, _)| *other != kind);
+        self.command_timeouts.push((kind, timeout));
+    }
+
+    /// Get the token cancelling the operations of this connection
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// Cancel the operations of this connection through `token`
+    ///
+    /// Once it is cancelled, waits such as the acknowledgment delay of
+    /// [`get_status`](Self::get_status), retry backoffs and
+    /// [`wait_for_test`](Self::wait_for_test) end right away, and every
+    /// command fails with [`UpsError::Cancelled`] until another token is set.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation = token;
+    }
+
+    /// Get the delay actually used after the acknowledgment, honoring model quirks
+    pub(crate) fn effective_ack_delay(&self) -> Duration {
+        self.quirks.ack_delay.unwrap_or(self.ack_delay)
+    }
+
+    /// Run an operation, retrying it with backoff according to the retry policy
+    ///
+    /// After `reset_after` consecutive pipe or I/O errors the transport is
+    /// reset before the next attempt.
+    fn retrying<R>(&self, transport: &T, mut operation: impl FnMut(u32) -> Result<R>) -> Result<R> {
+        let mut retry = 0;
+        let mut wedged = 0;
+        loop {
+            self.cancellation.check()?;
+            match operation(retry + 1) {
+                Err(e) if retry < self.retry_policy.retries && RetryPolicy::is_retryable(&e) => {
+                    wedged = if RetryPolicy::needs_reset(&e) {
+                        wedged + 1
+                    } else {
+                        0
+                    };
+                    if self.retry_policy.reset_after > 0 && wedged >= self.retry_policy.reset_after
+                    {
+                        // A failed reset shows up in the next attempt
+                        let _ = transport.reset();
+                        wedged = 0;
+                    }
+                    self.cancellation.sleep(self.retry_policy.backoff(retry))?;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Send a command, retrying failed transfers
+    pub(crate) fn send(&self, command: Command) -> Result<Response> {
+        self.send_on(&self.transport(), command)
+    }
+
+    /// Send a command and parse its response, retrying failed transfers and invalid responses
+    pub(crate) fn query<R>(
+        &self,
+        command: Command,
+        parse: impl Fn(&str) -> Result<R>,
+    ) -> Result<R> {
+        self.query_on(&self.transport(), command, parse)
+    }
+
+    /// Send a command on an already locked transport
+    fn send_on(&self, transport: &T, command: Command) -> Result<Response> {
+        self.send_within(transport, command, None)
+    }
+
+    /// Send a command on an already locked transport, overriding its timeout
+    ///
+    /// Only queries are retried. A command the UPS acted on before the
+    /// transfer failed would otherwise run twice, which undoes a beeper toggle.
+    fn send_within(
+        &self,
+        transport: &T,
+        command: Command,
+        timeout: Option<Duration>,
+    ) -> Result<Response> {
+        if !command.is_query() {
+            // Tests, the beeper and shutdowns change the status
+            self.cache_status(None);
+            self.cancellation.check()?;
+            return self.transact(transport, command, 1, timeout, Ok);
+        }
+        self.retrying(transport, |attempt| {
+            self.transact(transport, command, attempt, timeout, Ok)
+        })
+    }
+
+    /// Send a command and parse its response on an already locked transport
+    fn query_on<R>(
+        &self,
+        transport: &T,
+        command: Command,
+        parse: impl Fn(&str) -> Result<R>,
+    ) -> Result<R> {
+        self.query_within(transport, command, None, parse)
+    }
+
+    /// Send a command and parse its response on an already locked transport,
+    /// overriding its timeout
+    fn query_within<R>(
+        &self,
+        transport: &T,
+        command: Command,
+        timeout: Option<Duration>,
+        parse: impl Fn(&str) -> Result<R>,
+    ) -> Result<R> {
+        self.retrying(transport, |attempt| {
+            self.transact(transport, command, attempt, timeout, |response| {
+                parse(&response.text).map_err(|_| UpsError::ParseError {
+                    raw: response.raw,
+                    decoded: response.text,
+                })
+            })
+        })
+    }
+
+    /// Send a command once and interpret its response, reporting the transaction
+    ///
+    /// Without an explicit timeout the one set for the command kind applies.
+    fn transact<R>(
+        &self,
+        transport: &T,
+        command: Command,
+        attempt: u32,
+        timeout: Option<Duration>,
+        interpret: impl FnOnce(Response) -> Result<R>,
+    ) -> Result<R> {
+        #[cfg(feature = "tracing")]
+        let started = Instant::now();
+        let response = match timeout.or_else(|| self.command_timeout(command)) {
+            Some(timeout) => transport.send_command_with_timeout(command, timeout),
+            None => transport.send_command(command),
+        };
+        #[cfg(feature = "tracing")]
+        let response_len = response.as_ref().map_or(0, |response| response.raw.len());
+        let result = response.and_then(interpret);
+
+        #[cfg(feature = "tracing")]
+        if let Some(tracer) = &self.tracer {
+            tracer(&Transaction {
+                command,
+                attempt,
+                duration: started.elapsed(),
+                response_len,
+                error: result.as_ref().err(),
+            });
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = attempt;
+        result
+    }
+
+    /// Clear endpoint halts and reset the device, as replugging it would
+    ///
+    /// Transports without a recovery path return [`UpsError::Unsupported`].
+    pub fn reset(&self) -> Result<()> {
+        self.cache_status(None);
+        self.transport().reset()
     }
 
     /// Get the UPS name
     pub fn get_name(&self) -> Result<String> {
-        self.get_string_descriptor(2, 256)
+        Ok(self.send(Command::Name)?.text)
     }
 
     /// Get the UPS status with acknowledgment
     pub fn get_status(&self) -> Result<UpsStatus> {
+        if let Some(status) = self.cached_status() {
+            return Ok(status);
+        }
+        // Keep the transport locked so no other command gets between both requests
+        let transport = self.transport();
+        // Another reader may have refreshed the cache while this one waited for the lock
+        if let Some(status) = self.cached_status() {
+            return Ok(status);
+        }
+        let status = self.status_on(&transport)?;
+        self.cache_status(Some(&status));
+        Ok(status)
+    }
+
+    /// Get the UPS status, waiting up to `timeout` for each of its responses
+    ///
+    /// Bypasses the status cache, as a caller passing a timeout wants a fresh
+    /// reading, but refreshes it.
+    pub fn get_status_with_timeout(&self, timeout: Duration) -> Result<UpsStatus> {
+        let status = self.status_within(&self.transport(), Some(timeout))?;
+        self.cache_status(Some(&status));
+        Ok(status)
+    }
+
+    /// Get the UPS status with acknowledgment on an already locked transport
+    fn status_on(&self, transport: &T) -> Result<UpsStatus> {
+        self.status_within(transport, None)
+    }
+
+    /// Get the UPS status with acknowledgment, overriding the response timeout
+    fn status_within(&self, transport: &T, timeout: Option<Duration>) -> Result<UpsStatus> {
+        let command = self.dialect.command();
         // First request for acknowledgment
-        let _ = self.get_string_descriptor(3, 256)?;
-        std::thread::sleep(Duration::from_secs(1));
+        let _ = self.send_within(transport, command, timeout)?;
+        self.cancellation.sleep(self.effective_ack_delay())?;
+
+        for _ in 0..self.quirks.warmup_reads {
+            let _ = self.send_within(transport, command, timeout)?;
+        }
 
         // Second request for actual status
-        let status_str = self.get_string_descriptor(3, 256)?;
-        UpsStatus::from_str(&status_str)
+        self.query_within(transport, command, timeout, |text| self.parse_status(text))
     }
 
     /// Get the UPS status without acknowledgment
     pub fn get_status_no_ack(&self) -> Result<UpsStatus> {
-        let status_str = self.get_string_descriptor(3, 256)?;
-        UpsStatus::from_str(&status_str)
+        self.query(self.dialect.command(), |text| self.parse_status(text))
     }
 
     /// Test UPS for 10 seconds
-    pub fn test(&self) -> Result<()> {
-        self.get_string_descriptor(4, 256)?;
-        Ok(())
+    ///
+    /// The status is read back to tell whether the test is running, was
+    /// refused or the UPS reports a failure.
+    pub fn test(&self) -> Result<TestResult> {
+        self.start_test(Command::Test)
     }
 
     /// Test UPS until battery is low
-    pub fn test_until_battery_low(&self) -> Result<()> {
-        self.get_string_descriptor(5, 256)?;
-        Ok(())
+    pub fn test_until_battery_low(&self) -> Result<TestResult> {
+        self.start_test(Command::TestUntilBatteryLow)
     }
 
     /// Test UPS for specified minutes
-    pub fn test_with_time(&self, minutes: u8) -> Result<()> {
-        let calculated_time = Self::calculate_time(minutes)?;
-        self.get_string_descriptor(6, calculated_time)?;
-        Ok(())
+    pub fn test_with_time(&self, minutes: u8) -> Result<TestResult> {
+        self.start_test(Command::TestWithTime(minutes))
+    }
+
+    /// Wait up to `timeout` for a running test to end and get its result
+    ///
+    /// Returns [`TestResult::InProgress`] when the test still runs after the timeout.
+    pub fn wait_for_test(&self, timeout: Duration) -> Result<TestResult> {
+        let started = Instant::now();
+        loop {
+            let status = self.get_status()?;
+            if let Some(result) = TestResult::after_wait(&status.flags) {
+                return Ok(result);
+            }
+            if started.elapsed() >= timeout {
+                return Ok(TestResult::InProgress);
+            }
+            let remaining = timeout.saturating_sub(started.elapsed());
+            self.cancellation.sleep(TEST_POLL_INTERVAL.min(remaining))?;
+        }
+    }
+
+    /// Start a test and read back whether it is running
+    fn start_test(&self, command: Command) -> Result<TestResult> {
+        self.send(command)?;
+        let status = self.get_status()?;
+        Ok(TestResult::after_start(&status.flags))
     }
 
     /// Toggle UPS beep
     pub fn switch_beep(&self) -> Result<()> {
-        self.get_string_descriptor(7, 256)?;
+        self.send(Command::ToggleBeep)?;
+        Ok(())
+    }
+
+    /// Check if the UPS beeper is enabled
+    pub fn beeper_enabled(&self) -> Result<bool> {
+        Ok(self.get_status()?.flags.beeper_on)
+    }
+
+    /// Check if a battery test is running
+    pub fn test_in_progress(&self) -> Result<bool> {
+        Ok(self.get_status()?.flags.test_in_progress)
+    }
+
+    /// Check if a shutdown countdown is running
+    pub fn shutdown_pending(&self) -> Result<bool> {
+        Ok(self.get_status()?.flags.shutdown_active)
+    }
+
+    /// Enable or disable the UPS beeper, toggling it only when needed
+    pub fn set_beeper(&self, enabled: bool) -> Result<()> {
+        let transport = self.transport();
+        if self.status_on(&transport)?.flags.beeper_on != enabled {
+            self.send_on(&transport, Command::ToggleBeep)?;
+        }
         Ok(())
     }
 
     /// Abort current UPS test
     pub fn abort_test(&self) -> Result<()> {
-        self.get_string_descriptor(11, 256)?;
+        self.send(Command::AbortTest)?;
         Ok(())
     }
 
     /// Get UPS rating information
-    pub fn get_rating(&self) -> Result<String> {
-        self.get_string_descriptor(13, 256)
+    pub fn get_rating(&self) -> Result<RatingInfo> {
+        self.query(Command::Rating, RatingInfo::from_str)
     }
 
-    /// Shutdown UPS after 1 minute
-    pub fn shutdown(&self) -> Result<()> {
-        self.get_string_descriptor(105, 2460)?;
-        Ok(())
+    /// Get UPS manufacturer information
+    pub fn get_info(&self) -> Result<UpsInfo> {
+        self.query(Command::Info, UpsInfo::from_str)
     }
 
-    /// Calculate the protocol-specific time value for the test duration
-    fn calculate_time(minutes: u8) -> Result<u16> {
-        if minutes == 0 || minutes > 99 {
-            return Err(UpsError::InvalidTime);
+    /// Get the Voltronic/Axpert extended status (QPIGS)
+    ///
+    /// Fails on pure Megatec devices, which do not know the query, and on the
+    /// Mega(USB) string descriptor protocol.
+    pub fn get_extended_status(&self) -> Result<ExtendedStatus> {
+        self.query(Command::ExtendedStatus, ExtendedStatus::from_str)
+    }
+
+    /// Get the operating mode
+    ///
+    /// Asks Voltronic devices with QMOD; pure Megatec devices, which do not
+    /// know the query, get the mode derived from the status flags.
+    pub fn get_mode(&self) -> Result<UpsMode> {
+        let transport = self.transport();
+        match self.query_on(&transport, Command::Mode, UpsMode::from_str) {
+            Err(
+                UpsError::InvalidResponse
+                | UpsError::ParseError { .. }
+                | UpsError::Unsupported
+                | UpsError::Timeout,
+            ) => Ok(UpsMode::from_flags(&self.status_on(&transport)?.flags)),
+            mode => mode,
         }
+    }
 
-        let value = match minutes {
-            1..=9 => 100 + minutes,
-            10..=19 => 125 + (minutes - 19),
-            20..=99 => {
-                let range_start = ((minutes - 20) / 10) * 10 + 20;
-                132 + ((minutes - range_start) * 7)
-            }
-            _ => return Err(UpsError::InvalidTime),
+    /// Get the load in percent of the rated load
+    ///
+    /// The rating is only read for units with the
+    /// [`load_in_amps`](Quirks::load_in_amps) quirk.
+    pub fn load_percent(&self) -> Result<f64> {
+        let status = self.get_status()?;
+        if !self.quirks.load_in_amps {
+            return Ok(status.load_raw);
+        }
+        Ok(status.load_percent(&self.get_rating()?, &self.quirks))
+    }
+
+    /// Get the battery charge from 0 to 100%, derived from the battery voltage
+    ///
+    /// Uses the lead-acid [`BatteryThresholds`] for the rated battery voltage;
+    /// call [`battery_percent_with`](Self::battery_percent_with) for others.
+    pub fn battery_percent(&self) -> Result<Option<f64>> {
+        let thresholds = BatteryThresholds::for_nominal(self.get_rating()?.battery_voltage);
+        self.battery_percent_with(&thresholds)
+    }
+
+    /// Get the battery charge from 0 to 100% with the given thresholds
+    pub fn battery_percent_with(&self, thresholds: &BatteryThresholds) -> Result<Option<f64>> {
+        Ok(thresholds.percent(self.get_status()?.battery_voltage))
+    }
+
+    /// Estimate the minutes of battery runtime left at the current load
+    ///
+    /// Uses the lead-acid [`BatteryModel`] for the rated battery voltage; call
+    /// [`estimate_runtime_with`](Self::estimate_runtime_with) for another
+    /// chemistry or a measured full-load runtime.
+    pub fn estimate_runtime(&self) -> Result<Option<Duration>> {
+        let rating = self.get_rating()?;
+        let status = self.get_status()?.in_percent(&rating, &self.quirks);
+        Ok(BatteryModel::from_rating(&rating).estimate_runtime(&status))
+    }
+
+    /// Estimate the battery runtime left with the given battery model
+    ///
+    /// The rating is only read for units with the
+    /// [`load_in_amps`](Quirks::load_in_amps) quirk.
+    pub fn estimate_runtime_with(&self, model: &BatteryModel) -> Result<Option<Duration>> {
+        let mut status = self.get_status()?;
+        if self.quirks.load_in_amps {
+            status = status.in_percent(&self.get_rating()?, &self.quirks);
+        }
+        Ok(model.estimate_runtime(&status))
+    }
+
+    /// Find out which commands the UPS supports without changing its state
+    ///
+    /// Tests are detected by aborting a test when none is running, and the
+    /// beeper by toggling it twice.
+    pub fn probe_capabilities(&self) -> Result<Capabilities> {
+        let transport = self.transport();
+        let mut capabilities = Capabilities::empty();
+
+        if self.send_on(&transport, Command::Name).is_ok() {
+            capabilities |= Capabilities::NAME;
+        }
+        if self
+            .query_on(&transport, Command::Rating, RatingInfo::from_str)
+            .is_ok()
+        {
+            capabilities |= Capabilities::RATING;
+        }
+        if self
+            .query_on(&transport, Command::Info, UpsInfo::from_str)
+            .is_ok()
+        {
+            capabilities |= Capabilities::INFO;
+        }
+        if self
+            .query_on(
+                &transport,
+                Command::ExtendedStatus,
+                ExtendedStatus::from_str,
+            )
+            .is_ok()
+        {
+            capabilities |= Capabilities::EXTENDED_STATUS;
+        }
+        if self
+            .query_on(&transport, Command::Mode, UpsMode::from_str)
+            .is_ok()
+        {
+            capabilities |= Capabilities::MODE;
+        }
+
+        let status = match self.status_on(&transport) {
+            Ok(status) => status,
+            Err(_) => return Ok(capabilities),
         };
+        capabilities |= Capabilities::STATUS;
+
+        if !status.flags.test_in_progress && self.send_on(&transport, Command::AbortTest).is_ok() {
+            capabilities |= Capabilities::TEST;
+        }
+
+        if self.send_on(&transport, Command::ToggleBeep).is_ok() {
+            let toggled = self.status_on(&transport);
+            // Always put the beeper back into its original state
+            self.send_on(&transport, Command::ToggleBeep)?;
+
+            if toggled.is_ok_and(|toggled| toggled.flags.beeper_on != status.flags.beeper_on) {
+                capabilities |= Capabilities::BEEPER;
+            }
+        }
 
-        Ok(value as u16)
+        Ok(capabilities)
     }
-}
 
-/// Structure representing the UPS status values
-#[derive(Debug, Clone)]
-pub struct UpsStatus {
-    pub input_voltage: f64,
-    pub input_fault_voltage: f64,
-    pub output_voltage: f64,
-    pub output_current: f64,
-    pub input_frequency: f64,
-    pub battery_voltage: f64,
-    pub temperature: f64,
-}
+    /// Shutdown UPS after 1 minute
+    pub fn shutdown(&self) -> Result<()> {
+        self.send(Command::Shutdown(ShutdownDelay::ONE_MINUTE))?;
+        Ok(())
+    }
 
-impl UpsStatus {
-    /// Parse status string into UpsStatus struct
-    fn from_str(status: &str) -> Result<Self> {
-        let values: Vec<f64> = status
-            .split_whitespace()
-            .take(7)
-            .map(|s| s.parse::<f64>())
-            .collect::<std::result::Result<Vec<f64>, _>>()
-            .map_err(|_| UpsError::InvalidResponse)?;
-
-        if values.len() != 7 {
-            return Err(UpsError::InvalidResponse);
-        }
-
-        Ok(Self {
-            input_voltage: values[0],
-            input_fault_voltage: values[1],
-            output_voltage: values[2],
-            output_current: values[3],
-            input_frequency: values[4],
-            battery_voltage: values[5],
-            temperature: values[6],
-        })
+    /// Shutdown UPS after .2 to .9 minutes (in steps of .1) or 1 to 10 whole minutes
+    pub fn shutdown_after(&self, minutes: f32) -> Result<()> {
+        let delay = ShutdownDelay::from_minutes(minutes)?;
+        self.send(Command::Shutdown(delay))?;
+        Ok(())
     }
-}
 
-impl Drop for MegatecUps {
-    fn drop(&mut self) {
-        if let Ok(new_context) = Context::new() {
-            let _old_context = std::mem::replace(&mut self.context, new_context);
+    /// Cancel a pending shutdown, failing if the UPS reports none
+    pub fn cancel_shutdown(&self) -> Result<()> {
+        let transport = self.transport();
+        if !self.status_on(&transport)?.flags.shutdown_active {
+            return Err(UpsError::NoShutdownPending);
+        }
+
+        self.send_on(&transport, Command::CancelShutdown)?;
+        Ok(())
+    }
+
+    /// Shutdown UPS after `shutdown_minutes` and power the load back on `restore_minutes` later
+    pub fn shutdown_and_restore(&self, shutdown_minutes: f32, restore_minutes: u16) -> Result<()> {
+        let delay = ShutdownDelay::from_minutes(shutdown_minutes)?;
+        if restore_minutes > MAX_RESTORE_MINUTES {
+            return Err(UpsError::InvalidTime);
         }
+
+        self.send(Command::ShutdownAndRestore(delay, restore_minutes))?;
+        Ok(())
     }
 }
@@ -0,0 +1,30 @@
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+/// Pause after running out of file descriptors, so the accept loop does not spin
+const OUT_OF_DESCRIPTORS_PAUSE: Duration = Duration::from_millis(100);
+
+/// Report a failed `accept()` of a server, which then accepts the next client
+///
+/// Such errors concern a single connection, a client resetting during the
+/// handshake for instance, while the listener keeps working. When the process
+/// or the system is out of file descriptors the thread pauses briefly, giving
+/// the open connections time to close.
+pub(crate) fn accept_failed(server: &str, error: &io::Error) {
+    eprintln!(
+        "megatec-ups: {}: cannot accept a connection: {}",
+        server, error
+    );
+    if out_of_descriptors(error) {
+        thread::sleep(OUT_OF_DESCRIPTORS_PAUSE);
+    }
+}
+
+/// Whether the error is EMFILE or ENFILE (WSAEMFILE on Windows)
+fn out_of_descriptors(error: &io::Error) -> bool {
+    let codes: &[i32] = if cfg!(windows) { &[10024] } else { &[23, 24] };
+    error
+        .raw_os_error()
+        .is_some_and(|code| codes.contains(&code))
+}
@@ -0,0 +1,193 @@
+use crate::error::UpsError;
+use crate::hooks::{self, Hook};
+use crate::power_action::{PowerAction, PowerPolicy, ShutdownReason};
+use crate::status::UpsStatus;
+use crate::transport::Transport;
+use crate::MegatecUps;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Step of a [`LoadShedding`] plan, run once per outage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stage {
+    /// Time on battery after which the stage runs
+    pub after: Duration,
+    /// Commands run when the stage is reached, such as suspending noncritical services
+    pub hooks: Vec<Hook>,
+    /// Commands run when mains returns after the stage ran, to resume them
+    pub restore_hooks: Vec<Hook>,
+}
+
+impl Stage {
+    /// Stage running after `after` on battery, without commands yet
+    pub fn new(after: Duration) -> Self {
+        Self {
+            after,
+            hooks: Vec::new(),
+            restore_hooks: Vec::new(),
+        }
+    }
+
+    /// Add a command run when the stage is reached, its event is ignored
+    pub fn with_hook(mut self, hook: Hook) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Add a command run when mains returns after the stage ran, its event is ignored
+    pub fn with_restore_hook(mut self, hook: Hook) -> Self {
+        self.restore_hooks.push(hook);
+        self
+    }
+}
+
+/// What a [`LoadShedding`] plan did on a status reading
+#[derive(Debug)]
+pub enum SheddingStep {
+    /// The stage at this index, counted from 1, started its hooks
+    Stage(usize),
+    /// Mains returned after this many stages ran and their restore hooks started
+    Restored(usize),
+    /// The OS shutdown started
+    Shutdown(ShutdownReason),
+    /// The OS shutdown or the UPS shutdown command failed
+    ShutdownFailed(UpsError),
+}
+
+impl fmt::Display for SheddingStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SheddingStep::Stage(stage) => write!(f, "load shedding stage {}", stage),
+            SheddingStep::Restored(stages) => {
+                write!(f, "power restored, undoing {} load shedding stages", stages)
+            }
+            SheddingStep::Shutdown(reason) => write!(f, "shutting down, {}", reason),
+            SheddingStep::ShutdownFailed(e) => write!(f, "shutdown failed: {}", e),
+        }
+    }
+}
+
+/// Staged response to a power failure, ending with the OS shutdown
+///
+/// While the UPS is on battery the stages run in the order of their `after`
+/// times, measured from the start of the outage; a low battery runs the
+/// remaining ones at once. Once mains returns the restore hooks of the stages
+/// that ran start, latest stage first, and the plan starts over. The final
+/// shutdown follows a [`PowerPolicy`], usually with `on_battery_for` beyond
+/// the last stage.
+///
+/// Hooks get the variables of [`Hooks`](crate::Hooks) with `EVENT` set to
+/// `load-shedding` or `load-restored`, plus `STAGE`, the stage counted from 1.
+/// Give the plan to [`Monitor::set_load_shedding`](crate::Monitor::set_load_shedding)
+/// or feed it every successful reading with [`check`](Self::check).
+#[derive(Debug)]
+pub struct LoadShedding {
+    ups_name: String,
+    stages: Vec<Stage>,
+    shutdown: Option<PowerAction>,
+    on_battery_since: Option<Instant>,
+    /// Number of stages run during the current outage
+    reached: usize,
+}
+
+impl LoadShedding {
+    /// Create an empty plan for the UPS called `ups_name`
+    pub fn new<S: Into<String>>(ups_name: S) -> Self {
+        Self {
+            ups_name: ups_name.into(),
+            stages: Vec::new(),
+            shutdown: None,
+            on_battery_since: None,
+            reached: 0,
+        }
+    }
+
+    /// Add a stage, keeping the stages ordered by time
+    pub fn add_stage(&mut self, stage: Stage) {
+        let index = self
+            .stages
+            .partition_point(|known| known.after <= stage.after);
+        self.stages.insert(index, stage);
+    }
+
+    /// Shut the OS down according to `policy` after the stages
+    pub fn set_shutdown(&mut self, policy: PowerPolicy) {
+        self.shutdown = Some(PowerAction::new(policy));
+    }
+
+    /// Get the stages in the order they run
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    /// Get the final shutdown policy, if any
+    pub fn shutdown_policy(&self) -> Option<&PowerPolicy> {
+        self.shutdown.as_ref().map(PowerAction::policy)
+    }
+
+    /// Get the number of stages run during the current outage
+    pub fn reached(&self) -> usize {
+        self.reached
+    }
+
+    /// Check if the plan has neither stages nor a shutdown
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty() && self.shutdown.is_none()
+    }
+
+    /// Run the stages and the shutdown that are due after a status reading
+    pub fn check<T: Transport>(
+        &mut self,
+        ups: &MegatecUps<T>,
+        status: &UpsStatus,
+    ) -> Vec<SheddingStep> {
+        let mut steps = Vec::new();
+
+        if status.flags.on_battery() {
+            let elapsed = self
+                .on_battery_since
+                .get_or_insert_with(Instant::now)
+                .elapsed();
+            while let Some(stage) = self.stages.get(self.reached) {
+                if elapsed < stage.after && !status.flags.battery_low {
+                    break;
+                }
+                self.run(&stage.hooks, "load-shedding", self.reached + 1, status);
+                self.reached += 1;
+                steps.push(SheddingStep::Stage(self.reached));
+            }
+        } else {
+            self.on_battery_since = None;
+            if self.reached > 0 {
+                for (index, stage) in self.stages[..self.reached].iter().enumerate().rev() {
+                    self.run(&stage.restore_hooks, "load-restored", index + 1, status);
+                }
+                steps.push(SheddingStep::Restored(self.reached));
+                self.reached = 0;
+            }
+        }
+
+        if let Some(shutdown) = &mut self.shutdown {
+            if let Some(reason) = shutdown.update(status) {
+                steps.push(SheddingStep::Shutdown(reason));
+                if let Err(e) = shutdown.execute(ups) {
+                    steps.push(SheddingStep::ShutdownFailed(e));
+                }
+            }
+        }
+        steps
+    }
+
+    /// Start the hooks of a stage
+    fn run(&self, stage_hooks: &[Hook], event: &str, stage: usize, status: &UpsStatus) {
+        let mut environment = vec![
+            ("UPS_NAME", self.ups_name.clone()),
+            ("EVENT", event.to_string()),
+            ("STAGE", stage.to_string()),
+        ];
+        environment.extend(hooks::status_environment(status));
+        for hook in stage_hooks {
+            hooks::spawn(hook, &environment);
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use crate::error::Result;
+use crate::status::UpsStatus;
+use crate::transport::Transport;
+use crate::usb::UsbTransport;
+use crate::MegatecUps;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Latest reading of one managed UPS
+#[derive(Debug, Clone, Default)]
+pub struct DeviceStatus {
+    /// Name the UPS was added under
+    pub name: String,
+    /// Time of the last poll, `None` before the first one
+    pub time: Option<SystemTime>,
+    /// Last successful status reading
+    pub status: Option<UpsStatus>,
+    /// Error of the last poll, cleared by the next successful one
+    pub error: Option<String>,
+}
+
+impl DeviceStatus {
+    /// Check if the last poll succeeded
+    pub fn is_reachable(&self) -> bool {
+        self.status.is_some() && self.error.is_none()
+    }
+}
+
+/// Summary of the status of every managed UPS
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AggregateStatus {
+    /// Number of managed devices
+    pub devices: usize,
+    /// Devices whose last poll succeeded
+    pub reachable: usize,
+    /// Reachable devices running on battery
+    pub on_battery: usize,
+    /// Reachable devices reporting a low battery
+    pub battery_low: usize,
+    /// Reachable devices reporting a failure
+    pub failed: usize,
+    /// Average load of the reachable devices in percent
+    pub average_load: Option<f64>,
+    /// Lowest battery voltage of the reachable devices
+    pub min_battery_voltage: Option<f64>,
+}
+
+impl AggregateStatus {
+    /// Summarize the readings of several devices
+    pub fn from_devices(devices: &[DeviceStatus]) -> Self {
+        let reachable: Vec<&UpsStatus> = devices
+            .iter()
+            .filter(|device| device.is_reachable())
+            .filter_map(|device| device.status.as_ref())
+            .collect();
+        let count = |check: fn(&UpsStatus) -> bool| reachable.iter().filter(|s| check(s)).count();
+
+        let loads: Vec<f64> = reachable
+            .iter()
+            .map(|status| status.load_raw)
+            .filter(|load| load.is_finite())
+            .collect();
+        let min_battery_voltage = reachable
+            .iter()
+            .map(|status| status.battery_voltage)
+            .filter(|voltage| voltage.is_finite())
+            .reduce(f64::min);
+
+        AggregateStatus {
+            devices: devices.len(),
+            reachable: reachable.len(),
+            on_battery: count(|status| status.flags.on_battery()),
+            battery_low: count(|status| status.flags.battery_low),
+            failed: count(|status| status.flags.ups_failed),
+            average_load: (!loads.is_empty())
+                .then(|| loads.iter().sum::<f64>() / loads.len() as f64),
+            min_battery_voltage,
+        }
+    }
+
+    /// Check if every device is reachable and on utility power without failures
+    pub fn all_nominal(&self) -> bool {
+        self.reachable == self.devices && self.on_battery == 0 && self.failed == 0
+    }
+}
+
+struct Device<T: Transport> {
+    name: String,
+    ups: MegatecUps<T>,
+    status: Mutex<DeviceStatus>,
+}
+
+/// Owns the connections to several UPS devices and polls them concurrently
+///
+/// Every [`poll`](Self::poll) reads all devices at once, each on its own
+/// thread, so the acknowledgment delay is paid once rather than per device.
+/// Use `Box<dyn Transport + Send>` as `T` to mix USB and serial devices.
+pub struct UpsManager<T: Transport = UsbTransport> {
+    devices: Vec<Device<T>>,
+}
+
+impl UpsManager<UsbTransport> {
+    /// Open a USB device by vendor_id and product_id and add it under `name`
+    pub fn add_usb<S: Into<String>>(
+        &mut self,
+        name: S,
+        vendor_id: u16,
+        product_id: u16,
+    ) -> Result<()> {
+        let ups = MegatecUps::new(vendor_id, product_id)?;
+        self.add(name, ups);
+        Ok(())
+    }
+}
+
+impl<T: Transport> Default for UpsManager<T> {
+    fn default() -> Self {
+        Self {
+            devices: Vec::new(),
+        }
+    }
+}
+
+impl<T: Transport> UpsManager<T> {
+    /// Create a manager without devices
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a connection under `name`
+    pub fn add<S: Into<String>>(&mut self, name: S, ups: MegatecUps<T>) {
+        let name = name.into();
+        let status = DeviceStatus {
+            name: name.clone(),
+            ..DeviceStatus::default()
+        };
+        self.devices.push(Device {
+            name,
+            ups,
+            status: Mutex::new(status),
+        });
+    }
+
+    /// Get the number of devices
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    /// Check if no device was added
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Get the names of the devices, in the order they were added
+    pub fn names(&self) -> Vec<&str> {
+        self.devices
+            .iter()
+            .map(|device| device.name.as_str())
+            .collect()
+    }
+
+    /// Get the connection added under `name`
+    pub fn ups(&self, name: &str) -> Option<&MegatecUps<T>> {
+        self.find(name).map(|device| &device.ups)
+    }
+
+    /// Get the latest reading of the device added under `name`
+    pub fn status(&self, name: &str) -> Option<DeviceStatus> {
+        self.find(name).map(Device::status)
+    }
+
+    /// Get the latest reading of every device
+    pub fn statuses(&self) -> Vec<DeviceStatus> {
+        self.devices.iter().map(Device::status).collect()
+    }
+
+    /// Summarize the latest readings
+    pub fn aggregate(&self) -> AggregateStatus {
+        AggregateStatus::from_devices(&self.statuses())
+    }
+
+    fn find(&self, name: &str) -> Option<&Device<T>> {
+        self.devices.iter().find(|device| device.name == name)
+    }
+}
+
+impl<T: Transport + Send> UpsManager<T> {
+    /// Read the status of every device concurrently and get the new readings
+    pub fn poll(&self) -> Vec<DeviceStatus> {
+        thread::scope(|scope| {
+            let polls: Vec<_> = self
+                .devices
+                .iter()
+                .map(|device| scope.spawn(move || device.poll()))
+                .collect();
+            polls
+                .into_iter()
+                .zip(&self.devices)
+                .map(|(poll, device)| poll.join().unwrap_or_else(|_| device.status()))
+                .collect()
+        })
+    }
+
+    /// Poll every `interval` until `running` is cleared
+    pub fn run(&self, interval: Duration, running: &AtomicBool) {
+        while running.load(Ordering::Relaxed) {
+            let started = Instant::now();
+            self.poll();
+
+            while running.load(Ordering::Relaxed) {
+                let remaining = interval.saturating_sub(started.elapsed());
+                if remaining.is_zero() {
+                    break;
+                }
+                thread::sleep(remaining.min(STOP_CHECK_INTERVAL));
+            }
+        }
+    }
+}
+
+impl<T: Transport> Device<T> {
+    fn status(&self) -> DeviceStatus {
+        self.status
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn poll(&self) -> DeviceStatus {
+        let result = self.ups.get_status();
+        let mut status = self
+            .status
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        status.time = Some(SystemTime::now());
+        match result {
+            Ok(reading) => {
+                status.status = Some(reading);
+                status.error = None;
+            }
+            Err(e) => status.error = Some(e.to_string()),
+        }
+        status.clone()
+    }
+}
@@ -0,0 +1,205 @@
+use crate::error::{Result, UpsError};
+use crate::protocol::decode_response;
+use crate::transport::{Command, Response, Transport};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Status response of a healthy UPS running on utility power
+pub const ONLINE_STATUS: &str = "230.0 230.0 230.0 020 50.0 13.6 25.0 00001001";
+/// Rating response of a typical 230 V / 12 V unit
+pub const DEFAULT_RATING: &str = "#230.0 004 12.00 50.0";
+/// Info response of a generic unit
+pub const DEFAULT_INFO: &str = "#MEGATEC         MOCK UPS   V1.0      ";
+
+/// Fault injected into the next command sent through a MockTransport
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// The command times out
+    Timeout,
+    /// The device disconnects
+    Disconnected,
+    /// The device answers with the given bytes instead of the canned response
+    Garbage(Vec<u8>),
+}
+
+/// Transport returning canned responses, for testing without hardware
+///
+/// Clones share their state, so a test can keep a handle to inject faults and
+/// inspect sent commands after handing the transport to `MegatecUps`.
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<MockState>>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    responses: HashMap<Command, String>,
+    queued: HashMap<Command, VecDeque<String>>,
+    faults: VecDeque<Fault>,
+    sent: Vec<Command>,
+}
+
+impl MockTransport {
+    /// Create a mock without any canned responses
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a mock behaving like a healthy UPS running on utility power
+    pub fn online() -> Self {
+        Self::new()
+            .with_response(Command::Name, "MOCK UPS")
+            .with_response(Command::Status, ONLINE_STATUS)
+            .with_response(Command::Rating, DEFAULT_RATING)
+            .with_response(Command::Info, DEFAULT_INFO)
+    }
+
+    /// Answer every following `command` with `text`
+    pub fn with_response(self, command: Command, text: &str) -> Self {
+        self.set_response(command, text);
+        self
+    }
+
+    /// Answer every following `command` with `text`
+    pub fn set_response(&self, command: Command, text: &str) {
+        self.lock().responses.insert(command, text.to_string());
+    }
+
+    /// Answer the next `command` with `text`, before falling back to the permanent response
+    pub fn push_response(&self, command: Command, text: &str) {
+        self.lock()
+            .queued
+            .entry(command)
+            .or_default()
+            .push_back(text.to_string());
+    }
+
+    /// Make the next command fail or return garbage
+    pub fn inject_fault(&self, fault: Fault) {
+        self.lock().faults.push_back(fault);
+    }
+
+    /// Make the next command time out
+    pub fn inject_timeout(&self) {
+        self.inject_fault(Fault::Timeout);
+    }
+
+    /// Make the next command return the given bytes
+    pub fn inject_garbage(&self, raw: &[u8]) {
+        self.inject_fault(Fault::Garbage(raw.to_vec()));
+    }
+
+    /// Get all commands sent so far
+    pub fn sent_commands(&self) -> Vec<Command> {
+        self.lock().sent.clone()
+    }
+
+    /// Forget the commands sent so far
+    pub fn clear_sent_commands(&self) {
+        self.lock().sent.clear();
+    }
+
+    /// Lock the shared state, recovering from a poisoned mutex
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Transport for MockTransport {
+    fn send_command(&self, command: Command) -> Result<Response> {
+        let mut state = self.lock();
+        state.sent.push(command);
+
+        match state.faults.pop_front() {
+            Some(Fault::Timeout) => return Err(UpsError::Timeout),
+            Some(Fault::Disconnected) => return Err(UpsError::Disconnected),
+            Some(Fault::Garbage(raw)) => {
+                let text = decode_response(&raw);
+                return Ok(Response { raw, text });
+            }
+            None => {}
+        }
+
+        let queued = state
+            .queued
+            .get_mut(&command)
+            .and_then(|queue| queue.pop_front());
+        let text = match queued.or_else(|| state.responses.get(&command).cloned()) {
+            Some(text) => text,
+            None if command.is_query() => return Err(UpsError::InvalidResponse),
+            None => String::new(),
+        };
+
+        Ok(Response {
+            raw: text.clone().into_bytes(),
+            text,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MegatecUps, RetryPolicy};
+
+    #[test]
+    fn canned_and_queued_responses_are_answered() {
+        let transport = MockTransport::online();
+        transport.push_response(Command::Name, "FIRST");
+        let ups = MegatecUps::with_transport(transport.clone());
+        assert_eq!(ups.get_name().unwrap(), "FIRST");
+        assert_eq!(ups.get_name().unwrap(), "MOCK UPS");
+        assert_eq!(ups.get_rating().unwrap().rated_voltage, 230.0);
+        assert_eq!(ups.get_info().unwrap().model, "MOCK UPS");
+        assert_eq!(
+            transport.sent_commands(),
+            [Command::Name, Command::Name, Command::Rating, Command::Info]
+        );
+
+        transport.clear_sent_commands();
+        assert!(transport.sent_commands().is_empty());
+        // Queries without a canned response are invalid, commands are acknowledged
+        let bare = MegatecUps::with_transport(MockTransport::new());
+        assert!(bare.get_rating().is_err());
+        assert!(bare.transport().send_command(Command::ToggleBeep).is_ok());
+    }
+
+    #[test]
+    fn injected_faults_fail_one_command_each() {
+        let transport = MockTransport::online();
+        let mut ups = MegatecUps::with_transport(transport.clone());
+        ups.set_retry_policy(RetryPolicy::none());
+
+        transport.inject_timeout();
+        transport.inject_fault(Fault::Disconnected);
+        transport.inject_garbage(b"\xff\xfe");
+        assert!(matches!(ups.get_rating(), Err(UpsError::Timeout)));
+        assert!(matches!(ups.get_rating(), Err(UpsError::Disconnected)));
+        assert!(matches!(
+            ups.get_rating(),
+            Err(UpsError::ParseError { raw, .. }) if raw == b"\xff\xfe"
+        ));
+        assert!(ups.get_rating().is_ok());
+    }
+
+    #[test]
+    fn retries_recover_from_injected_faults() {
+        let transport = MockTransport::online();
+        transport.inject_garbage(b"#garbage");
+        transport.inject_timeout();
+        let ups = MegatecUps::with_transport(transport.clone());
+        assert_eq!(ups.get_rating().unwrap().battery_voltage, 12.0);
+        assert_eq!(transport.sent_commands(), [Command::Rating; 3]);
+    }
+
+    #[test]
+    fn failed_commands_are_not_repeated() {
+        let transport = MockTransport::online();
+        let ups = MegatecUps::with_transport(transport.clone());
+        transport.inject_timeout();
+        assert!(matches!(ups.switch_beep(), Err(UpsError::Timeout)));
+        assert_eq!(transport.sent_commands(), [Command::ToggleBeep]);
+    }
+}
@@ -0,0 +1,679 @@
+use crate::error::{Result, UpsError};
+use crate::info::UpsInfo;
+use crate::listener;
+use crate::rating::RatingInfo;
+use crate::status::UpsStatus;
+use crate::transport::{ShutdownDelay, Transport, MAX_RESTORE_MINUTES};
+use crate::MegatecUps;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default listen address of the Modbus TCP server, reachable from this host only
+///
+/// Modbus has no authentication. Bind another address only on a network
+/// where every host may read the UPS, and writes stay off unless
+/// [`ModbusServer::set_allow_writes`] turns them on.
+pub const DEFAULT_MODBUS_ADDRESS: &str = "127.0.0.1:502";
+
+/// How long a UPS reading answers requests before it is read again
+///
+/// Several clients polling a few register blocks each would otherwise queue
+/// one status query per request on the serial line.
+pub const DEFAULT_MODBUS_CACHE_TTL: Duration = Duration::from_secs(1);
+
+/// Connections without a request for this long are closed
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Largest PDU of Modbus TCP, function code included
+const MAX_PDU_LENGTH: usize = 253;
+
+// Function codes
+const READ_COILS: u8 = 0x01;
+const READ_DISCRETE_INPUTS: u8 = 0x02;
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+const WRITE_SINGLE_COIL: u8 = 0x05;
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+const WRITE_MULTIPLE_COILS: u8 = 0x0f;
+const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+// Exception codes
+const ILLEGAL_FUNCTION: u8 = 0x01;
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const ILLEGAL_DATA_VALUE: u8 = 0x03;
+const SERVER_DEVICE_FAILURE: u8 = 0x04;
+const GATEWAY_TARGET_FAILED: u8 = 0x0b;
+
+// Coils
+const COIL_BEEPER: u16 = 0;
+const COIL_TEST: u16 = 1;
+const COIL_TEST_UNTIL_BATTERY_LOW: u16 = 2;
+const COIL_SHUTDOWN: u16 = 3;
+const COIL_COUNT: u16 = 4;
+
+/// Discrete inputs, one per status flag
+const DISCRETE_INPUT_COUNT: u16 = 8;
+
+/// Input registers 0 to 7 come from the status, the others are read once
+const STATUS_REGISTER_COUNT: u16 = 8;
+const INPUT_REGISTER_COUNT: u16 = 38;
+
+// Holding registers
+const REGISTER_SHUTDOWN_DELAY: u16 = 0;
+const REGISTER_RESTORE_DELAY: u16 = 1;
+const REGISTER_TIMED_TEST: u16 = 2;
+const HOLDING_REGISTER_COUNT: u16 = 3;
+
+/// Modbus TCP server mapping the UPS to coils and registers
+///
+/// Every unit ID is answered. Addresses are zero based.
+///
+/// | Table             | Address | Content                                                   |
+/// |-------------------|---------|-----------------------------------------------------------|
+/// | Discrete input    | 0-7     | Utility fail, battery low, bypass, UPS failed, standby, test in progress, shutdown active, beeper on |
+/// | Coil              | 0       | Beeper on; write to enable or disable it                  |
+/// | Coil              | 1       | Test in progress; write 1 for a 10 second test, 0 to abort |
+/// | Coil              | 2       | Test in progress; write 1 to test until the battery is low, 0 to abort |
+/// | Coil              | 3       | Shutdown active; write 1 to shut down after holding registers 0 and 1, 0 to cancel |
+/// | Input register    | 0       | Input voltage, 0.1 V                                      |
+/// | Input register    | 1       | Input fault voltage, 0.1 V                                |
+/// | Input register    | 2       | Output voltage, 0.1 V                                     |
+/// | Input register    | 3       | Load, %                                                   |
+/// | Input register    | 4       | Input frequency, 0.1 Hz                                   |
+/// | Input register    | 5       | Battery voltage, 0.01 V                                   |
+/// | Input register    | 6       | Temperature, 0.1 °C, signed                               |
+/// | Input register    | 7       | Discrete inputs 0-7 as bits 0-7                           |
+/// | Input register    | 10-13   | Rated voltage 0.1 V, current 0.1 A, battery voltage 0.01 V, frequency 0.1 Hz |
+/// | Input register    | 20-27   | Company name, 16 ASCII characters, high byte first        |
+/// | Input register    | 28-32   | Model, 10 ASCII characters                                |
+/// | Input register    | 33-37   | Firmware version, 10 ASCII characters                     |
+/// | Holding register  | 0       | Shutdown delay in 0.1 minutes, 2-9 or 10-100 in steps of 10 [default: 10] |
+/// | Holding register  | 1       | Minutes until the load is restored after a shutdown, 0 to stay off [default: 0] |
+/// | Holding register  | 2       | Write 1-99 to test for that many minutes, reads 0         |
+///
+/// Unused input registers read as 0, as do the rating and identification
+/// registers when the UPS does not answer those queries. When the status
+/// cannot be read, requests touching it fail with exception 0x0B (gateway
+/// target failed to respond).
+///
+/// The server is read-only by default: writes to coils and holding registers
+/// fail with exception 0x01 (illegal function) until
+/// [`set_allow_writes`](Self::set_allow_writes) enables them. Modbus has no
+/// authentication, so any client that reaches the port can then run a test
+/// or cut the load.
+pub struct ModbusServer<T: Transport> {
+    ups: MegatecUps<T>,
+    listener: TcpListener,
+    cache_ttl: Duration,
+    cache: Mutex<Option<(Instant, UpsStatus)>>,
+    /// Rating and identification registers 10 to 37
+    identity: Vec<u16>,
+    /// Shutdown and restore delays of holding registers 0 and 1
+    delays: Mutex<(u16, u16)>,
+    allow_writes: bool,
+}
+
+impl<T: Transport> ModbusServer<T> {
+    /// Bind the server to an address such as [`DEFAULT_MODBUS_ADDRESS`]
+    pub fn bind<A: ToSocketAddrs>(ups: MegatecUps<T>, address: A) -> Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        Ok(Self {
+            ups,
+            listener,
+            cache_ttl: DEFAULT_MODBUS_CACHE_TTL,
+            cache: Mutex::new(None),
+            identity: Vec::new(),
+            delays: Mutex::new((10, 0)),
+            allow_writes: false,
+        })
+    }
+
+    /// Set how long a UPS reading is reused, see [`DEFAULT_MODBUS_CACHE_TTL`]
+    pub fn set_cache_ttl(&mut self, cache_ttl: Duration) {
+        self.cache_ttl = cache_ttl;
+    }
+
+    /// Accept writes to coils and holding registers, off by default
+    ///
+    /// Any client that can connect may then toggle the beeper, start tests
+    /// and shut the UPS down.
+    pub fn set_allow_writes(&mut self, allow_writes: bool) {
+        self.allow_writes = allow_writes;
+    }
+
+    /// Get the address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Get a reference to the served UPS
+    pub fn ups(&self) -> &MegatecUps<T> {
+        &self.ups
+    }
+
+    /// Answer requests, serving each client on its own thread
+    ///
+    /// Failed accepts are reported on stderr and do not stop the server.
+    pub fn serve(&mut self) -> Result<()>
+    where
+        T: Send,
+    {
+        self.identity = identity_registers(
+            self.ups.get_rating().ok().as_ref(),
+            self.ups.get_info().ok().as_ref(),
+        );
+
+        let server = &*self;
+        thread::scope(|scope| {
+            for stream in server.listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        listener::accept_failed("Modbus server", &e);
+                        continue;
+                    }
+                };
+                // A client that goes away is not a server failure
+                scope.spawn(move || server.handle(stream));
+            }
+            Ok(())
+        })
+    }
+
+    /// Answer the requests of one client until it disconnects or stays idle
+    fn handle(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        loop {
+            // Transaction ID, protocol ID, length and unit ID
+            let mut header = [0u8; 7];
+            stream.read_exact(&mut header)?;
+            let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+            if header[2..4] != [0, 0] || !(2..=MAX_PDU_LENGTH + 1).contains(&length) {
+                return Ok(());
+            }
+            let mut pdu = vec![0u8; length - 1];
+            stream.read_exact(&mut pdu)?;
+
+            let reply = match self.process(&pdu) {
+                Ok(reply) => reply,
+                Err(code) => vec![pdu[0] | 0x80, code],
+            };
+            let mut frame = Vec::with_capacity(7 + reply.len());
+            frame.extend_from_slice(&header[..4]);
+            frame.extend_from_slice(&(reply.len() as u16 + 1).to_be_bytes());
+            frame.push(header[6]);
+            frame.extend_from_slice(&reply);
+            stream.write_all(&frame)?;
+        }
+    }
+
+    /// Build the reply PDU to a request PDU, or the exception code
+    fn process(&self, pdu: &[u8]) -> std::result::Result<Vec<u8>, u8> {
+        let function = pdu[0];
+        let word = |offset: usize| {
+            pdu.get(offset..offset + 2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                .ok_or(ILLEGAL_DATA_VALUE)
+        };
+
+        match function {
+            WRITE_SINGLE_COIL
+            | WRITE_MULTIPLE_COILS
+            | WRITE_SINGLE_REGISTER
+            | WRITE_MULTIPLE_REGISTERS
+                if !self.allow_writes =>
+            {
+                Err(ILLEGAL_FUNCTION)
+            }
+            READ_COILS | READ_DISCRETE_INPUTS => {
+                let (start, count) = (word(1)?, word(3)?);
+                if !(1..=2000).contains(&count) {
+                    return Err(ILLEGAL_DATA_VALUE);
+                }
+                let size = if function == READ_COILS {
+                    COIL_COUNT
+                } else {
+                    DISCRETE_INPUT_COUNT
+                };
+                check_range(start, count, size)?;
+                let bits = flag_bits(&self.status()?);
+                let bits: Vec<bool> = if function == READ_COILS {
+                    // Beeper, test, test and shutdown flags
+                    vec![bits[7], bits[5], bits[5], bits[6]]
+                } else {
+                    bits.to_vec()
+                };
+                let bits = &bits[start as usize..(start + count) as usize];
+
+                let mut reply = vec![function, bits.len().div_ceil(8) as u8];
+                reply.extend(pack_bits(bits));
+                Ok(reply)
+            }
+            READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+                let (start, count) = (word(1)?, word(3)?);
+                if !(1..=125).contains(&count) {
+                    return Err(ILLEGAL_DATA_VALUE);
+                }
+                let registers = if function == READ_HOLDING_REGISTERS {
+                    check_range(start, count, HOLDING_REGISTER_COUNT)?;
+                    let (shutdown, restore) =
+                        *self.delays.lock().unwrap_or_else(|e| e.into_inner());
+                    vec![shutdown, restore, 0]
+                } else {
+                    check_range(start, count, INPUT_REGISTER_COUNT)?;
+                    let mut registers = if start < STATUS_REGISTER_COUNT {
+                        status_registers(&self.status()?)
+                    } else {
+                        vec![0; STATUS_REGISTER_COUNT as usize]
+                    };
+                    registers.resize(10, 0);
+                    registers.extend_from_slice(&self.identity);
+                    registers.resize(INPUT_REGISTER_COUNT as usize, 0);
+                    registers
+                };
+
+                let registers = &registers[start as usize..(start + count) as usize];
+                let mut reply = vec![function, (registers.len() * 2) as u8];
+                for register in registers {
+                    reply.extend_from_slice(&register.to_be_bytes());
+                }
+                Ok(reply)
+            }
+            WRITE_SINGLE_COIL => {
+                let (address, value) = (word(1)?, word(3)?);
+                let on = match value {
+                    0xff00 => true,
+                    0x0000 => false,
+                    _ => return Err(ILLEGAL_DATA_VALUE),
+                };
+                check_range(address, 1, COIL_COUNT)?;
+                self.write_coil(address, on)?;
+                Ok(pdu[..5].to_vec())
+            }
+            WRITE_MULTIPLE_COILS => {
+                let (start, count) = (word(1)?, word(3)?);
+                let data = pdu.get(6..).ok_or(ILLEGAL_DATA_VALUE)?;
+                if !(1..=1968).contains(&count)
+                    || pdu.get(5).map(|&n| n as usize) != Some(data.len())
+                    || data.len() != (count as usize).div_ceil(8)
+                {
+                    return Err(ILLEGAL_DATA_VALUE);
+                }
+                check_range(start, count, COIL_COUNT)?;
+                for index in 0..count {
+                    let on = data[index as usize / 8] & (1 << (index % 8)) != 0;
+                    self.write_coil(start + index, on)?;
+                }
+                Ok(pdu[..5].to_vec())
+            }
+            WRITE_SINGLE_REGISTER => {
+                let (address, value) = (word(1)?, word(3)?);
+                check_range(address, 1, HOLDING_REGISTER_COUNT)?;
+                self.write_register(address, value)?;
+                Ok(pdu[..5].to_vec())
+            }
+            WRITE_MULTIPLE_REGISTERS => {
+                let (start, count) = (word(1)?, word(3)?);
+                let data = pdu.get(6..).ok_or(ILLEGAL_DATA_VALUE)?;
+                if !(1..=123).contains(&count)
+                    || pdu.get(5).map(|&n| n as usize) != Some(data.len())
+                    || data.len() != count as usize * 2
+                {
+                    return Err(ILLEGAL_DATA_VALUE);
+                }
+                check_range(start, count, HOLDING_REGISTER_COUNT)?;
+                for (index, value) in data.chunks(2).enumerate() {
+                    let value = u16::from_be_bytes([value[0], value[1]]);
+                    self.write_register(start + index as u16, value)?;
+                }
+                Ok(pdu[..5].to_vec())
+            }
+            _ => Err(ILLEGAL_FUNCTION),
+        }
+    }
+
+    /// Run the command behind a coil
+    fn write_coil(&self, address: u16, on: bool) -> std::result::Result<(), u8> {
+        let result = match (address, on) {
+            (COIL_BEEPER, _) => self.ups.set_beeper(on),
+            (COIL_TEST, true) => self.ups.test().map(drop),
+            (COIL_TEST_UNTIL_BATTERY_LOW, true) => self.ups.test_until_battery_low().map(drop),
+            (COIL_TEST | COIL_TEST_UNTIL_BATTERY_LOW, false) => self.ups.abort_test(),
+            (COIL_SHUTDOWN, true) => {
+                let (shutdown, restore) = *self.delays.lock().unwrap_or_else(|e| e.into_inner());
+                let minutes = f32::from(shutdown) / 10.0;
+                if restore == 0 {
+                    self.ups.shutdown_after(minutes)
+                } else {
+                    self.ups.shutdown_and_restore(minutes, restore)
+                }
+            }
+            // Clearing the coil without a pending shutdown leaves it as asked
+            (COIL_SHUTDOWN, false) => match self.ups.cancel_shutdown() {
+                Err(UpsError::NoShutdownPending) => Ok(()),
+                result => result,
+            },
+            _ => return Err(ILLEGAL_DATA_ADDRESS),
+        };
+        self.invalidate();
+        result.map_err(|e| exception(&e))
+    }
+
+    /// Store a delay or start a timed test
+    fn write_register(&self, address: u16, value: u16) -> std::result::Result<(), u8> {
+        let mut delays = self.delays.lock().unwrap_or_else(|e| e.into_inner());
+        match address {
+            REGISTER_SHUTDOWN_DELAY => {
+                ShutdownDelay::from_minutes(f32::from(value) / 10.0)
+                    .map_err(|_| ILLEGAL_DATA_VALUE)?;
+                delays.0 = value;
+            }
+            REGISTER_RESTORE_DELAY if value <= MAX_RESTORE_MINUTES => delays.1 = value,
+            REGISTER_TIMED_TEST if (1..=99).contains(&value) => {
+                drop(delays);
+                let result = self.ups.test_with_time(value as u8);
+                self.invalidate();
+                result.map_err(|e| exception(&e))?;
+            }
+            _ => return Err(ILLEGAL_DATA_VALUE),
+        }
+        Ok(())
+    }
+
+    /// Get the cached status, reading the UPS again once it is older than the TTL
+    fn status(&self) -> std::result::Result<UpsStatus, u8> {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((read_at, status)) = &*cache {
+            if read_at.elapsed() < self.cache_ttl {
+                return Ok(status.clone());
+            }
+        }
+        let status = self.ups.get_status().map_err(|e| exception(&e))?;
+        *cache = Some((Instant::now(), status.clone()));
+        Ok(status)
+    }
+
+    /// Drop the cached status after a command changed it
+    fn invalidate(&self) {
+        *self.cache.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
+/// Exception code reported for a failed UPS command
+fn exception(error: &UpsError) -> u8 {
+    match error {
+        UpsError::Timeout | UpsError::Disconnected | UpsError::DeviceNotFound => {
+            GATEWAY_TARGET_FAILED
+        }
+        UpsError::InvalidTime | UpsError::NoShutdownPending => ILLEGAL_DATA_VALUE,
+        _ => SERVER_DEVICE_FAILURE,
+    }
+}
+
+fn check_range(start: u16, count: u16, size: u16) -> std::result::Result<(), u8> {
+    if u32::from(start) + u32::from(count) > u32::from(size) {
+        return Err(ILLEGAL_DATA_ADDRESS);
+    }
+    Ok(())
+}
+
+/// Status flags in the order of the discrete inputs
+fn flag_bits(status: &UpsStatus) -> [bool; 8] {
+    let flags = status.flags;
+    [
+        flags.utility_fail,
+        flags.battery_low,
+        flags.bypass_active,
+        flags.ups_failed,
+        flags.standby,
+        flags.test_in_progress,
+        flags.shutdown_active,
+        flags.beeper_on,
+    ]
+}
+
+/// Bits packed eight to a byte, the first one in the lowest bit
+fn pack_bits(bits: &[bool]) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0, |byte, (index, &bit)| byte | (u8::from(bit) << index))
+        })
+        .collect()
+}
+
+/// Input registers 0 to 7
+fn status_registers(status: &UpsStatus) -> Vec<u16> {
+    let flags = pack_bits(&flag_bits(status))[0];
+    vec![
+        scaled(status.input_voltage, 10.0),
+        scaled(status.input_fault_voltage, 10.0),
+        scaled(status.output_voltage, 10.0),
+        scaled(status.load_raw, 1.0),
+        scaled(status.input_frequency, 10.0),
+        scaled(status.battery_voltage, 100.0),
+        (status.temperature * 10.0).round().clamp(-32768.0, 32767.0) as i16 as u16,
+        u16::from(flags),
+    ]
+}
+
+/// Input registers 10 to 37, zero for what the UPS did not tell
+fn identity_registers(rating: Option<&RatingInfo>, info: Option<&UpsInfo>) -> Vec<u16> {
+    let mut registers = match rating {
+        Some(rating) => vec![
+            scaled(rating.rated_voltage, 10.0),
+            scaled(rating.rated_current, 10.0),
+            scaled(rating.battery_voltage, 100.0),
+            scaled(rating.rated_frequency, 10.0),
+        ],
+        None => vec![0; 4],
+    };
+    registers.resize(10, 0);
+    for (text, length) in [
+        (info.map(|info| info.company.as_str()), 16),
+        (info.map(|info| info.model.as_str()), 10),
+        (info.map(|info| info.firmware_version.as_str()), 10),
+    ] {
+        let mut bytes: Vec<u8> = text
+            .unwrap_or_default()
+            .bytes()
+            .filter(u8::is_ascii)
+            .take(length)
+            .collect();
+        let fill = if text.is_some() { b' ' } else { 0 };
+        bytes.resize(length, fill);
+        registers.extend(
+            bytes
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]])),
+        );
+    }
+    registers
+}
+
+/// Value in units of `1 / factor`, clamped to a register
+fn scaled(value: f64, factor: f64) -> u16 {
+    // NaN turns into 0
+    (value * factor).round().clamp(0.0, f64::from(u16::MAX)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockTransport, RetryPolicy};
+
+    fn server() -> ModbusServer<MockTransport> {
+        let ups = MegatecUps::with_transport(MockTransport::online());
+        let mut server = ModbusServer::bind(ups, "127.0.0.1:0").unwrap();
+        server.set_allow_writes(true);
+        server
+    }
+
+    #[test]
+    fn registers_and_bits_follow_the_status() {
+        let server = server();
+        assert_eq!(
+            server.process(&[READ_INPUT_REGISTERS, 0, 0, 0, 8]),
+            Ok(vec![
+                READ_INPUT_REGISTERS,
+                16,
+                0x08,
+                0xfc, // 230.0 V
+                0x08,
+                0xfc,
+                0x08,
+                0xfc,
+                0x00,
+                0x14, // 20 %
+                0x01,
+                0xf4, // 50.0 Hz
+                0x05,
+                0x50, // 13.60 V
+                0x00,
+                0xfa, // 25.0 °C
+                0x00,
+                0x90, // standby and beeper on
+            ])
+        );
+        assert_eq!(
+            server.process(&[READ_DISCRETE_INPUTS, 0, 0, 0, 8]),
+            Ok(vec![READ_DISCRETE_INPUTS, 1, 0x90])
+        );
+        assert_eq!(
+            server.process(&[READ_COILS, 0, 0, 0, 4]),
+            Ok(vec![READ_COILS, 1, 0x01])
+        );
+        assert_eq!(
+            server.process(&[READ_INPUT_REGISTERS, 0, 36, 0, 2]),
+            Ok(vec![READ_INPUT_REGISTERS, 4, 0, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn holding_registers_keep_valid_delays() {
+        let server = server();
+        let write = [WRITE_SINGLE_REGISTER, 0, 0, 0, 20];
+        assert_eq!(server.process(&write), Ok(write.to_vec()));
+        let write = [WRITE_MULTIPLE_REGISTERS, 0, 1, 0, 1, 2, 0, 5];
+        assert_eq!(server.process(&write), Ok(write[..5].to_vec()));
+        assert_eq!(
+            server.process(&[READ_HOLDING_REGISTERS, 0, 0, 0, 3]),
+            Ok(vec![READ_HOLDING_REGISTERS, 6, 0, 20, 0, 5, 0, 0])
+        );
+
+        // 1.1 minutes is not a delay the UPS accepts
+        assert_eq!(
+            server.process(&[WRITE_SINGLE_REGISTER, 0, 0, 0, 11]),
+            Err(ILLEGAL_DATA_VALUE)
+        );
+        assert_eq!(
+            server.process(&[WRITE_SINGLE_REGISTER, 0, 2, 0, 100]),
+            Err(ILLEGAL_DATA_VALUE)
+        );
+    }
+
+    #[test]
+    fn malformed_requests_get_exceptions() {
+        let server = server();
+        for (pdu, code) in [
+            (&[0x2b, 0x0e, 0x01, 0x00][..], ILLEGAL_FUNCTION),
+            (&[READ_HOLDING_REGISTERS], ILLEGAL_DATA_VALUE),
+            (&[READ_HOLDING_REGISTERS, 0, 0, 0], ILLEGAL_DATA_VALUE),
+            (&[READ_HOLDING_REGISTERS, 0, 0, 0, 0], ILLEGAL_DATA_VALUE),
+            (&[READ_INPUT_REGISTERS, 0, 0, 0, 126], ILLEGAL_DATA_VALUE),
+            (&[READ_INPUT_REGISTERS, 0, 37, 0, 2], ILLEGAL_DATA_ADDRESS),
+            (
+                &[READ_INPUT_REGISTERS, 0xff, 0xff, 0, 1],
+                ILLEGAL_DATA_ADDRESS,
+            ),
+            (&[READ_COILS, 0, 0, 0x07, 0xd1], ILLEGAL_DATA_VALUE),
+            (&[READ_COILS, 0, 3, 0, 2], ILLEGAL_DATA_ADDRESS),
+            (&[WRITE_SINGLE_COIL, 0, 0, 0x12, 0x34], ILLEGAL_DATA_VALUE),
+            (&[WRITE_SINGLE_COIL, 0, 4, 0xff, 0x00], ILLEGAL_DATA_ADDRESS),
+            (
+                &[WRITE_MULTIPLE_COILS, 0, 0, 0, 2, 2, 0x03],
+                ILLEGAL_DATA_VALUE,
+            ),
+            (
+                &[WRITE_MULTIPLE_COILS, 0, 0, 0, 9, 1, 0x03],
+                ILLEGAL_DATA_VALUE,
+            ),
+            (
+                &[WRITE_MULTIPLE_REGISTERS, 0, 0, 0, 1, 2, 0],
+                ILLEGAL_DATA_VALUE,
+            ),
+            (
+                &[WRITE_MULTIPLE_REGISTERS, 0, 2, 0, 2, 4, 0, 1, 0, 1],
+                ILLEGAL_DATA_ADDRESS,
+            ),
+        ] {
+            assert_eq!(server.process(pdu), Err(code), "{:02x?}", pdu);
+        }
+    }
+
+    #[test]
+    fn writes_are_refused_unless_allowed() {
+        let transport = MockTransport::online();
+        let ups = MegatecUps::with_transport(transport.clone());
+        let server = ModbusServer::bind(ups, "127.0.0.1:0").unwrap();
+        for pdu in [
+            &[WRITE_SINGLE_COIL, 0, COIL_SHUTDOWN as u8, 0xff, 0x00][..],
+            &[WRITE_MULTIPLE_COILS, 0, 0, 0, 4, 1, 0x0f],
+            &[WRITE_SINGLE_REGISTER, 0, REGISTER_TIMED_TEST as u8, 0, 5],
+            &[WRITE_MULTIPLE_REGISTERS, 0, 0, 0, 1, 2, 0, 20],
+        ] {
+            assert_eq!(server.process(pdu), Err(ILLEGAL_FUNCTION), "{:02x?}", pdu);
+        }
+        assert!(transport.sent_commands().is_empty());
+        assert!(server.process(&[READ_COILS, 0, 0, 0, 4]).is_ok());
+    }
+
+    #[test]
+    fn failed_status_reads_report_the_gateway() {
+        let transport = MockTransport::online();
+        transport.inject_timeout();
+        let mut ups = MegatecUps::with_transport(transport);
+        ups.set_retry_policy(RetryPolicy::none());
+        let server = ModbusServer::bind(ups, "127.0.0.1:0").unwrap();
+        assert_eq!(
+            server.process(&[READ_DISCRETE_INPUTS, 0, 0, 0, 8]),
+            Err(GATEWAY_TARGET_FAILED)
+        );
+    }
+
+    #[test]
+    fn frames_are_answered_until_a_bad_header() {
+        let server = server();
+        let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        let (stream, _) = server.listener.accept().unwrap();
+        thread::scope(|scope| {
+            let handler = scope.spawn(|| server.handle(stream));
+
+            // Transaction 7, unit 1, reading coil 0
+            client
+                .write_all(&[0, 7, 0, 0, 0, 6, 1, READ_COILS, 0, 0, 0, 1])
+                .unwrap();
+            let mut reply = [0u8; 10];
+            client.read_exact(&mut reply).unwrap();
+            assert_eq!(reply, [0, 7, 0, 0, 0, 4, 1, READ_COILS, 1, 1]);
+
+            // An exception keeps the transaction and the unit
+            client.write_all(&[0, 8, 0, 0, 0, 2, 9, 0x2b]).unwrap();
+            let mut reply = [0u8; 9];
+            client.read_exact(&mut reply).unwrap();
+            assert_eq!(reply, [0, 8, 0, 0, 0, 3, 9, 0xab, ILLEGAL_FUNCTION]);
+
+            // Another protocol ID ends the connection
+            client
+                .write_all(&[0, 9, 0, 1, 0, 2, 1, READ_COILS])
+                .unwrap();
+            assert!(handler.join().unwrap().is_ok());
+        });
+    }
+}
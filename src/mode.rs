@@ -0,0 +1,59 @@
+use crate::error::Result;
+use crate::protocol;
+use crate::status::StatusFlags;
+use std::fmt;
+
+/// Operating mode reported by the QMOD query of Voltronic devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UpsMode {
+    /// The load runs from utility power through the inverter
+    Line,
+    /// The load runs from battery, also during a battery test
+    Battery,
+    /// The load is fed from utility power bypassing the inverter
+    Bypass,
+    /// High efficiency (ECO or power saving) mode
+    Eco,
+    /// The UPS reports a fault
+    Fault,
+    /// Output is off, while powering on or after a shutdown
+    Standby,
+}
+
+impl UpsMode {
+    /// Parse the QMOD answer into UpsMode
+    pub(crate) fn from_str(mode: &str) -> Result<Self> {
+        Ok(protocol::parse_mode(mode)?)
+    }
+
+    /// Map the status flags of a pure Megatec device to a mode
+    pub fn from_flags(flags: &StatusFlags) -> Self {
+        if flags.ups_failed {
+            UpsMode::Fault
+        } else if flags.on_battery() || flags.test_in_progress {
+            UpsMode::Battery
+        } else if flags.bypass_active {
+            UpsMode::Bypass
+        } else {
+            UpsMode::Line
+        }
+    }
+
+    /// Get the mode name as used in logs
+    pub fn name(&self) -> &'static str {
+        match self {
+            UpsMode::Line => "line",
+            UpsMode::Battery => "battery",
+            UpsMode::Bypass => "bypass",
+            UpsMode::Eco => "eco",
+            UpsMode::Fault => "fault",
+            UpsMode::Standby => "standby",
+        }
+    }
+}
+
+impl fmt::Display for UpsMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -0,0 +1,435 @@
+use crate::alarms::{AlarmEvent, Alarms, Threshold};
+use crate::cancel::CancellationToken;
+use crate::error::{Result, UpsError};
+use crate::event::UpsEvent;
+use crate::load_shedding::{LoadShedding, SheddingStep};
+use crate::outage::OutageStats;
+use crate::power_quality::{PowerQuality, PowerQualityStats, VoltageBand};
+use crate::rating::RatingInfo;
+use crate::schedule::TestScheduler;
+use crate::smoothing::{Smoother, Smoothing};
+use crate::state::{StateTransition, UpsState, UpsStateMachine};
+use crate::status::{StatusFlags, UpsStatus};
+use crate::transport::Transport;
+use crate::MegatecUps;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Number of status readings a monitor keeps for [`Monitor::recent`]
+pub const DEFAULT_RECENT_SAMPLES: usize = 120;
+
+type Callback = Box<dyn FnMut(&UpsEvent) + Send>;
+type TransitionCallback = Box<dyn FnMut(&StateTransition) + Send>;
+type SheddingCallback = Box<dyn FnMut(&SheddingStep) + Send>;
+
+/// Polls the UPS on an interval and emits events when its state changes
+pub struct Monitor<T: Transport> {
+    ups: MegatecUps<T>,
+    interval: Duration,
+    callbacks: Vec<Callback>,
+    listeners: Vec<Sender<UpsEvent>>,
+    transition_callbacks: Vec<TransitionCallback>,
+    shedding_callbacks: Vec<SheddingCallback>,
+    state: UpsStateMachine,
+    last_flags: Option<StatusFlags>,
+    communication_lost: bool,
+    scheduler: TestScheduler,
+    temperature_alarm: Alarms,
+    overload_alarm: Alarms,
+    rating: Option<RatingInfo>,
+    power_quality: Option<PowerQuality>,
+    outages: OutageStats,
+    recent: VecDeque<(SystemTime, UpsStatus)>,
+    recent_capacity: usize,
+    smoother: Option<Smoother>,
+    load_shedding: Option<LoadShedding>,
+}
+
+impl<T: Transport> Monitor<T> {
+    /// Create a monitor polling the UPS every `interval`
+    pub fn new(ups: MegatecUps<T>, interval: Duration) -> Self {
+        Self {
+            ups,
+            interval,
+            callbacks: Vec::new(),
+            listeners: Vec::new(),
+            transition_callbacks: Vec::new(),
+            shedding_callbacks: Vec::new(),
+            state: UpsStateMachine::new(),
+            last_flags: None,
+            communication_lost: false,
+            scheduler: TestScheduler::new(),
+            temperature_alarm: Alarms::new(),
+            overload_alarm: Alarms::new(),
+            rating: None,
+            power_quality: None,
+            outages: OutageStats::default(),
+            recent: VecDeque::with_capacity(DEFAULT_RECENT_SAMPLES),
+            recent_capacity: DEFAULT_RECENT_SAMPLES,
+            smoother: None,
+            load_shedding: None,
+        }
+    }
+
+    /// Get a reference to the monitored UPS
+    pub fn ups(&self) -> &MegatecUps<T> {
+        &self.ups
+    }
+
+    /// Stop monitoring and get the UPS connection back
+    pub fn into_ups(self) -> MegatecUps<T> {
+        self.ups
+    }
+
+    /// Get the polling interval
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Register a callback invoked for every event
+    pub fn on_event<F>(&mut self, callback: F)
+    where
+        F: FnMut(&UpsEvent) + Send + 'static,
+    {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Subscribe to events through a channel
+    pub fn subscribe(&mut self) -> Receiver<UpsEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.listeners.push(sender);
+        receiver
+    }
+
+    /// Register a callback invoked whenever the [`UpsState`] changes
+    pub fn on_transition<F>(&mut self, callback: F)
+    where
+        F: FnMut(&StateTransition) + Send + 'static,
+    {
+        self.transition_callbacks.push(Box::new(callback));
+    }
+
+    /// Register a callback invoked for every step of the load shedding plan
+    pub fn on_load_shedding<F>(&mut self, callback: F)
+    where
+        F: FnMut(&SheddingStep) + Send + 'static,
+    {
+        self.shedding_callbacks.push(Box::new(callback));
+    }
+
+    /// Get the state of the UPS at the last poll, `None` before the first one
+    pub fn current_state(&self) -> Option<UpsState> {
+        self.state.current_state()
+    }
+
+    /// Run the battery tests of `scheduler` when they are due
+    ///
+    /// A due test is skipped with [`UpsEvent::TestSkipped`] while the UPS is on
+    /// battery or a test or shutdown is already active.
+    pub fn set_test_scheduler(&mut self, scheduler: TestScheduler) {
+        self.scheduler = scheduler;
+    }
+
+    /// Emit [`UpsEvent::OverTemperature`] when the temperature exceeds `threshold`
+    ///
+    /// The event is emitted again only after the alarm cleared, see
+    /// [`Threshold::over_temperature`].
+    pub fn set_temperature_alarm(&mut self, threshold: Threshold) {
+        self.temperature_alarm = Alarms::new();
+        self.temperature_alarm.add(threshold);
+    }
+
+    /// Emit [`UpsEvent::Overload`] when the load percentage exceeds `threshold`
+    ///
+    /// On units reporting the load in amps the percentage is computed from the
+    /// rating, read once. See [`Threshold::overload`].
+    pub fn set_overload_alarm(&mut self, threshold: Threshold) {
+        self.overload_alarm = Alarms::new();
+        self.overload_alarm.add(threshold);
+    }
+
+    /// Count the input voltage sags and swells outside `band`
+    pub fn track_power_quality(&mut self, band: VoltageBand) {
+        self.power_quality = Some(PowerQuality::new(band));
+    }
+
+    /// Get the power quality counters, `None` unless tracking is enabled
+    pub fn power_quality(&self) -> Option<&PowerQualityStats> {
+        self.power_quality.as_ref().map(PowerQuality::stats)
+    }
+
+    /// Get the last `n` status readings with their times, oldest first
+    pub fn recent(&self, n: usize) -> Vec<(SystemTime, UpsStatus)> {
+        let skip = self.recent.len().saturating_sub(n);
+        self.recent.iter().skip(skip).cloned().collect()
+    }
+
+    /// Keep the last `capacity` status readings, [`DEFAULT_RECENT_SAMPLES`] by default
+    pub fn set_recent_capacity(&mut self, capacity: usize) {
+        self.recent_capacity = capacity;
+        let excess = self.recent.len().saturating_sub(capacity);
+        self.recent.drain(..excess);
+    }
+
+    /// Smooth the measured values of every poll with `smoothing`
+    ///
+    /// Events, alarms and [`recent`](Self::recent) still use the raw readings.
+    pub fn set_smoothing(&mut self, smoothing: Smoothing) {
+        self.smoother = Some(Smoother::new(smoothing));
+    }
+
+    /// Get the smoothed last reading, `None` without smoothing or before the first poll
+    pub fn smoothed_status(&self) -> Option<&UpsStatus> {
+        self.smoother.as_ref().and_then(Smoother::current)
+    }
+
+    /// Run the stages and the final shutdown of `plan` while the UPS is on battery
+    ///
+    /// The timers of the stages advance with every successful poll; a lost
+    /// connection keeps them where they were.
+    pub fn set_load_shedding(&mut self, plan: LoadShedding) {
+        self.load_shedding = Some(plan);
+    }
+
+    /// Get the load shedding plan, `None` unless one is set
+    pub fn load_shedding(&self) -> Option<&LoadShedding> {
+        self.load_shedding.as_ref()
+    }
+
+    /// Get the outage counters
+    pub fn stats(&self) -> &OutageStats {
+        &self.outages
+    }
+
+    /// Continue counting from earlier outage counters, such as
+    /// [`OutageStats::from_events`] of a stored history
+    pub fn set_stats(&mut self, stats: OutageStats) {
+        self.outages = stats;
+    }
+
+    /// Read the status once and emit the events derived from it
+    pub fn poll(&mut self) -> Result<UpsStatus> {
+        match self.ups.get_status() {
+            Ok(status) => {
+                for event in self.events_for(&status) {
+                    self.emit(event);
+                }
+                for alarm in self.temperature_alarm.update(&status) {
+                    if let AlarmEvent::Raised { .. } = alarm {
+                        self.emit(UpsEvent::OverTemperature(status.clone()));
+                    }
+                }
+                self.outages.update(&status);
+                self.remember(&status);
+                if let Some(smoother) = &mut self.smoother {
+                    smoother.update(&status);
+                }
+                self.check_overload(&status);
+                if let Some(power_quality) = &mut self.power_quality {
+                    power_quality.update(&status);
+                }
+                if let Some(transition) = self.state.update(&status) {
+                    self.emit_transition(transition);
+                }
+                self.run_scheduled_test(&status);
+                self.shed_load(&status);
+                Ok(status)
+            }
+            // Stopping the monitor says nothing about the UPS
+            Err(UpsError::Cancelled) => Err(UpsError::Cancelled),
+            Err(e) => {
+                if let Some(transition) = self.state.communication_lost() {
+                    self.emit_transition(transition);
+                }
+                if !self.communication_lost {
+                    self.communication_lost = true;
+                    self.emit(UpsEvent::CommunicationLost);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Poll the UPS until `running` is cleared
+    pub fn run(&mut self, running: &AtomicBool) {
+        while running.load(Ordering::Relaxed) {
+            let started = Instant::now();
+            let _ = self.poll();
+
+            while running.load(Ordering::Relaxed) {
+                let remaining = self.interval.saturating_sub(started.elapsed());
+                if remaining.is_zero() {
+                    break;
+                }
+                thread::sleep(remaining.min(STOP_CHECK_INTERVAL));
+            }
+        }
+    }
+
+    /// Poll the UPS until `token` is cancelled
+    ///
+    /// Cancelling also interrupts a status read in progress, so the monitor
+    /// stops without waiting out the acknowledgment delay. The token of the
+    /// UPS connection is restored afterwards.
+    pub fn run_until(&mut self, token: &CancellationToken) {
+        let previous = self.ups.cancellation_token();
+        self.ups.set_cancellation_token(token.clone());
+
+        while !token.is_cancelled() {
+            let started = Instant::now();
+            let _ = self.poll();
+            if token
+                .sleep(self.interval.saturating_sub(started.elapsed()))
+                .is_err()
+            {
+                break;
+            }
+        }
+
+        self.ups.set_cancellation_token(previous);
+    }
+
+    /// Derive the events caused by a new status snapshot
+    fn events_for(&mut self, status: &UpsStatus) -> Vec<UpsEvent> {
+        let previous = self.last_flags.unwrap_or_default();
+        let mut events = Vec::new();
+
+        if self.communication_lost {
+            self.communication_lost = false;
+            events.push(UpsEvent::CommunicationRestored(status.clone()));
+        }
+        events.extend(UpsEvent::flag_changes(&previous, status));
+
+        self.last_flags = Some(status.flags);
+        events
+    }
+
+    /// Store a reading in the ring buffer, dropping the oldest when full
+    fn remember(&mut self, status: &UpsStatus) {
+        if self.recent_capacity == 0 {
+            return;
+        }
+        if self.recent.len() == self.recent_capacity {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((SystemTime::now(), status.clone()));
+    }
+
+    /// Emit an overload event when the load alarm is raised
+    fn check_overload(&mut self, status: &UpsStatus) {
+        if self.overload_alarm.is_empty() {
+            return;
+        }
+
+        let quirks = self.ups.quirks();
+        let mut load = status.clone();
+        if quirks.load_in_amps {
+            if self.rating.is_none() {
+                self.rating = self.ups.get_rating().ok();
+            }
+            // Without a rating the NaN load leaves the alarm unchanged
+            load = match &self.rating {
+                Some(rating) => status.in_percent(rating, &quirks),
+                None => UpsStatus {
+                    load_raw: f64::NAN,
+                    ..load
+                },
+            };
+        }
+
+        for alarm in self.overload_alarm.update(&load) {
+            if let AlarmEvent::Raised { .. } = alarm {
+                self.emit(UpsEvent::Overload(status.clone()));
+            }
+        }
+    }
+
+    /// Start the scheduled test if one is due
+    fn run_scheduled_test(&mut self, status: &UpsStatus) {
+        let kind = match self.scheduler.due(SystemTime::now()) {
+            Some(kind) => kind,
+            None => return,
+        };
+        let flags = status.flags;
+        let busy = flags.on_battery() || flags.test_in_progress || flags.shutdown_active;
+        if busy || self.ups.send(kind.command()).is_err() {
+            self.emit(UpsEvent::TestSkipped(status.clone()));
+        }
+    }
+
+    /// Advance the load shedding plan and report its steps
+    fn shed_load(&mut self, status: &UpsStatus) {
+        let steps = match &mut self.load_shedding {
+            Some(plan) => plan.check(&self.ups, status),
+            None => return,
+        };
+        for step in &steps {
+            for callback in &mut self.shedding_callbacks {
+                callback(step);
+            }
+        }
+    }
+
+    /// Deliver an event to every callback and subscriber
+    fn emit(&mut self, event: UpsEvent) {
+        for callback in &mut self.callbacks {
+            callback(&event);
+        }
+        self.listeners
+            .retain(|listener| listener.send(event.clone()).is_ok());
+    }
+
+    /// Deliver a state transition to every transition callback
+    fn emit_transition(&mut self, transition: StateTransition) {
+        for callback in &mut self.transition_callbacks {
+            callback(&transition);
+        }
+    }
+}
+
+impl<T: Transport + Send + 'static> Monitor<T> {
+    /// Run the monitor on a background thread
+    pub fn spawn(mut self) -> MonitorHandle<T> {
+        let token = CancellationToken::new();
+        let thread = {
+            let token = token.clone();
+            thread::spawn(move || {
+                self.run_until(&token);
+                self
+            })
+        };
+
+        MonitorHandle {
+            token,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Handle to a monitor running on a background thread
+pub struct MonitorHandle<T: Transport> {
+    token: CancellationToken,
+    thread: Option<JoinHandle<Monitor<T>>>,
+}
+
+impl<T: Transport> MonitorHandle<T> {
+    /// Stop the monitor and get it back
+    pub fn stop(mut self) -> Option<Monitor<T>> {
+        self.token.cancel();
+        self.thread.take().and_then(|thread| thread.join().ok())
+    }
+}
+
+impl<T: Transport> Drop for MonitorHandle<T> {
+    fn drop(&mut self) {
+        self.token.cancel();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
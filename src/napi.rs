@@ -0,0 +1,712 @@
+//! Node.js addon over the N-API
+//!
+//! Built with `cargo rustc --release --lib --features napi --crate-type cdylib`,
+//! the shared library renamed to `megatec_ups.node` is a Node-API module (version 4 or later) exporting
+//! `connect(vendorId, productId)`, `auto()`, `status(ups)`, `name(ups)`,
+//! `test(ups)`, `abortTest(ups)`, `switchBeep(ups)`, `shutdown(ups, minutes?)`,
+//! `cancelShutdown(ups)`, `monitor(ups, intervalMs, callback)` and
+//! `stopMonitor(monitor)`. `bindings/node/index.js` wraps them into an `Ups`
+//! class and an `EventEmitter` monitor. The N-API functions are resolved from
+//! the Node.js process when the addon is loaded.
+//!
+//! Every function but `monitor` and `stopMonitor` returns a Promise. The
+//! call runs on the libuv thread pool, so USB transfers, retries and the
+//! acknowledgment delay of `status` never block the event loop.
+
+use crate::error::UpsError;
+use crate::event::UpsEvent;
+use crate::monitor::{Monitor, MonitorHandle};
+use crate::status::UpsStatus;
+use crate::MegatecUps;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+type Env = *mut c_void;
+type Value = *mut c_void;
+type CallbackInfo = *mut c_void;
+type ThreadsafeFunction = *mut c_void;
+type Deferred = *mut c_void;
+type AsyncWork = *mut c_void;
+type Status = c_int;
+type Callback = unsafe extern "C" fn(Env, CallbackInfo) -> Value;
+type Finalize = unsafe extern "C" fn(Env, *mut c_void, *mut c_void);
+type CallJs = unsafe extern "C" fn(Env, Value, *mut c_void, *mut c_void);
+type Execute = unsafe extern "C" fn(Env, *mut c_void);
+type Complete = unsafe extern "C" fn(Env, Status, *mut c_void);
+
+const OK: Status = 0;
+const TYPE_UNDEFINED: c_int = 0;
+const TYPE_NULL: c_int = 1;
+const TSFN_RELEASE: c_int = 0;
+const TSFN_BLOCKING: c_int = 1;
+
+extern "C" {
+    fn napi_get_cb_info(
+        env: Env,
+        info: CallbackInfo,
+        argc: *mut usize,
+        argv: *mut Value,
+        this: *mut Value,
+        data: *mut *mut c_void,
+    ) -> Status;
+    fn napi_create_function(
+        env: Env,
+        name: *const c_char,
+        length: usize,
+        callback: Callback,
+        data: *mut c_void,
+        result: *mut Value,
+    ) -> Status;
+    fn napi_set_named_property(
+        env: Env,
+        object: Value,
+        name: *const c_char,
+        value: Value,
+    ) -> Status;
+    fn napi_create_object(env: Env, result: *mut Value) -> Status;
+    fn napi_create_double(env: Env, value: f64, result: *mut Value) -> Status;
+    fn napi_get_boolean(env: Env, value: bool, result: *mut Value) -> Status;
+    fn napi_create_string_utf8(
+        env: Env,
+        text: *const c_char,
+        length: usize,
+        result: *mut Value,
+    ) -> Status;
+    fn napi_get_undefined(env: Env, result: *mut Value) -> Status;
+    fn napi_get_null(env: Env, result: *mut Value) -> Status;
+    fn napi_typeof(env: Env, value: Value, result: *mut c_int) -> Status;
+    fn napi_get_value_uint32(env: Env, value: Value, result: *mut u32) -> Status;
+    fn napi_get_value_double(env: Env, value: Value, result: *mut f64) -> Status;
+    fn napi_throw_error(env: Env, code: *const c_char, message: *const c_char) -> Status;
+    fn napi_create_error(env: Env, code: Value, message: Value, result: *mut Value) -> Status;
+    fn napi_create_promise(env: Env, deferred: *mut Deferred, promise: *mut Value) -> Status;
+    fn napi_resolve_deferred(env: Env, deferred: Deferred, resolution: Value) -> Status;
+    fn napi_reject_deferred(env: Env, deferred: Deferred, rejection: Value) -> Status;
+    fn napi_create_async_work(
+        env: Env,
+        async_resource: Value,
+        async_resource_name: Value,
+        execute: Execute,
+        complete: Complete,
+        data: *mut c_void,
+        result: *mut AsyncWork,
+    ) -> Status;
+    fn napi_queue_async_work(env: Env, work: AsyncWork) -> Status;
+    fn napi_delete_async_work(env: Env, work: AsyncWork) -> Status;
+    fn napi_wrap(
+        env: Env,
+        object: Value,
+        native: *mut c_void,
+        finalize: Option<Finalize>,
+        hint: *mut c_void,
+        result: *mut c_void,
+    ) -> Status;
+    fn napi_unwrap(env: Env, object: Value, result: *mut *mut c_void) -> Status;
+    fn napi_call_function(
+        env: Env,
+        receiver: Value,
+        function: Value,
+        argc: usize,
+        argv: *const Value,
+        result: *mut Value,
+    ) -> Status;
+    fn napi_create_threadsafe_function(
+        env: Env,
+        function: Value,
+        async_resource: Value,
+        async_resource_name: Value,
+        max_queue_size: usize,
+        initial_thread_count: usize,
+        thread_finalize_data: *mut c_void,
+        thread_finalize: Option<Finalize>,
+        context: *mut c_void,
+        call_js: Option<CallJs>,
+        result: *mut ThreadsafeFunction,
+    ) -> Status;
+    fn napi_call_threadsafe_function(
+        function: ThreadsafeFunction,
+        data: *mut c_void,
+        mode: c_int,
+    ) -> Status;
+    fn napi_release_threadsafe_function(function: ThreadsafeFunction, mode: c_int) -> Status;
+}
+
+/// Message of an exception to throw into JavaScript
+struct Thrown(String);
+
+impl From<UpsError> for Thrown {
+    fn from(error: UpsError) -> Self {
+        Thrown(error.to_string())
+    }
+}
+
+type JsResult = std::result::Result<Value, Thrown>;
+
+/// Connection behind a JavaScript `ups` object, shared with the calls in
+/// flight and moved into a running monitor
+type Connection = Rc<RefCell<Option<Arc<MegatecUps>>>>;
+
+/// Result of a call run off the JavaScript thread
+enum Outcome {
+    Connection(Arc<MegatecUps>),
+    Status(UpsStatus),
+    Text(String),
+    Nothing,
+}
+
+type Call = Box<dyn FnOnce() -> Result<Outcome, Thrown> + Send>;
+
+/// Call queued on the libuv thread pool and the promise it settles
+struct Job {
+    call: Option<Call>,
+    outcome: Option<Result<Outcome, Thrown>>,
+    deferred: Deferred,
+    work: AsyncWork,
+}
+
+/// Threadsafe function handle, only called from the monitor thread
+struct EventSink(ThreadsafeFunction);
+
+// SAFETY: N-API threadsafe functions may be called from any thread
+unsafe impl Send for EventSink {}
+
+impl EventSink {
+    /// Queue an event for [`deliver_event`] on the main thread
+    fn send(&self, event: &UpsEvent) {
+        let event = Box::into_raw(Box::new(event.clone()));
+        let status = unsafe { napi_call_threadsafe_function(self.0, event.cast(), TSFN_BLOCKING) };
+        if status != OK {
+            drop(unsafe { Box::from_raw(event) });
+        }
+    }
+}
+
+/// Monitor behind a JavaScript `monitor` object
+struct Watch {
+    ups: Connection,
+    handle: Option<MonitorHandle<crate::usb::UsbTransport>>,
+    events: ThreadsafeFunction,
+}
+
+impl Watch {
+    /// Stop the monitor thread, hand the connection back and release the callback
+    fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            if let Some(monitor) = handle.stop() {
+                *self.ups.borrow_mut() = Some(Arc::new(monitor.into_ups()));
+            }
+            unsafe { napi_release_threadsafe_function(self.events, TSFN_RELEASE) };
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Register the exports of the addon
+///
+/// # Safety
+///
+/// Called by Node.js with a valid environment and exports object.
+#[no_mangle]
+pub unsafe extern "C" fn napi_register_module_v1(env: Env, exports: Value) -> Value {
+    let functions: [(&str, Callback); 11] = [
+        ("connect", connect),
+        ("auto", auto),
+        ("status", status),
+        ("name", name),
+        ("test", test),
+        ("abortTest", abort_test),
+        ("switchBeep", switch_beep),
+        ("shutdown", shutdown),
+        ("cancelShutdown", cancel_shutdown),
+        ("monitor", monitor),
+        ("stopMonitor", stop_monitor),
+    ];
+    for (name, callback) in functions {
+        let mut function = ptr::null_mut();
+        let name = CString::new(name).unwrap_or_default();
+        unsafe {
+            napi_create_function(
+                env,
+                name.as_ptr(),
+                name.as_bytes().len(),
+                callback,
+                ptr::null_mut(),
+                &mut function,
+            );
+            napi_set_named_property(env, exports, name.as_ptr(), function);
+        }
+    }
+    exports
+}
+
+unsafe extern "C" fn connect(env: Env, info: CallbackInfo) -> Value {
+    respond(env, || {
+        let [vendor_id, product_id] = unsafe { arguments(env, info) };
+        let vendor_id = unsafe { u16_argument(env, vendor_id, "vendorId") }?;
+        let product_id = unsafe { u16_argument(env, product_id, "productId") }?;
+        let call = move || {
+            let ups = MegatecUps::new(vendor_id, product_id)?;
+            Ok(Outcome::Connection(Arc::new(ups)))
+        };
+        unsafe { promise(env, Box::new(call)) }
+    })
+}
+
+unsafe extern "C" fn auto(env: Env, _info: CallbackInfo) -> Value {
+    respond(env, || unsafe {
+        promise(
+            env,
+            Box::new(|| {
+                let ups = MegatecUps::auto()?;
+                Ok(Outcome::Connection(Arc::new(ups)))
+            }),
+        )
+    })
+}
+
+unsafe extern "C" fn status(env: Env, info: CallbackInfo) -> Value {
+    respond(env, || unsafe {
+        with_connection(env, info, |ups| Ok(Outcome::Status(ups.get_status()?)))
+    })
+}
+
+unsafe extern "C" fn name(env: Env, info: CallbackInfo) -> Value {
+    respond(env, || unsafe {
+        with_connection(env, info, |ups| Ok(Outcome::Text(ups.get_name()?)))
+    })
+}
+
+unsafe extern "C" fn test(env: Env, info: CallbackInfo) -> Value {
+    respond(env, || unsafe {
+        with_connection(env, info, |ups| Ok(Outcome::Text(ups.test()?.to_string())))
+    })
+}
+
+unsafe extern "C" fn abort_test(env: Env, info: CallbackInfo) -> Value {
+    respond(env, || unsafe {
+        with_connection(env, info, |ups| {
+            ups.abort_test()?;
+            Ok(Outcome::Nothing)
+        })
+    })
+}
+
+unsafe extern "C" fn switch_beep(env: Env, info: CallbackInfo) -> Value {
+    respond(env, || unsafe {
+        with_connection(env, info, |ups| {
+            ups.switch_beep()?;
+            Ok(Outcome::Nothing)
+        })
+    })
+}
+
+/// `shutdown(ups)` after 1 minute, or `shutdown(ups, minutes)`
+unsafe extern "C" fn shutdown(env: Env, info: CallbackInfo) -> Value {
+    respond(env, || {
+        let [_, minutes] = unsafe { arguments(env, info) };
+        let minutes = match unsafe { is_missing(env, minutes) } {
+            true => None,
+            false => Some(unsafe { number_argument(env, minutes, "minutes") }? as f32),
+        };
+        unsafe {
+            with_connection(env, info, move |ups| {
+                match minutes {
+                    Some(minutes) => ups.shutdown_after(minutes)?,
+                    None => ups.shutdown()?,
+                }
+                Ok(Outcome::Nothing)
+            })
+        }
+    })
+}
+
+unsafe extern "C" fn cancel_shutdown(env: Env, info: CallbackInfo) -> Value {
+    respond(env, || unsafe {
+        with_connection(env, info, |ups| {
+            ups.cancel_shutdown()?;
+            Ok(Outcome::Nothing)
+        })
+    })
+}
+
+/// `monitor(ups, intervalMs, callback)` calling `callback(name, status)` for every event
+///
+/// The connection belongs to the monitor until `stopMonitor` hands it back.
+unsafe extern "C" fn monitor(env: Env, info: CallbackInfo) -> Value {
+    respond(env, || {
+        let [ups, interval, callback] = unsafe { arguments(env, info) };
+        let connection = unsafe { unwrap::<Connection>(env, ups) }?.clone();
+        let interval = unsafe { number_argument(env, interval, "intervalMs") }?;
+        let interval = Duration::try_from_secs_f64(interval / 1000.0)
+            .map_err(|_| Thrown("intervalMs must be a positive number".to_string()))?;
+        let resource_name = unsafe { string(env, "megatec-ups monitor") }?;
+        let ups = connection
+            .borrow_mut()
+            .take()
+            .ok_or_else(|| Thrown("The UPS is already monitored".to_string()))?;
+        let ups = Arc::try_unwrap(ups).map_err(|ups| {
+            *connection.borrow_mut() = Some(ups);
+            Thrown("Wait for the pending calls before monitoring the UPS".to_string())
+        })?;
+
+        let mut events = ptr::null_mut();
+        let status = unsafe {
+            napi_create_threadsafe_function(
+                env,
+                callback,
+                ptr::null_mut(),
+                resource_name,
+                0,
+                1,
+                ptr::null_mut(),
+                None,
+                ptr::null_mut(),
+                Some(deliver_event),
+                &mut events,
+            )
+        };
+        if status != OK {
+            *connection.borrow_mut() = Some(Arc::new(ups));
+            return Err(Thrown("The callback is not a function".to_string()));
+        }
+
+        let mut monitor = Monitor::new(ups, interval);
+        let sink = EventSink(events);
+        monitor.on_event(move |event| sink.send(event));
+
+        let watch = Watch {
+            ups: connection,
+            handle: Some(monitor.spawn()),
+            events,
+        };
+        unsafe { wrap(env, watch) }
+    })
+}
+
+unsafe extern "C" fn stop_monitor(env: Env, info: CallbackInfo) -> Value {
+    respond(env, || {
+        let [monitor] = unsafe { arguments(env, info) };
+        let watch = unsafe { unwrap_mut::<Watch>(env, monitor) }?;
+        watch.stop();
+        unsafe { undefined(env) }
+    })
+}
+
+/// Call the JavaScript callback of a monitor on the main thread
+unsafe extern "C" fn deliver_event(env: Env, callback: Value, _: *mut c_void, data: *mut c_void) {
+    let event = unsafe { Box::from_raw(data.cast::<UpsEvent>()) };
+    // The environment is gone while Node.js shuts down
+    if env.is_null() {
+        return;
+    }
+    let arguments = unsafe {
+        string(env, event.name()).and_then(|name| {
+            let status = match event.status() {
+                Some(status) => status_object(env, status)?,
+                None => null(env)?,
+            };
+            Ok([name, status])
+        })
+    };
+    if let Ok(arguments) = arguments {
+        let mut result = ptr::null_mut();
+        unsafe {
+            let receiver = undefined(env).unwrap_or(ptr::null_mut());
+            napi_call_function(
+                env,
+                receiver,
+                callback,
+                arguments.len(),
+                arguments.as_ptr(),
+                &mut result,
+            );
+        }
+    }
+}
+
+/// Run `call` on the libuv thread pool and return a promise of its outcome
+unsafe fn promise(env: Env, call: Call) -> JsResult {
+    let mut deferred = ptr::null_mut();
+    let mut promise = ptr::null_mut();
+    unsafe {
+        checked(
+            napi_create_promise(env, &mut deferred, &mut promise),
+            promise,
+        )
+    }?;
+    let job = Box::into_raw(Box::new(Job {
+        call: Some(call),
+        outcome: None,
+        deferred,
+        work: ptr::null_mut(),
+    }));
+
+    let queued = unsafe { string(env, "megatec-ups") }.and_then(|resource_name| unsafe {
+        checked(
+            napi_create_async_work(
+                env,
+                ptr::null_mut(),
+                resource_name,
+                execute,
+                complete,
+                job.cast(),
+                &mut (*job).work,
+            ),
+            ptr::null_mut(),
+        )?;
+        checked(napi_queue_async_work(env, (*job).work), ptr::null_mut())
+    });
+    if let Err(thrown) = queued {
+        let job = unsafe { Box::from_raw(job) };
+        if !job.work.is_null() {
+            unsafe { napi_delete_async_work(env, job.work) };
+        }
+        unsafe { settle(env, job.deferred, Err(thrown)) };
+    }
+    Ok(promise)
+}
+
+/// Run the call of a job on a thread of the pool, which must not use N-API
+unsafe extern "C" fn execute(_env: Env, data: *mut c_void) {
+    let job = unsafe { &mut *data.cast::<Job>() };
+    if let Some(call) = job.call.take() {
+        job.outcome = Some(
+            panic::catch_unwind(AssertUnwindSafe(call))
+                .unwrap_or_else(|_| Err(Thrown("The call panicked".to_string()))),
+        );
+    }
+}
+
+/// Settle the promise of a job on the main thread
+unsafe extern "C" fn complete(env: Env, _status: Status, data: *mut c_void) {
+    let job = unsafe { Box::from_raw(data.cast::<Job>()) };
+    unsafe { napi_delete_async_work(env, job.work) };
+    // A job without an outcome was cancelled before it ran
+    let outcome = job
+        .outcome
+        .unwrap_or_else(|| Err(Thrown("The call was cancelled".to_string())));
+    let value = outcome.and_then(|outcome| unsafe {
+        match outcome {
+            Outcome::Connection(ups) => wrap_connection(env, ups),
+            Outcome::Status(status) => status_object(env, &status),
+            Outcome::Text(text) => string(env, &text),
+            Outcome::Nothing => undefined(env),
+        }
+    });
+    unsafe { settle(env, job.deferred, value) };
+}
+
+/// Resolve a promise with the value or reject it with an `Error` of the message
+unsafe fn settle(env: Env, deferred: Deferred, value: JsResult) {
+    match value {
+        Ok(value) => unsafe {
+            napi_resolve_deferred(env, deferred, value);
+        },
+        Err(Thrown(message)) => unsafe {
+            let mut error = ptr::null_mut();
+            if let Ok(message) = string(env, &message) {
+                napi_create_error(env, ptr::null_mut(), message, &mut error);
+            }
+            napi_reject_deferred(env, deferred, error);
+        },
+    }
+}
+
+/// Return the value of a callback or throw its error into JavaScript
+fn respond(env: Env, body: impl FnOnce() -> JsResult) -> Value {
+    match body() {
+        Ok(value) => value,
+        Err(Thrown(message)) => {
+            let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+            unsafe { napi_throw_error(env, ptr::null(), message.as_ptr()) };
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Get the first `N` arguments of a call, `undefined` when missing
+unsafe fn arguments<const N: usize>(env: Env, info: CallbackInfo) -> [Value; N] {
+    let mut argv = [ptr::null_mut(); N];
+    let mut argc = N;
+    unsafe {
+        napi_get_cb_info(
+            env,
+            info,
+            &mut argc,
+            argv.as_mut_ptr(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    argv
+}
+
+unsafe fn is_missing(env: Env, value: Value) -> bool {
+    let mut kind = TYPE_UNDEFINED;
+    unsafe { napi_typeof(env, value, &mut kind) };
+    kind == TYPE_UNDEFINED || kind == TYPE_NULL
+}
+
+unsafe fn number_argument(env: Env, value: Value, name: &str) -> Result<f64, Thrown> {
+    let mut number = 0.0;
+    match unsafe { napi_get_value_double(env, value, &mut number) } {
+        OK => Ok(number),
+        _ => Err(Thrown(format!("{} must be a number", name))),
+    }
+}
+
+unsafe fn u16_argument(env: Env, value: Value, name: &str) -> Result<u16, Thrown> {
+    let mut number = 0;
+    match unsafe { napi_get_value_uint32(env, value, &mut number) } {
+        OK => u16::try_from(number).map_err(|_| Thrown(format!("{} must fit in 16 bits", name))),
+        _ => Err(Thrown(format!("{} must be a number", name))),
+    }
+}
+
+/// Run an operation on the connection passed as the first argument on the
+/// libuv thread pool, returning a promise of its outcome
+unsafe fn with_connection(
+    env: Env,
+    info: CallbackInfo,
+    operation: impl FnOnce(&MegatecUps) -> Result<Outcome, Thrown> + Send + 'static,
+) -> JsResult {
+    let [ups] = unsafe { arguments(env, info) };
+    let connection = unsafe { unwrap::<Connection>(env, ups) }?;
+    let ups = connection
+        .borrow()
+        .clone()
+        .ok_or_else(|| Thrown("The UPS is being monitored".to_string()))?;
+    unsafe { promise(env, Box::new(move || operation(&ups))) }
+}
+
+unsafe fn wrap_connection(env: Env, ups: Arc<MegatecUps>) -> JsResult {
+    unsafe { wrap::<Connection>(env, Rc::new(RefCell::new(Some(ups)))) }
+}
+
+/// Create an object owning `native`, dropped when the object is collected
+unsafe fn wrap<T>(env: Env, native: T) -> JsResult {
+    let object = unsafe { object(env) }?;
+    let native = Box::into_raw(Box::new(native));
+    let status = unsafe {
+        napi_wrap(
+            env,
+            object,
+            native.cast(),
+            Some(finalize::<T>),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    if status != OK {
+        drop(unsafe { Box::from_raw(native) });
+        return Err(Thrown("Cannot wrap the native object".to_string()));
+    }
+    Ok(object)
+}
+
+unsafe extern "C" fn finalize<T>(_env: Env, native: *mut c_void, _hint: *mut c_void) {
+    drop(unsafe { Box::from_raw(native.cast::<T>()) });
+}
+
+/// Get the native value of an object created by [`wrap`]
+///
+/// The caller names the type the object was wrapped with.
+unsafe fn unwrap<'a, T>(env: Env, object: Value) -> Result<&'a T, Thrown> {
+    unsafe { unwrap_mut(env, object) }.map(|native| &*native)
+}
+
+unsafe fn unwrap_mut<'a, T>(env: Env, object: Value) -> Result<&'a mut T, Thrown> {
+    let mut native = ptr::null_mut();
+    match unsafe { napi_unwrap(env, object, &mut native) } {
+        OK if !native.is_null() => Ok(unsafe { &mut *native.cast::<T>() }),
+        _ => Err(Thrown(
+            "Expected an object created by this addon".to_string(),
+        )),
+    }
+}
+
+unsafe fn status_object(env: Env, status: &UpsStatus) -> JsResult {
+    let object = unsafe { object(env) }?;
+    let flags = status.flags;
+    let numbers = [
+        ("inputVoltage", status.input_voltage),
+        ("inputFaultVoltage", status.input_fault_voltage),
+        ("outputVoltage", status.output_voltage),
+        ("load", status.load_raw),
+        ("inputFrequency", status.input_frequency),
+        ("batteryVoltage", status.battery_voltage),
+        ("temperature", status.temperature),
+    ];
+    let booleans = [
+        ("utilityFail", flags.utility_fail),
+        ("batteryLow", flags.battery_low),
+        ("bypassActive", flags.bypass_active),
+        ("upsFailed", flags.ups_failed),
+        ("standby", flags.standby),
+        ("testInProgress", flags.test_in_progress),
+        ("shutdownActive", flags.shutdown_active),
+        ("beeperOn", flags.beeper_on),
+    ];
+    for (name, number) in numbers {
+        let mut value = ptr::null_mut();
+        unsafe {
+            napi_create_double(env, number, &mut value);
+            set_property(env, object, name, value);
+        }
+    }
+    for (name, flag) in booleans {
+        let mut value = ptr::null_mut();
+        unsafe {
+            napi_get_boolean(env, flag, &mut value);
+            set_property(env, object, name, value);
+        }
+    }
+    Ok(object)
+}
+
+unsafe fn set_property(env: Env, object: Value, name: &str, value: Value) {
+    let name = CString::new(name).unwrap_or_default();
+    unsafe { napi_set_named_property(env, object, name.as_ptr(), value) };
+}
+
+unsafe fn object(env: Env) -> JsResult {
+    let mut value = ptr::null_mut();
+    unsafe { checked(napi_create_object(env, &mut value), value) }
+}
+
+unsafe fn string(env: Env, text: &str) -> JsResult {
+    let mut value = ptr::null_mut();
+    unsafe {
+        checked(
+            napi_create_string_utf8(env, text.as_ptr().cast(), text.len(), &mut value),
+            value,
+        )
+    }
+}
+
+unsafe fn undefined(env: Env) -> JsResult {
+    let mut value = ptr::null_mut();
+    unsafe { checked(napi_get_undefined(env, &mut value), value) }
+}
+
+unsafe fn null(env: Env) -> JsResult {
+    let mut value = ptr::null_mut();
+    unsafe { checked(napi_get_null(env, &mut value), value) }
+}
+
+fn checked(status: Status, value: Value) -> JsResult {
+    match status {
+        OK => Ok(value),
+        _ => Err(Thrown(format!("N-API call failed with status {}", status))),
+    }
+}
@@ -0,0 +1,241 @@
+use crate::battery::BatteryModel;
+use crate::error::Result;
+use crate::info::UpsInfo;
+use crate::listener;
+use crate::rating::RatingInfo;
+use crate::status::UpsStatus;
+use crate::timestamp;
+use crate::transport::Transport;
+use crate::MegatecUps;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Default listen address of the apcupsd Network Information Server
+pub const DEFAULT_NIS_ADDRESS: &str = "0.0.0.0:3551";
+
+/// Connections without a request for this long are closed
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Version reported in the `VERSION` field, an apcupsd release clients know
+const APCUPSD_VERSION: &str = "3.14.14 (31 May 2016)";
+
+/// Server speaking the apcupsd Network Information Server protocol
+///
+/// Clients such as `apcaccess`, the apcupsd CGI pages, Telegraf or Nagios
+/// `check_apcupsd` connect to port 3551 and send `status` in a record of a
+/// two-byte big-endian length and the text. The reply is a record per line
+/// in the apcupsd layout, `LINEV    : 230.0 Volts`, ended by an empty record.
+///
+/// `STATUS` is `ONLINE` or `ONBATT`, followed by `LOWBATT`, `CAL` while a test
+/// runs, `SHUTTING DOWN` or `COMMLOST` when the UPS does not answer.
+/// `BCHARGE` and `TIMELEFT` are estimated with a [`BatteryModel`] of the
+/// rating. `events` is answered with an empty log.
+pub struct NisServer<T: Transport> {
+    ups: MegatecUps<T>,
+    listener: TcpListener,
+    hostname: String,
+    ups_name: String,
+    started: SystemTime,
+    rating: Option<RatingInfo>,
+    info: Option<UpsInfo>,
+}
+
+impl<T: Transport> NisServer<T> {
+    /// Bind the server to an address such as [`DEFAULT_NIS_ADDRESS`]
+    pub fn bind<A: ToSocketAddrs>(ups: MegatecUps<T>, address: A) -> Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let hostname = fs::read_to_string("/etc/hostname")
+            .ok()
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "localhost".to_string());
+        Ok(Self {
+            ups,
+            listener,
+            hostname,
+            ups_name: "ups".to_string(),
+            started: SystemTime::now(),
+            rating: None,
+            info: None,
+        })
+    }
+
+    /// Set the `HOSTNAME` field, read from `/etc/hostname` by default
+    pub fn set_hostname<S: Into<String>>(&mut self, hostname: S) {
+        self.hostname = hostname.into();
+    }
+
+    /// Set the `UPSNAME` field, `ups` by default
+    pub fn set_ups_name<S: Into<String>>(&mut self, ups_name: S) {
+        self.ups_name = ups_name.into();
+    }
+
+    /// Get the address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Get a reference to the served UPS
+    pub fn ups(&self) -> &MegatecUps<T> {
+        &self.ups
+    }
+
+    /// Answer requests, serving each client on its own thread
+    ///
+    /// Failed accepts are reported on stderr and do not stop the server.
+    pub fn serve(&mut self) -> Result<()>
+    where
+        T: Send,
+    {
+        self.rating = self.ups.get_rating().ok();
+        self.info = self.ups.get_info().ok();
+
+        let server = &*self;
+        thread::scope(|scope| {
+            for stream in server.listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        listener::accept_failed("NIS server", &e);
+                        continue;
+                    }
+                };
+                // A client that goes away is not a server failure
+                scope.spawn(move || server.handle(stream));
+            }
+            Ok(())
+        })
+    }
+
+    /// Read the status and build the fields of a `status` reply, `APC` and `END APC` included
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let now = timestamp::apcupsd_date(SystemTime::now());
+        let mut fields = vec![
+            ("DATE", now.clone()),
+            ("HOSTNAME", self.hostname.clone()),
+            (
+                "VERSION",
+                format!("{} megatec-ups-control", APCUPSD_VERSION),
+            ),
+            ("UPSNAME", self.ups_name.clone()),
+            ("CABLE", "USB Cable".to_string()),
+            ("DRIVER", "Megatec UPS Driver".to_string()),
+            ("UPSMODE", "Stand Alone".to_string()),
+            ("STARTTIME", timestamp::apcupsd_date(self.started)),
+        ];
+        if let Some(info) = &self.info {
+            fields.push(("MODEL", info.model.clone()));
+        }
+        match self.ups.get_status() {
+            Ok(status) => fields.extend(self.status_fields(&status)),
+            Err(_) => fields.push(("STATUS", "COMMLOST".to_string())),
+        }
+        if let Some(rating) = &self.rating {
+            fields.push(("NOMINV", format!("{:.0} Volts", rating.rated_voltage)));
+            fields.push(("NOMBATTV", format!("{:.1} Volts", rating.battery_voltage)));
+        }
+        if let Some(info) = &self.info {
+            fields.push(("FIRMWARE", info.firmware_version.clone()));
+        }
+
+        // Format version, number of records and length of the report
+        let records = fields.len() + 2;
+        let length: usize = fields
+            .iter()
+            .map(|(name, value)| record_line(name, value).len())
+            .sum::<usize>()
+            + record_line("END APC", &now).len();
+        fields.insert(0, ("APC", format!("001,{:03},{:04}", records, length)));
+        fields.push(("END APC", now));
+        fields
+    }
+
+    /// Fields taken from a status reading
+    fn status_fields(&self, status: &UpsStatus) -> Vec<(&'static str, String)> {
+        let flags = status.flags;
+        let mut ups_status = vec![if flags.utility_fail {
+            "ONBATT"
+        } else {
+            "ONLINE"
+        }];
+        if flags.battery_low {
+            ups_status.push("LOWBATT");
+        }
+        if flags.test_in_progress {
+            ups_status.push("CAL");
+        }
+        if flags.shutdown_active {
+            ups_status.push("SHUTTING DOWN");
+        }
+
+        let status = match &self.rating {
+            Some(rating) => status.in_percent(rating, &self.ups.quirks()),
+            None => status.clone(),
+        };
+        let mut fields = vec![
+            ("STATUS", ups_status.join(" ")),
+            ("LINEV", format!("{:.1} Volts", status.input_voltage)),
+            ("LOADPCT", format!("{:.1} Percent", status.load_raw)),
+        ];
+        if let Some(rating) = &self.rating {
+            let model = BatteryModel::from_rating(rating);
+            if let Some(charge) = model.state_of_charge(&status) {
+                fields.push(("BCHARGE", format!("{:.1} Percent", charge * 100.0)));
+            }
+            if let Some(runtime) = model.estimate_runtime(&status) {
+                fields.push((
+                    "TIMELEFT",
+                    format!("{:.1} Minutes", runtime.as_secs_f64() / 60.0),
+                ));
+            }
+        }
+        fields.extend([
+            ("OUTPUTV", format!("{:.1} Volts", status.output_voltage)),
+            ("ITEMP", format!("{:.1} C", status.temperature)),
+            ("BATTV", format!("{:.1} Volts", status.battery_voltage)),
+            ("LINEFREQ", format!("{:.1} Hz", status.input_frequency)),
+        ]);
+        // Values missing from truncated replies are left out
+        fields.retain(|(_, value)| !value.starts_with("NaN"));
+        fields
+    }
+
+    /// Answer the requests of one client until it disconnects or stays idle
+    fn handle(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        loop {
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length)?;
+            let mut request = vec![0u8; u16::from_be_bytes(length) as usize];
+            stream.read_exact(&mut request)?;
+
+            let mut reply = Vec::new();
+            match String::from_utf8_lossy(&request).trim() {
+                "status" => {
+                    for (name, value) in self.fields() {
+                        push_record(&mut reply, &record_line(name, &value));
+                    }
+                }
+                "events" => {}
+                _ => push_record(&mut reply, "Invalid command\n"),
+            }
+            push_record(&mut reply, "");
+            stream.write_all(&reply)?;
+        }
+    }
+}
+
+/// Line of a field, the name padded to 9 characters as apcupsd prints it
+fn record_line(name: &str, value: &str) -> String {
+    format!("{:<9}: {}\n", name, value)
+}
+
+/// Append a record, its length followed by the text
+fn push_record(reply: &mut Vec<u8>, text: &str) {
+    reply.extend_from_slice(&(text.len() as u16).to_be_bytes());
+    reply.extend_from_slice(text.as_bytes());
+}
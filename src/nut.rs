@@ -0,0 +1,399 @@
+use crate::error::{Result, UpsError};
+use crate::info::UpsInfo;
+use crate::rating::RatingInfo;
+use crate::status::UpsStatus;
+use crate::transport::Transport;
+use crate::MegatecUps;
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Directory where upsd looks for driver sockets on most distributions
+pub const DEFAULT_NUT_STATE_PATH: &str = "/var/run/nut";
+
+const LOOP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Instant commands announced to upsd
+const INSTANT_COMMANDS: [&str; 9] = [
+    "test.battery.start.quick",
+    "test.battery.start.deep",
+    "test.battery.stop",
+    "beeper.toggle",
+    "beeper.enable",
+    "beeper.disable",
+    "shutdown.return",
+    "shutdown.stayoff",
+    "shutdown.stop",
+];
+
+// Result codes of the TRACKING reply, as defined by upsd
+const STAT_HANDLED: u8 = 0;
+const STAT_UNKNOWN: u8 = 1;
+const STAT_FAILED: u8 = 3;
+
+/// Map a status snapshot to NUT variable names
+pub fn nut_status_variables(status: &UpsStatus) -> Vec<(&'static str, String)> {
+    let flags = status.flags;
+    let mut ups_status = vec![if flags.utility_fail { "OB" } else { "OL" }];
+    if flags.battery_low {
+        ups_status.push("LB");
+    }
+    if flags.bypass_active {
+        ups_status.push("BYPASS");
+    }
+    if flags.test_in_progress {
+        ups_status.push("CAL");
+    }
+    if flags.ups_failed {
+        ups_status.push("ALARM");
+    }
+
+    let mut variables = vec![
+        ("input.voltage", format!("{:.1}", status.input_voltage)),
+        (
+            "input.voltage.fault",
+            format!("{:.1}", status.input_fault_voltage),
+        ),
+        ("output.voltage", format!("{:.1}", status.output_voltage)),
+        ("ups.load", format!("{:.0}", status.load_raw)),
+        ("input.frequency", format!("{:.1}", status.input_frequency)),
+        ("battery.voltage", format!("{:.2}", status.battery_voltage)),
+        ("ups.temperature", format!("{:.1}", status.temperature)),
+        ("ups.status", ups_status.join(" ")),
+        (
+            "ups.beeper.status",
+            if flags.beeper_on {
+                "enabled"
+            } else {
+                "disabled"
+            }
+            .to_string(),
+        ),
+        (
+            "ups.type",
+            if flags.standby {
+                "offline / line interactive"
+            } else {
+                "online"
+            }
+            .to_string(),
+        ),
+    ];
+    if flags.ups_failed {
+        variables.push(("ups.alarm", "UPS failed".to_string()));
+    }
+
+    variables
+}
+
+/// Map the rating information to NUT nominal variables
+pub fn nut_rating_variables(rating: &RatingInfo) -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "input.voltage.nominal",
+            format!("{:.0}", rating.rated_voltage),
+        ),
+        ("input.current.nominal", rating.rated_current.to_string()),
+        (
+            "battery.voltage.nominal",
+            format!("{:.1}", rating.battery_voltage),
+        ),
+        (
+            "input.frequency.nominal",
+            format!("{:.0}", rating.rated_frequency),
+        ),
+    ]
+}
+
+/// Map the manufacturer information to NUT device variables
+pub fn nut_info_variables(info: &UpsInfo) -> Vec<(&'static str, String)> {
+    vec![
+        ("device.mfr", info.company.clone()),
+        ("device.model", info.model.clone()),
+        ("ups.mfr", info.company.clone()),
+        ("ups.model", info.model.clone()),
+        ("ups.firmware", info.firmware_version.clone()),
+    ]
+}
+
+/// Driver speaking the NUT driver socket protocol, so upsd can use the UPS
+///
+/// upsd connects to the socket `<state path>/<driver>-<ups name>` and receives
+/// `SETINFO` updates after every poll. Instant commands sent with `INSTCMD` are
+/// forwarded to the UPS.
+pub struct NutDriver<T: Transport> {
+    ups: MegatecUps<T>,
+    listener: UnixListener,
+    path: PathBuf,
+    interval: Duration,
+    clients: Vec<Client>,
+    variables: Vec<(&'static str, String)>,
+    stale: bool,
+}
+
+/// Connection from upsd with its partially read command line
+struct Client {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+}
+
+impl<T: Transport> NutDriver<T> {
+    /// Create the driver socket, replacing a stale socket left by a previous run
+    pub fn bind<P: AsRef<Path>>(ups: MegatecUps<T>, path: P, interval: Duration) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            ups,
+            listener,
+            path,
+            interval,
+            clients: Vec::new(),
+            variables: Vec::new(),
+            stale: true,
+        })
+    }
+
+    /// Get the path of the driver socket
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get a reference to the UPS driven by this driver
+    pub fn ups(&self) -> &MegatecUps<T> {
+        &self.ups
+    }
+
+    /// Poll the UPS and serve upsd until the socket fails
+    pub fn serve(&mut self) -> Result<()> {
+        self.read_static_variables();
+        let mut last_poll: Option<Instant> = None;
+
+        loop {
+            self.accept()?;
+            self.read_clients();
+
+            if last_poll.is_none_or(|poll| poll.elapsed() >= self.interval) {
+                last_poll = Some(Instant::now());
+                self.update();
+            }
+
+            thread::sleep(LOOP_INTERVAL);
+        }
+    }
+
+    /// Read the values that do not change while the driver runs
+    fn read_static_variables(&mut self) {
+        self.variables
+            .push(("driver.name", env!("CARGO_PKG_NAME").to_string()));
+        self.variables
+            .push(("driver.version", env!("CARGO_PKG_VERSION").to_string()));
+        if let Ok(info) = self.ups.get_info() {
+            self.variables.extend(nut_info_variables(&info));
+        }
+        if let Ok(rating) = self.ups.get_rating() {
+            self.variables.extend(nut_rating_variables(&rating));
+        }
+    }
+
+    /// Read the status and push changed variables to every client
+    fn update(&mut self) {
+        let status = match self.ups.get_status() {
+            Ok(status) => status,
+            Err(_) => {
+                if !self.stale {
+                    self.stale = true;
+                    self.broadcast("DATASTALE\n");
+                }
+                return;
+            }
+        };
+
+        let mut changes = String::new();
+        for (name, value) in nut_status_variables(&status) {
+            match self.variables.iter_mut().find(|(known, _)| *known == name) {
+                Some((_, known)) if *known == value => {}
+                Some((_, known)) => {
+                    changes.push_str(&setinfo(name, &value));
+                    *known = value;
+                }
+                None => {
+                    changes.push_str(&setinfo(name, &value));
+                    self.variables.push((name, value));
+                }
+            }
+        }
+        if self.stale {
+            self.stale = false;
+            changes.push_str("DATAOK\n");
+        }
+        self.broadcast(&changes);
+    }
+
+    /// Accept pending connections from upsd
+    fn accept(&mut self) -> Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    self.clients.push(Client {
+                        stream,
+                        buffer: Vec::new(),
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Read and answer the commands sent by every client
+    fn read_clients(&mut self) {
+        let mut clients = std::mem::take(&mut self.clients);
+        clients.retain_mut(|client| {
+            let lines = match client.read_lines() {
+                Ok(lines) => lines,
+                Err(_) => return false,
+            };
+            lines.iter().all(|line| {
+                let reply = self.answer(line);
+                reply.is_empty() || client.stream.write_all(reply.as_bytes()).is_ok()
+            })
+        });
+        self.clients = clients;
+    }
+
+    /// Build the reply to a single command line
+    fn answer(&self, line: &str) -> String {
+        let words = split_words(line);
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+
+        match words.as_slice() {
+            ["PING"] => "PONG\n".to_string(),
+            ["DUMPALL"] => {
+                let mut reply: String = self
+                    .variables
+                    .iter()
+                    .map(|(name, value)| setinfo(name, value))
+                    .collect();
+                for command in INSTANT_COMMANDS {
+                    reply.push_str(&format!("ADDCMD {}\n", command));
+                }
+                reply.push_str(if self.stale {
+                    "DATASTALE\n"
+                } else {
+                    "DATAOK\n"
+                });
+                reply.push_str("DUMPDONE\n");
+                reply
+            }
+            ["INSTCMD", command, rest @ ..] => {
+                let status = if !INSTANT_COMMANDS.contains(command) {
+                    STAT_UNKNOWN
+                } else if self.instant_command(command).is_ok() {
+                    STAT_HANDLED
+                } else {
+                    STAT_FAILED
+                };
+                match rest {
+                    [.., "TRACKING", id] => format!("TRACKING {} {}\n", id, status),
+                    _ => String::new(),
+                }
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// Execute an instant command on the UPS
+    fn instant_command(&self, command: &str) -> Result<()> {
+        match command {
+            "test.battery.start.quick" => self.ups.test().map(drop),
+            "test.battery.start.deep" => self.ups.test_until_battery_low().map(drop),
+            "test.battery.stop" => self.ups.abort_test(),
+            "beeper.toggle" => self.ups.switch_beep(),
+            "beeper.enable" => self.ups.set_beeper(true),
+            "beeper.disable" => self.ups.set_beeper(false),
+            "shutdown.return" => self.ups.shutdown_and_restore(1.0, 3),
+            "shutdown.stayoff" => self.ups.shutdown(),
+            "shutdown.stop" => self.ups.cancel_shutdown(),
+            _ => Err(UpsError::Unsupported),
+        }
+    }
+
+    /// Send a message to every client, dropping the ones that went away
+    fn broadcast(&mut self, message: &str) {
+        if message.is_empty() {
+            return;
+        }
+        self.clients
+            .retain_mut(|client| client.stream.write_all(message.as_bytes()).is_ok());
+    }
+}
+
+impl<T: Transport> Drop for NutDriver<T> {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl Client {
+    /// Read everything available and return the complete lines
+    fn read_lines(&mut self) -> io::Result<Vec<String>> {
+        let mut chunk = [0u8; 512];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut lines = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            lines.push(String::from_utf8_lossy(&line).trim().to_string());
+        }
+        Ok(lines)
+    }
+}
+
+/// Format a SETINFO line with the value quoted and escaped
+fn setinfo(name: &str, value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("SETINFO {} \"{}\"\n", name, escaped)
+}
+
+/// Split a command line into words, honouring double quotes
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if quoted => word.extend(chars.next()),
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
@@ -0,0 +1,333 @@
+use crate::error::{Result, UpsError};
+use crate::event::UpsEvent;
+use crate::http::{self, Url};
+use crate::json::Value;
+use crate::monitor::Monitor;
+use crate::status::UpsStatus;
+use crate::transport::Transport;
+use std::io;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Default OTLP/HTTP endpoint of a local OpenTelemetry Collector
+pub const DEFAULT_OTLP_ENDPOINT: &str = "http://localhost:4318";
+
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Cumulative temporality of the event counter
+const AGGREGATION_TEMPORALITY_CUMULATIVE: f64 = 2.0;
+
+/// Exports the readings and events of a [`Monitor`] as OpenTelemetry metrics
+///
+/// Each [`push`](Self::push) polls the monitor once and posts an OTLP/HTTP
+/// request with JSON encoding to `<endpoint>/v1/metrics`:
+///
+/// | Metric                    | Type          | Unit      |
+/// |---------------------------|---------------|-----------|
+/// | `ups.up`                  | Gauge         | `1`       |
+/// | `ups.input.voltage`       | Gauge         | `V`       |
+/// | `ups.input.fault_voltage` | Gauge         | `V`       |
+/// | `ups.input.frequency`     | Gauge         | `Hz`      |
+/// | `ups.output.voltage`      | Gauge         | `V`       |
+/// | `ups.load`                | Gauge         | `%`       |
+/// | `ups.battery.voltage`     | Gauge         | `V`       |
+/// | `ups.temperature`         | Gauge         | `Cel`     |
+/// | `ups.flag`                | Gauge, `flag` attribute | `1` |
+/// | `ups.events`              | Cumulative sum, `event` attribute | `{event}` |
+///
+/// The resource carries `service.name`, the UPS name (`ups.name`) and model
+/// (`ups.model`) read when the exporter is created, plus the attributes added
+/// with [`add_attribute`](Self::add_attribute). Only plain `http://` endpoints
+/// are supported; put a collector in front of TLS backends.
+pub struct OtelExporter<T: Transport> {
+    monitor: Monitor<T>,
+    events: Receiver<UpsEvent>,
+    url: Url,
+    headers: Vec<(String, String)>,
+    attributes: Vec<(String, String)>,
+    started: SystemTime,
+    /// Events seen so far, by name
+    counts: Vec<(&'static str, u64)>,
+}
+
+impl<T: Transport> OtelExporter<T> {
+    /// Create an exporter posting to an OTLP/HTTP endpoint such as [`DEFAULT_OTLP_ENDPOINT`]
+    pub fn new(mut monitor: Monitor<T>, endpoint: &str) -> Result<Self> {
+        let base = Url::parse(endpoint.trim_end_matches('/')).map_err(UpsError::Io)?;
+        let path = format!("{}/v1/metrics", base.path.trim_end_matches('/'));
+
+        let mut attributes = vec![("service.name".to_string(), "megatec-ups".to_string())];
+        if let Ok(name) = monitor.ups().get_name() {
+            attributes.push(("ups.name".to_string(), name.trim().to_string()));
+        }
+        if let Ok(info) = monitor.ups().get_info() {
+            attributes.push(("ups.model".to_string(), info.model.trim().to_string()));
+        }
+        attributes.retain(|(_, value)| !value.is_empty());
+
+        Ok(Self {
+            events: monitor.subscribe(),
+            monitor,
+            url: Url { path, ..base },
+            headers: Vec::new(),
+            attributes,
+            started: SystemTime::now(),
+            counts: Vec::new(),
+        })
+    }
+
+    /// Add a resource attribute, such as `host.name` or `deployment.environment`
+    pub fn add_attribute<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        let key = key.into();
+        self.attributes.retain(|(known, _)| *known != key);
+        self.attributes.push((key, value.into()));
+    }
+
+    /// Add a header sent with every request, such as the API key of a hosted backend
+    pub fn add_header<K: Into<String>, V: Into<String>>(&mut self, name: K, value: V) {
+        self.headers.push((name.into(), value.into()));
+    }
+
+    /// Get a mutable reference to the monitor, to register callbacks
+    pub fn monitor(&mut self) -> &mut Monitor<T> {
+        &mut self.monitor
+    }
+
+    /// Poll the UPS and export the reading with the event counts
+    ///
+    /// Events are counted even when the export fails, so the next push
+    /// carries them.
+    pub fn push(&mut self) -> Result<()> {
+        let status = self.monitor.poll();
+        for event in self.events.try_iter() {
+            let name = event.name();
+            match self.counts.iter_mut().find(|(known, _)| *known == name) {
+                Some((_, count)) => *count += 1,
+                None => self.counts.push((name, 1)),
+            }
+        }
+        let body = self.request(SystemTime::now(), status.as_ref().ok());
+        self.export(&body.to_string())
+    }
+
+    /// Push every interval of the monitor, forever
+    ///
+    /// Failed reads are exported as `ups.up` 0 and failed exports skip the
+    /// sample; use [`push`](Self::push) in a custom loop to report them.
+    pub fn serve(&mut self) -> Result<()> {
+        loop {
+            let started = Instant::now();
+            let _ = self.push();
+            thread::sleep(self.monitor.interval().saturating_sub(started.elapsed()));
+        }
+    }
+
+    /// Build the `ExportMetricsServiceRequest` of a reading, `None` if the UPS did not answer
+    pub fn request(&self, time: SystemTime, status: Option<&UpsStatus>) -> Value {
+        let now = nanos(time);
+        let start = nanos(self.started);
+        let up = if status.is_some() { 1.0 } else { 0.0 };
+        let mut metrics = vec![gauge(
+            "ups.up",
+            "1",
+            "UPS answered the status query",
+            &[(vec![], up)],
+            &now,
+        )];
+
+        if let Some(status) = status {
+            for (name, unit, description, value) in [
+                (
+                    "ups.input.voltage",
+                    "V",
+                    "Input voltage",
+                    status.input_voltage,
+                ),
+                (
+                    "ups.input.fault_voltage",
+                    "V",
+                    "Input fault voltage",
+                    status.input_fault_voltage,
+                ),
+                (
+                    "ups.input.frequency",
+                    "Hz",
+                    "Input frequency",
+                    status.input_frequency,
+                ),
+                (
+                    "ups.output.voltage",
+                    "V",
+                    "Output voltage",
+                    status.output_voltage,
+                ),
+                ("ups.load", "%", "Load field of the status", status.load_raw),
+                (
+                    "ups.battery.voltage",
+                    "V",
+                    "Battery voltage",
+                    status.battery_voltage,
+                ),
+                ("ups.temperature", "Cel", "Temperature", status.temperature),
+            ] {
+                // Values missing from truncated replies are left out
+                if value.is_finite() {
+                    metrics.push(gauge(name, unit, description, &[(vec![], value)], &now));
+                }
+            }
+
+            let flags = &status.flags;
+            let points: Vec<(Vec<(&str, &str)>, f64)> = [
+                ("on_battery", flags.on_battery()),
+                ("battery_low", flags.battery_low),
+                ("bypass_active", flags.bypass_active),
+                ("ups_failed", flags.ups_failed),
+                ("test_in_progress", flags.test_in_progress),
+                ("shutdown_active", flags.shutdown_active),
+                ("beeper_on", flags.beeper_on),
+            ]
+            .iter()
+            .map(|(flag, set)| (vec![("flag", *flag)], if *set { 1.0 } else { 0.0 }))
+            .collect();
+            metrics.push(gauge(
+                "ups.flag",
+                "1",
+                "Status flag, 1 when set",
+                &points,
+                &now,
+            ));
+        }
+
+        if !self.counts.is_empty() {
+            let points: Vec<Value> = self
+                .counts
+                .iter()
+                .map(|(event, count)| {
+                    Value::object(vec![
+                        ("attributes", attributes(&[("event", event)])),
+                        ("startTimeUnixNano", start.as_str().into()),
+                        ("timeUnixNano", now.as_str().into()),
+                        // int64 values are strings in the JSON encoding
+                        ("asInt", count.to_string().into()),
+                    ])
+                })
+                .collect();
+            let sum = Value::object(vec![
+                ("dataPoints", Value::Array(points)),
+                (
+                    "aggregationTemporality",
+                    AGGREGATION_TEMPORALITY_CUMULATIVE.into(),
+                ),
+                ("isMonotonic", true.into()),
+            ]);
+            metrics.push(Value::object(vec![
+                ("name", "ups.events".into()),
+                ("unit", "{event}".into()),
+                ("description", "Monitor events by name".into()),
+                ("sum", sum),
+            ]));
+        }
+
+        let resource: Vec<(&str, &str)> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        let scope = Value::object(vec![
+            ("name", env!("CARGO_PKG_NAME").into()),
+            ("version", env!("CARGO_PKG_VERSION").into()),
+        ]);
+        let scope_metrics =
+            Value::object(vec![("scope", scope), ("metrics", Value::Array(metrics))]);
+        let resource_metrics = Value::object(vec![
+            (
+                "resource",
+                Value::object(vec![("attributes", attributes(&resource))]),
+            ),
+            ("scopeMetrics", Value::Array(vec![scope_metrics])),
+        ]);
+        Value::object(vec![(
+            "resourceMetrics",
+            Value::Array(vec![resource_metrics]),
+        )])
+    }
+
+    fn export(&self, body: &str) -> Result<()> {
+        let headers: Vec<(&str, String)> = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.clone()))
+            .collect();
+        let status = http::post(
+            &self.url,
+            "application/json",
+            &headers,
+            body.as_bytes(),
+            EXPORT_TIMEOUT,
+        )?;
+        if (200..300).contains(&status) {
+            Ok(())
+        } else {
+            let message = format!("OTLP endpoint replied {}", status);
+            Err(UpsError::Io(io::Error::other(message)))
+        }
+    }
+}
+
+/// Gauge metric with one data point per attribute set
+fn gauge(
+    name: &str,
+    unit: &str,
+    description: &str,
+    points: &[(Vec<(&str, &str)>, f64)],
+    time: &str,
+) -> Value {
+    let points: Vec<Value> = points
+        .iter()
+        .map(|(point_attributes, value)| {
+            let mut point = Vec::new();
+            if !point_attributes.is_empty() {
+                point.push(("attributes", attributes(point_attributes)));
+            }
+            point.push(("timeUnixNano", time.into()));
+            point.push(("asDouble", (*value).into()));
+            Value::object(point)
+        })
+        .collect();
+    Value::object(vec![
+        ("name", name.into()),
+        ("unit", unit.into()),
+        ("description", description.into()),
+        (
+            "gauge",
+            Value::object(vec![("dataPoints", Value::Array(points))]),
+        ),
+    ])
+}
+
+/// `KeyValue` list of string attributes
+fn attributes(pairs: &[(&str, &str)]) -> Value {
+    Value::Array(
+        pairs
+            .iter()
+            .map(|(key, value)| {
+                Value::object(vec![
+                    ("key", (*key).into()),
+                    (
+                        "value",
+                        Value::object(vec![("stringValue", (*value).into())]),
+                    ),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// Nanoseconds since the Unix epoch, as the string the JSON encoding uses for fixed64
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
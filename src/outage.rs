@@ -0,0 +1,90 @@
+use crate::event::UpsEvent;
+use crate::status::UpsStatus;
+use crate::timestamp;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// Counters of the power failures seen so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutageStats {
+    /// Number of transfers to battery
+    pub transfers: u64,
+    /// Cumulative time on battery of the outages that ended
+    pub time_on_battery: Duration,
+    /// Duration of the longest outage that ended
+    pub longest_outage: Duration,
+    /// Start of the last outage
+    pub last_outage: Option<SystemTime>,
+    /// Start of the outage in progress, if any
+    pub on_battery_since: Option<SystemTime>,
+}
+
+impl OutageStats {
+    /// Replay stored [`UpsEvent::PowerFailure`] and [`UpsEvent::PowerRestored`] events
+    ///
+    /// Events must be ordered oldest first. An outage without a restore event
+    /// stays in progress and is closed by the next reading on utility power.
+    pub fn from_events<'a, I>(events: I) -> Self
+    where
+        I: IntoIterator<Item = (SystemTime, &'a UpsEvent)>,
+    {
+        let mut stats = Self::default();
+        for (time, event) in events {
+            match event {
+                UpsEvent::PowerFailure(_) => stats.begin(time),
+                UpsEvent::PowerRestored(_) => stats.end(time),
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    /// Evaluate a status reading taken now
+    pub fn update(&mut self, status: &UpsStatus) {
+        self.update_at(SystemTime::now(), status)
+    }
+
+    /// Evaluate a status reading taken at `time`
+    pub fn update_at(&mut self, time: SystemTime, status: &UpsStatus) {
+        if status.flags.on_battery() {
+            self.begin(time);
+        } else {
+            self.end(time);
+        }
+    }
+
+    /// Count a transfer to battery at `time` unless an outage is in progress
+    pub fn begin(&mut self, time: SystemTime) {
+        if self.on_battery_since.is_none() {
+            self.transfers += 1;
+            self.last_outage = Some(time);
+            self.on_battery_since = Some(time);
+        }
+    }
+
+    /// End the outage in progress, if any, at `time`
+    pub fn end(&mut self, time: SystemTime) {
+        if let Some(start) = self.on_battery_since.take() {
+            let duration = time.duration_since(start).unwrap_or_default();
+            self.time_on_battery += duration;
+            self.longest_outage = self.longest_outage.max(duration);
+        }
+    }
+}
+
+/// One line per counter
+impl fmt::Display for OutageStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Transfers to Battery: {}", self.transfers)?;
+        writeln!(f, "Time on Battery: {} s", self.time_on_battery.as_secs())?;
+        writeln!(f, "Longest Outage: {} s", self.longest_outage.as_secs())?;
+        match self.last_outage {
+            Some(time) => write!(f, "Last Outage: {}", timestamp::rfc3339(time))?,
+            None => write!(f, "Last Outage: none")?,
+        }
+        if self.on_battery_since.is_some() {
+            f.write_str(" (in progress)")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,313 @@
+use crate::alarms::{Alarms, Threshold};
+use crate::error::Result;
+use crate::status::UpsStatus;
+use crate::transport::{Command, Response, Transport};
+use crate::MegatecUps;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::mem::{self, Discriminant};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Time left between the end of one command and the start of the next by default
+pub const DEFAULT_MIN_COMMAND_GAP: Duration = Duration::from_millis(200);
+
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Status reading kept by the poller
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Time the reading completed
+    pub time: SystemTime,
+    pub status: UpsStatus,
+    /// Alarms raised at the time of the reading
+    pub alarms: Vec<Threshold>,
+}
+
+impl Snapshot {
+    /// Check if the UPS is on battery or an alarm is raised
+    pub fn needs_attention(&self) -> bool {
+        self.status.flags.on_battery() || self.status.flags.battery_low || !self.alarms.is_empty()
+    }
+}
+
+struct RateLimiter {
+    min_gap: Duration,
+    limits: HashMap<Discriminant<Command>, Duration>,
+    last_any: Option<Instant>,
+    last: HashMap<Discriminant<Command>, Instant>,
+}
+
+impl RateLimiter {
+    /// Time to wait before the command may be sent
+    fn wait_for(&self, kind: Discriminant<Command>) -> Duration {
+        let since = |last: Option<&Instant>, gap: Duration| {
+            last.map_or(Duration::ZERO, |last| gap.saturating_sub(last.elapsed()))
+        };
+        let any = since(self.last_any.as_ref(), self.min_gap);
+        let own = match self.limits.get(&kind) {
+            Some(gap) => since(self.last.get(&kind), *gap),
+            None => Duration::ZERO,
+        };
+        any.max(own)
+    }
+
+    fn sent(&mut self, kind: Discriminant<Command>) {
+        let now = Instant::now();
+        self.last_any = Some(now);
+        self.last.insert(kind, now);
+    }
+}
+
+struct Shared<T: Transport> {
+    ups: MegatecUps<T>,
+    limiter: Mutex<RateLimiter>,
+    alarms: Mutex<Alarms>,
+    latest: Mutex<Option<Arc<Snapshot>>>,
+}
+
+impl<T: Transport> Shared<T> {
+    fn limiter(&self) -> MutexGuard<'_, RateLimiter> {
+        self.limiter.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// Run an operation once the rate limits of `command` allow it
+    fn limited<R>(&self, command: Command, operation: impl FnOnce() -> Result<R>) -> Result<R> {
+        let kind = mem::discriminant(&command);
+        let mut limiter = self.limiter();
+        thread::sleep(limiter.wait_for(kind));
+        let result = operation();
+        limiter.sent(kind);
+        result
+    }
+
+    fn poll(&self) -> Result<Arc<Snapshot>> {
+        let status = self.limited(Command::Status, || self.ups.get_status())?;
+        let alarms = {
+            let mut alarms = self.alarms.lock().unwrap_or_else(|p| p.into_inner());
+            alarms.update(&status);
+            alarms.active().copied().collect()
+        };
+        let snapshot = Arc::new(Snapshot {
+            time: SystemTime::now(),
+            status,
+            alarms,
+        });
+        *self.latest.lock().unwrap_or_else(|p| p.into_inner()) = Some(Arc::clone(&snapshot));
+        Ok(snapshot)
+    }
+
+    fn latest(&self) -> Option<Arc<Snapshot>> {
+        self.latest
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .clone()
+    }
+}
+
+/// Reads the status on an interval and keeps the latest snapshot
+///
+/// Commands issued through the poller are rate limited: at least
+/// [`DEFAULT_MIN_COMMAND_GAP`] is left between two of them, since some units
+/// get confused by back-to-back commands, and [`set_rate_limit`](Self::set_rate_limit)
+/// adds a minimum interval for one kind of command. A random delay of up to
+/// the jitter is added to every interval so several pollers do not hit a
+/// shared bus at the same time.
+///
+/// With a [fast interval](Self::set_fast_interval) the poller samples more
+/// often while the UPS is on battery or an alarm is raised, and goes back to
+/// the normal interval once everything is nominal again.
+pub struct Poller<T: Transport> {
+    shared: Arc<Shared<T>>,
+    interval: Duration,
+    fast_interval: Option<Duration>,
+    jitter: Duration,
+}
+
+impl<T: Transport> Poller<T> {
+    /// Create a poller reading the status every `interval`
+    pub fn new(ups: MegatecUps<T>, interval: Duration) -> Self {
+        let limiter = RateLimiter {
+            min_gap: DEFAULT_MIN_COMMAND_GAP,
+            limits: HashMap::new(),
+            last_any: None,
+            last: HashMap::new(),
+        };
+        Self {
+            shared: Arc::new(Shared {
+                ups,
+                limiter: Mutex::new(limiter),
+                alarms: Mutex::new(Alarms::new()),
+                latest: Mutex::new(None),
+            }),
+            interval,
+            fast_interval: None,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    /// Get a reference to the polled UPS
+    ///
+    /// Commands sent directly on it bypass the rate limits.
+    pub fn ups(&self) -> &MegatecUps<T> {
+        &self.shared.ups
+    }
+
+    /// Get the polling interval
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Set the interval used while the UPS is on battery or an alarm is raised
+    pub fn set_fast_interval(&mut self, fast_interval: Duration) {
+        self.fast_interval = Some(fast_interval);
+    }
+
+    /// Add an alarm evaluated on every reading, polled faster while raised
+    pub fn add_alarm(&mut self, threshold: Threshold) {
+        self.shared
+            .alarms
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .add(threshold);
+    }
+
+    /// Get the interval until the next reading, given the latest one
+    pub fn current_interval(&self) -> Duration {
+        match (self.fast_interval, self.latest()) {
+            (Some(fast_interval), Some(latest)) if latest.needs_attention() => fast_interval,
+            _ => self.interval,
+        }
+    }
+
+    /// Set the largest random delay added to every interval
+    pub fn set_jitter(&mut self, jitter: Duration) {
+        self.jitter = jitter;
+    }
+
+    /// Set the time left between any two commands
+    pub fn set_min_command_gap(&mut self, gap: Duration) {
+        self.shared.limiter().min_gap = gap;
+    }
+
+    /// Send a kind of command at most once per `min_interval`
+    ///
+    /// Commands with a parameter share one limit, `TestWithTime(1)` and
+    /// `TestWithTime(5)` alike.
+    pub fn set_rate_limit(&mut self, command: Command, min_interval: Duration) {
+        let kind = mem::discriminant(&command);
+        self.shared.limiter().limits.insert(kind, min_interval);
+    }
+
+    /// Get the latest successful reading, `None` before the first one
+    pub fn latest(&self) -> Option<Arc<Snapshot>> {
+        self.shared.latest()
+    }
+
+    /// Read the status once, waiting for the rate limits first
+    pub fn poll(&self) -> Result<Arc<Snapshot>> {
+        self.shared.poll()
+    }
+
+    /// Send a command, waiting for the rate limits first
+    pub fn command(&self, command: Command) -> Result<Response> {
+        self.shared
+            .limited(command, || self.shared.ups.send(command))
+    }
+
+    /// Poll until `running` is cleared
+    pub fn run(&self, running: &AtomicBool) {
+        while running.load(Ordering::Relaxed) {
+            let started = Instant::now();
+            let _ = self.poll();
+
+            let interval = self.current_interval() + random_delay(self.jitter);
+            while running.load(Ordering::Relaxed) {
+                let remaining = interval.saturating_sub(started.elapsed());
+                if remaining.is_zero() {
+                    break;
+                }
+                thread::sleep(remaining.min(STOP_CHECK_INTERVAL));
+            }
+        }
+    }
+}
+
+impl<T: Transport + Send + 'static> Poller<T> {
+    /// Poll on a background thread
+    pub fn spawn(self) -> PollerHandle<T> {
+        let running = Arc::new(AtomicBool::new(true));
+        let shared = Arc::clone(&self.shared);
+        let thread = {
+            let running = Arc::clone(&running);
+            thread::spawn(move || {
+                self.run(&running);
+                self
+            })
+        };
+
+        PollerHandle {
+            shared,
+            running,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Handle to a poller running on a background thread
+pub struct PollerHandle<T: Transport> {
+    shared: Arc<Shared<T>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Poller<T>>>,
+}
+
+impl<T: Transport> PollerHandle<T> {
+    /// Get the latest successful reading, `None` before the first one
+    pub fn latest(&self) -> Option<Arc<Snapshot>> {
+        self.shared.latest()
+    }
+
+    /// Get a reference to the polled UPS
+    pub fn ups(&self) -> &MegatecUps<T> {
+        &self.shared.ups
+    }
+
+    /// Send a command between two polls, honoring the rate limits
+    pub fn command(&self, command: Command) -> Result<Response> {
+        self.shared
+            .limited(command, || self.shared.ups.send(command))
+    }
+
+    /// Stop the poller and get it back
+    pub fn stop(mut self) -> Option<Poller<T>> {
+        self.running.store(false, Ordering::Relaxed);
+        self.thread.take().and_then(|thread| thread.join().ok())
+    }
+}
+
+impl<T: Transport> Drop for PollerHandle<T> {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Random delay from zero up to `max`
+fn random_delay(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    max.mul_f64((hasher.finish() >> 11) as f64 / (1u64 << 53) as f64)
+}
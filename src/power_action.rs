@@ -0,0 +1,177 @@
+use crate::error::{Result, UpsError};
+use crate::status::UpsStatus;
+use crate::transport::Transport;
+use crate::MegatecUps;
+use std::fmt;
+use std::io;
+use std::process::Command as Process;
+use std::time::{Duration, Instant};
+
+/// Delay before the UPS turns its output off after the OS shutdown started
+pub const DEFAULT_UPS_SHUTDOWN_DELAY_MINUTES: f32 = 2.0;
+
+/// When and how the computer is shut down while running on battery
+#[derive(Debug, Clone, PartialEq)]
+pub struct PowerPolicy {
+    /// Shut down as soon as the UPS reports a low battery
+    pub on_low_battery: bool,
+    /// Shut down once the UPS has been on battery for this long
+    pub on_battery_for: Option<Duration>,
+    /// Program and arguments that shut the OS down
+    pub command: Vec<String>,
+    /// Turn the UPS off after the OS shutdown started, and back on once mains returns
+    pub ups_shutdown: Option<UpsShutdown>,
+}
+
+/// Shutdown-and-restore command sent to the UPS after the OS shutdown started
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpsShutdown {
+    /// Minutes before the UPS turns its output off, long enough for the OS to halt
+    pub delay_minutes: f32,
+    /// Minutes the UPS waits after mains returns before turning the output back on
+    pub restore_minutes: u16,
+}
+
+impl Default for PowerPolicy {
+    fn default() -> Self {
+        Self {
+            on_low_battery: true,
+            on_battery_for: None,
+            command: default_shutdown_command(),
+            ups_shutdown: None,
+        }
+    }
+}
+
+/// Why the shutdown was started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownReason {
+    LowBattery,
+    OnBatteryFor(Duration),
+}
+
+impl fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShutdownReason::LowBattery => f.write_str("battery low"),
+            ShutdownReason::OnBatteryFor(time) => {
+                write!(f, "on battery for {} s", time.as_secs())
+            }
+        }
+    }
+}
+
+/// Command that halts and powers off the computer on the current OS
+pub fn default_shutdown_command() -> Vec<String> {
+    let command: &[&str] = if cfg!(windows) {
+        &["shutdown", "/s", "/t", "0"]
+    } else {
+        &["shutdown", "-h", "now"]
+    };
+    command.iter().map(|arg| arg.to_string()).collect()
+}
+
+/// Shuts the OS down according to a [`PowerPolicy`]
+///
+/// Feed every successful status reading to [`check`](Self::check). The
+/// shutdown is started at most once.
+#[derive(Debug)]
+pub struct PowerAction {
+    policy: PowerPolicy,
+    on_battery_since: Option<Instant>,
+    triggered: bool,
+}
+
+impl PowerAction {
+    /// Create a power action following `policy`
+    pub fn new(policy: PowerPolicy) -> Self {
+        Self {
+            policy,
+            on_battery_since: None,
+            triggered: false,
+        }
+    }
+
+    /// Get the policy
+    pub fn policy(&self) -> &PowerPolicy {
+        &self.policy
+    }
+
+    /// Check if the shutdown was already started
+    pub fn is_triggered(&self) -> bool {
+        self.triggered
+    }
+
+    /// Decide from a status reading whether the shutdown is due, without running it
+    pub fn update(&mut self, status: &UpsStatus) -> Option<ShutdownReason> {
+        if !status.flags.on_battery() {
+            self.on_battery_since = None;
+            return None;
+        }
+        let since = *self.on_battery_since.get_or_insert_with(Instant::now);
+
+        if self.triggered {
+            return None;
+        }
+        let reason = if self.policy.on_low_battery && status.flags.battery_low {
+            ShutdownReason::LowBattery
+        } else {
+            match self.policy.on_battery_for {
+                Some(limit) if since.elapsed() >= limit => {
+                    ShutdownReason::OnBatteryFor(since.elapsed())
+                }
+                _ => return None,
+            }
+        };
+
+        self.triggered = true;
+        Some(reason)
+    }
+
+    /// Update from a status reading and run the shutdown when it is due
+    pub fn check<T: Transport>(
+        &mut self,
+        ups: &MegatecUps<T>,
+        status: &UpsStatus,
+    ) -> Result<Option<ShutdownReason>> {
+        match self.update(status) {
+            Some(reason) => {
+                self.execute(ups)?;
+                Ok(Some(reason))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Start the OS shutdown, then send the UPS its shutdown-and-restore command
+    ///
+    /// The UPS command is sent even when the OS command fails, so the battery
+    /// is not drained completely; the OS error is returned afterwards.
+    pub fn execute<T: Transport>(&self, ups: &MegatecUps<T>) -> Result<()> {
+        let os_result = run_command(&self.policy.command);
+        if let Some(shutdown) = self.policy.ups_shutdown {
+            ups.shutdown_and_restore(shutdown.delay_minutes, shutdown.restore_minutes)?;
+        }
+        os_result
+    }
+}
+
+/// Run the shutdown command and wait until it returns
+pub(crate) fn run_command(command: &[String]) -> Result<()> {
+    let (program, args) = command.split_first().ok_or_else(|| {
+        UpsError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "empty shutdown command",
+        ))
+    })?;
+    let status = Process::new(program)
+        .args(args)
+        .status()
+        .map_err(UpsError::Io)?;
+    if status.success() {
+        Ok(())
+    } else {
+        let message = format!("{} exited with {}", program, status);
+        Err(UpsError::Io(io::Error::other(message)))
+    }
+}
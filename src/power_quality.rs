@@ -0,0 +1,269 @@
+use crate::status::UpsStatus;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// Tolerance of the mains voltage around its nominal value, as in EN 50160
+pub const DEFAULT_VOLTAGE_TOLERANCE: f64 = 0.10;
+
+/// Input voltage range considered normal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VoltageBand {
+    /// Voltages below this are a sag
+    pub low: f64,
+    /// Voltages above this are a swell
+    pub high: f64,
+}
+
+impl VoltageBand {
+    /// Band between `low` and `high` volts
+    pub fn new(low: f64, high: f64) -> Self {
+        Self { low, high }
+    }
+
+    /// Band of [`DEFAULT_VOLTAGE_TOLERANCE`] around a nominal voltage such as 230 V
+    pub fn for_nominal(nominal: f64) -> Self {
+        Self::with_tolerance(nominal, DEFAULT_VOLTAGE_TOLERANCE)
+    }
+
+    /// Band of `tolerance` (0.1 for ±10%) around a nominal voltage
+    pub fn with_tolerance(nominal: f64, tolerance: f64) -> Self {
+        Self::new(nominal * (1.0 - tolerance), nominal * (1.0 + tolerance))
+    }
+
+    /// Check if a voltage is within the band
+    pub fn contains(&self, voltage: f64) -> bool {
+        (self.low..=self.high).contains(&voltage)
+    }
+}
+
+/// Direction of an input voltage excursion
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExcursionKind {
+    /// The voltage fell below the band (brownout)
+    Sag,
+    /// The voltage rose above the band
+    Swell,
+}
+
+impl fmt::Display for ExcursionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExcursionKind::Sag => "sag",
+            ExcursionKind::Swell => "swell",
+        })
+    }
+}
+
+/// One stretch of samples with the input voltage outside the band
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Excursion {
+    pub kind: ExcursionKind,
+    /// Time of the first sample outside the band
+    pub start: SystemTime,
+    /// Time between the first sample outside the band and the first one back
+    pub duration: Duration,
+    /// Lowest voltage of a sag or highest voltage of a swell
+    pub extreme_voltage: f64,
+    /// Distance of the extreme voltage from the band in volts
+    pub depth: f64,
+}
+
+/// Kind, extreme voltage and duration, such as `sag to 196.0 V (-11.0 V) for 12 s`
+impl fmt::Display for Excursion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = match self.kind {
+            ExcursionKind::Sag => '-',
+            ExcursionKind::Swell => '+',
+        };
+        write!(
+            f,
+            "{} to {:.1} V ({}{:.1} V) for {} s",
+            self.kind,
+            self.extreme_voltage,
+            sign,
+            self.depth,
+            self.duration.as_secs()
+        )
+    }
+}
+
+/// Counters of the input voltage excursions seen so far
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PowerQualityStats {
+    pub sags: u64,
+    pub swells: u64,
+    /// Total time below the band
+    pub sag_time: Duration,
+    /// Total time above the band
+    pub swell_time: Duration,
+    /// Duration of the longest sag
+    pub longest_sag: Duration,
+    /// Duration of the longest swell
+    pub longest_swell: Duration,
+    /// Lowest voltage of all sags
+    pub lowest_voltage: Option<f64>,
+    /// Highest voltage of all swells
+    pub highest_voltage: Option<f64>,
+    /// The excursion that ended last
+    pub last: Option<Excursion>,
+}
+
+impl PowerQualityStats {
+    fn record(&mut self, excursion: Excursion) {
+        match excursion.kind {
+            ExcursionKind::Sag => {
+                self.sags += 1;
+                self.sag_time += excursion.duration;
+                self.longest_sag = self.longest_sag.max(excursion.duration);
+                self.lowest_voltage =
+                    Some(self.lowest_voltage.map_or(excursion.extreme_voltage, |v| {
+                        v.min(excursion.extreme_voltage)
+                    }));
+            }
+            ExcursionKind::Swell => {
+                self.swells += 1;
+                self.swell_time += excursion.duration;
+                self.longest_swell = self.longest_swell.max(excursion.duration);
+                self.highest_voltage =
+                    Some(self.highest_voltage.map_or(excursion.extreme_voltage, |v| {
+                        v.max(excursion.extreme_voltage)
+                    }));
+            }
+        }
+        self.last = Some(excursion);
+    }
+}
+
+/// One line per counter
+impl fmt::Display for PowerQualityStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Sags: {} ({} s total, longest {} s)",
+            self.sags,
+            self.sag_time.as_secs(),
+            self.longest_sag.as_secs()
+        )?;
+        writeln!(
+            f,
+            "Swells: {} ({} s total, longest {} s)",
+            self.swells,
+            self.swell_time.as_secs(),
+            self.longest_swell.as_secs()
+        )?;
+        match self.lowest_voltage {
+            Some(voltage) => writeln!(f, "Lowest Voltage: {:.1} V", voltage)?,
+            None => writeln!(f, "Lowest Voltage: none")?,
+        }
+        match self.highest_voltage {
+            Some(voltage) => writeln!(f, "Highest Voltage: {:.1} V", voltage)?,
+            None => writeln!(f, "Highest Voltage: none")?,
+        }
+        match &self.last {
+            Some(excursion) => write!(f, "Last: {}", excursion),
+            None => write!(f, "Last: none"),
+        }
+    }
+}
+
+/// Tracks input voltage sags and swells against a [`VoltageBand`]
+///
+/// Feed every status reading to [`update`](Self::update). Readings on battery
+/// are outages rather than sags: they end a running excursion without
+/// counting it, and readings lacking the input voltage are skipped.
+#[derive(Debug, Clone)]
+pub struct PowerQuality {
+    band: VoltageBand,
+    stats: PowerQualityStats,
+    current: Option<Excursion>,
+}
+
+impl PowerQuality {
+    /// Track excursions outside `band`
+    pub fn new(band: VoltageBand) -> Self {
+        Self {
+            band,
+            stats: PowerQualityStats::default(),
+            current: None,
+        }
+    }
+
+    /// Get the band
+    pub fn band(&self) -> VoltageBand {
+        self.band
+    }
+
+    /// Get the counters of the excursions that ended
+    pub fn stats(&self) -> &PowerQualityStats {
+        &self.stats
+    }
+
+    /// Get the excursion in progress, if any
+    pub fn current(&self) -> Option<&Excursion> {
+        self.current.as_ref()
+    }
+
+    /// Reset the counters
+    pub fn reset(&mut self) {
+        self.stats = PowerQualityStats::default();
+        self.current = None;
+    }
+
+    /// Evaluate a status reading taken now, returning the excursion it ended
+    pub fn update(&mut self, status: &UpsStatus) -> Option<Excursion> {
+        self.update_at(SystemTime::now(), status)
+    }
+
+    /// Evaluate a status reading taken at `time`, returning the excursion it ended
+    pub fn update_at(&mut self, time: SystemTime, status: &UpsStatus) -> Option<Excursion> {
+        if status.flags.on_battery() {
+            self.current = None;
+            return None;
+        }
+        let voltage = status.input_voltage;
+        if !voltage.is_finite() {
+            return None;
+        }
+
+        let kind = if voltage < self.band.low {
+            Some(ExcursionKind::Sag)
+        } else if voltage > self.band.high {
+            Some(ExcursionKind::Swell)
+        } else {
+            None
+        };
+
+        match (&mut self.current, kind) {
+            (Some(current), Some(kind)) if current.kind == kind => {
+                current.duration = time.duration_since(current.start).unwrap_or_default();
+                let depth = match kind {
+                    ExcursionKind::Sag => self.band.low - voltage,
+                    ExcursionKind::Swell => voltage - self.band.high,
+                };
+                if depth > current.depth {
+                    current.depth = depth;
+                    current.extreme_voltage = voltage;
+                }
+                None
+            }
+            (_, kind) => {
+                let ended = self.current.take().map(|mut excursion| {
+                    excursion.duration = time.duration_since(excursion.start).unwrap_or_default();
+                    self.stats.record(excursion);
+                    excursion
+                });
+                self.current = kind.map(|kind| Excursion {
+                    kind,
+                    start: time,
+                    duration: Duration::ZERO,
+                    extreme_voltage: voltage,
+                    depth: match kind {
+                        ExcursionKind::Sag => self.band.low - voltage,
+                        ExcursionKind::Swell => voltage - self.band.high,
+                    },
+                });
+                ended
+            }
+        }
+    }
+}
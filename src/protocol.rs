@@ -0,0 +1,534 @@
+//! Transport-free Megatec protocol encoding and parsing
+//!
+//! Everything here works on plain strings and bytes, so gateways speaking to
+//! the UPS over their own link can reuse the parsers without opening a USB
+//! device. The crate itself still depends on `std` and `rusb`.
+
+use crate::extended::ExtendedStatus;
+use crate::info::UpsInfo;
+use crate::mode::UpsMode;
+use crate::rating::RatingInfo;
+use crate::status::{StatusFlags, UpsStatus};
+use crate::transport::{Command, MAX_RESTORE_MINUTES};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+const ASCII_MIN: char = ' ';
+const ASCII_MAX: char = '~';
+const CHAR_QUOTE: char = '"';
+const CHAR_BACKTICK: char = '`';
+const CHAR_PAREN: char = '(';
+const CHAR_HASH: char = '#';
+const STATUS_VALUES: usize = 7;
+const RATING_VALUES: usize = 4;
+const EXTENDED_VALUES: usize = 16;
+const GENERAL_VALUES: usize = 11;
+const GENERAL_BITS: usize = 12;
+const COMPANY_WIDTH: usize = 15;
+const MODEL_WIDTH: usize = 10;
+const VERSION_WIDTH: usize = 10;
+
+/// Errors of the protocol layer, converted into [`UpsError`](crate::UpsError) by the transports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// The answer does not follow the protocol
+    InvalidResponse,
+    /// A time value is out of range for the command
+    InvalidTime,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ProtocolError::InvalidResponse => "Invalid response",
+            ProtocolError::InvalidTime => "Invalid time value",
+        })
+    }
+}
+
+/// Deviations from the Q1 answer format accepted by [`parse_status`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatusFormat {
+    /// Values may use a comma as decimal separator ("229,5")
+    pub comma_decimal: bool,
+    /// Trailing fields may be missing, reported as NaN (flags as cleared)
+    pub truncated: bool,
+    /// The leading '(' may still be present
+    pub leading_paren: bool,
+}
+
+impl StatusFormat {
+    /// Exactly the format defined by the protocol
+    pub const STRICT: Self = Self {
+        comma_decimal: false,
+        truncated: false,
+        leading_paren: false,
+    };
+
+    /// The formatting deviations seen in firmware clones
+    ///
+    /// Missing fields are still rejected, as a reply cut short by a transfer
+    /// error would otherwise read as a UPS on line with cleared flags. Devices
+    /// that really answer that way set `truncated` on top.
+    pub const TOLERANT: Self = Self {
+        comma_decimal: true,
+        truncated: false,
+        leading_paren: true,
+    };
+}
+
+/// Parse a Q1 answer such as `230.0 230.0 230.0 020 50.0 13.6 25.0 00001001`
+pub fn parse_status(status: &str, format: StatusFormat) -> Result<UpsStatus, ProtocolError> {
+    let status = match status.trim_start().strip_prefix(CHAR_PAREN) {
+        Some(rest) if format.leading_paren => rest,
+        _ => status,
+    };
+    let status = if format.comma_decimal {
+        status.replace(',', ".")
+    } else {
+        status.to_string()
+    };
+
+    let mut fields = status.split_whitespace();
+    let mut values: Vec<f64> = fields
+        .by_ref()
+        .take(STATUS_VALUES)
+        .map(|s| s.parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| ProtocolError::InvalidResponse)?;
+
+    if values.len() != STATUS_VALUES {
+        if !format.truncated || values.is_empty() {
+            return Err(ProtocolError::InvalidResponse);
+        }
+        values.resize(STATUS_VALUES, f64::NAN);
+    }
+
+    let flags = match fields.next() {
+        Some(bits) => parse_flags(bits)?,
+        None if format.truncated => StatusFlags::default(),
+        None => return Err(ProtocolError::InvalidResponse),
+    };
+
+    #[allow(deprecated)]
+    Ok(UpsStatus {
+        input_voltage: values[0],
+        input_fault_voltage: values[1],
+        output_voltage: values[2],
+        load_raw: values[3],
+        output_current: values[3],
+        load_in_amps: false,
+        input_frequency: values[4],
+        battery_voltage: values[5],
+        temperature: values[6],
+        flags,
+    })
+}
+
+/// Parse the 8-character bit string (b7 first) of a Q1 answer
+pub fn parse_flags(bits: &str) -> Result<StatusFlags, ProtocolError> {
+    let bits: Vec<bool> = bits
+        .chars()
+        .map(|c| match c {
+            '0' => Ok(false),
+            '1' => Ok(true),
+            _ => Err(ProtocolError::InvalidResponse),
+        })
+        .collect::<Result<Vec<bool>, _>>()?;
+
+    if bits.len() != 8 {
+        return Err(ProtocolError::InvalidResponse);
+    }
+
+    Ok(StatusFlags {
+        utility_fail: bits[0],
+        battery_low: bits[1],
+        bypass_active: bits[2],
+        ups_failed: bits[3],
+        standby: bits[4],
+        test_in_progress: bits[5],
+        shutdown_active: bits[6],
+        beeper_on: bits[7],
+    })
+}
+
+/// Parse a QGS answer of Voltronic UPSes into the Q1 values
+///
+/// Unavailable values (`---.-`) are reported as NaN. The status bits run
+/// `b9..b0 a0 a1`, with the UPS type in b9 and b8 (00 for standby).
+pub fn parse_general_status(status: &str) -> Result<UpsStatus, ProtocolError> {
+    let status = status.trim_start();
+    let status = status.strip_prefix(CHAR_PAREN).unwrap_or(status);
+
+    let mut fields = status.split_whitespace();
+    let values: Vec<f64> = fields
+        .by_ref()
+        .take(GENERAL_VALUES)
+        .map(|s| match s.parse::<f64>() {
+            Ok(value) => Ok(value),
+            Err(_) if s.chars().all(|c| c == '-' || c == '.') => Ok(f64::NAN),
+            Err(_) => Err(ProtocolError::InvalidResponse),
+        })
+        .collect::<Result<Vec<f64>, _>>()?;
+
+    if values.len() != GENERAL_VALUES {
+        return Err(ProtocolError::InvalidResponse);
+    }
+
+    let bits = fields.next().ok_or(ProtocolError::InvalidResponse)?;
+    if bits.len() != GENERAL_BITS || !bits.chars().all(|c| c == '0' || c == '1') {
+        return Err(ProtocolError::InvalidResponse);
+    }
+    let bit = |index: usize| bits.as_bytes()[9 - index] == b'1';
+
+    #[allow(deprecated)]
+    Ok(UpsStatus {
+        input_voltage: values[0],
+        input_fault_voltage: values[0],
+        output_voltage: values[2],
+        load_raw: values[5],
+        output_current: values[5],
+        load_in_amps: false,
+        input_frequency: values[1],
+        battery_voltage: values[8],
+        temperature: values[10],
+        flags: StatusFlags {
+            utility_fail: bit(7),
+            battery_low: bit(6),
+            bypass_active: bit(5),
+            ups_failed: bit(4),
+            standby: !bit(9) && !bit(8),
+            test_in_progress: bit(2),
+            shutdown_active: bit(1),
+            // b0 reports the beeper silenced
+            beeper_on: !bit(0),
+        },
+    })
+}
+
+/// Parse a QPIGS answer of Voltronic/Axpert devices
+///
+/// The 16 values are followed by the `b7..b0` device status bits; newer
+/// firmware appends more fields, of which only the PV charging power is kept.
+pub fn parse_extended_status(status: &str) -> Result<ExtendedStatus, ProtocolError> {
+    let status = status.trim_start();
+    let status = status.strip_prefix(CHAR_PAREN).unwrap_or(status);
+
+    let mut fields = status.split_whitespace();
+    let values: Vec<f64> = fields
+        .by_ref()
+        .take(EXTENDED_VALUES)
+        .map(|s| s.parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| ProtocolError::InvalidResponse)?;
+
+    if values.len() != EXTENDED_VALUES {
+        return Err(ProtocolError::InvalidResponse);
+    }
+
+    let bits = fields.next().ok_or(ProtocolError::InvalidResponse)?;
+    if bits.len() != 8 || !bits.chars().all(|c| c == '0' || c == '1') {
+        return Err(ProtocolError::InvalidResponse);
+    }
+    let bit = |index: usize| bits.as_bytes()[7 - index] == b'1';
+
+    // Battery voltage offset and EEPROM version come before the PV charging power
+    let pv_charging_power = fields.nth(2).and_then(|s| s.parse::<f64>().ok());
+
+    Ok(ExtendedStatus {
+        grid_voltage: values[0],
+        grid_frequency: values[1],
+        output_voltage: values[2],
+        output_frequency: values[3],
+        output_apparent_power: values[4],
+        output_active_power: values[5],
+        load_percent: values[6],
+        bus_voltage: values[7],
+        battery_voltage: values[8],
+        battery_charging_current: values[9],
+        battery_capacity: values[10],
+        heat_sink_temperature: values[11],
+        pv_input_current: values[12],
+        pv_input_voltage: values[13],
+        scc_battery_voltage: values[14],
+        battery_discharge_current: values[15],
+        pv_charging_power,
+        load_on: bit(4),
+        charging: bit(2),
+        scc_charging: bit(1),
+        ac_charging: bit(0),
+    })
+}
+
+/// Parse a QMOD answer such as `(L`
+///
+/// UPS and inverter firmware use different letters; the battery test (T)
+/// counts as battery mode, converter mode (C) as line mode and power on (P)
+/// and shutdown (D) as standby.
+pub fn parse_mode(mode: &str) -> Result<UpsMode, ProtocolError> {
+    let mode = mode.trim();
+    let mode = mode.strip_prefix(CHAR_PAREN).unwrap_or(mode);
+
+    match mode {
+        "L" | "C" => Ok(UpsMode::Line),
+        "B" | "T" => Ok(UpsMode::Battery),
+        "Y" => Ok(UpsMode::Bypass),
+        "E" | "H" => Ok(UpsMode::Eco),
+        "F" => Ok(UpsMode::Fault),
+        "S" | "P" | "D" => Ok(UpsMode::Standby),
+        _ => Err(ProtocolError::InvalidResponse),
+    }
+}
+
+/// Parse an F answer such as `#230.0 004 12.00 50.0`
+pub fn parse_rating(rating: &str) -> Result<RatingInfo, ProtocolError> {
+    let values: Vec<f64> = rating
+        .trim_start_matches(CHAR_HASH)
+        .split_whitespace()
+        .map(|s| s.parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| ProtocolError::InvalidResponse)?;
+
+    if values.len() != RATING_VALUES {
+        return Err(ProtocolError::InvalidResponse);
+    }
+
+    Ok(RatingInfo {
+        rated_voltage: values[0],
+        rated_current: values[1],
+        battery_voltage: values[2],
+        rated_frequency: values[3],
+    })
+}
+
+/// Parse the fixed-width I answer (company, model and firmware version)
+pub fn parse_info(info: &str) -> Result<UpsInfo, ProtocolError> {
+    let chars: Vec<char> = info.trim_start_matches(CHAR_HASH).chars().collect();
+    let model_start = COMPANY_WIDTH + 1;
+    let version_start = model_start + MODEL_WIDTH + 1;
+
+    if chars.len() < version_start + 1 {
+        return Err(ProtocolError::InvalidResponse);
+    }
+
+    let field = |start: usize, width: usize| -> String {
+        let end = (start + width).min(chars.len());
+        chars[start..end]
+            .iter()
+            .collect::<String>()
+            .trim()
+            .to_string()
+    };
+
+    Ok(UpsInfo {
+        company: field(0, COMPANY_WIDTH),
+        model: field(model_start, MODEL_WIDTH),
+        firmware_version: field(version_start, VERSION_WIDTH),
+    })
+}
+
+/// Map a command to the text sent over serial-style links, without the carriage return
+pub fn command_text(command: Command) -> Result<String, ProtocolError> {
+    let text = match command {
+        Command::Name => "I".to_string(),
+        Command::Status => "Q1".to_string(),
+        Command::Test => "T".to_string(),
+        Command::TestUntilBatteryLow => "TL".to_string(),
+        Command::TestWithTime(minutes) => {
+            if minutes == 0 || minutes > 99 {
+                return Err(ProtocolError::InvalidTime);
+            }
+            format!("T{:02}", minutes)
+        }
+        Command::ToggleBeep => "Q".to_string(),
+        Command::AbortTest => "CT".to_string(),
+        Command::CancelShutdown => "C".to_string(),
+        Command::Rating => "F".to_string(),
+        Command::Info => "I".to_string(),
+        Command::ExtendedStatus => "QPIGS".to_string(),
+        Command::Mode => "QMOD".to_string(),
+        Command::StatusQs => "QS".to_string(),
+        Command::GeneralStatus => "QGS".to_string(),
+        Command::Shutdown(delay) => format!("S{}", delay.to_protocol_string()),
+        Command::ShutdownAndRestore(delay, restore_minutes) => {
+            if restore_minutes > MAX_RESTORE_MINUTES {
+                return Err(ProtocolError::InvalidTime);
+            }
+            format!("S{}R{:04}", delay.to_protocol_string(), restore_minutes)
+        }
+    };
+
+    Ok(text)
+}
+
+/// Check whether the UPS answers the given command on serial-style links
+pub fn expects_response(command: Command) -> bool {
+    command.is_query()
+}
+
+/// Encode a test duration as the descriptor length of the Mega(USB) T<n> request
+pub fn usb_test_time(minutes: u8) -> Result<u16, ProtocolError> {
+    if minutes == 0 || minutes > 99 {
+        return Err(ProtocolError::InvalidTime);
+    }
+
+    let value = match minutes {
+        1..=9 => 100 + minutes,
+        10..=19 => 125 + (minutes - 10),
+        20..=99 => {
+            let range_start = ((minutes - 20) / 10) * 10 + 20;
+            132 + ((minutes - range_start) * 7)
+        }
+        _ => return Err(ProtocolError::InvalidTime),
+    };
+
+    Ok(value as u16)
+}
+
+/// Decode raw response bytes, keeping only the characters that are valid according to protocol rules
+pub fn decode_response(data: &[u8]) -> String {
+    filter_response(data.iter().map(|&c| c as char))
+}
+
+/// Keep only the characters that are valid according to protocol rules
+pub fn filter_response<I: IntoIterator<Item = char>>(chars: I) -> String {
+    chars.into_iter().filter(|&c| is_valid_char(c)).collect()
+}
+
+/// Check if a character is valid according to protocol rules
+fn is_valid_char(c: char) -> bool {
+    (ASCII_MIN..=ASCII_MAX).contains(&c) && c != CHAR_QUOTE && c != CHAR_BACKTICK && c != CHAR_PAREN
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANSWER: &str = "230.0 229.5 231.0 020 50.0 13.6 25.0 10001001";
+
+    #[test]
+    fn strict_parsing_reads_every_field() {
+        let status = parse_status(ANSWER, StatusFormat::STRICT).unwrap();
+        assert_eq!(status.input_voltage, 230.0);
+        assert_eq!(status.input_fault_voltage, 229.5);
+        assert_eq!(status.output_voltage, 231.0);
+        assert_eq!(status.load_raw, 20.0);
+        assert_eq!(status.input_frequency, 50.0);
+        assert_eq!(status.battery_voltage, 13.6);
+        assert_eq!(status.temperature, 25.0);
+        assert_eq!(
+            status.flags,
+            StatusFlags {
+                utility_fail: true,
+                standby: true,
+                beeper_on: true,
+                ..StatusFlags::default()
+            }
+        );
+    }
+
+    #[test]
+    fn clone_formatting_needs_the_tolerant_format() {
+        for answer in [
+            "(230.0 229.5 231.0 020 50.0 13.6 25.0 10001001",
+            "230,0 229,5 231,0 020 50,0 13,6 25,0 10001001",
+        ] {
+            assert!(parse_status(answer, StatusFormat::STRICT).is_err());
+            let status = parse_status(answer, StatusFormat::TOLERANT).unwrap();
+            assert_eq!(status.input_fault_voltage, 229.5);
+        }
+    }
+
+    #[test]
+    fn malformed_answers_are_rejected() {
+        for answer in [
+            "",
+            "   ",
+            "NAK",
+            "230.0 229.5 231.0 020 50.0 13.6 25.0",
+            "230.0 229.5 231.0 O20 50.0 13.6 25.0 10001001",
+            "230.0 229.5 231.0 020 50.0 13.6 25.0 1000100",
+            "230.0 229.5 231.0 020 50.0 13.6 25.0 100010011",
+            "230.0 229.5 231.0 020 50.0 13.6 25.0 1000100x",
+            "230.0 229.5 231.0 020 50.0 13.6 25.0 10001001"
+                .repeat(2)
+                .as_str(),
+        ] {
+            for format in [StatusFormat::STRICT, StatusFormat::TOLERANT] {
+                assert!(parse_status(answer, format).is_err(), "{:?}", answer);
+            }
+        }
+        // Even a truncated answer needs a value and well formed flags
+        let truncated = StatusFormat {
+            truncated: true,
+            ..StatusFormat::TOLERANT
+        };
+        assert!(parse_status("", truncated).is_err());
+        assert!(parse_status("230.0 229.5 231.0 020 50.0 13.6 25.0 10", truncated).is_err());
+    }
+
+    #[test]
+    fn rating_and_info_answers_are_parsed() {
+        let rating = parse_rating("#230.0 004 12.00 50.0").unwrap();
+        assert_eq!(rating.rated_current, 4.0);
+        assert_eq!(rating.battery_voltage, 12.0);
+        assert!(parse_rating("#230.0 004 12.00").is_err());
+        assert!(parse_rating("#230.0 004 12.00 50.0 1").is_err());
+        assert!(parse_rating("#230.0 OO4 12.00 50.0").is_err());
+
+        let info = parse_info("#MEGATEC         MOCK UPS   V1.0      ").unwrap();
+        assert_eq!(info.company, "MEGATEC");
+        assert_eq!(info.model, "MOCK UPS");
+        assert_eq!(info.firmware_version, "V1.0");
+        assert!(parse_info("#MEGATEC").is_err());
+        assert!(parse_info("").is_err());
+    }
+
+    #[test]
+    fn tolerant_parsing_rejects_missing_fields() {
+        let format = StatusFormat::TOLERANT;
+        assert!(matches!(
+            parse_status("230.0 230.0", format),
+            Err(ProtocolError::InvalidResponse)
+        ));
+        assert!(matches!(
+            parse_status("230.0 230.0 230.0 020 50.0 13.6 25.0", format),
+            Err(ProtocolError::InvalidResponse)
+        ));
+    }
+
+    #[test]
+    fn truncated_format_accepts_missing_fields() {
+        let format = StatusFormat {
+            truncated: true,
+            ..StatusFormat::TOLERANT
+        };
+        let status = parse_status("230.0 230.0", format).unwrap();
+        assert_eq!(status.input_voltage, 230.0);
+        assert!(status.battery_voltage.is_nan());
+        assert_eq!(status.flags, StatusFlags::default());
+    }
+
+    #[test]
+    fn usb_test_time_covers_every_duration() {
+        for minutes in 1..=99 {
+            let value = usb_test_time(minutes).unwrap();
+            let expected = match minutes {
+                1..=9 => 100 + minutes as u16,
+                10..=19 => 115 + minutes as u16,
+                _ => 132 + (minutes as u16 % 10) * 7,
+            };
+            assert_eq!(value, expected, "{} minutes", minutes);
+        }
+        assert_eq!(usb_test_time(10), Ok(125));
+        assert_eq!(usb_test_time(19), Ok(134));
+    }
+
+    #[test]
+    fn usb_test_time_rejects_out_of_range() {
+        assert_eq!(usb_test_time(0), Err(ProtocolError::InvalidTime));
+        assert_eq!(usb_test_time(100), Err(ProtocolError::InvalidTime));
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+/// Model-specific deviations from the standard protocol behavior
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// Delay between the acknowledgment and the actual status read, overriding the configured one
+    pub ack_delay: Option<Duration>,
+    /// Additional status reads issued before the actual status read
+    pub warmup_reads: u32,
+    /// Status values use a comma as decimal separator ("229,5")
+    pub comma_decimal: bool,
+    /// Status strings may lack trailing fields, which are then reported as NaN (flags as cleared)
+    pub truncated_status: bool,
+    /// The load field of the status reports the output current in amps instead of percent
+    pub load_in_amps: bool,
+    /// String descriptor index of the status query on USB, overriding the default one
+    pub status_descriptor: Option<u8>,
+    /// String descriptor index of the rating query on USB, overriding the default one
+    pub rating_descriptor: Option<u8>,
+    /// String descriptor index of the info query on USB, overriding the default one
+    pub info_descriptor: Option<u8>,
+}
+
+impl Quirks {
+    /// Standard protocol behavior
+    pub const NONE: Self = Self {
+        ack_delay: None,
+        warmup_reads: 0,
+        comma_decimal: false,
+        truncated_status: false,
+        load_in_amps: false,
+        status_descriptor: None,
+        rating_descriptor: None,
+        info_descriptor: None,
+    };
+
+    /// Look up the quirks of a device by its USB IDs and model string
+    pub fn lookup(vendor_id: u16, product_id: u16, model: Option<&str>) -> Self {
+        KNOWN_QUIRKS
+            .iter()
+            .filter(|entry| entry.matches(vendor_id, product_id, model))
+            .fold(Self::NONE, |quirks, entry| quirks.merge(&entry.quirks))
+    }
+
+    /// Combine two sets of quirks, preferring the settings of `other`
+    pub fn merge(&self, other: &Quirks) -> Self {
+        Self {
+            ack_delay: other.ack_delay.or(self.ack_delay),
+            warmup_reads: self.warmup_reads.max(other.warmup_reads),
+            comma_decimal: self.comma_decimal || other.comma_decimal,
+            truncated_status: self.truncated_status || other.truncated_status,
+            load_in_amps: self.load_in_amps || other.load_in_amps,
+            status_descriptor: other.status_descriptor.or(self.status_descriptor),
+            rating_descriptor: other.rating_descriptor.or(self.rating_descriptor),
+            info_descriptor: other.info_descriptor.or(self.info_descriptor),
+        }
+    }
+}
+
+/// Entry of the quirks registry
+#[derive(Debug, Clone, Copy)]
+pub struct QuirkEntry {
+    /// Vendor ID the entry applies to, any when None
+    pub vendor_id: Option<u16>,
+    /// Product ID the entry applies to, any when None
+    pub product_id: Option<u16>,
+    /// Case-insensitive substring of the model string the entry applies to, any when None
+    pub model: Option<&'static str>,
+    pub quirks: Quirks,
+}
+
+impl QuirkEntry {
+    /// Check if the entry applies to the given device
+    pub fn matches(&self, vendor_id: u16, product_id: u16, model: Option<&str>) -> bool {
+        let model_matches = match (self.model, model) {
+            (None, _) => true,
+            (Some(pattern), Some(model)) => model
+                .to_ascii_lowercase()
+                .contains(&pattern.to_ascii_lowercase()),
+            (Some(_), None) => false,
+        };
+
+        self.vendor_id.is_none_or(|id| id == vendor_id)
+            && self.product_id.is_none_or(|id| id == product_id)
+            && model_matches
+    }
+}
+
+/// Registry of devices known to need quirks
+pub const KNOWN_QUIRKS: &[QuirkEntry] = &[QuirkEntry {
+    vendor_id: None,
+    product_id: None,
+    model: Some("mustek"),
+    quirks: Quirks {
+        ack_delay: Some(Duration::from_secs(2)),
+        warmup_reads: 2,
+        ..Quirks::NONE
+    },
+}];
@@ -0,0 +1,37 @@
+use crate::error::Result;
+use crate::protocol;
+use std::fmt;
+
+/// Structure representing the UPS rating information
+#[derive(Debug, Clone)]
+pub struct RatingInfo {
+    pub rated_voltage: f64,
+    pub rated_current: f64,
+    pub battery_voltage: f64,
+    pub rated_frequency: f64,
+}
+
+impl RatingInfo {
+    /// Parse rating string into RatingInfo struct
+    pub(crate) fn from_str(rating: &str) -> Result<Self> {
+        Ok(protocol::parse_rating(rating)?)
+    }
+}
+
+/// Compact one-line summary, or one line per value with the alternate flag (`{:#}`)
+impl fmt::Display for RatingInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            writeln!(f, "Rated Voltage: {:.1} V", self.rated_voltage)?;
+            writeln!(f, "Rated Current: {} A", self.rated_current)?;
+            writeln!(f, "Battery Voltage: {:.2} V", self.battery_voltage)?;
+            write!(f, "Rated Frequency: {:.1} Hz", self.rated_frequency)
+        } else {
+            write!(
+                f,
+                "{:.1}V {}A BAT {:.2}V {:.1}Hz",
+                self.rated_voltage, self.rated_current, self.battery_voltage, self.rated_frequency
+            )
+        }
+    }
+}
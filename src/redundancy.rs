@@ -0,0 +1,229 @@
+use crate::error::Result;
+use crate::manager::{DeviceStatus, UpsManager};
+use crate::power_action::{self, PowerPolicy, ShutdownReason};
+use crate::transport::Transport;
+use std::fmt;
+use std::time::Instant;
+
+/// Factor applied by default to the weight of a unit reporting a UPS failure
+pub const DEFAULT_FAILED_WEIGHT: f64 = 0.5;
+
+/// Shutdown policy for a host fed by several UPS units
+///
+/// Every reachable unit whose readings meet the conditions of `power` (low
+/// battery, or on battery for `on_battery_for`) votes for the shutdown with
+/// its weight. The vote passes once the votes reach `required`, the N of an
+/// N-of-M rule. Units count with their health: an unreachable unit has no
+/// weight and one reporting a UPS failure counts with `failed_weight`, so a
+/// single broken unit cannot shut the host down on its own. When the units
+/// left cannot reach `required`, the vote needs all of their weight instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedundancyPolicy {
+    /// Conditions a unit must meet to vote, and how the host is shut down
+    pub power: PowerPolicy,
+    /// Weighted votes needed for the shutdown
+    pub required: f64,
+    /// Weight of the units by name, 1 for the others
+    pub weights: Vec<(String, f64)>,
+    /// Factor applied to the weight of a unit reporting a UPS failure
+    pub failed_weight: f64,
+}
+
+impl RedundancyPolicy {
+    /// Shut down once `required` units of weight 1 vote for it, with the default power policy
+    pub fn new(required: f64) -> Self {
+        Self {
+            power: PowerPolicy::default(),
+            required,
+            weights: Vec::new(),
+            failed_weight: DEFAULT_FAILED_WEIGHT,
+        }
+    }
+
+    /// Give the unit added to the manager under `name` another weight than 1
+    pub fn with_weight<S: Into<String>>(mut self, name: S, weight: f64) -> Self {
+        let name = name.into();
+        self.weights.retain(|(known, _)| *known != name);
+        self.weights.push((name, weight));
+        self
+    }
+
+    /// Get the configured weight of a unit
+    pub fn weight(&self, name: &str) -> f64 {
+        self.weights
+            .iter()
+            .find(|(known, _)| known == name)
+            .map_or(1.0, |(_, weight)| *weight)
+    }
+}
+
+/// Outcome of a vote over the latest readings of the units
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuorumVote {
+    /// Weight of the units voting for the shutdown
+    pub votes: f64,
+    /// Votes needed, `required` or less when the healthy units cannot reach it
+    pub required: f64,
+    /// Units voting for the shutdown and why
+    pub units: Vec<(String, ShutdownReason)>,
+}
+
+impl QuorumVote {
+    /// Check if the vote passes
+    pub fn is_due(&self) -> bool {
+        // Weights are fractional, so equality must survive rounding
+        self.votes > 0.0 && self.votes + 1e-9 >= self.required
+    }
+}
+
+impl fmt::Display for QuorumVote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} of {} votes", self.votes, self.required)?;
+        for (index, (name, reason)) in self.units.iter().enumerate() {
+            let separator = if index == 0 { ": " } else { ", " };
+            write!(f, "{}{} {}", separator, name, reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Shuts the OS down when enough of the units managed by a [`UpsManager`] agree
+///
+/// Feed the readings of every [`UpsManager::poll`] to
+/// [`check`](Self::check). The shutdown is started at most once.
+#[derive(Debug)]
+pub struct RedundantPowerAction {
+    policy: RedundancyPolicy,
+    /// When each unit went on battery
+    on_battery_since: Vec<(String, Instant)>,
+    triggered: bool,
+}
+
+impl RedundantPowerAction {
+    /// Create a power action following `policy`
+    pub fn new(policy: RedundancyPolicy) -> Self {
+        Self {
+            policy,
+            on_battery_since: Vec::new(),
+            triggered: false,
+        }
+    }
+
+    /// Get the policy
+    pub fn policy(&self) -> &RedundancyPolicy {
+        &self.policy
+    }
+
+    /// Check if the shutdown was already started
+    pub fn is_triggered(&self) -> bool {
+        self.triggered
+    }
+
+    /// Count the votes of the units in their latest readings
+    ///
+    /// Unreachable units keep the time they went on battery, so a flaky link
+    /// does not restart the `on_battery_for` timer.
+    pub fn vote(&mut self, devices: &[DeviceStatus]) -> QuorumVote {
+        let power = &self.policy.power;
+        let mut healthy_weight = 0.0;
+        let mut vote = QuorumVote {
+            votes: 0.0,
+            required: self.policy.required,
+            units: Vec::new(),
+        };
+
+        for device in devices {
+            let status = match &device.status {
+                Some(status) if device.is_reachable() => status,
+                _ => continue,
+            };
+            let health = if status.flags.ups_failed {
+                self.policy.failed_weight
+            } else {
+                1.0
+            };
+            let weight = self.policy.weight(&device.name) * health;
+            healthy_weight += weight;
+
+            if !status.flags.on_battery() {
+                self.on_battery_since
+                    .retain(|(name, _)| *name != device.name);
+                continue;
+            }
+            let since = match self
+                .on_battery_since
+                .iter()
+                .find(|(name, _)| *name == device.name)
+            {
+                Some((_, since)) => *since,
+                None => {
+                    let now = Instant::now();
+                    self.on_battery_since.push((device.name.clone(), now));
+                    now
+                }
+            };
+            let reason = if power.on_low_battery && status.flags.battery_low {
+                ShutdownReason::LowBattery
+            } else {
+                match power.on_battery_for {
+                    Some(limit) if since.elapsed() >= limit => {
+                        ShutdownReason::OnBatteryFor(since.elapsed())
+                    }
+                    _ => continue,
+                }
+            };
+            vote.votes += weight;
+            vote.units.push((device.name.clone(), reason));
+        }
+
+        vote.required = vote.required.min(healthy_weight);
+        vote
+    }
+
+    /// Decide from the latest readings whether the shutdown is due, without running it
+    pub fn update(&mut self, devices: &[DeviceStatus]) -> Option<QuorumVote> {
+        let vote = self.vote(devices);
+        if self.triggered || !vote.is_due() {
+            return None;
+        }
+        self.triggered = true;
+        Some(vote)
+    }
+
+    /// Update from the latest readings and run the shutdown when it is due
+    pub fn check<T: Transport>(
+        &mut self,
+        manager: &UpsManager<T>,
+        devices: &[DeviceStatus],
+    ) -> Result<Option<QuorumVote>> {
+        match self.update(devices) {
+            Some(vote) => {
+                self.execute(manager)?;
+                Ok(Some(vote))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Start the OS shutdown, then send every unit its shutdown-and-restore command
+    ///
+    /// Every unit is sent the command even when the OS command or another
+    /// unit fails; the first error is returned afterwards.
+    pub fn execute<T: Transport>(&self, manager: &UpsManager<T>) -> Result<()> {
+        let mut result = power_action::run_command(&self.policy.power.command);
+        if let Some(shutdown) = self.policy.power.ups_shutdown {
+            for name in manager.names() {
+                let sent = match manager.ups(name) {
+                    Some(ups) => {
+                        ups.shutdown_and_restore(shutdown.delay_minutes, shutdown.restore_minutes)
+                    }
+                    None => continue,
+                };
+                if result.is_ok() {
+                    result = sent;
+                }
+            }
+        }
+        result
+    }
+}
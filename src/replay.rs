@@ -0,0 +1,221 @@
+use crate::error::{Result, UpsError};
+use crate::transport::{Command, Response, Transport};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+const TRACE_HEADER: &str = "# megatec-ups-control trace v1";
+
+/// Transport wrapper recording every command and raw response to a trace
+///
+/// Each exchange is written as a `>` line with the command mnemonic, followed by
+/// either a `<` line with the raw bytes in hex and the decoded text, or a `!`
+/// line with the error.
+pub struct RecordingTransport<T: Transport, W: Write = BufWriter<File>> {
+    inner: T,
+    writer: Mutex<W>,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    /// Record the traffic of `inner` into a new file at the given path
+    pub fn create<P: AsRef<Path>>(inner: T, path: P) -> Result<Self> {
+        let file = File::create(path)?;
+        Self::new(inner, BufWriter::new(file))
+    }
+}
+
+impl<T: Transport, W: Write> RecordingTransport<T, W> {
+    /// Record the traffic of `inner` into the given writer
+    pub fn new(inner: T, mut writer: W) -> Result<Self> {
+        writeln!(writer, "{}", TRACE_HEADER)?;
+        writer.flush()?;
+
+        Ok(Self {
+            inner,
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Get a reference to the wrapped transport
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Stop recording and return the wrapped transport and the writer
+    pub fn into_parts(self) -> (T, W) {
+        let writer = self
+            .writer
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        (self.inner, writer)
+    }
+
+    /// Write one exchange to the trace
+    fn record(&self, command: Command, result: &Result<Response>) -> io::Result<()> {
+        let mut writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        writeln!(writer, "> {}", command.mnemonic())?;
+        match result {
+            Ok(response) => writeln!(writer, "< {}\t{}", to_hex(&response.raw), response.text)?,
+            Err(e) => writeln!(writer, "! {}", error_name(e))?,
+        }
+        writer.flush()
+    }
+}
+
+impl<T: Transport, W: Write> Transport for RecordingTransport<T, W> {
+    fn send_command(&self, command: Command) -> Result<Response> {
+        let result = self.inner.send_command(command);
+        // A broken trace must not break the communication with the UPS
+        let _ = self.record(command, &result);
+        result
+    }
+
+    fn send_command_with_timeout(&self, command: Command, timeout: Duration) -> Result<Response> {
+        let result = self.inner.send_command_with_timeout(command, timeout);
+        let _ = self.record(command, &result);
+        result
+    }
+
+    fn reset(&self) -> Result<()> {
+        self.inner.reset()
+    }
+}
+
+/// Transport feeding a recorded trace back, for reproducing sessions without hardware
+pub struct ReplayTransport {
+    entries: Mutex<VecDeque<TraceEntry>>,
+}
+
+/// One recorded exchange
+struct TraceEntry {
+    command: Command,
+    outcome: std::result::Result<Response, String>,
+}
+
+impl ReplayTransport {
+    /// Load a trace written by RecordingTransport from the given path
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Load a trace written by RecordingTransport from a reader
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Self> {
+        let mut entries = VecDeque::new();
+        let mut command = None;
+
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let kind = line.get(..2).unwrap_or("");
+            let rest = line.get(2..).unwrap_or("");
+            match (kind, command.take()) {
+                ("> ", None) => {
+                    command = Some(Command::from_mnemonic(rest).ok_or(UpsError::InvalidResponse)?);
+                }
+                ("< ", Some(command)) => {
+                    let (hex, text) = rest.split_once('\t').unwrap_or((rest, ""));
+                    let raw = from_hex(hex).ok_or(UpsError::InvalidResponse)?;
+                    let text = text.to_string();
+                    entries.push_back(TraceEntry {
+                        command,
+                        outcome: Ok(Response { raw, text }),
+                    });
+                }
+                ("! ", Some(command)) => entries.push_back(TraceEntry {
+                    command,
+                    outcome: Err(rest.to_string()),
+                }),
+                _ => return Err(UpsError::InvalidResponse),
+            }
+        }
+
+        Ok(Self {
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Get the number of exchanges left in the trace
+    pub fn remaining(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Lock the remaining entries, recovering from a poisoned mutex
+    fn lock(&self) -> MutexGuard<'_, VecDeque<TraceEntry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn send_command(&self, command: Command) -> Result<Response> {
+        let entry = self.lock().pop_front().ok_or(UpsError::Disconnected)?;
+
+        if entry.command != command {
+            return Err(UpsError::Io(io::Error::other(format!(
+                "trace expected {} but {} was sent",
+                entry.command.mnemonic(),
+                command.mnemonic()
+            ))));
+        }
+
+        entry.outcome.map_err(|name| error_from_name(&name))
+    }
+}
+
+/// Get the name an error is recorded under
+fn error_name(error: &UpsError) -> String {
+    match error {
+        UpsError::DeviceNotFound => "DeviceNotFound".to_string(),
+        UpsError::PermissionDenied => "PermissionDenied".to_string(),
+        UpsError::Timeout => "Timeout".to_string(),
+        UpsError::Disconnected => "Disconnected".to_string(),
+        UpsError::InvalidResponse => "InvalidResponse".to_string(),
+        UpsError::InvalidTime => "InvalidTime".to_string(),
+        UpsError::Unsupported => "Unsupported".to_string(),
+        other => format!("Other {}", other),
+    }
+}
+
+/// Recreate a recorded error
+fn error_from_name(name: &str) -> UpsError {
+    match name {
+        "DeviceNotFound" => UpsError::DeviceNotFound,
+        "PermissionDenied" => UpsError::PermissionDenied,
+        "Timeout" => UpsError::Timeout,
+        "Disconnected" => UpsError::Disconnected,
+        "InvalidResponse" => UpsError::InvalidResponse,
+        "InvalidTime" => UpsError::InvalidTime,
+        "Unsupported" => UpsError::Unsupported,
+        other => UpsError::Io(io::Error::other(
+            other.strip_prefix("Other ").unwrap_or(other).to_string(),
+        )),
+    }
+}
+
+/// Encode bytes as lowercase hex
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decode lowercase or uppercase hex into bytes
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,245 @@
+use crate::error::{Result, UpsError};
+#[cfg(feature = "graphql")]
+use crate::graphql;
+use crate::http::{self, error, ConnectionLimit, Reply, Request};
+use crate::json::{ToJson, Value};
+use crate::listener;
+#[cfg(feature = "graphql")]
+use crate::sqlite::SqliteHistory;
+use crate::transport::{Transport, MAX_RESTORE_MINUTES};
+use crate::MegatecUps;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(feature = "graphql")]
+use std::sync::Arc;
+use std::thread;
+
+/// Default listen address of the REST API
+pub const DEFAULT_REST_ADDRESS: &str = "127.0.0.1:8080";
+
+const CONTENT_TYPE: &str = "application/json";
+/// Clients served at the same time, each on its own thread
+const MAX_CONNECTIONS: usize = 64;
+
+/// HTTP server exposing the UPS as a small JSON API
+///
+/// | Method   | Path        | Action                                                        |
+/// |----------|-------------|---------------------------------------------------------------|
+/// | `GET`    | `/status`   | [`MegatecUps::get_status`]                                    |
+/// | `GET`    | `/rating`   | [`MegatecUps::get_rating`]                                    |
+/// | `GET`    | `/info`     | [`MegatecUps::get_info`]                                      |
+/// | `POST`   | `/test`     | 10 second test, `{"minutes": n}` or `{"until_battery_low": true}` |
+/// | `DELETE` | `/test`     | Abort the test                                                |
+/// | `POST`   | `/beeper`   | Toggle the beeper, or `{"enabled": bool}`                     |
+/// | `POST`   | `/shutdown` | `{"minutes": f, "restore_minutes": n}`, both optional         |
+/// | `DELETE` | `/shutdown` | Cancel the pending shutdown                                   |
+/// | `GET`, `POST` | `/graphql` | GraphQL queries, with the `graphql` feature               |
+///
+/// `POST` and `DELETE` requests and `/graphql` need an `Authorization: Bearer <token>`
+/// header matching the configured token. Without a token they are refused.
+pub struct RestServer<T: Transport> {
+    ups: MegatecUps<T>,
+    listener: TcpListener,
+    token: Option<String>,
+    #[cfg(feature = "graphql")]
+    history: Option<Arc<SqliteHistory>>,
+}
+
+impl<T: Transport> RestServer<T> {
+    /// Bind the server to an address such as [`DEFAULT_REST_ADDRESS`]
+    pub fn bind<A: ToSocketAddrs>(ups: MegatecUps<T>, address: A) -> Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        Ok(Self {
+            ups,
+            listener,
+            token: None,
+            #[cfg(feature = "graphql")]
+            history: None,
+        })
+    }
+
+    /// Set the token required by the control endpoints
+    pub fn set_token<S: Into<String>>(&mut self, token: S) {
+        self.token = Some(token.into());
+    }
+
+    /// Answer the `history` and `events` GraphQL queries from this database
+    ///
+    /// The database can be shared with a [`Monitor`](crate::Monitor) recording
+    /// into it, or be written by another process.
+    #[cfg(feature = "graphql")]
+    pub fn set_history(&mut self, history: Arc<SqliteHistory>) {
+        self.history = Some(history);
+    }
+
+    /// Get the GraphQL schema in the schema definition language
+    ///
+    /// Introspection queries are not answered, so this is the schema to give
+    /// to clients and code generators.
+    #[cfg(feature = "graphql")]
+    pub fn graphql_schema() -> String {
+        graphql::schema()
+    }
+
+    /// Get the address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Get a reference to the served UPS
+    pub fn ups(&self) -> &MegatecUps<T> {
+        &self.ups
+    }
+
+    /// Handle requests, serving each client on its own thread
+    ///
+    /// Clients beyond 64 at the same time are answered `503`. Failed accepts
+    /// are reported on stderr and do not stop the server.
+    pub fn serve(&self) -> Result<()>
+    where
+        T: Send,
+    {
+        let limit = ConnectionLimit::new(MAX_CONNECTIONS);
+        thread::scope(|scope| {
+            for stream in self.listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        listener::accept_failed("REST API", &e);
+                        continue;
+                    }
+                };
+                let Some(slot) = limit.acquire() else {
+                    let _ = respond(&stream, error(503, "too many connections"));
+                    continue;
+                };
+                // A client that goes away is not a server failure
+                scope.spawn(move || {
+                    let _slot = slot;
+                    self.handle(&stream)
+                });
+            }
+            Ok(())
+        })
+    }
+
+    /// Answer a single HTTP request
+    fn handle(&self, stream: &TcpStream) -> std::io::Result<()> {
+        let reply = match Request::read(stream) {
+            Ok(request) => self.route(&request),
+            Err(_) => error(400, "bad request"),
+        };
+        respond(stream, reply)
+    }
+
+    /// Dispatch a request to its endpoint
+    fn route(&self, request: &Request) -> Reply {
+        let method = request.method.as_str();
+        let path = request.route();
+
+        match (method, path) {
+            ("GET", "/status") => reply(self.ups.get_status().map(|s| s.to_json())),
+            ("GET", "/rating") => reply(self.ups.get_rating().map(|r| r.to_json())),
+            ("GET", "/info") => reply(self.ups.get_info().map(|i| i.to_json())),
+            ("POST" | "DELETE", "/test" | "/beeper" | "/shutdown") => {
+                if !http::authorized(request.header("authorization"), self.token.as_deref()) {
+                    return error(401, "missing or invalid token");
+                }
+                let body = match parse_body(&request.body) {
+                    Ok(body) => body,
+                    Err(message) => return error(400, &message),
+                };
+                match self.control(method, path, &body) {
+                    Some(result) => {
+                        reply(result.map(|()| Value::object(vec![("ok", true.into())])))
+                    }
+                    None => error(405, "method not allowed"),
+                }
+            }
+            #[cfg(feature = "graphql")]
+            ("GET" | "POST", "/graphql") => {
+                // Checked before the query and variables are parsed
+                if !http::authorized(request.header("authorization"), self.token.as_deref()) {
+                    return error(401, "missing or invalid token");
+                }
+                graphql::handle(&self.ups, self.history.as_deref(), request)
+            }
+            #[cfg(feature = "graphql")]
+            (_, "/graphql") => error(405, "method not allowed"),
+            (_, "/status" | "/rating" | "/info") => error(405, "method not allowed"),
+            _ => error(404, "not found"),
+        }
+    }
+
+    /// Execute a control request, `None` when the method does not apply to the path
+    fn control(&self, method: &str, path: &str, body: &Value) -> Option<Result<()>> {
+        let result = match (method, path) {
+            ("POST", "/test") => {
+                if let Some(minutes) = body.get("minutes").and_then(Value::as_f64) {
+                    if minutes.fract() == 0.0 && (0.0..=u8::MAX as f64).contains(&minutes) {
+                        self.ups.test_with_time(minutes as u8).map(drop)
+                    } else {
+                        Err(UpsError::InvalidTime)
+                    }
+                } else if body.get("until_battery_low").and_then(Value::as_bool) == Some(true) {
+                    self.ups.test_until_battery_low().map(drop)
+                } else {
+                    self.ups.test().map(drop)
+                }
+            }
+            ("DELETE", "/test") => self.ups.abort_test(),
+            ("POST", "/beeper") => match body.get("enabled").and_then(Value::as_bool) {
+                Some(enabled) => self.ups.set_beeper(enabled),
+                None => self.ups.switch_beep(),
+            },
+            ("POST", "/shutdown") => {
+                let minutes = body.get("minutes").and_then(Value::as_f64).unwrap_or(1.0);
+                match body.get("restore_minutes").and_then(Value::as_f64) {
+                    Some(restore)
+                        if restore.fract() == 0.0
+                            && (1.0..=MAX_RESTORE_MINUTES as f64).contains(&restore) =>
+                    {
+                        self.ups
+                            .shutdown_and_restore(minutes as f32, restore as u16)
+                    }
+                    Some(_) => Err(UpsError::InvalidTime),
+                    None => self.ups.shutdown_after(minutes as f32),
+                }
+            }
+            ("DELETE", "/shutdown") => self.ups.cancel_shutdown(),
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+/// Write a reply as a JSON document
+fn respond(stream: &TcpStream, (status, body): Reply) -> std::io::Result<()> {
+    let mut body = body.to_string();
+    body.push('\n');
+    http::respond(stream, status, CONTENT_TYPE, body.as_bytes())
+}
+
+/// Parse an optional JSON request body
+fn parse_body(body: &[u8]) -> std::result::Result<Value, String> {
+    let text = std::str::from_utf8(body).map_err(|_| "body is not UTF-8".to_string())?;
+    if text.trim().is_empty() {
+        return Ok(Value::Object(Vec::new()));
+    }
+    Value::parse(text).map_err(|e| e.to_string())
+}
+
+/// Turn the result of a UPS operation into a reply
+fn reply(result: Result<Value>) -> Reply {
+    match result {
+        Ok(value) => (200, value),
+        Err(e) => {
+            let status = match e {
+                UpsError::InvalidTime => 400,
+                UpsError::NoShutdownPending => 409,
+                UpsError::Unsupported => 501,
+                _ => 503,
+            };
+            error(status, &e.to_string())
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::error::UpsError;
+use std::time::Duration;
+
+/// Policy describing how failed UPS queries are retried
+///
+/// Commands changing the UPS state, such as tests, beeper toggles and
+/// shutdowns, are sent once whatever the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt
+    pub retries: u32,
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between retries
+    pub max_backoff: Duration,
+    /// Factor the delay is multiplied by after every retry
+    pub multiplier: u32,
+    /// Consecutive USB pipe or I/O errors after which the transport is reset
+    /// before retrying, 0 to never reset
+    pub reset_after: u32,
+}
+
+impl RetryPolicy {
+    /// Policy that never retries
+    pub fn none() -> Self {
+        Self {
+            retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Policy retrying the given number of times with the default backoff
+    pub fn with_retries(retries: u32) -> Self {
+        Self {
+            retries,
+            ..Self::default()
+        }
+    }
+
+    /// Get the delay before the given retry, counting from 0
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = self.multiplier.max(1).saturating_pow(retry);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Check whether an error may go away when the transaction is repeated
+    pub(crate) fn is_retryable(error: &UpsError) -> bool {
+        !matches!(
+            error,
+            UpsError::InvalidTime
+                | UpsError::Unsupported
+                | UpsError::NoShutdownPending
+                | UpsError::Cancelled
+                | UpsError::DeviceNotFound
+                | UpsError::PermissionDenied
+        )
+    }
+
+    /// Check whether an error suggests a stalled endpoint or wedged device
+    pub(crate) fn needs_reset(error: &UpsError) -> bool {
+        matches!(
+            error,
+            UpsError::Usb(rusb::Error::Pipe) | UpsError::Usb(rusb::Error::Io)
+        )
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            multiplier: 2,
+            reset_after: 2,
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use crate::timestamp::DateTime;
+use crate::transport::Command;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Longest gap between two checks over which missed minutes are caught up
+const MAX_CATCH_UP_MINUTES: i64 = 24 * 60;
+
+/// Battery test started by the scheduler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestKind {
+    /// The 10-second self test
+    Quick,
+    /// Test until the battery is low
+    Deep,
+    /// Test for the given number of minutes
+    Timed(u8),
+}
+
+impl TestKind {
+    /// Get the command starting the test
+    pub fn command(&self) -> Command {
+        match self {
+            TestKind::Quick => Command::Test,
+            TestKind::Deep => Command::TestUntilBatteryLow,
+            TestKind::Timed(minutes) => Command::TestWithTime(*minutes),
+        }
+    }
+
+    /// Look a test up by name: `quick`, `deep` or a number of minutes
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "quick" => Some(TestKind::Quick),
+            "deep" => Some(TestKind::Deep),
+            minutes => minutes
+                .parse()
+                .ok()
+                .filter(|minutes| (1..=99).contains(minutes))
+                .map(TestKind::Timed),
+        }
+    }
+}
+
+impl fmt::Display for TestKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestKind::Quick => f.write_str("quick"),
+            TestKind::Deep => f.write_str("deep"),
+            TestKind::Timed(minutes) => write!(f, "{}", minutes),
+        }
+    }
+}
+
+/// Cron-like schedule with minute, hour, day of month, month and day of week fields
+///
+/// Every field is `*`, a value, a range `a-b`, a step `*/n` or `a-b/n`, or a
+/// comma separated list of those. Days of the week run from 0 (Sunday) to 7
+/// (Sunday again). As in cron, when both the day of month and the day of week
+/// are restricted, a day matching either one matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Schedule {
+    /// Parse a five field cron expression such as `0 3 * * 0`
+    pub fn parse(expression: &str) -> Option<Self> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return None;
+        };
+
+        let mut weekday_bits = parse_field(weekdays, 0, 7)?;
+        if weekday_bits & 1 << 7 != 0 {
+            weekday_bits = (weekday_bits | 1) & !(1 << 7);
+        }
+        Some(Self {
+            expression: fields.join(" "),
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+
+    /// Every week on `weekday` (0 for Sunday) at the given time
+    pub fn weekly(weekday: u8, hour: u8, minute: u8) -> Option<Self> {
+        Self::parse(&format!("{} {} * * {}", minute, hour, weekday))
+    }
+
+    /// Every month on `day` at the given time
+    pub fn monthly(day: u8, hour: u8, minute: u8) -> Option<Self> {
+        Self::parse(&format!("{} {} {} * *", minute, hour, day))
+    }
+
+    /// Check if the schedule fires in the minute of the given calendar time
+    fn matches(&self, time: &DateTime) -> bool {
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => has(self.days, time.day) || has(self.weekdays, time.weekday),
+            _ => has(self.days, time.day) && has(self.weekdays, time.weekday),
+        };
+        has(self.minutes, time.minute as u32)
+            && has(self.hours, time.hour as u32)
+            && has(self.months, time.month)
+            && day_matches
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+/// Battery test run on a schedule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledTest {
+    pub schedule: Schedule,
+    pub kind: TestKind,
+}
+
+impl fmt::Display for ScheduledTest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} test at {}", self.kind, self.schedule)
+    }
+}
+
+/// Decides when scheduled battery tests are due
+///
+/// Schedules are evaluated in UTC shifted by the [UTC offset](Self::set_utc_offset).
+/// Minutes passed between two calls of [`due`](Self::due) are caught up, so
+/// a test is not missed when the polling interval exceeds a minute.
+#[derive(Debug, Clone, Default)]
+pub struct TestScheduler {
+    tests: Vec<ScheduledTest>,
+    utc_offset_minutes: i32,
+    last_minute: Option<i64>,
+}
+
+impl TestScheduler {
+    /// Create a scheduler without tests
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run the test `kind` whenever `schedule` fires
+    pub fn add(&mut self, schedule: Schedule, kind: TestKind) {
+        self.tests.push(ScheduledTest { schedule, kind });
+    }
+
+    /// Check if no test is scheduled
+    pub fn is_empty(&self) -> bool {
+        self.tests.is_empty()
+    }
+
+    /// Get the scheduled tests
+    pub fn tests(&self) -> &[ScheduledTest] {
+        &self.tests
+    }
+
+    /// Evaluate the schedules in local time `minutes` ahead of UTC
+    pub fn set_utc_offset(&mut self, minutes: i32) {
+        self.utc_offset_minutes = minutes;
+    }
+
+    /// Get the test due since the previous call, if any
+    ///
+    /// When several tests are due, the one added first wins. The first call
+    /// only looks at the current minute.
+    pub fn due(&mut self, now: SystemTime) -> Option<TestKind> {
+        let seconds = match now.duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_secs() as i64,
+            Err(_) => return None,
+        };
+        let minute = (seconds + i64::from(self.utc_offset_minutes) * 60).div_euclid(60);
+        let first = match self.last_minute {
+            Some(last) if last >= minute => return None,
+            Some(last) => (last + 1).max(minute - MAX_CATCH_UP_MINUTES),
+            None => minute,
+        };
+        self.last_minute = Some(minute);
+
+        (first..=minute).find_map(|minute| {
+            let time = DateTime::from_unix_seconds(minute * 60);
+            self.tests
+                .iter()
+                .find(|test| test.schedule.matches(&time))
+                .map(|test| test.kind)
+        })
+    }
+}
+
+/// Parse one cron field into a bit set of the allowed values
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|step| *step > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+                // A single value with a step runs to the end of the range
+                None if step > 1 => (range.parse().ok()?, max),
+                None => {
+                    let value = range.parse().ok()?;
+                    (value, value)
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Some(bits)
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & 1 << value != 0
+}
@@ -0,0 +1,141 @@
+use crate::error::{Result, UpsError};
+use crate::protocol::{command_text, decode_response, expects_response};
+use crate::transport::{Command, Response, Transport};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::Duration;
+
+const CHAR_CR: u8 = b'\r';
+const MAX_RESPONSE_LENGTH: usize = 256;
+/// Read timeout in tenths of a second (VTIME)
+const READ_TIMEOUT_TENTHS: u8 = 10;
+
+/// Transport talking to the UPS over an RS-232 serial port (2400 8N1)
+pub struct SerialTransport {
+    port: File,
+}
+
+impl SerialTransport {
+    /// Open and configure the serial port at the given path, e.g. `/dev/ttyS0`
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let port = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(path)?;
+        Self::configure(&port)?;
+
+        Ok(Self { port })
+    }
+
+    /// Send a command line that the crate does not wrap, such as a vendor specific query
+    ///
+    /// The carriage return is appended. With `expect_response` the answer line
+    /// is read back, otherwise an empty response is returned right away.
+    pub fn send_raw_command(&self, text: &str, expect_response: bool) -> Result<Response> {
+        if text.contains(char::from(CHAR_CR)) {
+            let error = io::Error::new(io::ErrorKind::InvalidInput, "command contains CR");
+            return Err(UpsError::Io(error));
+        }
+        (&self.port).write_all(text.as_bytes())?;
+        (&self.port).write_all(&[CHAR_CR])?;
+
+        if !expect_response {
+            return Ok(Response::default());
+        }
+
+        let raw = self.read_line()?;
+        let text = decode_response(&raw);
+        Ok(Response { raw, text })
+    }
+
+    /// Put the port into raw mode at 2400 baud, 8 data bits, no parity, 1 stop bit
+    fn configure(port: &File) -> io::Result<()> {
+        let fd = port.as_raw_fd();
+        // SAFETY: `termios` is a plain C struct that is fully initialized by tcgetattr
+        let mut tty: libc::termios = unsafe { std::mem::zeroed() };
+
+        // SAFETY: `fd` is a valid open descriptor and `tty` points to writable memory
+        unsafe {
+            if libc::tcgetattr(fd, &mut tty) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            libc::cfmakeraw(&mut tty);
+            tty.c_cflag &= !(libc::PARENB | libc::CSTOPB | libc::CSIZE | libc::CRTSCTS);
+            tty.c_cflag |= libc::CS8 | libc::CLOCAL | libc::CREAD;
+            // Return from read after at most 1 second without data
+            tty.c_cc[libc::VMIN] = 0;
+            tty.c_cc[libc::VTIME] = READ_TIMEOUT_TENTHS;
+
+            if libc::cfsetispeed(&mut tty, libc::B2400) != 0
+                || libc::cfsetospeed(&mut tty, libc::B2400) != 0
+                || libc::tcsetattr(fd, libc::TCSANOW, &tty) != 0
+                || libc::tcflush(fd, libc::TCIOFLUSH) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set how long a read waits for the next byte, in tenths of a second
+    fn set_read_timeout(&self, tenths: u8) -> io::Result<()> {
+        let fd = self.port.as_raw_fd();
+        // SAFETY: `termios` is a plain C struct that is fully initialized by tcgetattr
+        let mut tty: libc::termios = unsafe { std::mem::zeroed() };
+
+        // SAFETY: `fd` is a valid open descriptor and `tty` points to writable memory
+        unsafe {
+            if libc::tcgetattr(fd, &mut tty) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            tty.c_cc[libc::VTIME] = tenths;
+            if libc::tcsetattr(fd, libc::TCSANOW, &tty) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a single carriage-return terminated response line
+    fn read_line(&self) -> Result<Vec<u8>> {
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+
+        while line.len() < MAX_RESPONSE_LENGTH {
+            if (&self.port).read(&mut byte)? == 0 {
+                // Timed out before the terminating carriage return arrived
+                return Err(UpsError::Timeout);
+            }
+            if byte[0] == CHAR_CR {
+                return Ok(line);
+            }
+            line.push(byte[0]);
+        }
+
+        Err(UpsError::InvalidResponse)
+    }
+}
+
+impl Transport for SerialTransport {
+    fn send_command(&self, command: Command) -> Result<Response> {
+        self.send_raw_command(&command_text(command)?, expects_response(command))
+    }
+
+    /// Wait up to `timeout` (at most 25.5 seconds) for every byte of the response
+    fn send_command_with_timeout(&self, command: Command, timeout: Duration) -> Result<Response> {
+        let tenths = (timeout.as_millis() / 100).clamp(1, u128::from(u8::MAX)) as u8;
+        self.set_read_timeout(tenths)?;
+        let result = self.send_command(command);
+        let restored = self.set_read_timeout(READ_TIMEOUT_TENTHS);
+        let response = result?;
+        restored?;
+        Ok(response)
+    }
+}
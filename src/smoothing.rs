@@ -0,0 +1,129 @@
+use crate::status::UpsStatus;
+use std::collections::VecDeque;
+
+/// Number of smoothed values of a status reading
+const FIELDS: usize = 7;
+
+/// Filter applied to the measured values of successive status readings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// Exponential moving average, `alpha` from 0.0 (frozen) to 1.0 (raw values)
+    Exponential { alpha: f64 },
+    /// Average of the last `window` readings
+    MovingAverage { window: usize },
+}
+
+/// Smooths the measured values of a stream of status readings
+///
+/// Voltages, load, frequency and temperature are filtered while the flags are
+/// passed on from the latest reading. Missing (NaN) values are skipped.
+#[derive(Debug, Clone)]
+pub struct Smoother {
+    smoothing: Smoothing,
+    window: VecDeque<[f64; FIELDS]>,
+    current: Option<UpsStatus>,
+}
+
+impl Smoother {
+    /// Create a smoother applying `smoothing`
+    pub fn new(smoothing: Smoothing) -> Self {
+        Self {
+            smoothing,
+            window: VecDeque::new(),
+            current: None,
+        }
+    }
+
+    /// Get the filter
+    pub fn smoothing(&self) -> Smoothing {
+        self.smoothing
+    }
+
+    /// Get the last smoothed reading, `None` before the first one
+    pub fn current(&self) -> Option<&UpsStatus> {
+        self.current.as_ref()
+    }
+
+    /// Add a reading and get the smoothed one
+    pub fn update(&mut self, status: &UpsStatus) -> &UpsStatus {
+        let values = fields(status);
+        let smoothed = match self.smoothing {
+            Smoothing::Exponential { alpha } => {
+                let alpha = alpha.clamp(0.0, 1.0);
+                match &self.current {
+                    Some(previous) => {
+                        let previous = fields(previous);
+                        let mut smoothed = values;
+                        for (value, previous) in smoothed.iter_mut().zip(previous) {
+                            *value = if value.is_nan() {
+                                previous
+                            } else if previous.is_nan() {
+                                *value
+                            } else {
+                                previous + alpha * (*value - previous)
+                            };
+                        }
+                        smoothed
+                    }
+                    None => values,
+                }
+            }
+            Smoothing::MovingAverage { window } => {
+                if self.window.len() >= window.max(1) {
+                    self.window.pop_front();
+                }
+                self.window.push_back(values);
+                let mut smoothed = [f64::NAN; FIELDS];
+                for (index, value) in smoothed.iter_mut().enumerate() {
+                    let (sum, count) = self
+                        .window
+                        .iter()
+                        .map(|values| values[index])
+                        .filter(|value| !value.is_nan())
+                        .fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+                    if count > 0 {
+                        *value = sum / f64::from(count);
+                    }
+                }
+                smoothed
+            }
+        };
+        self.current.insert(with_fields(status, smoothed))
+    }
+
+    /// Forget the readings seen so far
+    pub fn reset(&mut self) {
+        self.window.clear();
+        self.current = None;
+    }
+}
+
+fn fields(status: &UpsStatus) -> [f64; FIELDS] {
+    [
+        status.input_voltage,
+        status.input_fault_voltage,
+        status.output_voltage,
+        status.load_raw,
+        status.input_frequency,
+        status.battery_voltage,
+        status.temperature,
+    ]
+}
+
+fn with_fields(status: &UpsStatus, values: [f64; FIELDS]) -> UpsStatus {
+    let [input_voltage, input_fault_voltage, output_voltage, load_raw, input_frequency, battery_voltage, temperature] =
+        values;
+    #[allow(deprecated)]
+    UpsStatus {
+        input_voltage,
+        input_fault_voltage,
+        output_voltage,
+        load_raw,
+        output_current: load_raw,
+        load_in_amps: status.load_in_amps,
+        input_frequency,
+        battery_voltage,
+        temperature,
+        flags: status.flags,
+    }
+}
@@ -0,0 +1,791 @@
+use crate::error::Result;
+use crate::info::UpsInfo;
+use crate::rating::RatingInfo;
+use crate::status::UpsStatus;
+use crate::transport::Transport;
+use crate::MegatecUps;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Default listen address of the SNMP agent
+pub const DEFAULT_SNMP_ADDRESS: &str = "0.0.0.0:161";
+
+/// How long a UPS reading answers requests before it is read again
+///
+/// A walk sends one request per object; without caching every one of them
+/// would wait for the status acknowledgment delay.
+pub const DEFAULT_SNMP_CACHE_TTL: Duration = Duration::from_secs(5);
+
+const MAX_PACKET_SIZE: usize = 1472;
+const MAX_BULK_VARBINDS: usize = 48;
+
+/// `upsMIB` from RFC 1628
+const UPS_MIB: [u32; 7] = [1, 3, 6, 1, 2, 1, 33];
+const SYSTEM: [u32; 7] = [1, 3, 6, 1, 2, 1, 1];
+
+// BER tags
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_ID: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const COUNTER32: u8 = 0x41;
+const GAUGE32: u8 = 0x42;
+const TIME_TICKS: u8 = 0x43;
+const NO_SUCH_OBJECT: u8 = 0x80;
+const END_OF_MIB_VIEW: u8 = 0x82;
+
+// PDU tags
+const GET_REQUEST: u8 = 0xa0;
+const GET_NEXT_REQUEST: u8 = 0xa1;
+const GET_RESPONSE: u8 = 0xa2;
+const SET_REQUEST: u8 = 0xa3;
+const GET_BULK_REQUEST: u8 = 0xa5;
+
+// Error statuses
+const NO_SUCH_NAME: i64 = 2;
+const NOT_WRITABLE: i64 = 17;
+
+const VERSION_1: i64 = 0;
+const VERSION_2C: i64 = 1;
+
+// Well-known alarms from `upsWellKnownAlarms`
+const ALARM_ON_BATTERY: u32 = 2;
+const ALARM_LOW_BATTERY: u32 = 3;
+const ALARM_ON_BYPASS: u32 = 9;
+const ALARM_GENERAL_FAULT: u32 = 18;
+const ALARM_COMMUNICATIONS_LOST: u32 = 20;
+const ALARM_SHUTDOWN_PENDING: u32 = 22;
+const ALARM_TEST_IN_PROGRESS: u32 = 24;
+
+type Oid = Vec<u32>;
+
+/// Value of a managed object
+#[derive(Debug, Clone)]
+enum SnmpValue {
+    Integer(i64),
+    OctetString(Vec<u8>),
+    ObjectId(Oid),
+    Counter32(u32),
+    Gauge32(u32),
+    TimeTicks(u32),
+}
+
+/// SNMP v1/v2c agent serving the RFC 1628 UPS-MIB
+///
+/// Answers `GET`, `GETNEXT` and `GETBULK` for the `upsIdent`, `upsBattery`,
+/// `upsInput`, `upsOutput`, `upsAlarm` and `upsConfig` groups, plus the
+/// `system` group NMS tools use to discover the device. The MIB is read-only,
+/// `SET` requests are refused. Requests with another community are dropped.
+pub struct SnmpAgent<T: Transport> {
+    ups: MegatecUps<T>,
+    socket: UdpSocket,
+    community: String,
+    cache_ttl: Duration,
+    started: Instant,
+    info: Option<UpsInfo>,
+    rating: Option<RatingInfo>,
+    mib: Vec<(Oid, SnmpValue)>,
+    read_at: Option<Instant>,
+    on_battery_since: Option<Instant>,
+    input_line_bads: u32,
+    alarms: Vec<(u32, u32)>,
+}
+
+impl<T: Transport> SnmpAgent<T> {
+    /// Bind the agent to an address such as [`DEFAULT_SNMP_ADDRESS`]
+    pub fn bind<A: ToSocketAddrs>(ups: MegatecUps<T>, address: A, community: &str) -> Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        Ok(Self {
+            ups,
+            socket,
+            community: community.to_string(),
+            cache_ttl: DEFAULT_SNMP_CACHE_TTL,
+            started: Instant::now(),
+            info: None,
+            rating: None,
+            mib: Vec::new(),
+            read_at: None,
+            on_battery_since: None,
+            input_line_bads: 0,
+            alarms: Vec::new(),
+        })
+    }
+
+    /// Set how long a UPS reading is reused, see [`DEFAULT_SNMP_CACHE_TTL`]
+    pub fn set_cache_ttl(&mut self, cache_ttl: Duration) {
+        self.cache_ttl = cache_ttl;
+    }
+
+    /// Get the address the agent is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Get a reference to the served UPS
+    pub fn ups(&self) -> &MegatecUps<T> {
+        &self.ups
+    }
+
+    /// Answer requests until the socket fails
+    pub fn serve(&mut self) -> Result<()> {
+        self.info = self.ups.get_info().ok();
+        self.rating = self.ups.get_rating().ok();
+
+        let mut packet = [0u8; 65535];
+        loop {
+            let (length, peer) = self.socket.recv_from(&mut packet)?;
+            if let Some(reply) = self.handle(&packet[..length]) {
+                let _ = self.socket.send_to(&reply, peer);
+            }
+        }
+    }
+
+    /// Build the reply to a request, `None` when it must be dropped
+    fn handle(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        let mut message = Reader::new(Reader::new(packet).expect(SEQUENCE)?);
+        let version = decode_integer(message.expect(INTEGER)?)?;
+        if version != VERSION_1 && version != VERSION_2C {
+            return None;
+        }
+        if message.expect(OCTET_STRING)? != self.community.as_bytes() {
+            return None;
+        }
+
+        let (pdu_type, pdu) = message.read()?;
+        let mut pdu = Reader::new(pdu);
+        let request_id = pdu.expect(INTEGER)?;
+        let non_repeaters = decode_integer(pdu.expect(INTEGER)?)?;
+        let max_repetitions = decode_integer(pdu.expect(INTEGER)?)?;
+        let mut list = Reader::new(pdu.expect(SEQUENCE)?);
+        let mut oids = Vec::new();
+        while !list.is_empty() {
+            let mut varbind = Reader::new(list.expect(SEQUENCE)?);
+            oids.push(decode_oid(varbind.expect(OBJECT_ID)?)?);
+        }
+
+        self.refresh();
+        let (error_status, error_index, varbinds) = match pdu_type {
+            GET_REQUEST => self.get(version, &oids),
+            GET_NEXT_REQUEST => self.get_next(version, &oids),
+            GET_BULK_REQUEST if version == VERSION_2C => {
+                self.get_bulk(&oids, non_repeaters, max_repetitions)
+            }
+            SET_REQUEST => {
+                let error_status = if version == VERSION_1 {
+                    NO_SUCH_NAME
+                } else {
+                    NOT_WRITABLE
+                };
+                let varbinds = oids.iter().map(|oid| (oid.clone(), None)).collect();
+                (error_status, 1, varbinds)
+            }
+            _ => return None,
+        };
+
+        let reply = encode_response(
+            version,
+            &self.community,
+            request_id,
+            error_status,
+            error_index,
+            &varbinds,
+        );
+        if reply.len() > MAX_PACKET_SIZE && varbinds.len() > 1 {
+            // tooBig, with the varbinds left out as RFC 3416 asks
+            return Some(encode_response(
+                version,
+                &self.community,
+                request_id,
+                1,
+                0,
+                &[],
+            ));
+        }
+        Some(reply)
+    }
+
+    /// Answer a `GET` request
+    fn get(&self, version: i64, oids: &[Oid]) -> (i64, i64, Vec<Varbind>) {
+        let mut varbinds = Vec::new();
+        for (index, oid) in oids.iter().enumerate() {
+            match self.mib.iter().find(|(known, _)| known == oid) {
+                Some((_, value)) => {
+                    varbinds.push((oid.clone(), Some(Encoded::Value(value.clone()))))
+                }
+                None if version == VERSION_1 => return no_such_name(oids, index),
+                None => varbinds.push((oid.clone(), Some(Encoded::Exception(NO_SUCH_OBJECT)))),
+            }
+        }
+        (0, 0, varbinds)
+    }
+
+    /// Answer a `GETNEXT` request
+    fn get_next(&self, version: i64, oids: &[Oid]) -> (i64, i64, Vec<Varbind>) {
+        let mut varbinds = Vec::new();
+        for (index, oid) in oids.iter().enumerate() {
+            match self.next(oid) {
+                Some(varbind) => varbinds.push(varbind),
+                None if version == VERSION_1 => return no_such_name(oids, index),
+                None => varbinds.push((oid.clone(), Some(Encoded::Exception(END_OF_MIB_VIEW)))),
+            }
+        }
+        (0, 0, varbinds)
+    }
+
+    /// Answer a `GETBULK` request
+    fn get_bulk(
+        &self,
+        oids: &[Oid],
+        non_repeaters: i64,
+        max_repetitions: i64,
+    ) -> (i64, i64, Vec<Varbind>) {
+        let non_repeaters = (non_repeaters.max(0) as usize).min(oids.len());
+        let (_, _, mut varbinds) = self.get_next(VERSION_2C, &oids[..non_repeaters]);
+
+        let mut cursors = oids[non_repeaters..].to_vec();
+        for _ in 0..max_repetitions.max(0) {
+            if cursors.is_empty() || varbinds.len() + cursors.len() > MAX_BULK_VARBINDS {
+                break;
+            }
+            let (_, _, round) = self.get_next(VERSION_2C, &cursors);
+            let finished = round
+                .iter()
+                .all(|(_, value)| matches!(value, Some(Encoded::Exception(_))));
+            cursors = round.iter().map(|(oid, _)| oid.clone()).collect();
+            varbinds.extend(round);
+            if finished {
+                break;
+            }
+        }
+
+        (0, 0, varbinds)
+    }
+
+    /// First object after `oid` in lexicographic order
+    fn next(&self, oid: &Oid) -> Option<Varbind> {
+        self.mib
+            .iter()
+            .find(|(known, _)| known > oid)
+            .map(|(known, value)| (known.clone(), Some(Encoded::Value(value.clone()))))
+    }
+
+    /// Read the UPS again once the cached reading is older than the TTL
+    fn refresh(&mut self) {
+        if self
+            .read_at
+            .is_some_and(|read_at| read_at.elapsed() < self.cache_ttl)
+        {
+            return;
+        }
+        self.read_at = Some(Instant::now());
+
+        let status = self.ups.get_status().ok();
+        if let Some(status) = &status {
+            match (status.flags.utility_fail, self.on_battery_since) {
+                (true, None) => {
+                    self.on_battery_since = Some(Instant::now());
+                    self.input_line_bads = self.input_line_bads.wrapping_add(1);
+                }
+                (false, Some(_)) => self.on_battery_since = None,
+                _ => {}
+            }
+        }
+        self.update_alarms(status.as_ref());
+        self.mib = self.build_mib(status.as_ref());
+    }
+
+    /// Keep the alarm table in step with the status, remembering when each alarm appeared
+    fn update_alarms(&mut self, status: Option<&UpsStatus>) {
+        let present: Vec<u32> = match status {
+            None => vec![ALARM_COMMUNICATIONS_LOST],
+            Some(status) => {
+                let flags = status.flags;
+                [
+                    (flags.utility_fail, ALARM_ON_BATTERY),
+                    (flags.battery_low, ALARM_LOW_BATTERY),
+                    (flags.bypass_active, ALARM_ON_BYPASS),
+                    (flags.ups_failed, ALARM_GENERAL_FAULT),
+                    (flags.shutdown_active, ALARM_SHUTDOWN_PENDING),
+                    (flags.test_in_progress, ALARM_TEST_IN_PROGRESS),
+                ]
+                .iter()
+                .filter(|(active, _)| *active)
+                .map(|(_, alarm)| *alarm)
+                .collect()
+            }
+        };
+
+        let now = self.uptime();
+        self.alarms.retain(|(alarm, _)| present.contains(alarm));
+        for alarm in present {
+            if !self.alarms.iter().any(|(known, _)| *known == alarm) {
+                self.alarms.push((alarm, now));
+            }
+        }
+    }
+
+    /// Build the sorted list of managed objects
+    fn build_mib(&self, status: Option<&UpsStatus>) -> Vec<(Oid, SnmpValue)> {
+        let mut mib = Vec::new();
+        let mut ups = |suffix: &[u32], value: SnmpValue| {
+            let mut oid = UPS_MIB.to_vec();
+            oid.push(1);
+            oid.extend_from_slice(suffix);
+            mib.push((oid, value));
+        };
+        let string = |text: &str| SnmpValue::OctetString(text.as_bytes().to_vec());
+
+        // upsIdent
+        if let Some(info) = &self.info {
+            ups(&[1, 1, 0], string(&info.company));
+            ups(&[1, 2, 0], string(&info.model));
+            ups(&[1, 3, 0], string(&info.firmware_version));
+        }
+        ups(
+            &[1, 4, 0],
+            string(concat!(
+                env!("CARGO_PKG_NAME"),
+                " ",
+                env!("CARGO_PKG_VERSION")
+            )),
+        );
+        ups(&[1, 5, 0], string(""));
+
+        if let Some(status) = status {
+            let seconds_on_battery = self
+                .on_battery_since
+                .map_or(0, |since| since.elapsed().as_secs() as i64);
+            let battery_status = if status.flags.battery_low { 3 } else { 2 };
+
+            // upsBattery
+            ups(&[2, 1, 0], SnmpValue::Integer(battery_status));
+            ups(&[2, 2, 0], SnmpValue::Integer(seconds_on_battery));
+            if let Some(voltage) = scaled(status.battery_voltage, 10.0) {
+                ups(&[2, 5, 0], SnmpValue::Integer(voltage));
+            }
+            if let Some(temperature) = scaled(status.temperature, 1.0) {
+                ups(&[2, 7, 0], SnmpValue::Integer(temperature));
+            }
+
+            // upsInput
+            ups(&[3, 1, 0], SnmpValue::Counter32(self.input_line_bads));
+            ups(&[3, 2, 0], SnmpValue::Integer(1));
+            ups(&[3, 3, 1, 1, 1], SnmpValue::Integer(1));
+            if let Some(frequency) = scaled(status.input_frequency, 10.0) {
+                ups(&[3, 3, 1, 2, 1], SnmpValue::Integer(frequency));
+            }
+            if let Some(voltage) = scaled(status.input_voltage, 1.0) {
+                ups(&[3, 3, 1, 3, 1], SnmpValue::Integer(voltage));
+            }
+
+            // upsOutput
+            let source = if status.flags.bypass_active {
+                4
+            } else if status.flags.utility_fail {
+                5
+            } else {
+                3
+            };
+            ups(&[4, 1, 0], SnmpValue::Integer(source));
+            ups(&[4, 3, 0], SnmpValue::Integer(1));
+            ups(&[4, 4, 1, 1, 1], SnmpValue::Integer(1));
+            if let Some(voltage) = scaled(status.output_voltage, 1.0) {
+                ups(&[4, 4, 1, 2, 1], SnmpValue::Integer(voltage));
+            }
+            if let Some(load) = scaled(status.load_raw, 1.0) {
+                ups(&[4, 4, 1, 5, 1], SnmpValue::Integer(load));
+            }
+        }
+
+        // upsAlarm
+        ups(&[6, 1, 0], SnmpValue::Gauge32(self.alarms.len() as u32));
+        for column in 1..=3 {
+            for (row, (alarm, time)) in self.alarms.iter().enumerate() {
+                let id = row as u32 + 1;
+                let value = match column {
+                    1 => SnmpValue::Integer(id as i64),
+                    2 => {
+                        let mut descr = UPS_MIB.to_vec();
+                        descr.extend_from_slice(&[1, 6, 3, *alarm]);
+                        SnmpValue::ObjectId(descr)
+                    }
+                    _ => SnmpValue::TimeTicks(*time),
+                };
+                ups(&[6, 2, 1, column, id], value);
+            }
+        }
+
+        // upsConfig
+        if let Some(rating) = &self.rating {
+            if let Some(voltage) = scaled(rating.rated_voltage, 1.0) {
+                ups(&[9, 1, 0], SnmpValue::Integer(voltage));
+            }
+            if let Some(frequency) = scaled(rating.rated_frequency, 10.0) {
+                ups(&[9, 2, 0], SnmpValue::Integer(frequency));
+            }
+        }
+
+        // system
+        let description = match &self.info {
+            Some(info) => format!("{} {} (Megatec protocol)", info.company, info.model),
+            None => "Megatec protocol UPS".to_string(),
+        };
+        for (suffix, value) in [
+            (1, string(&description)),
+            (2, SnmpValue::ObjectId(UPS_MIB.to_vec())),
+            (3, SnmpValue::TimeTicks(self.uptime())),
+        ] {
+            let mut oid = SYSTEM.to_vec();
+            oid.extend_from_slice(&[suffix, 0]);
+            mib.push((oid, value));
+        }
+
+        mib.sort_by(|(a, _), (b, _)| a.cmp(b));
+        mib
+    }
+
+    /// Agent uptime in hundredths of a second
+    fn uptime(&self) -> u32 {
+        (self.started.elapsed().as_millis() / 10) as u32
+    }
+}
+
+/// Object identifier with its value, an exception, or no value at all
+type Varbind = (Oid, Option<Encoded>);
+
+/// What is encoded in the value field of a varbind
+#[derive(Debug, Clone)]
+enum Encoded {
+    Value(SnmpValue),
+    Exception(u8),
+}
+
+/// SNMPv1 error reply echoing the request varbinds
+fn no_such_name(oids: &[Oid], index: usize) -> (i64, i64, Vec<Varbind>) {
+    let varbinds = oids.iter().map(|oid| (oid.clone(), None)).collect();
+    (NO_SUCH_NAME, index as i64 + 1, varbinds)
+}
+
+/// Scale a reading to the integer unit of the MIB, skipping values the UPS did not report
+fn scaled(value: f64, factor: f64) -> Option<i64> {
+    value.is_finite().then(|| (value * factor).round() as i64)
+}
+
+/// Encode a GetResponse message
+fn encode_response(
+    version: i64,
+    community: &str,
+    request_id: &[u8],
+    error_status: i64,
+    error_index: i64,
+    varbinds: &[Varbind],
+) -> Vec<u8> {
+    let mut list = Vec::new();
+    for (oid, value) in varbinds {
+        let value = match value {
+            Some(Encoded::Value(value)) => encode_value(value),
+            Some(Encoded::Exception(tag)) => tlv(*tag, &[]),
+            None => tlv(NULL, &[]),
+        };
+        let mut varbind = tlv(OBJECT_ID, &encode_oid(oid));
+        varbind.extend(value);
+        list.extend(tlv(SEQUENCE, &varbind));
+    }
+
+    let mut pdu = tlv(INTEGER, request_id);
+    pdu.extend(tlv(INTEGER, &encode_integer(error_status)));
+    pdu.extend(tlv(INTEGER, &encode_integer(error_index)));
+    pdu.extend(tlv(SEQUENCE, &list));
+
+    let mut message = tlv(INTEGER, &encode_integer(version));
+    message.extend(tlv(OCTET_STRING, community.as_bytes()));
+    message.extend(tlv(GET_RESPONSE, &pdu));
+    tlv(SEQUENCE, &message)
+}
+
+/// Encode a value with its tag
+fn encode_value(value: &SnmpValue) -> Vec<u8> {
+    match value {
+        SnmpValue::Integer(value) => tlv(INTEGER, &encode_integer(*value)),
+        SnmpValue::OctetString(bytes) => tlv(OCTET_STRING, bytes),
+        SnmpValue::ObjectId(oid) => tlv(OBJECT_ID, &encode_oid(oid)),
+        SnmpValue::Counter32(value) => tlv(COUNTER32, &encode_integer(*value as i64)),
+        SnmpValue::Gauge32(value) => tlv(GAUGE32, &encode_integer(*value as i64)),
+        SnmpValue::TimeTicks(value) => tlv(TIME_TICKS, &encode_integer(*value as i64)),
+    }
+}
+
+/// Wrap content in a tag and a definite length
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let length = content.len();
+    if length < 0x80 {
+        out.push(length as u8);
+    } else {
+        let bytes: Vec<u8> = length
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&b| b == 0)
+            .collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// Shortest two's complement encoding of an integer
+fn encode_integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    while start < 7 {
+        let redundant = (bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0);
+        if !redundant {
+            break;
+        }
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+fn decode_integer(content: &[u8]) -> Option<i64> {
+    if content.is_empty() || content.len() > 8 {
+        return None;
+    }
+    let mut value = if content[0] & 0x80 != 0 { -1i64 } else { 0 };
+    for &byte in content {
+        value = (value << 8) | byte as i64;
+    }
+    Some(value)
+}
+
+fn encode_oid(oid: &[u32]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let (first, rest) = match oid {
+        [a, b, rest @ ..] => (a * 40 + b, rest),
+        [a] => (a * 40, &[][..]),
+        [] => return out,
+    };
+    for arc in std::iter::once(first).chain(rest.iter().copied()) {
+        let mut chunk = vec![(arc & 0x7f) as u8];
+        let mut arc = arc >> 7;
+        while arc > 0 {
+            chunk.push(0x80 | (arc & 0x7f) as u8);
+            arc >>= 7;
+        }
+        out.extend(chunk.iter().rev());
+    }
+    out
+}
+
+fn decode_oid(content: &[u8]) -> Option<Oid> {
+    // The last arc must not continue past the content
+    if content.last()? & 0x80 != 0 {
+        return None;
+    }
+    let mut arcs = Vec::new();
+    let mut arc: u32 = 0;
+    for &byte in content {
+        arc = arc.checked_mul(128)? | (byte & 0x7f) as u32;
+        if byte & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        }
+    }
+    let first = *arcs.first()?;
+    let mut oid = vec![(first / 40).min(2), first - 40 * (first / 40).min(2)];
+    oid.extend_from_slice(&arcs[1..]);
+    Some(oid)
+}
+
+/// Cursor over BER encoded data
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Read the next tag and its content
+    fn read(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.data.split_first()?;
+        let (&first, rest) = rest.split_first()?;
+        let (length, rest) = if first & 0x80 == 0 {
+            (first as usize, rest)
+        } else {
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 || rest.len() < count {
+                return None;
+            }
+            let length = rest[..count]
+                .iter()
+                .fold(0usize, |length, &b| (length << 8) | b as usize);
+            (length, &rest[count..])
+        };
+        if rest.len() < length {
+            return None;
+        }
+        let (content, rest) = rest.split_at(length);
+        self.data = rest;
+        Some((tag, content))
+    }
+
+    /// Read the content of the next element, which must have the given tag
+    fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        match self.read()? {
+            (found, content) if found == tag => Some(content),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockTransport;
+
+    const AGENT_VERSION: [u32; 11] = [1, 3, 6, 1, 2, 1, 33, 1, 1, 4, 0];
+
+    fn agent() -> SnmpAgent<MockTransport> {
+        let ups = MegatecUps::with_transport(MockTransport::online());
+        SnmpAgent::bind(ups, "127.0.0.1:0", "public").unwrap()
+    }
+
+    fn request(pdu_type: u8, community: &str, oid: &[u32]) -> Vec<u8> {
+        let mut varbind = tlv(OBJECT_ID, &encode_oid(oid));
+        varbind.extend(tlv(NULL, &[]));
+        let mut pdu = tlv(INTEGER, &encode_integer(42));
+        pdu.extend(tlv(INTEGER, &[0]));
+        pdu.extend(tlv(INTEGER, &[0]));
+        pdu.extend(tlv(SEQUENCE, &tlv(SEQUENCE, &varbind)));
+        let mut message = tlv(INTEGER, &encode_integer(VERSION_2C));
+        message.extend(tlv(OCTET_STRING, community.as_bytes()));
+        message.extend(tlv(pdu_type, &pdu));
+        tlv(SEQUENCE, &message)
+    }
+
+    #[test]
+    fn integers_round_trip_in_their_shortest_form() {
+        for (value, bytes) in [
+            (0, vec![0x00]),
+            (127, vec![0x7f]),
+            (128, vec![0x00, 0x80]),
+            (256, vec![0x01, 0x00]),
+            (-1, vec![0xff]),
+            (-128, vec![0x80]),
+            (-129, vec![0xff, 0x7f]),
+        ] {
+            assert_eq!(encode_integer(value), bytes);
+            assert_eq!(decode_integer(&bytes), Some(value));
+        }
+        for value in [i64::MIN, i64::MAX, u32::MAX as i64] {
+            assert_eq!(decode_integer(&encode_integer(value)), Some(value));
+        }
+        assert_eq!(decode_integer(&[]), None);
+        assert_eq!(decode_integer(&[0; 9]), None);
+    }
+
+    #[test]
+    fn oids_round_trip() {
+        for oid in [
+            AGENT_VERSION.to_vec(),
+            vec![1, 3, 6, 1, 4, 1, 2021, 10, 1, 3, 1],
+            vec![2, 999, u32::MAX],
+        ] {
+            assert_eq!(decode_oid(&encode_oid(&oid)), Some(oid));
+        }
+        assert_eq!(encode_oid(&[1, 3, 6, 1]), vec![0x2b, 0x06, 0x01]);
+    }
+
+    #[test]
+    fn malformed_oids_are_rejected() {
+        assert_eq!(decode_oid(&[]), None);
+        // Unterminated last arc
+        assert_eq!(decode_oid(&[0x2b, 0x86]), None);
+        // Arc above 32 bits
+        assert_eq!(decode_oid(&[0x2b, 0x90, 0x80, 0x80, 0x80, 0x00]), None);
+    }
+
+    #[test]
+    fn reader_handles_long_and_malformed_lengths() {
+        let content = vec![7u8; 300];
+        let encoded = tlv(OCTET_STRING, &content);
+        assert_eq!(&encoded[..4], &[OCTET_STRING, 0x82, 0x01, 0x2c]);
+        assert_eq!(
+            Reader::new(&encoded).read(),
+            Some((OCTET_STRING, &content[..]))
+        );
+
+        for data in [
+            &[][..],
+            &[SEQUENCE],
+            // Indefinite length, too many length bytes, truncated length
+            &[SEQUENCE, 0x80],
+            &[SEQUENCE, 0x85, 0, 0, 0, 0, 1, 0],
+            &[SEQUENCE, 0x82, 0x01],
+            // Content shorter than its length
+            &[SEQUENCE, 0x03, 0x02, 0x01],
+            &[SEQUENCE, 0x84, 0xff, 0xff, 0xff, 0xff],
+        ] {
+            assert_eq!(Reader::new(data).read(), None, "{:02x?}", data);
+        }
+        assert_eq!(Reader::new(&[INTEGER, 0x01, 0x00]).expect(SEQUENCE), None);
+    }
+
+    #[test]
+    fn get_requests_are_answered() {
+        let mut agent = agent();
+        let reply = agent
+            .handle(&request(GET_REQUEST, "public", &AGENT_VERSION))
+            .unwrap();
+
+        let mut message = Reader::new(Reader::new(&reply).expect(SEQUENCE).unwrap());
+        assert_eq!(
+            decode_integer(message.expect(INTEGER).unwrap()),
+            Some(VERSION_2C)
+        );
+        assert_eq!(message.expect(OCTET_STRING), Some(&b"public"[..]));
+        let mut pdu = Reader::new(message.expect(GET_RESPONSE).unwrap());
+        assert_eq!(decode_integer(pdu.expect(INTEGER).unwrap()), Some(42));
+        assert_eq!(decode_integer(pdu.expect(INTEGER).unwrap()), Some(0));
+        assert_eq!(decode_integer(pdu.expect(INTEGER).unwrap()), Some(0));
+        let mut list = Reader::new(pdu.expect(SEQUENCE).unwrap());
+        let mut varbind = Reader::new(list.expect(SEQUENCE).unwrap());
+        assert_eq!(
+            decode_oid(varbind.expect(OBJECT_ID).unwrap()),
+            Some(AGENT_VERSION.to_vec())
+        );
+        let version = varbind.expect(OCTET_STRING).unwrap();
+        assert!(version.starts_with(env!("CARGO_PKG_NAME").as_bytes()));
+    }
+
+    #[test]
+    fn malformed_requests_are_dropped() {
+        let mut agent = agent();
+        let valid = request(GET_REQUEST, "public", &AGENT_VERSION);
+        assert!(agent
+            .handle(&request(GET_REQUEST, "private", &AGENT_VERSION))
+            .is_none());
+        assert!(agent
+            .handle(&request(0xa7, "public", &AGENT_VERSION))
+            .is_none());
+        for length in 0..valid.len() {
+            assert!(agent.handle(&valid[..length]).is_none(), "{} bytes", length);
+        }
+
+        // Nested sequences are not recursed into
+        let mut nested = Vec::new();
+        for _ in 0..10_000 {
+            nested = tlv(SEQUENCE, &nested);
+        }
+        assert!(agent.handle(&nested).is_none());
+    }
+}
@@ -0,0 +1,548 @@
+use crate::battery::BatteryModel;
+use crate::error::{Result, UpsError};
+use crate::event::UpsEvent;
+use crate::health::BatteryHealthReport;
+use crate::json::{FromJson, ToJson};
+use crate::outage::OutageStats;
+use crate::status::{StatusFlags, UpsStatus};
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::io;
+use std::path::Path;
+use std::ptr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_ROW: c_int = 100;
+const SQLITE_DONE: c_int = 101;
+const SQLITE_NULL: c_int = 5;
+
+const SQLITE_OPEN_READWRITE: c_int = 0x0000_0002;
+const SQLITE_OPEN_CREATE: c_int = 0x0000_0004;
+const SQLITE_OPEN_FULLMUTEX: c_int = 0x0001_0000;
+
+/// Destructor value telling SQLite to copy bound text (`SQLITE_TRANSIENT`)
+const SQLITE_TRANSIENT: isize = -1;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS samples (
+    time INTEGER NOT NULL,
+    input_voltage REAL,
+    input_fault_voltage REAL,
+    output_voltage REAL,
+    output_current REAL,
+    input_frequency REAL,
+    battery_voltage REAL,
+    temperature REAL,
+    utility_fail INTEGER NOT NULL,
+    battery_low INTEGER NOT NULL,
+    bypass_active INTEGER NOT NULL,
+    ups_failed INTEGER NOT NULL,
+    standby INTEGER NOT NULL,
+    test_in_progress INTEGER NOT NULL,
+    shutdown_active INTEGER NOT NULL,
+    beeper_on INTEGER NOT NULL,
+    load_in_amps INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS samples_time ON samples (time);
+CREATE TABLE IF NOT EXISTS events (
+    time INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    event TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS events_time ON events (time);
+";
+
+const SAMPLE_COLUMNS: &str = "time, input_voltage, input_fault_voltage, output_voltage, \
+output_current, input_frequency, battery_voltage, temperature, utility_fail, battery_low, \
+bypass_active, ups_failed, standby, test_in_progress, shutdown_active, beeper_on, load_in_amps";
+
+/// Column added to databases created before the unit of the load was stored,
+/// whose loads are taken for percentages
+const LOAD_UNIT_MIGRATION: &str =
+    "ALTER TABLE samples ADD COLUMN load_in_amps INTEGER NOT NULL DEFAULT 0";
+
+#[repr(C)]
+struct Sqlite3 {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct Sqlite3Stmt {
+    _private: [u8; 0],
+}
+
+#[link(name = "sqlite3")]
+extern "C" {
+    fn sqlite3_open_v2(
+        filename: *const c_char,
+        db: *mut *mut Sqlite3,
+        flags: c_int,
+        vfs: *const c_char,
+    ) -> c_int;
+    fn sqlite3_close(db: *mut Sqlite3) -> c_int;
+    fn sqlite3_errmsg(db: *mut Sqlite3) -> *const c_char;
+    fn sqlite3_exec(
+        db: *mut Sqlite3,
+        sql: *const c_char,
+        callback: *const c_void,
+        argument: *mut c_void,
+        error: *mut *mut c_char,
+    ) -> c_int;
+    fn sqlite3_prepare_v2(
+        db: *mut Sqlite3,
+        sql: *const c_char,
+        length: c_int,
+        statement: *mut *mut Sqlite3Stmt,
+        tail: *mut *const c_char,
+    ) -> c_int;
+    fn sqlite3_finalize(statement: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_step(statement: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_bind_int64(statement: *mut Sqlite3Stmt, index: c_int, value: i64) -> c_int;
+    fn sqlite3_bind_double(statement: *mut Sqlite3Stmt, index: c_int, value: f64) -> c_int;
+    fn sqlite3_bind_null(statement: *mut Sqlite3Stmt, index: c_int) -> c_int;
+    fn sqlite3_bind_text(
+        statement: *mut Sqlite3Stmt,
+        index: c_int,
+        text: *const c_char,
+        length: c_int,
+        destructor: isize,
+    ) -> c_int;
+    fn sqlite3_column_type(statement: *mut Sqlite3Stmt, column: c_int) -> c_int;
+    fn sqlite3_column_int64(statement: *mut Sqlite3Stmt, column: c_int) -> i64;
+    fn sqlite3_column_double(statement: *mut Sqlite3Stmt, column: c_int) -> f64;
+    fn sqlite3_column_text(statement: *mut Sqlite3Stmt, column: c_int) -> *const u8;
+}
+
+/// Status sample read back from the history
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub time: SystemTime,
+    pub status: UpsStatus,
+}
+
+/// Monitor event read back from the history
+#[derive(Debug, Clone)]
+pub struct EventRecord {
+    pub time: SystemTime,
+    pub event: UpsEvent,
+}
+
+/// Status samples and events stored in a local SQLite database
+///
+/// Samples go into the `samples` table with one column per value and flag,
+/// `output_current` holding the load in the unit `load_in_amps` tells, events into `events` with their [`UpsEvent::name`] and JSON form, and
+/// times are stored as Unix milliseconds, so the database can also be queried
+/// directly with `sqlite3`. Links against the system SQLite library.
+pub struct SqliteHistory {
+    db: *mut Sqlite3,
+}
+
+// SAFETY: the connection is opened in serialized mode (SQLITE_OPEN_FULLMUTEX),
+// so SQLite locks every call on it internally and it can be shared between threads
+unsafe impl Send for SqliteHistory {}
+unsafe impl Sync for SqliteHistory {}
+
+impl SqliteHistory {
+    /// Open or create a database, creating the tables when missing
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_string_lossy();
+        let path = CString::new(path.as_bytes()).map_err(|_| invalid_input("path contains NUL"))?;
+
+        let mut db = ptr::null_mut();
+        let flags = SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE | SQLITE_OPEN_FULLMUTEX;
+        // SAFETY: the path is NUL terminated and `db` receives the handle
+        let result = unsafe { sqlite3_open_v2(path.as_ptr(), &mut db, flags, ptr::null()) };
+        // Even on failure SQLite usually allocates a handle that must be closed
+        let history = Self { db };
+        if result != SQLITE_OK {
+            return Err(history.error());
+        }
+
+        history.exec(SCHEMA)?;
+        if history.prepare("SELECT load_in_amps FROM samples").is_err() {
+            history.exec(LOAD_UNIT_MIGRATION)?;
+        }
+        Ok(history)
+    }
+
+    /// Store a sample taken now
+    pub fn record(&self, status: &UpsStatus) -> Result<()> {
+        self.record_at(SystemTime::now(), status)
+    }
+
+    /// Store a sample taken at `time`
+    pub fn record_at(&self, time: SystemTime, status: &UpsStatus) -> Result<()> {
+        let sql = format!(
+            "INSERT INTO samples ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, \
+             ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+            SAMPLE_COLUMNS
+        );
+        let statement = self.prepare(&sql)?;
+        statement.bind_int(1, to_millis(time))?;
+        let values = [
+            status.input_voltage,
+            status.input_fault_voltage,
+            status.output_voltage,
+            status.load_raw,
+            status.input_frequency,
+            status.battery_voltage,
+            status.temperature,
+        ];
+        for (index, value) in (2..).zip(values) {
+            statement.bind_real(index, value)?;
+        }
+        for (index, bit) in (9..).zip(flag_bits(&status.flags)) {
+            statement.bind_int(index, i64::from(bit))?;
+        }
+        statement.bind_int(17, i64::from(status.load_in_amps))?;
+        statement.execute()
+    }
+
+    /// Store an event that happened now
+    pub fn record_event(&self, event: &UpsEvent) -> Result<()> {
+        self.record_event_at(SystemTime::now(), event)
+    }
+
+    /// Store an event that happened at `time`
+    pub fn record_event_at(&self, time: SystemTime, event: &UpsEvent) -> Result<()> {
+        let statement =
+            self.prepare("INSERT INTO events (time, name, event) VALUES (?1, ?2, ?3)")?;
+        statement.bind_int(1, to_millis(time))?;
+        statement.bind_text(2, event.name())?;
+        statement.bind_text(3, &event.to_json_string())?;
+        statement.execute()
+    }
+
+    /// Get the samples taken from `from` (inclusive) to `to` (exclusive), oldest first
+    pub fn range(&self, from: SystemTime, to: SystemTime) -> Result<Vec<Sample>> {
+        let sql = format!(
+            "SELECT {} FROM samples WHERE time >= ?1 AND time < ?2 ORDER BY time",
+            SAMPLE_COLUMNS
+        );
+        let statement = self.prepare(&sql)?;
+        statement.bind_int(1, to_millis(from))?;
+        statement.bind_int(2, to_millis(to))?;
+
+        let mut samples = Vec::new();
+        while statement.next_row()? {
+            let bits: Vec<bool> = (8..16).map(|column| statement.int(column) != 0).collect();
+            #[allow(deprecated)]
+            let status = UpsStatus {
+                input_voltage: statement.real(1),
+                input_fault_voltage: statement.real(2),
+                output_voltage: statement.real(3),
+                load_raw: statement.real(4),
+                output_current: statement.real(4),
+                load_in_amps: statement.int(16) != 0,
+                input_frequency: statement.real(5),
+                battery_voltage: statement.real(6),
+                temperature: statement.real(7),
+                flags: StatusFlags {
+                    utility_fail: bits[0],
+                    battery_low: bits[1],
+                    bypass_active: bits[2],
+                    ups_failed: bits[3],
+                    standby: bits[4],
+                    test_in_progress: bits[5],
+                    shutdown_active: bits[6],
+                    beeper_on: bits[7],
+                },
+            };
+            samples.push(Sample {
+                time: from_millis(statement.int(0)),
+                status,
+            });
+        }
+        Ok(samples)
+    }
+
+    /// Compute the battery health indicators of the samples from `from` to `to`
+    pub fn battery_health(
+        &self,
+        from: SystemTime,
+        to: SystemTime,
+        model: &BatteryModel,
+    ) -> Result<BatteryHealthReport> {
+        let samples: Vec<(SystemTime, UpsStatus)> = self
+            .range(from, to)?
+            .into_iter()
+            .map(|sample| (sample.time, sample.status))
+            .collect();
+        Ok(BatteryHealthReport::from_samples(&samples, model))
+    }
+
+    /// Get every stored event, oldest first
+    pub fn events(&self) -> Result<Vec<EventRecord>> {
+        self.select_events(0, i64::MAX)
+    }
+
+    /// Get the events from `from` (inclusive) to `to` (exclusive), oldest first
+    pub fn events_range(&self, from: SystemTime, to: SystemTime) -> Result<Vec<EventRecord>> {
+        self.select_events(to_millis(from), to_millis(to))
+    }
+
+    /// Compute the outage counters from the stored power failure events
+    pub fn outage_stats(&self) -> Result<OutageStats> {
+        let statement = self.prepare(
+            "SELECT time, event FROM events WHERE name IN ('power-failure', 'power-restored') \
+             ORDER BY time",
+        )?;
+        let mut events = Vec::new();
+        while statement.next_row()? {
+            let event = UpsEvent::from_json_str(&statement.text(1))
+                .map_err(|e| UpsError::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+            events.push((from_millis(statement.int(0)), event));
+        }
+        Ok(OutageStats::from_events(
+            events.iter().map(|(time, event)| (*time, event)),
+        ))
+    }
+
+    /// Read the events between two times in Unix milliseconds
+    fn select_events(&self, from: i64, to: i64) -> Result<Vec<EventRecord>> {
+        let statement = self.prepare(
+            "SELECT time, event FROM events WHERE time >= ?1 AND time < ?2 ORDER BY time",
+        )?;
+        statement.bind_int(1, from)?;
+        statement.bind_int(2, to)?;
+
+        let mut events = Vec::new();
+        while statement.next_row()? {
+            let event = UpsEvent::from_json_str(&statement.text(1))
+                .map_err(|e| UpsError::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+            events.push(EventRecord {
+                time: from_millis(statement.int(0)),
+                event,
+            });
+        }
+        Ok(events)
+    }
+
+    /// Run statements returning no rows
+    fn exec(&self, sql: &str) -> Result<()> {
+        let sql = CString::new(sql).map_err(|_| invalid_input("SQL contains NUL"))?;
+        // SAFETY: the handle is open and the SQL is NUL terminated
+        let result = unsafe {
+            sqlite3_exec(
+                self.db,
+                sql.as_ptr(),
+                ptr::null(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        self.check(result)
+    }
+
+    /// Compile a statement
+    fn prepare(&self, sql: &str) -> Result<Statement<'_>> {
+        let sql = CString::new(sql).map_err(|_| invalid_input("SQL contains NUL"))?;
+        let mut statement = ptr::null_mut();
+        // SAFETY: the handle is open, the SQL is NUL terminated and `statement` receives the result
+        let result = unsafe {
+            sqlite3_prepare_v2(self.db, sql.as_ptr(), -1, &mut statement, ptr::null_mut())
+        };
+        self.check(result)?;
+        Ok(Statement {
+            history: self,
+            statement,
+        })
+    }
+
+    /// Turn a result code into the error message of the connection
+    fn check(&self, result: c_int) -> Result<()> {
+        if result == SQLITE_OK {
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    fn error(&self) -> UpsError {
+        let message = if self.db.is_null() {
+            "out of memory".to_string()
+        } else {
+            // SAFETY: the handle is valid and the message is a NUL terminated string owned by SQLite
+            unsafe { CStr::from_ptr(sqlite3_errmsg(self.db)) }
+                .to_string_lossy()
+                .into_owned()
+        };
+        UpsError::Io(io::Error::other(format!("SQLite: {}", message)))
+    }
+}
+
+impl Drop for SqliteHistory {
+    fn drop(&mut self) {
+        // SAFETY: every statement borrows the connection, so none is left unfinalized
+        unsafe {
+            sqlite3_close(self.db);
+        }
+    }
+}
+
+/// Prepared statement, finalized on drop
+struct Statement<'a> {
+    history: &'a SqliteHistory,
+    statement: *mut Sqlite3Stmt,
+}
+
+impl Statement<'_> {
+    fn bind_int(&self, index: c_int, value: i64) -> Result<()> {
+        // SAFETY: the statement is valid until dropped
+        self.history
+            .check(unsafe { sqlite3_bind_int64(self.statement, index, value) })
+    }
+
+    /// Bind a value, `NaN` (a value missing from the UPS reply) as NULL
+    fn bind_real(&self, index: c_int, value: f64) -> Result<()> {
+        // SAFETY: the statement is valid until dropped
+        let result = unsafe {
+            if value.is_nan() {
+                sqlite3_bind_null(self.statement, index)
+            } else {
+                sqlite3_bind_double(self.statement, index, value)
+            }
+        };
+        self.history.check(result)
+    }
+
+    fn bind_text(&self, index: c_int, text: &str) -> Result<()> {
+        let length = c_int::try_from(text.len()).map_err(|_| invalid_input("text too long"))?;
+        // SAFETY: SQLITE_TRANSIENT makes SQLite copy the text before the call returns
+        let result = unsafe {
+            sqlite3_bind_text(
+                self.statement,
+                index,
+                text.as_ptr() as *const c_char,
+                length,
+                SQLITE_TRANSIENT,
+            )
+        };
+        self.history.check(result)
+    }
+
+    /// Run a statement returning no rows
+    fn execute(&self) -> Result<()> {
+        match self.next_row()? {
+            false => Ok(()),
+            true => Err(UpsError::InvalidResponse),
+        }
+    }
+
+    /// Step to the next row, `false` once done
+    fn next_row(&self) -> Result<bool> {
+        // SAFETY: the statement is valid until dropped
+        match unsafe { sqlite3_step(self.statement) } {
+            SQLITE_ROW => Ok(true),
+            SQLITE_DONE => Ok(false),
+            _ => Err(self.history.error()),
+        }
+    }
+
+    fn int(&self, column: c_int) -> i64 {
+        // SAFETY: called on a row returned by next_row
+        unsafe { sqlite3_column_int64(self.statement, column) }
+    }
+
+    /// Read a value, NULL as `NaN`
+    fn real(&self, column: c_int) -> f64 {
+        // SAFETY: called on a row returned by next_row
+        unsafe {
+            if sqlite3_column_type(self.statement, column) == SQLITE_NULL {
+                f64::NAN
+            } else {
+                sqlite3_column_double(self.statement, column)
+            }
+        }
+    }
+
+    fn text(&self, column: c_int) -> String {
+        // SAFETY: called on a row returned by next_row; the text is NUL terminated
+        // and valid until the next step
+        unsafe {
+            let text = sqlite3_column_text(self.statement, column);
+            if text.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(text as *const c_char)
+                    .to_string_lossy()
+                    .into_owned()
+            }
+        }
+    }
+}
+
+impl Drop for Statement<'_> {
+    fn drop(&mut self) {
+        // SAFETY: the statement is finalized exactly once
+        unsafe {
+            sqlite3_finalize(self.statement);
+        }
+    }
+}
+
+/// Flags in the order of the sample columns
+fn flag_bits(flags: &StatusFlags) -> [bool; 8] {
+    [
+        flags.utility_fail,
+        flags.battery_low,
+        flags.bypass_active,
+        flags.ups_failed,
+        flags.standby,
+        flags.test_in_progress,
+        flags.shutdown_active,
+        flags.beeper_on,
+    ]
+}
+
+fn to_millis(time: SystemTime) -> i64 {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    i64::try_from(millis).unwrap_or(i64::MAX)
+}
+
+fn from_millis(millis: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64)
+}
+
+fn invalid_input(message: &str) -> UpsError {
+    UpsError::Io(io::Error::new(io::ErrorKind::InvalidInput, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{parse_status, StatusFormat};
+    use std::{env, fs, process};
+
+    #[test]
+    fn databases_without_the_load_unit_are_migrated() {
+        let path = env::temp_dir().join(format!("megatec-ups-history-{}.db", process::id()));
+        let _ = fs::remove_file(&path);
+        let old = SqliteHistory::open(&path).unwrap();
+        old.exec("DROP TABLE samples").unwrap();
+        old.exec(&SCHEMA.replace(",\n    load_in_amps INTEGER NOT NULL DEFAULT 0", ""))
+            .unwrap();
+        old.exec("INSERT INTO samples VALUES (1000, 230, 230, 230, 20, 50, 13.6, 25, 0, 0, 0, 0, 0, 0, 0, 1)")
+            .unwrap();
+        assert!(old.prepare("SELECT load_in_amps FROM samples").is_err());
+        drop(old);
+
+        let history = SqliteHistory::open(&path).unwrap();
+        let answer = "230.0 230.0 230.0 002 50.0 13.6 25.0 00000001";
+        let mut status = parse_status(answer, StatusFormat::STRICT).unwrap();
+        status.load_in_amps = true;
+        history.record_at(from_millis(2000), &status).unwrap();
+        let samples = history.range(UNIX_EPOCH, from_millis(3000)).unwrap();
+        drop(history);
+        fs::remove_file(&path).unwrap();
+
+        let loads: Vec<(f64, bool)> = samples
+            .iter()
+            .map(|sample| (sample.status.load_raw, sample.status.load_in_amps))
+            .collect();
+        assert_eq!(loads, [(20.0, false), (2.0, true)]);
+    }
+}
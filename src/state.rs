@@ -0,0 +1,148 @@
+use crate::status::{StatusFlags, UpsStatus};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Operating state of the UPS derived from its status flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UpsState {
+    /// An online (double conversion) UPS running from utility power
+    Online,
+    /// A standby (offline) UPS running the load from utility power
+    Standby,
+    /// On utility power with bypass or boost/buck active
+    Bypass,
+    /// Utility power failed and the UPS runs from battery
+    OnBattery,
+    /// Running from battery and the battery is low
+    LowBattery,
+    /// The UPS reports a failure
+    Fault,
+    /// The UPS does not answer
+    CommLost,
+}
+
+impl UpsState {
+    /// Map the status flags to a state, the most severe condition winning
+    pub fn from_flags(flags: &StatusFlags) -> Self {
+        if flags.ups_failed {
+            UpsState::Fault
+        } else if flags.on_battery() && flags.battery_low {
+            UpsState::LowBattery
+        } else if flags.on_battery() {
+            UpsState::OnBattery
+        } else if flags.bypass_active {
+            UpsState::Bypass
+        } else if flags.standby {
+            UpsState::Standby
+        } else {
+            UpsState::Online
+        }
+    }
+
+    /// Check if the load is fed from utility power
+    pub fn on_line(&self) -> bool {
+        matches!(
+            self,
+            UpsState::Online | UpsState::Standby | UpsState::Bypass
+        )
+    }
+
+    /// Get the state name as used in logs
+    pub fn name(&self) -> &'static str {
+        match self {
+            UpsState::Online => "online",
+            UpsState::Standby => "standby",
+            UpsState::Bypass => "bypass",
+            UpsState::OnBattery => "on-battery",
+            UpsState::LowBattery => "low-battery",
+            UpsState::Fault => "fault",
+            UpsState::CommLost => "comm-lost",
+        }
+    }
+}
+
+impl fmt::Display for UpsState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Change from one state to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateTransition {
+    /// State before the change, `None` for the first reading
+    pub from: Option<UpsState>,
+    pub to: UpsState,
+    /// Time spent in the previous state
+    pub duration: Duration,
+}
+
+impl fmt::Display for StateTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.from {
+            Some(from) => write!(f, "{} -> {}", from, self.to),
+            None => write!(f, "{}", self.to),
+        }
+    }
+}
+
+/// Tracks the state of the UPS across status readings
+///
+/// Feed every status reading to [`update`](Self::update) and every failed
+/// read to [`communication_lost`](Self::communication_lost); both return the
+/// transition when the state changed.
+#[derive(Debug)]
+pub struct UpsStateMachine {
+    state: Option<UpsState>,
+    since: Instant,
+}
+
+impl Default for UpsStateMachine {
+    fn default() -> Self {
+        Self {
+            state: None,
+            since: Instant::now(),
+        }
+    }
+}
+
+impl UpsStateMachine {
+    /// Create a state machine with no reading yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the current state, `None` before the first reading
+    pub fn current_state(&self) -> Option<UpsState> {
+        self.state
+    }
+
+    /// Get the time spent in the current state
+    pub fn time_in_state(&self) -> Duration {
+        self.since.elapsed()
+    }
+
+    /// Update from a status reading
+    pub fn update(&mut self, status: &UpsStatus) -> Option<StateTransition> {
+        self.enter(UpsState::from_flags(&status.flags))
+    }
+
+    /// Update after the UPS failed to answer
+    pub fn communication_lost(&mut self) -> Option<StateTransition> {
+        self.enter(UpsState::CommLost)
+    }
+
+    fn enter(&mut self, state: UpsState) -> Option<StateTransition> {
+        if self.state == Some(state) {
+            return None;
+        }
+        let transition = StateTransition {
+            from: self.state,
+            to: state,
+            duration: self.since.elapsed(),
+        };
+        self.state = Some(state);
+        self.since = Instant::now();
+        Some(transition)
+    }
+}
@@ -0,0 +1,292 @@
+use crate::error::{Result, UpsError};
+use crate::protocol::{self, StatusFormat};
+use crate::quirks::Quirks;
+use crate::rating::RatingInfo;
+use std::fmt;
+use std::str::FromStr;
+
+/// Structure representing the UPS status values
+#[derive(Debug, Clone)]
+pub struct UpsStatus {
+    pub input_voltage: f64,
+    pub input_fault_voltage: f64,
+    pub output_voltage: f64,
+    /// Load field of the answer, a percentage of the rated load on most units
+    /// but amps on a few; see [`load_percent`](Self::load_percent)
+    pub load_raw: f64,
+    /// Former name of the load field, holding the same value as `load_raw`
+    #[deprecated(
+        note = "a percentage on most units but amps on a few, use `load_raw` or `load_percent`"
+    )]
+    pub output_current: f64,
+    /// The load field is the output current in amps, from the
+    /// [`load_in_amps`](Quirks::load_in_amps) quirk of the parsing unit
+    pub load_in_amps: bool,
+    pub input_frequency: f64,
+    pub battery_voltage: f64,
+    pub temperature: f64,
+    pub flags: StatusFlags,
+}
+
+impl UpsStatus {
+    /// Get the status with typed units, the load as a ratio rather than a current
+    #[cfg(feature = "units")]
+    pub fn typed(&self) -> crate::units::UpsStatusTyped {
+        self.into()
+    }
+
+    /// Get the load in percent of the rated load
+    ///
+    /// Units with the [`load_in_amps`](Quirks::load_in_amps) quirk report the
+    /// output current, which is converted with the rated current (NaN when
+    /// the rating lacks it).
+    pub fn load_percent(&self, rating: &RatingInfo, quirks: &Quirks) -> f64 {
+        if !quirks.load_in_amps {
+            self.load_raw
+        } else if rating.rated_current > 0.0 {
+            self.load_raw / rating.rated_current * 100.0
+        } else {
+            f64::NAN
+        }
+    }
+
+    /// Copy of the status with the load field converted to percent by [`load_percent`](Self::load_percent)
+    #[allow(deprecated)]
+    pub(crate) fn in_percent(&self, rating: &RatingInfo, quirks: &Quirks) -> Self {
+        let load = self.load_percent(rating, quirks);
+        Self {
+            load_raw: load,
+            output_current: load,
+            load_in_amps: false,
+            ..self.clone()
+        }
+    }
+
+    /// Parse a status string exactly as the protocol defines it
+    ///
+    /// Unlike [`from_str`](Self::from_str), a leading '(' and comma decimal
+    /// separators are rejected.
+    pub fn parse_strict(status: &str) -> Result<Self> {
+        Self::parse(status, &Quirks::NONE)
+    }
+
+    /// Parse status string, tolerating the formatting of firmware clones
+    ///
+    /// A leading '(' and comma decimal separators ("229,5") are accepted.
+    /// Missing trailing fields, reported as NaN with cleared flags, are only
+    /// accepted with the `truncated_status` quirk.
+    pub(crate) fn parse_tolerant(status: &str, quirks: &Quirks) -> Result<Self> {
+        let format = StatusFormat {
+            truncated: quirks.truncated_status,
+            ..StatusFormat::TOLERANT
+        };
+        let mut status = protocol::parse_status(status, format)?;
+        status.load_in_amps = quirks.load_in_amps;
+        Ok(status)
+    }
+
+    /// Parse status string into UpsStatus struct, accounting for model quirks
+    pub(crate) fn parse(status: &str, quirks: &Quirks) -> Result<Self> {
+        let format = StatusFormat {
+            comma_decimal: quirks.comma_decimal,
+            truncated: quirks.truncated_status,
+            leading_paren: false,
+        };
+        let mut status = protocol::parse_status(status, format)?;
+        status.load_in_amps = quirks.load_in_amps;
+        Ok(status)
+    }
+}
+
+/// Tolerant parsing of a Q1 answer, see [`UpsStatus::parse_strict`] for the strict one
+impl FromStr for UpsStatus {
+    type Err = UpsError;
+
+    fn from_str(status: &str) -> Result<Self> {
+        Self::parse_tolerant(status, &Quirks::NONE)
+    }
+}
+
+/// Compact one-line summary, or one line per value with the alternate flag (`{:#}`)
+impl fmt::Display for UpsStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            writeln!(f, "Input Voltage: {:.1} V", self.input_voltage)?;
+            writeln!(f, "Input Fault Voltage: {:.1} V", self.input_fault_voltage)?;
+            writeln!(f, "Output Voltage: {:.1} V", self.output_voltage)?;
+            if self.load_in_amps {
+                writeln!(f, "Load: {:.1} A", self.load_raw)?;
+            } else {
+                writeln!(f, "Load: {:.0}%", self.load_raw)?;
+            }
+            writeln!(f, "Input Frequency: {:.1} Hz", self.input_frequency)?;
+            writeln!(f, "Battery Voltage: {:.2} V", self.battery_voltage)?;
+            writeln!(f, "Temperature: {:.1} °C", self.temperature)?;
+            write!(f, "Status: {}", self.flags)
+        } else {
+            let load = if self.load_in_amps {
+                format!("{:.1}A", self.load_raw)
+            } else {
+                format!("{:.0}%", self.load_raw)
+            };
+            write!(
+                f,
+                "IN {:.1}V OUT {:.1}V LOAD {} BAT {:.1}V {:.1}°C {}",
+                self.input_voltage,
+                self.output_voltage,
+                load,
+                self.battery_voltage,
+                self.temperature,
+                self.flags
+            )
+        }
+    }
+}
+
+/// Structure representing the status flags byte reported by the UPS
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatusFlags {
+    /// Utility power has failed and the UPS is running on battery
+    pub utility_fail: bool,
+    /// Battery is low
+    pub battery_low: bool,
+    /// Bypass or boost/buck is active
+    pub bypass_active: bool,
+    /// UPS has failed
+    pub ups_failed: bool,
+    /// UPS is a standby (offline) type rather than online
+    pub standby: bool,
+    /// A test is in progress
+    pub test_in_progress: bool,
+    /// A shutdown is active
+    pub shutdown_active: bool,
+    /// Beeper is on
+    pub beeper_on: bool,
+}
+
+impl StatusFlags {
+    /// Check if the UPS is running on battery
+    pub fn on_battery(&self) -> bool {
+        self.utility_fail
+    }
+}
+
+/// Active conditions such as "ON-BATTERY LOW-BATTERY", or one line per flag with the alternate flag (`{:#}`)
+impl fmt::Display for StatusFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+            writeln!(f, "Utility Fail: {}", yes_no(self.utility_fail))?;
+            writeln!(f, "Battery Low: {}", yes_no(self.battery_low))?;
+            writeln!(f, "Bypass Active: {}", yes_no(self.bypass_active))?;
+            writeln!(f, "UPS Failed: {}", yes_no(self.ups_failed))?;
+            writeln!(f, "Standby: {}", yes_no(self.standby))?;
+            writeln!(f, "Test In Progress: {}", yes_no(self.test_in_progress))?;
+            writeln!(f, "Shutdown Active: {}", yes_no(self.shutdown_active))?;
+            return write!(f, "Beeper On: {}", yes_no(self.beeper_on));
+        }
+
+        f.write_str(if self.utility_fail {
+            "ON-BATTERY"
+        } else {
+            "ON-LINE"
+        })?;
+
+        let conditions = [
+            (self.battery_low, "LOW-BATTERY"),
+            (self.bypass_active, "BYPASS"),
+            (self.ups_failed, "FAILED"),
+            (self.test_in_progress, "TEST"),
+            (self.shutdown_active, "SHUTDOWN"),
+        ];
+        for (_, name) in conditions.iter().filter(|(active, _)| *active) {
+            write!(f, " {}", name)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Outcome of a battery test, read back from the status flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestResult {
+    /// The test is running
+    InProgress,
+    /// The test ended without the UPS reporting a failure
+    Passed,
+    /// The UPS reports a failure
+    Failed,
+    /// No test is running although one was requested, for example because
+    /// the UPS is on battery or does not support the test
+    NotStarted,
+}
+
+impl TestResult {
+    /// Result right after a test was requested
+    pub(crate) fn after_start(flags: &StatusFlags) -> Self {
+        if flags.ups_failed {
+            TestResult::Failed
+        } else if flags.test_in_progress {
+            TestResult::InProgress
+        } else {
+            TestResult::NotStarted
+        }
+    }
+
+    /// Result while waiting for a running test, `None` while it still runs
+    pub(crate) fn after_wait(flags: &StatusFlags) -> Option<Self> {
+        if flags.ups_failed {
+            Some(TestResult::Failed)
+        } else if flags.test_in_progress {
+            None
+        } else {
+            Some(TestResult::Passed)
+        }
+    }
+
+    /// Check if the test ended or was refused
+    pub fn is_done(&self) -> bool {
+        *self != TestResult::InProgress
+    }
+}
+
+impl fmt::Display for TestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TestResult::InProgress => "in progress",
+            TestResult::Passed => "passed",
+            TestResult::Failed => "failed",
+            TestResult::NotStarted => "not started",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANSWER: &str = "229.5 229.5 230.1 012 50.1 27.3 30.5 00001000";
+
+    #[test]
+    #[allow(deprecated)]
+    fn former_load_field_matches_load_raw() {
+        let status = UpsStatus::parse_strict(ANSWER).unwrap();
+        assert_eq!(status.load_raw, 12.0);
+        assert_eq!(status.output_current, status.load_raw);
+    }
+
+    #[test]
+    fn display_uses_the_unit_of_the_load() {
+        let percent = UpsStatus::parse(ANSWER, &Quirks::NONE).unwrap();
+        assert!(percent.to_string().contains("LOAD 12%"));
+        assert!(format!("{:#}", percent).contains("Load: 12%"));
+
+        let quirks = Quirks {
+            load_in_amps: true,
+            ..Quirks::NONE
+        };
+        let amps = UpsStatus::parse(ANSWER, &quirks).unwrap();
+        assert!(amps.to_string().contains("LOAD 12.0A"));
+        assert!(format!("{:#}", amps).contains("Load: 12.0 A"));
+    }
+}
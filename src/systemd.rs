@@ -0,0 +1,177 @@
+use crate::error::Result;
+use crate::event::UpsEvent;
+use std::env;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::{Duration, Instant};
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+// Syslog priorities used by the journal
+const PRIORITY_CRITICAL: u8 = 2;
+const PRIORITY_WARNING: u8 = 4;
+const PRIORITY_NOTICE: u8 = 5;
+const PRIORITY_INFO: u8 = 6;
+
+/// Send a state string such as `READY=1` to the service manager
+///
+/// Returns `false` when the process was not started by systemd with
+/// `Type=notify` (no `NOTIFY_SOCKET` in the environment).
+pub fn notify(state: &str) -> Result<bool> {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+    let path = path.to_string_lossy();
+
+    let address = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes())?,
+        None => SocketAddr::from_pathname(path.as_ref())?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &address)?;
+    Ok(true)
+}
+
+/// Tell systemd the service finished starting up
+pub fn notify_ready() -> Result<bool> {
+    notify("READY=1")
+}
+
+/// Tell systemd the service is shutting down
+pub fn notify_stopping() -> Result<bool> {
+    notify("STOPPING=1")
+}
+
+/// Set the one-line status shown by `systemctl status`
+pub fn notify_status(status: &str) -> Result<bool> {
+    notify(&format!("STATUS={}", status.replace('\n', " ")))
+}
+
+/// Watchdog interval requested with `WatchdogSec=`, if it applies to this process
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Pings the systemd watchdog, meant to be fed after every successful poll
+///
+/// Pings are sent at most every half watchdog interval, as sd_watchdog_enabled(3)
+/// recommends. When polls keep failing no ping is sent and systemd restarts the
+/// service once the interval runs out.
+#[derive(Debug)]
+pub struct Watchdog {
+    interval: Option<Duration>,
+    last_ping: Option<Instant>,
+}
+
+impl Watchdog {
+    /// Create a watchdog from the environment set up by systemd
+    pub fn from_env() -> Self {
+        Self {
+            interval: watchdog_interval(),
+            last_ping: None,
+        }
+    }
+
+    /// Check if systemd asked for watchdog pings
+    pub fn is_enabled(&self) -> bool {
+        self.interval.is_some()
+    }
+
+    /// Record a successful poll, pinging systemd when a ping is due
+    pub fn poll_succeeded(&mut self) -> Result<()> {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+        if self
+            .last_ping
+            .is_some_and(|ping| ping.elapsed() < interval / 2)
+        {
+            return Ok(());
+        }
+        notify("WATCHDOG=1")?;
+        self.last_ping = Some(Instant::now());
+        Ok(())
+    }
+}
+
+/// Structured logging to journald through its native protocol
+pub struct Journal {
+    socket: UnixDatagram,
+    identifier: String,
+}
+
+impl Journal {
+    /// Connect to the journal, tagging entries with `identifier`
+    pub fn open(identifier: &str) -> Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNAL_SOCKET)?;
+        Ok(Self {
+            socket,
+            identifier: identifier.to_string(),
+        })
+    }
+
+    /// Send an entry with a syslog priority and extra fields
+    ///
+    /// Field names must be uppercase ASCII letters, digits and underscores.
+    pub fn send(&self, priority: u8, message: &str, fields: &[(&str, String)]) -> Result<()> {
+        let mut entry = Vec::new();
+        append_field(&mut entry, "MESSAGE", message);
+        append_field(&mut entry, "PRIORITY", &priority.to_string());
+        append_field(&mut entry, "SYSLOG_IDENTIFIER", &self.identifier);
+        for (name, value) in fields {
+            append_field(&mut entry, name, value);
+        }
+        self.socket.send(&entry)?;
+        Ok(())
+    }
+
+    /// Log a monitor event with the status values as fields
+    pub fn log_event(&self, event: &UpsEvent) -> Result<()> {
+        let priority = match event {
+            UpsEvent::LowBattery(_) => PRIORITY_CRITICAL,
+            UpsEvent::PowerFailure(_)
+            | UpsEvent::OverTemperature(_)
+            | UpsEvent::Overload(_)
+            | UpsEvent::CommunicationLost => PRIORITY_WARNING,
+            UpsEvent::PowerRestored(_) | UpsEvent::CommunicationRestored(_) => PRIORITY_NOTICE,
+            UpsEvent::TestSkipped(_) => PRIORITY_NOTICE,
+            UpsEvent::TestStarted(_) | UpsEvent::TestFinished(_) => PRIORITY_INFO,
+        };
+
+        let mut fields = vec![("UPS_EVENT", event.name().to_string())];
+        if let Some(status) = event.status() {
+            fields.extend([
+                ("UPS_INPUT_VOLTAGE", status.input_voltage.to_string()),
+                ("UPS_OUTPUT_VOLTAGE", status.output_voltage.to_string()),
+                ("UPS_LOAD", status.load_raw.to_string()),
+                ("UPS_BATTERY_VOLTAGE", status.battery_voltage.to_string()),
+                ("UPS_TEMPERATURE", status.temperature.to_string()),
+                ("UPS_ON_BATTERY", status.flags.on_battery().to_string()),
+            ]);
+        }
+
+        self.send(priority, &event.to_string(), &fields)
+    }
+}
+
+/// Append a field, using the length-prefixed form for values spanning lines
+fn append_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
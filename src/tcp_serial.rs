@@ -0,0 +1,352 @@
+use crate::error::{Result, UpsError};
+use crate::protocol::{command_text, decode_response, expects_response};
+use crate::transport::{Command, Response, Transport};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const CHAR_CR: u8 = b'\r';
+const MAX_RESPONSE_LENGTH: usize = 256;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+const OPTION_BINARY: u8 = 0;
+const OPTION_SUPPRESS_GO_AHEAD: u8 = 3;
+const OPTION_COM_PORT: u8 = 44;
+
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const PARITY_NONE: u8 = 1;
+const STOPSIZE_ONE: u8 = 1;
+const CONTROL_NO_FLOW_CONTROL: u8 = 1;
+
+/// How the serial bridge carries the bytes of the UPS port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpSerialMode {
+    /// Bytes passed through unchanged, as by ser2net `raw` ports or ESP-Link
+    Raw,
+    /// Telnet with the RFC 2217 com port option, which also sets the port to 2400 8N1
+    Rfc2217,
+}
+
+/// Transport talking to a UPS behind a serial-to-TCP bridge
+///
+/// Terminal servers, ser2net and ESP based bridges expose the RS-232 port of
+/// the UPS on a TCP port; the Q1 command lines of the serial transport are
+/// sent over the connection. Raw bridges must already be set to 2400 8N1.
+pub struct TcpSerialTransport {
+    connection: Mutex<Connection>,
+    mode: TcpSerialMode,
+    timeout: Duration,
+}
+
+struct Connection {
+    stream: TcpStream,
+    /// Telnet state in RFC 2217 mode, raw bridges pass every byte through
+    telnet: Option<Telnet>,
+}
+
+impl TcpSerialTransport {
+    /// Connect to a bridge passing the serial bytes through unchanged
+    pub fn connect(address: impl ToSocketAddrs) -> Result<Self> {
+        Self::connect_with_mode(address, TcpSerialMode::Raw)
+    }
+
+    /// Connect to an RFC 2217 server and set its serial port to 2400 8N1
+    pub fn connect_rfc2217(address: impl ToSocketAddrs) -> Result<Self> {
+        Self::connect_with_mode(address, TcpSerialMode::Rfc2217)
+    }
+
+    /// Connect to a bridge speaking the given mode
+    pub fn connect_with_mode(address: impl ToSocketAddrs, mode: TcpSerialMode) -> Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        let mut connection = Connection {
+            stream,
+            telnet: None,
+        };
+        if mode == TcpSerialMode::Rfc2217 {
+            connection.negotiate()?;
+        }
+        Ok(Self {
+            connection: Mutex::new(connection),
+            mode,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    pub fn mode(&self) -> TcpSerialMode {
+        self.mode
+    }
+
+    /// Get how long a command waits for its whole answer line
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Set how long a command waits for its whole answer line
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Send a command line that the crate does not wrap, such as a vendor specific query
+    ///
+    /// The carriage return is appended. With `expect_response` the answer line
+    /// is read back, otherwise an empty response is returned right away.
+    pub fn send_raw_command(&self, text: &str, expect_response: bool) -> Result<Response> {
+        self.exchange(text, expect_response, self.timeout)
+    }
+
+    fn exchange(&self, text: &str, expect_response: bool, timeout: Duration) -> Result<Response> {
+        if text.contains(char::from(CHAR_CR)) {
+            let error = io::Error::new(io::ErrorKind::InvalidInput, "command contains CR");
+            return Err(UpsError::Io(error));
+        }
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        // A late answer to a command that timed out must not be taken for this one
+        connection.discard_input()?;
+
+        let mut line = text.as_bytes().to_vec();
+        line.push(CHAR_CR);
+        if self.mode == TcpSerialMode::Rfc2217 {
+            line = escape(&line);
+        }
+        connection.stream.write_all(&line)?;
+        if !expect_response {
+            return Ok(Response::default());
+        }
+        let raw = connection.read_line(Instant::now() + timeout)?;
+        let text = decode_response(&raw);
+        Ok(Response { raw, text })
+    }
+}
+
+impl Transport for TcpSerialTransport {
+    fn send_command(&self, command: Command) -> Result<Response> {
+        self.send_command_with_timeout(command, self.timeout)
+    }
+
+    fn send_command_with_timeout(&self, command: Command, timeout: Duration) -> Result<Response> {
+        self.exchange(&command_text(command)?, expects_response(command), timeout)
+    }
+}
+
+impl Connection {
+    /// Offer binary mode and the com port option, then configure the port
+    fn negotiate(&mut self) -> Result<()> {
+        let mut request = vec![
+            IAC,
+            WILL,
+            OPTION_BINARY,
+            IAC,
+            DO,
+            OPTION_BINARY,
+            IAC,
+            DO,
+            OPTION_SUPPRESS_GO_AHEAD,
+            IAC,
+            WILL,
+            OPTION_COM_PORT,
+        ];
+        for (command, value) in [
+            (SET_BAUDRATE, &2400u32.to_be_bytes()[..]),
+            (SET_DATASIZE, &[8]),
+            (SET_PARITY, &[PARITY_NONE]),
+            (SET_STOPSIZE, &[STOPSIZE_ONE]),
+            (SET_CONTROL, &[CONTROL_NO_FLOW_CONTROL]),
+        ] {
+            request.extend_from_slice(&[IAC, SB, OPTION_COM_PORT, command]);
+            request.extend(escape(value));
+            request.extend_from_slice(&[IAC, SE]);
+        }
+        // Acknowledgements of these offers and requests need no answer
+        self.telnet = Some(Telnet {
+            local: vec![OPTION_BINARY, OPTION_COM_PORT],
+            remote: vec![OPTION_BINARY, OPTION_SUPPRESS_GO_AHEAD],
+            ..Telnet::default()
+        });
+        self.stream.write_all(&request)?;
+        Ok(())
+    }
+
+    /// Drop whatever arrived since the last answer, answering telnet negotiation
+    fn discard_input(&mut self) -> Result<()> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0u8; MAX_RESPONSE_LENGTH];
+        let result = loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => break Err(UpsError::Disconnected),
+                Ok(length) => {
+                    for &byte in &buffer[..length] {
+                        self.receive(byte);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e.into()),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result?;
+        self.send_replies()
+    }
+
+    /// Read a single carriage-return terminated response line before `deadline`
+    fn read_line(&mut self, deadline: Instant) -> Result<Vec<u8>> {
+        let mut line = Vec::new();
+        let mut buffer = [0u8; MAX_RESPONSE_LENGTH];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                // Timed out before the terminating carriage return arrived
+                return Err(UpsError::Timeout);
+            }
+            self.stream.set_read_timeout(Some(remaining))?;
+            let length = match self.stream.read(&mut buffer)? {
+                0 => return Err(UpsError::Disconnected),
+                length => length,
+            };
+            for &byte in &buffer[..length] {
+                match self.receive(byte) {
+                    Some(CHAR_CR) => {
+                        self.send_replies()?;
+                        return Ok(line);
+                    }
+                    Some(byte) if line.len() < MAX_RESPONSE_LENGTH => line.push(byte),
+                    Some(_) => return Err(UpsError::InvalidResponse),
+                    None => {}
+                }
+            }
+            self.send_replies()?;
+        }
+    }
+
+    /// Get a received byte back if it is serial data
+    fn receive(&mut self, byte: u8) -> Option<u8> {
+        match &mut self.telnet {
+            Some(telnet) => telnet.receive(byte),
+            None => Some(byte),
+        }
+    }
+
+    /// Send the answers to the telnet negotiation received so far
+    fn send_replies(&mut self) -> Result<()> {
+        if let Some(telnet) = &mut self.telnet {
+            if !telnet.replies.is_empty() {
+                self.stream.write_all(&telnet.replies)?;
+                telnet.replies.clear();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Telnet receiver, stripping commands from the data and collecting the answers to them
+#[derive(Default)]
+struct Telnet {
+    state: TelnetState,
+    /// Options enabled on this side and on the server
+    local: Vec<u8>,
+    remote: Vec<u8>,
+    replies: Vec<u8>,
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum TelnetState {
+    #[default]
+    Data,
+    Command,
+    Option(u8),
+    Subnegotiation,
+    SubnegotiationCommand,
+}
+
+impl Telnet {
+    /// Process one received byte and get it back if it is serial data
+    fn receive(&mut self, byte: u8) -> Option<u8> {
+        match self.state {
+            TelnetState::Data if byte == IAC => self.state = TelnetState::Command,
+            TelnetState::Data => return Some(byte),
+            TelnetState::Command => match byte {
+                IAC => {
+                    self.state = TelnetState::Data;
+                    return Some(IAC);
+                }
+                WILL | WONT | DO | DONT => self.state = TelnetState::Option(byte),
+                SB => self.state = TelnetState::Subnegotiation,
+                _ => self.state = TelnetState::Data,
+            },
+            TelnetState::Option(verb) => {
+                self.negotiate(verb, byte);
+                self.state = TelnetState::Data;
+            }
+            // Answers to the com port settings and notifications are not needed
+            TelnetState::Subnegotiation if byte == IAC => {
+                self.state = TelnetState::SubnegotiationCommand
+            }
+            TelnetState::Subnegotiation => {}
+            TelnetState::SubnegotiationCommand if byte == SE => self.state = TelnetState::Data,
+            TelnetState::SubnegotiationCommand => self.state = TelnetState::Subnegotiation,
+        }
+        None
+    }
+
+    /// Accept binary mode, suppressed go-ahead and the com port option, refuse anything else
+    fn negotiate(&mut self, verb: u8, option: u8) {
+        match verb {
+            WILL | WONT => {
+                let accepted =
+                    verb == WILL && matches!(option, OPTION_BINARY | OPTION_SUPPRESS_GO_AHEAD);
+                let answer = if accepted { DO } else { DONT };
+                if toggle(&mut self.remote, option, accepted) || verb == WILL && !accepted {
+                    self.replies.extend_from_slice(&[IAC, answer, option]);
+                }
+            }
+            _ => {
+                let accepted = verb == DO && matches!(option, OPTION_BINARY | OPTION_COM_PORT);
+                let answer = if accepted { WILL } else { WONT };
+                if toggle(&mut self.local, option, accepted) || verb == DO && !accepted {
+                    self.replies.extend_from_slice(&[IAC, answer, option]);
+                }
+            }
+        }
+    }
+}
+
+/// Enable or disable `option` in `options`, telling whether it changed
+fn toggle(options: &mut Vec<u8>, option: u8, enabled: bool) -> bool {
+    let position = options.iter().position(|&known| known == option);
+    match (position, enabled) {
+        (None, true) => {
+            options.push(option);
+            true
+        }
+        (Some(position), false) => {
+            options.remove(position);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Double every IAC byte of the data
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data {
+        escaped.push(byte);
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+    }
+    escaped
+}
@@ -0,0 +1,185 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// UTC date and time split into calendar fields
+pub(crate) struct DateTime {
+    pub(crate) year: i64,
+    pub(crate) month: u32,
+    pub(crate) day: u32,
+    /// Day of the week, 0 for Sunday
+    pub(crate) weekday: u32,
+    pub(crate) hour: u64,
+    pub(crate) minute: u64,
+    pub(crate) second: u64,
+}
+
+impl DateTime {
+    pub(crate) fn from_system_time(time: SystemTime) -> Self {
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Self::from_unix_seconds(seconds as i64)
+    }
+
+    /// Split seconds since 1970-01-01 into calendar fields
+    pub(crate) fn from_unix_seconds(seconds: i64) -> Self {
+        let days = seconds.div_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        let seconds = seconds.rem_euclid(86400) as u64;
+        Self {
+            year,
+            month,
+            day,
+            // 1970-01-01 was a Thursday
+            weekday: (days + 4).rem_euclid(7) as u32,
+            hour: seconds / 3600,
+            minute: seconds / 60 % 60,
+            second: seconds % 60,
+        }
+    }
+}
+
+/// Format a time as an RFC 3339 timestamp in UTC, such as `2024-05-01T12:00:00Z`
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let date = DateTime::from_system_time(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        date.year, date.month, date.day, date.hour, date.minute, date.second
+    )
+}
+
+/// Format a time as apcupsd prints dates, such as `2024-05-01 12:00:00 +0000`
+#[cfg(feature = "nis")]
+pub(crate) fn apcupsd_date(time: SystemTime) -> String {
+    let date = DateTime::from_system_time(time);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} +0000",
+        date.year, date.month, date.day, date.hour, date.minute, date.second
+    )
+}
+
+/// Parse an RFC 3339 timestamp such as `2024-05-01T12:00:00Z` or `2024-05-01T14:00:00.5+02:00`
+#[cfg(feature = "graphql")]
+pub(crate) fn parse_rfc3339(text: &str) -> Option<SystemTime> {
+    let bytes = text.as_bytes();
+    if bytes.len() < 20 || !text.is_ascii() {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = &text[range];
+        if digits.bytes().all(|b| b.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    };
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    if separators.iter().any(|&(index, byte)| bytes[index] != byte)
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+    {
+        return None;
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let mut rest = &text[19..];
+    let mut nanos = 0u32;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let length = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if length == 0 {
+            return None;
+        }
+        let digits = format!("{:0<9}", &fraction[..length.min(9)]);
+        nanos = digits.parse().ok()?;
+        rest = &fraction[length..];
+    }
+    let offset = match rest.as_bytes() {
+        [b'Z' | b'z'] => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let offset = number(text.len() - 5..text.len() - 3)? * 3600
+                + number(text.len() - 2..text.len())? * 60;
+            if *sign == b'+' {
+                offset
+            } else {
+                -offset
+            }
+        }
+        _ => return None,
+    };
+
+    let seconds = days_from_civil(year, month as u32, day as u32) * 86400
+        + hour * 3600
+        + minute * 60
+        // A leap second is read as the last second of its minute
+        + second.min(59)
+        - offset;
+    let seconds = u64::try_from(seconds).ok()?;
+    Some(UNIX_EPOCH + std::time::Duration::new(seconds, nanos))
+}
+
+/// Format a time as an RFC 5322 date in UTC, as used by email headers
+#[cfg(feature = "email")]
+pub(crate) fn rfc5322(time: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86400;
+    let date = DateTime::from_system_time(time);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} +0000",
+        // 1970-01-01 was a Thursday
+        DAYS[((days + 3) % 7) as usize],
+        date.day,
+        MONTHS[date.month as usize - 1],
+        date.year,
+        date.hour,
+        date.minute,
+        date.second
+    )
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's algorithm, with eras of 400 years starting on March 1st
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Convert a (year, month, day) date to days since 1970-01-01, the inverse of [`civil_from_days`]
+#[cfg(feature = "graphql")]
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = i64::from(if month > 2 { month - 3 } else { month + 9 });
+    let day_of_year = (153 * month_index + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
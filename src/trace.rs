@@ -0,0 +1,55 @@
+use crate::error::UpsError;
+use crate::transport::Command;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Callback receiving every protocol transaction
+pub(crate) type Tracer = Arc<dyn Fn(&Transaction<'_>) + Send + Sync>;
+
+/// One command sent to the device and the answer it got, as seen by a tracer
+///
+/// Every attempt of a retried command is reported on its own.
+#[derive(Debug, Clone, Copy)]
+pub struct Transaction<'a> {
+    pub command: Command,
+    /// Attempt number, from 1
+    pub attempt: u32,
+    /// Time from sending the command to the parsed answer or the failure
+    pub duration: Duration,
+    /// Length of the raw answer, 0 when none arrived
+    pub response_len: usize,
+    /// Error the attempt failed with, `None` when it succeeded
+    pub error: Option<&'a UpsError>,
+}
+
+impl Transaction<'_> {
+    /// Get a short name of the outcome: `ok`, `parse-error`, `timeout` or `error`
+    pub fn outcome(&self) -> &'static str {
+        match self.error {
+            None => "ok",
+            Some(UpsError::ParseError { .. }) | Some(UpsError::InvalidResponse) => "parse-error",
+            Some(UpsError::Timeout) => "timeout",
+            Some(_) => "error",
+        }
+    }
+}
+
+/// One line such as `Q1 attempt 1: ok, 47 bytes in 12 ms`
+impl fmt::Display for Transaction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} attempt {}: {}, {} bytes in {} ms",
+            self.command.mnemonic(),
+            self.attempt,
+            self.outcome(),
+            self.response_len,
+            self.duration.as_millis()
+        )?;
+        if let Some(error) = self.error {
+            write!(f, " ({})", error)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use crate::error::{Result, UpsError};
+use std::time::Duration;
+
+/// Commands understood by Megatec UPS devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Command {
+    /// Query the UPS name
+    Name,
+    /// Query the UPS status (Q1)
+    Status,
+    /// Test for 10 seconds (T)
+    Test,
+    /// Test until battery is low (TL)
+    TestUntilBatteryLow,
+    /// Test for the given number of minutes (T<n>)
+    TestWithTime(u8),
+    /// Toggle the beeper (Q)
+    ToggleBeep,
+    /// Abort the current test (CT)
+    AbortTest,
+    /// Query rating information (F)
+    Rating,
+    /// Query manufacturer information (I)
+    Info,
+    /// Shutdown after the given delay (S<n>)
+    Shutdown(ShutdownDelay),
+    /// Shutdown after the given delay and restore after the given minutes (S<n>R<m>)
+    ShutdownAndRestore(ShutdownDelay, u16),
+    /// Cancel a pending shutdown (C)
+    CancelShutdown,
+    /// Query the Voltronic/Axpert extended status (QPIGS)
+    ExtendedStatus,
+    /// Query the Voltronic operating mode (QMOD)
+    Mode,
+    /// Query the UPS status in the Q1 layout on clones not knowing Q1 (QS)
+    StatusQs,
+    /// Query the Voltronic general status (QGS)
+    GeneralStatus,
+}
+
+impl Command {
+    /// Get the Megatec mnemonic of the command, e.g. `Q1` or `S.5R0010` (`NAME` for the name query)
+    pub fn mnemonic(&self) -> String {
+        match self {
+            Command::Name => "NAME".to_string(),
+            Command::Status => "Q1".to_string(),
+            Command::Test => "T".to_string(),
+            Command::TestUntilBatteryLow => "TL".to_string(),
+            Command::TestWithTime(minutes) => format!("T{:02}", minutes),
+            Command::ToggleBeep => "Q".to_string(),
+            Command::AbortTest => "CT".to_string(),
+            Command::Rating => "F".to_string(),
+            Command::Info => "I".to_string(),
+            Command::Shutdown(delay) => format!("S{}", delay.to_protocol_string()),
+            Command::ShutdownAndRestore(delay, restore_minutes) => {
+                format!("S{}R{:04}", delay.to_protocol_string(), restore_minutes)
+            }
+            Command::CancelShutdown => "C".to_string(),
+            Command::ExtendedStatus => "QPIGS".to_string(),
+            Command::Mode => "QMOD".to_string(),
+            Command::StatusQs => "QS".to_string(),
+            Command::GeneralStatus => "QGS".to_string(),
+        }
+    }
+
+    /// Check if the command only reads from the UPS and is answered
+    pub fn is_query(&self) -> bool {
+        matches!(
+            self,
+            Command::Name
+                | Command::Status
+                | Command::Rating
+                | Command::Info
+                | Command::ExtendedStatus
+                | Command::Mode
+                | Command::StatusQs
+                | Command::GeneralStatus
+        )
+    }
+
+    /// Parse a Megatec mnemonic as produced by `mnemonic()`
+    pub fn from_mnemonic(text: &str) -> Option<Self> {
+        let command = match text {
+            "NAME" => Command::Name,
+            "Q1" => Command::Status,
+            "T" => Command::Test,
+            "TL" => Command::TestUntilBatteryLow,
+            "Q" => Command::ToggleBeep,
+            "CT" => Command::AbortTest,
+            "F" => Command::Rating,
+            "I" => Command::Info,
+            "C" => Command::CancelShutdown,
+            "QPIGS" => Command::ExtendedStatus,
+            "QMOD" => Command::Mode,
+            "QS" => Command::StatusQs,
+            "QGS" => Command::GeneralStatus,
+            _ => {
+                if let Some(minutes) = text.strip_prefix('T') {
+                    return minutes.parse().ok().map(Command::TestWithTime);
+                }
+
+                let text = text.strip_prefix('S')?;
+                let (delay, restore) = match text.split_once('R') {
+                    Some((delay, restore)) => (delay, Some(restore.parse().ok()?)),
+                    None => (text, None),
+                };
+                let delay = ShutdownDelay::from_minutes(delay.parse().ok()?).ok()?;
+
+                match restore {
+                    Some(restore_minutes) => Command::ShutdownAndRestore(delay, restore_minutes),
+                    None => Command::Shutdown(delay),
+                }
+            }
+        };
+
+        Some(command)
+    }
+}
+
+/// Longest restore delay accepted by the S<n>R<m> command, in minutes
+pub const MAX_RESTORE_MINUTES: u16 = 9999;
+
+/// Delay before a shutdown, as accepted by the S<n> command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShutdownDelay {
+    tenths: u8,
+}
+
+impl ShutdownDelay {
+    /// Delay of 1 minute
+    pub const ONE_MINUTE: Self = Self { tenths: 10 };
+
+    /// Create a delay of .2 to .9 minutes in steps of .1, or 1 to 10 whole minutes
+    pub fn from_minutes(minutes: f32) -> Result<Self> {
+        if !minutes.is_finite() || !(0.2..=10.0).contains(&minutes) {
+            return Err(UpsError::InvalidTime);
+        }
+
+        let tenths = (minutes * 10.0).round();
+        if (minutes * 10.0 - tenths).abs() > 0.01 {
+            return Err(UpsError::InvalidTime);
+        }
+
+        let tenths = tenths as u8;
+        if tenths >= 10 && !tenths.is_multiple_of(10) {
+            return Err(UpsError::InvalidTime);
+        }
+
+        Ok(Self { tenths })
+    }
+
+    /// Get the delay in minutes
+    pub fn minutes(&self) -> f32 {
+        self.tenths as f32 / 10.0
+    }
+
+    /// Get the delay as it is written in Megatec commands (".2" to ".9", "01" to "10")
+    pub fn to_protocol_string(&self) -> String {
+        if self.tenths < 10 {
+            format!(".{}", self.tenths)
+        } else {
+            format!("{:02}", self.tenths / 10)
+        }
+    }
+}
+
+/// Response received from a UPS device
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Response {
+    /// Bytes exactly as received from the device
+    pub raw: Vec<u8>,
+    /// Text decoded from the raw bytes
+    pub text: String,
+}
+
+/// Link used to exchange commands with a UPS device
+pub trait Transport {
+    /// Send a command and read back the response
+    fn send_command(&self, command: Command) -> Result<Response>;
+
+    /// Send a command, waiting up to `timeout` for the response instead of the usual timeout
+    ///
+    /// Links without a configurable timeout ignore it.
+    fn send_command_with_timeout(&self, command: Command, timeout: Duration) -> Result<Response> {
+        let _ = timeout;
+        self.send_command(command)
+    }
+
+    /// Recover a wedged link, for example by resetting the device
+    ///
+    /// Links without a recovery path return [`UpsError::Unsupported`].
+    fn reset(&self) -> Result<()> {
+        Err(UpsError::Unsupported)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send_command(&self, command: Command) -> Result<Response> {
+        (**self).send_command(command)
+    }
+
+    fn send_command_with_timeout(&self, command: Command, timeout: Duration) -> Result<Response> {
+        (**self).send_command_with_timeout(command, timeout)
+    }
+
+    fn reset(&self) -> Result<()> {
+        (**self).reset()
+    }
+}
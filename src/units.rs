@@ -0,0 +1,140 @@
+use crate::status::{StatusFlags, UpsStatus};
+use std::fmt;
+
+/// Electric potential in volts
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct ElectricPotential(f64);
+
+impl ElectricPotential {
+    /// Create a voltage in volts
+    pub fn from_volts(volts: f64) -> Self {
+        Self(volts)
+    }
+
+    /// Get the voltage in volts
+    pub fn volts(&self) -> f64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ElectricPotential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.1} V", self.0)
+    }
+}
+
+/// Frequency in hertz
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct Frequency(f64);
+
+impl Frequency {
+    /// Create a frequency in hertz
+    pub fn from_hertz(hertz: f64) -> Self {
+        Self(hertz)
+    }
+
+    /// Get the frequency in hertz
+    pub fn hertz(&self) -> f64 {
+        self.0
+    }
+}
+
+impl fmt::Display for Frequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.1} Hz", self.0)
+    }
+}
+
+/// Temperature in degrees Celsius
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct ThermodynamicTemperature(f64);
+
+impl ThermodynamicTemperature {
+    /// Create a temperature in degrees Celsius
+    pub fn from_degrees_celsius(degrees: f64) -> Self {
+        Self(degrees)
+    }
+
+    /// Get the temperature in degrees Celsius
+    pub fn degrees_celsius(&self) -> f64 {
+        self.0
+    }
+
+    /// Get the temperature in kelvin
+    pub fn kelvin(&self) -> f64 {
+        self.0 + 273.15
+    }
+}
+
+impl fmt::Display for ThermodynamicTemperature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.1} °C", self.0)
+    }
+}
+
+/// Dimensionless ratio such as the load
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct Ratio(f64);
+
+impl Ratio {
+    /// Create a ratio from a percentage
+    pub fn from_percent(percent: f64) -> Self {
+        Self(percent / 100.0)
+    }
+
+    /// Get the ratio in percent
+    pub fn percent(&self) -> f64 {
+        self.0 * 100.0
+    }
+
+    /// Get the ratio as a fraction, 1.0 for 100 %
+    pub fn fraction(&self) -> f64 {
+        self.0
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.0} %", self.percent())
+    }
+}
+
+/// UPS status with every value carrying its unit
+///
+/// The Q1 answer calls the load field "output current" although it is a
+/// percentage of the rated load; here it is a [`Ratio`] named `load`, so it
+/// cannot be mixed up with a voltage at compile time.
+/// The quantity types are named like their uom counterparts, without the
+/// dependency.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpsStatusTyped {
+    pub input_voltage: ElectricPotential,
+    pub input_fault_voltage: ElectricPotential,
+    pub output_voltage: ElectricPotential,
+    pub load: Ratio,
+    pub input_frequency: Frequency,
+    pub battery_voltage: ElectricPotential,
+    pub temperature: ThermodynamicTemperature,
+    pub flags: StatusFlags,
+}
+
+impl From<&UpsStatus> for UpsStatusTyped {
+    fn from(status: &UpsStatus) -> Self {
+        Self {
+            input_voltage: ElectricPotential::from_volts(status.input_voltage),
+            input_fault_voltage: ElectricPotential::from_volts(status.input_fault_voltage),
+            output_voltage: ElectricPotential::from_volts(status.output_voltage),
+            load: Ratio::from_percent(status.load_raw),
+            input_frequency: Frequency::from_hertz(status.input_frequency),
+            battery_voltage: ElectricPotential::from_volts(status.battery_voltage),
+            temperature: ThermodynamicTemperature::from_degrees_celsius(status.temperature),
+            flags: status.flags,
+        }
+    }
+}
+
+impl From<UpsStatus> for UpsStatusTyped {
+    fn from(status: UpsStatus) -> Self {
+        Self::from(&status)
+    }
+}
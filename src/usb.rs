@@ -0,0 +1,419 @@
+use crate::discovery::{read_serial_number, USB_CLASS_HUB};
+use crate::error::{Result, UpsError};
+use crate::protocol::{self, decode_response, filter_response};
+use crate::quirks::Quirks;
+use crate::transport::{Command, Response, ShutdownDelay, Transport};
+use crate::wire::WireLog;
+use rusb::{Context, DeviceHandle, UsbContext};
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+/// Interface bound by usbhid on Megatec devices
+const INTERFACE: u8 = 0;
+
+/// Transport talking to the UPS through USB string descriptors
+pub struct UsbTransport {
+    handle: DeviceHandle<Context>,
+    timeout: Duration,
+    vendor_id: u16,
+    product_id: u16,
+    quirks: Quirks,
+    claimed: bool,
+    wire_log: Option<Arc<WireLog>>,
+    selection: Selection,
+}
+
+/// How the device was selected when the transport was opened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Selection {
+    /// The first device with the given vendor and product ID
+    Ids { vendor_id: u16, product_id: u16 },
+    /// The device reporting the given serial number
+    Serial(String),
+    /// The device plugged into the given bus and chain of ports
+    PortPath {
+        bus_number: u8,
+        port_numbers: Vec<u8>,
+    },
+}
+
+impl UsbTransport {
+    /// Open the USB device with the given vendor_id and product_id
+    pub fn open(vendor_id: u16, product_id: u16) -> Result<Self> {
+        Self::open_with_timeout(vendor_id, product_id, DEFAULT_TIMEOUT)
+    }
+
+    /// Open the USB device using the given control transfer timeout
+    pub fn open_with_timeout(vendor_id: u16, product_id: u16, timeout: Duration) -> Result<Self> {
+        let context = Context::new()?;
+        let handle = Self::open_handle(&context, vendor_id, product_id)?;
+
+        Ok(Self {
+            handle,
+            timeout,
+            vendor_id,
+            product_id,
+            quirks: Quirks::NONE,
+            claimed: false,
+            wire_log: None,
+            selection: Selection::Ids {
+                vendor_id,
+                product_id,
+            },
+        })
+    }
+
+    /// Open the USB device reporting the given serial number
+    ///
+    /// Tells apart several units sharing the same vendor and product ID.
+    pub fn open_by_serial(serial_number: &str) -> Result<Self> {
+        Self::open_by_serial_with_timeout(serial_number, DEFAULT_TIMEOUT)
+    }
+
+    /// Open the USB device reporting the given serial number using the given control transfer timeout
+    pub fn open_by_serial_with_timeout(serial_number: &str, timeout: Duration) -> Result<Self> {
+        let context = Context::new()?;
+        for device in context.devices()?.iter() {
+            let descriptor = match device.device_descriptor() {
+                Ok(descriptor) => descriptor,
+                Err(_) => continue,
+            };
+            if descriptor.class_code() == USB_CLASS_HUB {
+                continue;
+            }
+
+            // The serial number can only be read from devices we are allowed to open
+            let handle = match device.open() {
+                Ok(handle) => handle,
+                Err(_) => continue,
+            };
+            if read_serial_number(&handle, &descriptor, timeout).as_deref() == Some(serial_number) {
+                return Ok(Self {
+                    handle,
+                    timeout,
+                    vendor_id: descriptor.vendor_id(),
+                    product_id: descriptor.product_id(),
+                    quirks: Quirks::NONE,
+                    claimed: false,
+                    wire_log: None,
+                    selection: Selection::Serial(serial_number.to_string()),
+                });
+            }
+        }
+
+        Err(UpsError::DeviceNotFound)
+    }
+
+    /// Open the USB device plugged into the given bus and chain of ports
+    ///
+    /// The physical topology stays the same across reboots, so this selects
+    /// one of several identical units that report no serial number.
+    pub fn open_by_port_path(bus_number: u8, port_numbers: &[u8]) -> Result<Self> {
+        Self::open_by_port_path_with_timeout(bus_number, port_numbers, DEFAULT_TIMEOUT)
+    }
+
+    /// Open the USB device at the given port path using the given control transfer timeout
+    pub fn open_by_port_path_with_timeout(
+        bus_number: u8,
+        port_numbers: &[u8],
+        timeout: Duration,
+    ) -> Result<Self> {
+        let context = Context::new()?;
+        for device in context.devices()?.iter() {
+            if device.bus_number() != bus_number
+                || device.port_numbers().ok().as_deref() != Some(port_numbers)
+            {
+                continue;
+            }
+
+            let descriptor = device.device_descriptor()?;
+            let handle = device.open()?;
+            return Ok(Self {
+                handle,
+                timeout,
+                vendor_id: descriptor.vendor_id(),
+                product_id: descriptor.product_id(),
+                quirks: Quirks::NONE,
+                claimed: false,
+                wire_log: None,
+                selection: Selection::PortPath {
+                    bus_number,
+                    port_numbers: port_numbers.to_vec(),
+                },
+            });
+        }
+
+        Err(UpsError::DeviceNotFound)
+    }
+
+    /// Open the device selected the same way as an earlier transport
+    pub(crate) fn reopen(selection: &Selection, timeout: Duration) -> Result<Self> {
+        match selection {
+            Selection::Ids {
+                vendor_id,
+                product_id,
+            } => Self::open_with_timeout(*vendor_id, *product_id, timeout),
+            Selection::Serial(serial_number) => {
+                Self::open_by_serial_with_timeout(serial_number, timeout)
+            }
+            Selection::PortPath {
+                bus_number,
+                port_numbers,
+            } => Self::open_by_port_path_with_timeout(*bus_number, port_numbers, timeout),
+        }
+    }
+
+    /// Get how the device was selected when it was opened
+    pub(crate) fn selection(&self) -> &Selection {
+        &self.selection
+    }
+
+    /// Get the vendor ID of the device
+    pub fn vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+
+    /// Get the product ID of the device
+    pub fn product_id(&self) -> u16 {
+        self.product_id
+    }
+
+    /// Use the descriptor indices overridden by the given quirks
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Get the quirks the descriptor indices are taken from
+    pub(crate) fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Detach the kernel driver and claim the interface
+    ///
+    /// Without this, usbhid or hid-generic may keep polling the device and
+    /// string descriptor reads intermittently fail. The kernel driver is
+    /// reattached when the interface is released or the transport dropped.
+    /// Detaching is only supported by libusb on Linux; elsewhere the interface
+    /// is claimed as is.
+    pub fn claim_interface(&mut self) -> Result<()> {
+        if self.claimed {
+            return Ok(());
+        }
+        match self.handle.set_auto_detach_kernel_driver(true) {
+            Ok(()) | Err(rusb::Error::NotSupported) => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.handle.claim_interface(INTERFACE)?;
+        self.claimed = true;
+        Ok(())
+    }
+
+    /// Release the interface claimed by [`claim_interface`](Self::claim_interface)
+    /// and reattach the kernel driver
+    pub fn release_interface(&mut self) -> Result<()> {
+        if self.claimed {
+            self.handle.release_interface(INTERFACE)?;
+            self.claimed = false;
+        }
+        Ok(())
+    }
+
+    /// Release the interface, reattach the kernel driver and close the device
+    ///
+    /// Dropping the transport does the same but cannot report failures.
+    pub fn close(mut self) -> Result<()> {
+        self.release_interface()
+    }
+
+    /// Check if the interface is claimed
+    pub fn is_interface_claimed(&self) -> bool {
+        self.claimed
+    }
+
+    /// Open the first device matching vendor_id and product_id, keeping the reason of failures
+    fn open_handle(
+        context: &Context,
+        vendor_id: u16,
+        product_id: u16,
+    ) -> Result<DeviceHandle<Context>> {
+        for device in context.devices()?.iter() {
+            let descriptor = match device.device_descriptor() {
+                Ok(descriptor) => descriptor,
+                Err(_) => continue,
+            };
+
+            if descriptor.vendor_id() == vendor_id && descriptor.product_id() == product_id {
+                return Ok(device.open()?);
+            }
+        }
+
+        Err(UpsError::DeviceNotFound)
+    }
+
+    /// Get the control transfer timeout
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Set the control transfer timeout
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Log every descriptor request and the bytes returned to `log`
+    pub fn set_wire_log(&mut self, log: WireLog) {
+        self.wire_log = Some(Arc::new(log));
+    }
+
+    /// Get the wire log, to pass it on to a transport opened again
+    pub(crate) fn wire_log(&self) -> Option<Arc<WireLog>> {
+        self.wire_log.clone()
+    }
+
+    /// Log to a wire log shared with another transport
+    pub(crate) fn set_shared_wire_log(&mut self, log: Arc<WireLog>) {
+        self.wire_log = Some(log);
+    }
+
+    /// Read the string descriptor `index` of at most `length` bytes and return it unparsed
+    ///
+    /// Escape hatch for vendor specific commands the crate does not wrap. The
+    /// bytes are returned as received, including the descriptor header.
+    pub fn send_raw(&self, index: u8, length: u16) -> Result<Vec<u8>> {
+        self.transfer(index, length, self.timeout)
+    }
+
+    /// Read a string descriptor, logging the transfer to the wire log
+    fn transfer(&self, index: u8, length: u16, timeout: Duration) -> Result<Vec<u8>> {
+        if let Some(log) = &self.wire_log {
+            log.request(format_args!(
+                "GET_DESCRIPTOR index {} length {}",
+                index, length
+            ));
+        }
+        let data = self.read_string_descriptor(index, length, timeout);
+        if let Some(log) = &self.wire_log {
+            match &data {
+                Ok(data) => log.response(data),
+                Err(e) => log.error(e),
+            }
+        }
+        data
+    }
+
+    /// Get a string descriptor from the device
+    fn get_string_descriptor(&self, index: u8, length: u16, timeout: Duration) -> Result<Response> {
+        let data = self.transfer(index, length, timeout)?;
+        if data.len() >= 3 {
+            let text = decode_descriptor(&data);
+            Ok(Response { raw: data, text })
+        } else {
+            Err(UpsError::InvalidResponse)
+        }
+    }
+
+    /// Read the raw bytes of a string descriptor with a control transfer
+    fn read_string_descriptor(&self, index: u8, length: u16, timeout: Duration) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; length as usize];
+        let result = self.handle.read_control(
+            rusb::request_type(
+                rusb::Direction::In,
+                rusb::RequestType::Standard,
+                rusb::Recipient::Device,
+            ),
+            rusb::constants::LIBUSB_REQUEST_GET_DESCRIPTOR,
+            (rusb::constants::LIBUSB_DT_STRING as u16) << 8 | index as u16,
+            0,
+            &mut data,
+            timeout,
+        )?;
+        data.truncate(result);
+        Ok(data)
+    }
+}
+
+impl Transport for UsbTransport {
+    fn send_command(&self, command: Command) -> Result<Response> {
+        self.send_command_with_timeout(command, self.timeout)
+    }
+
+    fn send_command_with_timeout(&self, command: Command, timeout: Duration) -> Result<Response> {
+        let (index, length) = descriptor_for(&self.quirks, command)?;
+        self.get_string_descriptor(index, length, timeout)
+    }
+
+    /// Clear the halt of every endpoint and reset the device
+    ///
+    /// Claimed interfaces are restored by libusb after the reset. A device
+    /// that re-enumerates with a new address reports
+    /// [`UpsError::DeviceNotFound`] and has to be opened again.
+    fn reset(&self) -> Result<()> {
+        if let Ok(config) = self.handle.device().active_config_descriptor() {
+            for interface in config.interfaces() {
+                for descriptor in interface.descriptors() {
+                    for endpoint in descriptor.endpoint_descriptors() {
+                        let _ = self.handle.clear_halt(endpoint.address());
+                    }
+                }
+            }
+        }
+        self.handle.reset()?;
+        Ok(())
+    }
+}
+
+impl Drop for UsbTransport {
+    fn drop(&mut self) {
+        // Same cleanup as close, with nowhere to report failures
+        let _ = self.release_interface();
+    }
+}
+
+/// Decode a string descriptor (bLength, bDescriptorType, UTF-16LE payload)
+pub(crate) fn decode_descriptor(data: &[u8]) -> String {
+    if data[1] != rusb::constants::LIBUSB_DT_STRING {
+        // Not framed as a string descriptor, keep the raw characters
+        return decode_response(data);
+    }
+
+    let length = match data[0] as usize {
+        declared if (2..=data.len()).contains(&declared) => declared,
+        _ => data.len(),
+    };
+    let units: Vec<u16> = data[2..length]
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+
+    match String::from_utf16(&units) {
+        Ok(text) => filter_response(text.chars()),
+        Err(_) => filter_response(String::from_utf16_lossy(&units).chars()),
+    }
+}
+
+/// Map a command to its string descriptor index and length
+pub(crate) fn descriptor_for(quirks: &Quirks, command: Command) -> Result<(u8, u16)> {
+    let descriptor = match command {
+        Command::Name => (2, 256),
+        Command::Status => (quirks.status_descriptor.unwrap_or(3), 256),
+        Command::Test => (4, 256),
+        Command::TestUntilBatteryLow => (5, 256),
+        Command::TestWithTime(minutes) => (6, protocol::usb_test_time(minutes)?),
+        Command::ToggleBeep => (7, 256),
+        Command::CancelShutdown => (10, 256),
+        Command::AbortTest => (11, 256),
+        Command::Info => (quirks.info_descriptor.unwrap_or(12), 256),
+        Command::Rating => (quirks.rating_descriptor.unwrap_or(13), 256),
+        // Only the 1 minute delay is known for the Mega(USB) protocol
+        Command::Shutdown(ShutdownDelay::ONE_MINUTE) => (105, 2460),
+        Command::Shutdown(_)
+        | Command::ShutdownAndRestore(..)
+        | Command::ExtendedStatus
+        | Command::Mode
+        | Command::StatusQs
+        | Command::GeneralStatus => return Err(UpsError::Unsupported),
+    };
+
+    Ok(descriptor)
+}
@@ -0,0 +1,278 @@
+use crate::error::{Result, UpsError};
+use crate::quirks::Quirks;
+use crate::transport::{Command, Response, Transport};
+use crate::usb::{decode_descriptor, descriptor_for};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// TCP port of usbipd
+pub const DEFAULT_USBIP_PORT: u16 = 3240;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+const PROTOCOL_VERSION: u16 = 0x0111;
+
+const OP_REQ_DEVLIST: u16 = 0x8005;
+const OP_REP_DEVLIST: u16 = 0x0005;
+const OP_REQ_IMPORT: u16 = 0x8003;
+const OP_REP_IMPORT: u16 = 0x0003;
+
+const USBIP_CMD_SUBMIT: u32 = 1;
+const USBIP_RET_SUBMIT: u32 = 3;
+const USBIP_RET_UNLINK: u32 = 4;
+const USBIP_DIR_IN: u32 = 1;
+
+/// Size of the usbip_usb_device record: path, bus ID, numbers and descriptor fields
+const DEVICE_RECORD_LENGTH: usize = 312;
+/// Size of the header of every URB message
+const URB_HEADER_LENGTH: usize = 48;
+
+/// USB device exported by a usbipd server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsbIpDevice {
+    /// Bus ID to import, such as `1-1.2`
+    pub busid: String,
+    /// sysfs path of the device on the server
+    pub path: String,
+    pub bus_number: u32,
+    pub device_number: u32,
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+impl UsbIpDevice {
+    fn parse(record: &[u8]) -> Self {
+        let text = |bytes: &[u8]| {
+            let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..end]).into_owned()
+        };
+        Self {
+            path: text(&record[..256]),
+            busid: text(&record[256..288]),
+            bus_number: read_u32(record, 288),
+            device_number: read_u32(record, 292),
+            vendor_id: u16::from_be_bytes([record[300], record[301]]),
+            product_id: u16::from_be_bytes([record[302], record[303]]),
+        }
+    }
+
+    /// Device ID used in URB messages
+    fn devid(&self) -> u32 {
+        self.bus_number << 16 | self.device_number
+    }
+}
+
+/// Transport talking to a UPS exported over USB/IP, without the vhci kernel driver
+///
+/// The device is imported from usbipd on a gateway (`usbip bind -b <BUSID>`)
+/// and the string descriptor requests of [`UsbTransport`](crate::UsbTransport)
+/// are sent as URBs over the TCP connection. Devices answering through HID
+/// reports are not supported; attach those with `usbip attach` and open them
+/// as local USB devices instead.
+pub struct UsbIpTransport {
+    connection: Mutex<Connection>,
+    device: UsbIpDevice,
+    timeout: Duration,
+    quirks: Quirks,
+}
+
+struct Connection {
+    stream: TcpStream,
+    seqnum: u32,
+}
+
+impl UsbIpTransport {
+    /// Import the device `busid` from the usbipd server at `address`
+    pub fn connect(address: impl ToSocketAddrs, busid: &str) -> Result<Self> {
+        let mut stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(DEFAULT_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+
+        let mut request = operation(OP_REQ_IMPORT);
+        let mut name = [0u8; 32];
+        let length = busid.len().min(name.len() - 1);
+        name[..length].copy_from_slice(&busid.as_bytes()[..length]);
+        request.extend_from_slice(&name);
+        stream.write_all(&request)?;
+
+        let status = read_operation_reply(&mut stream, OP_REP_IMPORT)?;
+        if status != 0 {
+            // Not exported, or already imported by another client
+            return Err(UpsError::DeviceNotFound);
+        }
+        let mut record = [0u8; DEVICE_RECORD_LENGTH];
+        stream.read_exact(&mut record)?;
+
+        Ok(Self {
+            connection: Mutex::new(Connection { stream, seqnum: 0 }),
+            device: UsbIpDevice::parse(&record),
+            timeout: DEFAULT_TIMEOUT,
+            quirks: Quirks::default(),
+        })
+    }
+
+    /// List the devices exported by the usbipd server at `address`
+    pub fn list_devices(address: impl ToSocketAddrs) -> Result<Vec<UsbIpDevice>> {
+        let mut stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(DEFAULT_TIMEOUT))?;
+        stream.write_all(&operation(OP_REQ_DEVLIST))?;
+        if read_operation_reply(&mut stream, OP_REP_DEVLIST)? != 0 {
+            return Err(UpsError::InvalidResponse);
+        }
+
+        let mut count = [0u8; 4];
+        stream.read_exact(&mut count)?;
+        let mut devices = Vec::new();
+        for _ in 0..u32::from_be_bytes(count) {
+            let mut record = [0u8; DEVICE_RECORD_LENGTH];
+            stream.read_exact(&mut record)?;
+            // Class, subclass, protocol and padding of every interface
+            let mut interfaces = vec![0u8; record[DEVICE_RECORD_LENGTH - 1] as usize * 4];
+            stream.read_exact(&mut interfaces)?;
+            devices.push(UsbIpDevice::parse(&record));
+        }
+        Ok(devices)
+    }
+
+    /// Get the imported device
+    pub fn device(&self) -> &UsbIpDevice {
+        &self.device
+    }
+
+    /// Use the descriptor indices overridden by the given quirks
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Get the control transfer timeout
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Set the control transfer timeout
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Read the string descriptor `index` of at most `length` bytes and return it unparsed
+    pub fn send_raw(&self, index: u8, length: u16) -> Result<Vec<u8>> {
+        self.read_string_descriptor(index, length, self.timeout)
+    }
+
+    /// Send GET_DESCRIPTOR for a string as a control URB and wait for its answer
+    fn read_string_descriptor(&self, index: u8, length: u16, timeout: Duration) -> Result<Vec<u8>> {
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        connection.seqnum = connection.seqnum.wrapping_add(1);
+        let seqnum = connection.seqnum;
+
+        let mut submit = Vec::with_capacity(URB_HEADER_LENGTH);
+        for field in [
+            USBIP_CMD_SUBMIT,
+            seqnum,
+            self.device.devid(),
+            USBIP_DIR_IN,
+            0, // endpoint
+            0, // transfer flags
+            u32::from(length),
+            0, // start frame
+            0, // number of packets
+            0, // interval
+        ] {
+            submit.extend_from_slice(&field.to_be_bytes());
+        }
+        // bmRequestType device to host, GET_DESCRIPTOR, string descriptor `index`
+        submit.extend_from_slice(&[0x80, 0x06, index, 0x03, 0x00, 0x00]);
+        submit.extend_from_slice(&length.to_le_bytes());
+
+        let stream = &mut connection.stream;
+        stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        stream.write_all(&submit)?;
+
+        loop {
+            let mut header = [0u8; URB_HEADER_LENGTH];
+            stream.read_exact(&mut header)?;
+            let command = read_u32(&header, 0);
+            let reply = read_u32(&header, 4);
+            match command {
+                USBIP_RET_SUBMIT => {
+                    let status = read_u32(&header, 20) as i32;
+                    let actual = read_u32(&header, 24) as usize;
+                    // Only IN transfers are submitted, so every answer carries its data
+                    let mut data = vec![0u8; actual];
+                    stream.read_exact(&mut data)?;
+                    // Late answers to requests that already timed out are skipped
+                    if reply != seqnum {
+                        continue;
+                    }
+                    if status != 0 {
+                        return Err(urb_error(status));
+                    }
+                    return Ok(data);
+                }
+                USBIP_RET_UNLINK => continue,
+                _ => return Err(UpsError::InvalidResponse),
+            }
+        }
+    }
+}
+
+impl Transport for UsbIpTransport {
+    fn send_command(&self, command: Command) -> Result<Response> {
+        self.send_command_with_timeout(command, self.timeout)
+    }
+
+    fn send_command_with_timeout(&self, command: Command, timeout: Duration) -> Result<Response> {
+        let (index, length) = descriptor_for(&self.quirks, command)?;
+        let data = self.read_string_descriptor(index, length, timeout)?;
+        if data.len() >= 3 {
+            let text = decode_descriptor(&data);
+            Ok(Response { raw: data, text })
+        } else {
+            Err(UpsError::InvalidResponse)
+        }
+    }
+}
+
+/// Map the negative errno of a completed URB to the error libusb would report
+fn urb_error(status: i32) -> UpsError {
+    const EPIPE: i32 = 32;
+    const ENODEV: i32 = 19;
+    const ESHUTDOWN: i32 = 108;
+    const ETIMEDOUT: i32 = 110;
+    match -status {
+        // The device stalled the request, as for descriptors it does not know
+        EPIPE => UpsError::Usb(rusb::Error::Pipe),
+        ENODEV | ESHUTDOWN => UpsError::Disconnected,
+        ETIMEDOUT => UpsError::Timeout,
+        errno => UpsError::Io(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+/// Header of an operation request: version, code and status
+fn operation(code: u16) -> Vec<u8> {
+    let mut request = Vec::with_capacity(40);
+    request.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    request.extend_from_slice(&code.to_be_bytes());
+    request.extend_from_slice(&0u32.to_be_bytes());
+    request
+}
+
+/// Read the header of an operation reply and get its status
+fn read_operation_reply(stream: &mut TcpStream, expected: u16) -> Result<u32> {
+    let mut header = [0u8; 8];
+    stream.read_exact(&mut header)?;
+    if u16::from_be_bytes([header[2], header[3]]) != expected {
+        return Err(UpsError::InvalidResponse);
+    }
+    Ok(read_u32(&header, 4))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
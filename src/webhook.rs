@@ -0,0 +1,161 @@
+use crate::error::{Result, UpsError};
+use crate::event::UpsEvent;
+use crate::http::{self, Url};
+use crate::json::{ToJson, Value};
+use crate::retry::RetryPolicy;
+use std::io;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Time allowed for connecting to a webhook and for each read or write
+pub const DEFAULT_WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts monitor events as JSON to webhook URLs
+///
+/// The payload carries a `text` and a `content` member with a one-line summary,
+/// which Slack, Mattermost, Teams and Discord incoming webhooks display, next to
+/// `ups`, `event`, `timestamp` (Unix seconds) and `status` for automation tools
+/// such as n8n or Node-RED.
+///
+/// Requests are sent over plain HTTP. These services only accept `https://`
+/// URLs, which are added with [`add_relayed_url`](Self::add_relayed_url) and
+/// posted through a local TLS relay such as stunnel in client mode.
+///
+/// Connection errors, `429` and `5xx` responses are retried according to the
+/// retry policy, other `4xx` responses fail immediately.
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    ups_name: String,
+    urls: Vec<Url>,
+    events: Option<Vec<String>>,
+    timeout: Duration,
+    retry_policy: RetryPolicy,
+}
+
+impl WebhookNotifier {
+    /// Create a notifier for the UPS called `ups_name`, without URLs
+    pub fn new<S: Into<String>>(ups_name: S) -> Self {
+        Self {
+            ups_name: ups_name.into(),
+            urls: Vec::new(),
+            events: None,
+            timeout: DEFAULT_WEBHOOK_TIMEOUT,
+            retry_policy: RetryPolicy {
+                retries: 3,
+                initial_backoff: Duration::from_secs(1),
+                max_backoff: Duration::from_secs(30),
+                multiplier: 2,
+                reset_after: 0,
+            },
+        }
+    }
+
+    /// Add a URL every event is posted to
+    pub fn add_url(&mut self, url: &str) -> Result<()> {
+        self.urls.push(Url::parse(url).map_err(UpsError::Io)?);
+        Ok(())
+    }
+
+    /// Add an `https://` URL posted through the TLS relay listening at `relay`, such as `127.0.0.1:8443`
+    ///
+    /// The relay connects to the host of the URL over TLS, verifying its
+    /// certificate; the `Host` header names the host of the URL, not the relay.
+    pub fn add_relayed_url(&mut self, url: &str, relay: &str) -> Result<()> {
+        self.urls
+            .push(Url::parse_relayed(url, relay).map_err(UpsError::Io)?);
+        Ok(())
+    }
+
+    /// Only post the events with these names (as returned by [`UpsEvent::name`])
+    pub fn set_events(&mut self, events: Vec<String>) {
+        self.events = Some(events);
+    }
+
+    /// Set the connect, read and write timeout
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Set how failed deliveries are retried
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Post an event to every URL, blocking until all deliveries finished
+    ///
+    /// Every URL is tried even when an earlier one fails; the first error is
+    /// returned.
+    pub fn notify(&self, event: &UpsEvent) -> Result<()> {
+        if self
+            .events
+            .as_ref()
+            .is_some_and(|events| !events.iter().any(|name| name == event.name()))
+        {
+            return Ok(());
+        }
+
+        let body = self.payload(event).to_string();
+        let mut result = Ok(());
+        for url in &self.urls {
+            if let Err(e) = self.deliver(url, body.as_bytes()) {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Post an event from a background thread, so a slow endpoint does not delay polling
+    pub fn notify_in_background(&self, event: &UpsEvent) -> thread::JoinHandle<Result<()>> {
+        let notifier = self.clone();
+        let event = event.clone();
+        thread::spawn(move || notifier.notify(&event))
+    }
+
+    /// Post a body to one URL, retrying transient failures
+    fn deliver(&self, url: &Url, body: &[u8]) -> Result<()> {
+        let mut retry = 0;
+        loop {
+            let error = match http::post(url, "application/json", &[], body, self.timeout) {
+                Ok(status) if (200..300).contains(&status) => return Ok(()),
+                Ok(status) => {
+                    let error = io::Error::other(format!("{} replied {}", url, status));
+                    if status != 429 && status < 500 {
+                        return Err(UpsError::Io(error));
+                    }
+                    UpsError::Io(error)
+                }
+                Err(e) => UpsError::from(e),
+            };
+
+            if retry >= self.retry_policy.retries {
+                return Err(error);
+            }
+            thread::sleep(self.retry_policy.backoff(retry));
+            retry += 1;
+        }
+    }
+
+    /// JSON document posted for an event
+    fn payload(&self, event: &UpsEvent) -> Value {
+        let text = format!("{}: {}", self.ups_name, event);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Value::object(vec![
+            ("text", text.as_str().into()),
+            ("content", text.into()),
+            ("ups", self.ups_name.as_str().into()),
+            ("event", event.name().into()),
+            ("timestamp", (timestamp as f64).into()),
+            (
+                "status",
+                event
+                    .status()
+                    .map_or(Value::Null, |status| status.to_json()),
+            ),
+        ])
+    }
+}
@@ -0,0 +1,244 @@
+use crate::error::Result;
+use crate::http::{self, Request};
+use crate::json::{ToJson, Value};
+use crate::monitor::Monitor;
+use crate::transport::Transport;
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default listen address of the WebSocket server
+pub const DEFAULT_WEBSOCKET_ADDRESS: &str = "127.0.0.1:8081";
+
+/// Path clients connect to
+pub const WEBSOCKET_PATH: &str = "/ws";
+
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Pushes every status snapshot and event of a [`Monitor`] to WebSocket clients
+///
+/// Clients connect to `ws://<address>/ws` and receive one JSON text message per
+/// poll, `{"type":"status","status":{..}}`, preceded by
+/// `{"type":"event","event":..}` messages for the events the poll produced.
+/// Messages sent by clients are ignored.
+pub struct WebSocketServer<T: Transport> {
+    monitor: Monitor<T>,
+    listener: TcpListener,
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl<T: Transport> WebSocketServer<T> {
+    /// Bind the server to an address such as [`DEFAULT_WEBSOCKET_ADDRESS`]
+    pub fn bind<A: ToSocketAddrs>(monitor: Monitor<T>, address: A) -> Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        Ok(Self {
+            monitor,
+            listener,
+            clients: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    /// Get the address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Get a mutable reference to the monitor, to register callbacks
+    pub fn monitor(&mut self) -> &mut Monitor<T> {
+        &mut self.monitor
+    }
+
+    /// Get the number of connected clients
+    pub fn client_count(&self) -> usize {
+        self.lock_clients().len()
+    }
+
+    /// Accept clients in the background and stream to them until the listener fails
+    pub fn serve(&mut self) -> Result<()> {
+        let listener = self.listener.try_clone()?;
+        let clients = Arc::clone(&self.clients);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Ok(()) = handshake(&stream) {
+                    let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+                    clients
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .push(stream);
+                }
+            }
+        });
+
+        let events = self.monitor.subscribe();
+        loop {
+            let started = Instant::now();
+            let status = self.monitor.poll();
+
+            for event in events.try_iter() {
+                let message =
+                    Value::object(vec![("type", "event".into()), ("event", event.to_json())]);
+                self.broadcast(&message);
+            }
+            if let Ok(status) = status {
+                let message = Value::object(vec![
+                    ("type", "status".into()),
+                    ("status", status.to_json()),
+                ]);
+                self.broadcast(&message);
+            }
+
+            thread::sleep(self.monitor.interval().saturating_sub(started.elapsed()));
+        }
+    }
+
+    /// Send a message to every client, dropping the ones that went away
+    fn broadcast(&self, message: &Value) {
+        let frame = text_frame(&message.to_string());
+        self.lock_clients()
+            .retain_mut(|client| client.write_all(&frame).is_ok());
+    }
+
+    fn lock_clients(&self) -> std::sync::MutexGuard<'_, Vec<TcpStream>> {
+        self.clients
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Answer the opening handshake of a client
+fn handshake(stream: &TcpStream) -> std::io::Result<()> {
+    let request = match Request::read(stream) {
+        Ok(request) => request,
+        Err(e) => {
+            let _ = http::respond(stream, 400, "text/plain", b"bad request\n");
+            return Err(e);
+        }
+    };
+
+    if request.route() != WEBSOCKET_PATH {
+        http::respond(stream, 404, "text/plain", b"not found\n")?;
+        return Err(std::io::ErrorKind::InvalidInput.into());
+    }
+    upgrade(stream, &request)
+}
+
+/// Switch a connection to WebSocket, answering its upgrade request
+pub(crate) fn upgrade(stream: &TcpStream, request: &Request) -> std::io::Result<()> {
+    let upgrade = request
+        .header("upgrade")
+        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+    let key = match request.header("sec-websocket-key") {
+        Some(key) if upgrade && request.method == "GET" => key,
+        _ => {
+            http::respond(stream, 426, "text/plain", b"websocket upgrade required\n")?;
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
+    };
+
+    let accept = base64(&sha1(format!("{}{}", key, HANDSHAKE_GUID).as_bytes()));
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    )?;
+    stream.flush()
+}
+
+/// Encode an unmasked, unfragmented text frame
+pub(crate) fn text_frame(text: &str) -> Vec<u8> {
+    let payload = text.as_bytes();
+    let mut frame = vec![0x81];
+    match payload.len() {
+        length @ 0..=125 => frame.push(length as u8),
+        length @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// SHA-1 digest, needed only to compute the handshake accept key
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (chunk, word) in digest.chunks_mut(4).zip(h) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// Standard base64 with padding
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let triple = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(triple >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,295 @@
+use crate::error::Result;
+use std::ffi::c_void;
+use std::io;
+use std::iter;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// Name the service is registered under by default
+pub const DEFAULT_SERVICE_NAME: &str = "megatec-ups";
+
+const SERVICE_WIN32_OWN_PROCESS: u32 = 0x10;
+const SERVICE_AUTO_START: u32 = 2;
+const SERVICE_ERROR_NORMAL: u32 = 1;
+
+const SERVICE_STOPPED: u32 = 1;
+const SERVICE_STOP_PENDING: u32 = 3;
+const SERVICE_RUNNING: u32 = 4;
+
+const SERVICE_ACCEPT_STOP: u32 = 0x1;
+const SERVICE_ACCEPT_SHUTDOWN: u32 = 0x4;
+
+const SERVICE_CONTROL_STOP: u32 = 1;
+const SERVICE_CONTROL_INTERROGATE: u32 = 4;
+const SERVICE_CONTROL_SHUTDOWN: u32 = 5;
+
+const SC_MANAGER_CONNECT: u32 = 0x0001;
+const SC_MANAGER_CREATE_SERVICE: u32 = 0x0002;
+const SERVICE_START: u32 = 0x0010;
+const SERVICE_STOP: u32 = 0x0020;
+const DELETE: u32 = 0x0001_0000;
+
+const NO_ERROR: u32 = 0;
+const ERROR_CALL_NOT_IMPLEMENTED: u32 = 120;
+
+/// Time the SCM is asked to wait while the monitor finishes its current poll
+const STOP_WAIT_HINT_MS: u32 = 5000;
+
+type Handle = isize;
+type ServiceMain = extern "system" fn(u32, *mut *mut u16);
+type HandlerEx = extern "system" fn(u32, u32, *mut c_void, *mut c_void) -> u32;
+
+#[repr(C)]
+#[derive(Default)]
+struct ServiceStatus {
+    service_type: u32,
+    current_state: u32,
+    controls_accepted: u32,
+    win32_exit_code: u32,
+    service_specific_exit_code: u32,
+    check_point: u32,
+    wait_hint: u32,
+}
+
+#[repr(C)]
+struct ServiceTableEntry {
+    service_name: *mut u16,
+    service_proc: Option<ServiceMain>,
+}
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn StartServiceCtrlDispatcherW(start_table: *const ServiceTableEntry) -> i32;
+    fn RegisterServiceCtrlHandlerExW(
+        service_name: *const u16,
+        handler: HandlerEx,
+        context: *mut c_void,
+    ) -> Handle;
+    fn SetServiceStatus(status_handle: Handle, status: *const ServiceStatus) -> i32;
+    fn OpenSCManagerW(machine_name: *const u16, database_name: *const u16, access: u32) -> Handle;
+    fn CreateServiceW(
+        manager: Handle,
+        service_name: *const u16,
+        display_name: *const u16,
+        access: u32,
+        service_type: u32,
+        start_type: u32,
+        error_control: u32,
+        binary_path_name: *const u16,
+        load_order_group: *const u16,
+        tag_id: *mut u32,
+        dependencies: *const u16,
+        service_start_name: *const u16,
+        password: *const u16,
+    ) -> Handle;
+    fn OpenServiceW(manager: Handle, service_name: *const u16, access: u32) -> Handle;
+    fn DeleteService(service: Handle) -> i32;
+    fn StartServiceW(service: Handle, argc: u32, argv: *const *const u16) -> i32;
+    fn ControlService(service: Handle, control: u32, status: *mut ServiceStatus) -> i32;
+    fn CloseServiceHandle(handle: Handle) -> i32;
+}
+
+type ServiceBody = Box<dyn FnOnce(&AtomicBool) + Send>;
+
+static SERVICE_NAME: OnceLock<Vec<u16>> = OnceLock::new();
+static SERVICE_BODY: Mutex<Option<ServiceBody>> = Mutex::new(None);
+static STATUS_HANDLE: AtomicIsize = AtomicIsize::new(0);
+static RUNNING: AtomicBool = AtomicBool::new(true);
+
+/// Register the service with the Service Control Manager
+///
+/// `command_line` is appended to the quoted path of the current executable and
+/// must make it call [`run`] with the same service name. The service starts
+/// automatically at boot.
+pub fn install(name: &str, display_name: &str, command_line: &str) -> Result<()> {
+    let executable = std::env::current_exe()?;
+    let binary_path = format!("\"{}\" {}", executable.display(), command_line);
+
+    let manager = Scm::open(SC_MANAGER_CONNECT | SC_MANAGER_CREATE_SERVICE)?;
+    let (name, display_name, binary_path) = (wide(name), wide(display_name), wide(&binary_path));
+    // SAFETY: every string is NUL terminated and outlives the call
+    let service = unsafe {
+        CreateServiceW(
+            manager.0,
+            name.as_ptr(),
+            display_name.as_ptr(),
+            SERVICE_START,
+            SERVICE_WIN32_OWN_PROCESS,
+            SERVICE_AUTO_START,
+            SERVICE_ERROR_NORMAL,
+            binary_path.as_ptr(),
+            ptr::null(),
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null(),
+            ptr::null(),
+        )
+    };
+    Scm::wrap(service).map(drop)
+}
+
+/// Remove the service from the Service Control Manager
+pub fn uninstall(name: &str) -> Result<()> {
+    let service = Scm::open_service(name, DELETE)?;
+    // SAFETY: the handle is valid until `service` is dropped
+    check(unsafe { DeleteService(service.0) })
+}
+
+/// Ask the Service Control Manager to start the service
+pub fn start(name: &str) -> Result<()> {
+    let service = Scm::open_service(name, SERVICE_START)?;
+    // SAFETY: no arguments are passed, so the null argument vector is not read
+    check(unsafe { StartServiceW(service.0, 0, ptr::null()) })
+}
+
+/// Ask the Service Control Manager to stop the service
+pub fn stop(name: &str) -> Result<()> {
+    let service = Scm::open_service(name, SERVICE_STOP)?;
+    let mut status = ServiceStatus::default();
+    // SAFETY: `status` is a valid SERVICE_STATUS the call writes into
+    check(unsafe { ControlService(service.0, SERVICE_CONTROL_STOP, &mut status) })
+}
+
+/// Run `service` as the body of the service, when started by the Service Control Manager
+///
+/// Blocks until the service stops. The body receives a flag that is cleared
+/// when the SCM asks the service to stop or the system shuts down; it must
+/// return soon after, for example by handing it to [`Monitor::run`](crate::Monitor::run).
+/// Fails when the process was not started by the SCM.
+pub fn run<F>(name: &str, service: F) -> Result<()>
+where
+    F: FnOnce(&AtomicBool) + Send + 'static,
+{
+    let name = SERVICE_NAME.get_or_init(|| wide(name));
+    *SERVICE_BODY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Box::new(service));
+
+    let table = [
+        ServiceTableEntry {
+            service_name: name.as_ptr() as *mut u16,
+            service_proc: Some(service_main),
+        },
+        ServiceTableEntry {
+            service_name: ptr::null_mut(),
+            service_proc: None,
+        },
+    ];
+    // SAFETY: the table is terminated by a null entry and lives until the dispatcher returns
+    check(unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) })
+}
+
+/// Entry point called by the dispatcher on its own thread
+extern "system" fn service_main(_argc: u32, _argv: *mut *mut u16) {
+    let name = match SERVICE_NAME.get() {
+        Some(name) => name,
+        None => return,
+    };
+    // SAFETY: the name is NUL terminated and stored in a static
+    let handle =
+        unsafe { RegisterServiceCtrlHandlerExW(name.as_ptr(), control_handler, ptr::null_mut()) };
+    if handle == 0 {
+        return;
+    }
+    STATUS_HANDLE.store(handle, Ordering::SeqCst);
+
+    report(SERVICE_RUNNING);
+    let body = SERVICE_BODY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .take();
+    if let Some(body) = body {
+        body(&RUNNING);
+    }
+    report(SERVICE_STOPPED);
+}
+
+/// Handler for control requests sent by the SCM
+extern "system" fn control_handler(
+    control: u32,
+    _event_type: u32,
+    _event_data: *mut c_void,
+    _context: *mut c_void,
+) -> u32 {
+    match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+            RUNNING.store(false, Ordering::SeqCst);
+            report(SERVICE_STOP_PENDING);
+            NO_ERROR
+        }
+        SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+        _ => ERROR_CALL_NOT_IMPLEMENTED,
+    }
+}
+
+/// Report the state of the service to the SCM
+fn report(state: u32) {
+    let status = ServiceStatus {
+        service_type: SERVICE_WIN32_OWN_PROCESS,
+        current_state: state,
+        controls_accepted: if state == SERVICE_RUNNING {
+            SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN
+        } else {
+            0
+        },
+        wait_hint: if state == SERVICE_STOP_PENDING {
+            STOP_WAIT_HINT_MS
+        } else {
+            0
+        },
+        ..ServiceStatus::default()
+    };
+    // SAFETY: the handle was returned by RegisterServiceCtrlHandlerExW and is never closed
+    unsafe {
+        SetServiceStatus(STATUS_HANDLE.load(Ordering::SeqCst), &status);
+    }
+}
+
+/// Owned Service Control Manager or service handle
+struct Scm(Handle);
+
+impl Scm {
+    fn open(access: u32) -> Result<Self> {
+        // SAFETY: null names select the local machine and the active database
+        Self::wrap(unsafe { OpenSCManagerW(ptr::null(), ptr::null(), access) })
+    }
+
+    fn open_service(name: &str, access: u32) -> Result<Self> {
+        let manager = Self::open(SC_MANAGER_CONNECT)?;
+        let name = wide(name);
+        // SAFETY: the name is NUL terminated and outlives the call
+        Self::wrap(unsafe { OpenServiceW(manager.0, name.as_ptr(), access) })
+    }
+
+    fn wrap(handle: Handle) -> Result<Self> {
+        if handle == 0 {
+            Err(io::Error::last_os_error().into())
+        } else {
+            Ok(Self(handle))
+        }
+    }
+}
+
+impl Drop for Scm {
+    fn drop(&mut self) {
+        // SAFETY: the handle is owned and closed exactly once
+        unsafe {
+            CloseServiceHandle(self.0);
+        }
+    }
+}
+
+/// Turn a Win32 BOOL into a result carrying the last OS error
+fn check(result: i32) -> Result<()> {
+    if result == 0 {
+        Err(io::Error::last_os_error().into())
+    } else {
+        Ok(())
+    }
+}
+
+/// NUL terminated UTF-16 copy of a string
+fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain(iter::once(0)).collect()
+}
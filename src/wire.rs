@@ -0,0 +1,109 @@
+use crate::error::{Result, UpsError};
+use crate::timestamp;
+use std::fmt::{self, Write as _};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Bytes shown per hex dump line
+const DUMP_WIDTH: usize = 16;
+
+/// Opt-in log of the USB transfers of a transport
+///
+/// Every request is written as a `>` line naming the descriptor index or report
+/// sent, followed by a hex dump of the exact bytes returned or a `!` line with
+/// the error, each prefixed with an RFC 3339 UTC timestamp:
+///
+/// ```text
+/// 2024-05-01T12:00:00Z > GET_DESCRIPTOR index 3 length 256
+/// 2024-05-01T12:00:00Z < 94 bytes
+///   0000  5e 03 28 00 32 00 33 00  30 00 2e 00 30 00 20 00  ^.(.2.3.0...0. .
+/// ```
+///
+/// Unlike [`RecordingTransport`](crate::RecordingTransport), which records
+/// commands and replayable answers, the wire log shows what the USB bridge
+/// itself was asked for, which is what a garbled or InvalidResponse answer
+/// needs to be diagnosed remotely.
+pub struct WireLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl WireLog {
+    /// Log into the given writer
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Append the log to the file at the given path, creating it when missing
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(file))
+    }
+
+    /// Log to the standard error
+    pub fn stderr() -> Self {
+        Self::new(io::stderr())
+    }
+
+    /// Log a request sent to the device
+    pub(crate) fn request(&self, request: fmt::Arguments<'_>) {
+        self.write(|out| writeln!(out, "> {}", request))
+    }
+
+    /// Log the bytes returned by the device
+    pub(crate) fn response(&self, data: &[u8]) {
+        self.write(|out| {
+            writeln!(out, "< {} bytes", data.len())?;
+            out.write_all(hex_dump(data).as_bytes())
+        })
+    }
+
+    /// Log a failed transfer
+    pub(crate) fn error(&self, error: &UpsError) {
+        self.write(|out| writeln!(out, "! {}", error))
+    }
+
+    /// Write one timestamped entry, ignoring failures of the log itself
+    fn write(&self, entry: impl FnOnce(&mut dyn Write) -> io::Result<()>) {
+        let mut writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _ = write!(writer, "{} ", timestamp::rfc3339(SystemTime::now()))
+            .and_then(|()| entry(&mut *writer))
+            .and_then(|()| writer.flush());
+    }
+}
+
+/// Format bytes as hex dump lines with offsets and their printable characters
+pub fn hex_dump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in data.chunks(DUMP_WIDTH).enumerate() {
+        let _ = write!(out, "  {:04x} ", line * DUMP_WIDTH);
+        for column in 0..DUMP_WIDTH {
+            if column == DUMP_WIDTH / 2 {
+                out.push(' ');
+            }
+            match chunk.get(column) {
+                Some(byte) => {
+                    let _ = write!(out, " {:02x}", byte);
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  ");
+        out.extend(chunk.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        out.push('\n');
+    }
+    out
+}